@@ -0,0 +1,58 @@
+//! Throughput of the compiler phases on large generated programs
+//!
+//! Run with `cargo bench`, the corpus is the same for every run
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use tncc::{
+    analyse, gen_asm, parse,
+    progen::{generate_with, GenConfig},
+    scan, GenOpts, Target,
+};
+
+/// seeds of the corpus
+const SEEDS: [u64; 2] = [1, 2];
+
+/// large program within the subset of the code generation, so all phases run
+fn corpus(seed: u64) -> String {
+    let config = GenConfig {
+        max_funcs: 400,
+        max_stmts: 12,
+        max_block_depth: 3,
+        codegen_only: true,
+        ..GenConfig::default()
+    };
+    generate_with(seed, config)
+}
+
+fn phases(c: &mut Criterion) {
+    let opts = GenOpts::new(Target::Linux);
+    for seed in SEEDS {
+        let src = corpus(seed);
+        let toks = scan(&src).unwrap();
+        let ast = parse(toks.clone()).unwrap();
+
+        let mut group = c.benchmark_group(format!("corpus-{}", seed));
+        group.throughput(Throughput::Bytes(src.len() as u64));
+        group.bench_function("scan", |b| b.iter(|| scan(&src).unwrap()));
+        group.bench_function("parse", |b| {
+            b.iter_batched(
+                || toks.clone(),
+                |t| parse(t).unwrap(),
+                BatchSize::LargeInput,
+            )
+        });
+        group.bench_function("semantics", |b| b.iter(|| analyse(&ast).unwrap()));
+        group.bench_function("codegen", |b| b.iter(|| gen_asm(&ast, &opts).unwrap()));
+        group.bench_function("pipeline", |b| {
+            b.iter(|| {
+                let ast = parse(scan(&src).unwrap()).unwrap();
+                analyse(&ast).unwrap();
+                gen_asm(&ast, &opts).unwrap()
+            })
+        });
+        group.finish();
+    }
+}
+
+criterion_group!(benches, phases);
+criterion_main!(benches);
@@ -0,0 +1,6 @@
+int puts(char *s);
+
+int main() {
+    puts("hello");
+    return 0;
+}
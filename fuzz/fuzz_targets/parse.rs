@@ -0,0 +1,9 @@
+//! Fuzz the scanner and parser: `cargo +nightly fuzz run parse`
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = tncc::try_parse(data);
+});
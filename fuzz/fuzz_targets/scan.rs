@@ -0,0 +1,9 @@
+//! Fuzz the scanner: `cargo +nightly fuzz run scan`
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = tncc::try_scan(data);
+});
@@ -1,65 +1,191 @@
 //! Data structure for abstract syntax tree
 
+use serde::Serialize;
+
+use crate::{common::Span, intern::Symbol};
+
+pub mod printer;
+
 /// Abstract syntax tree parsed from source
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 pub struct Ast(pub Vec<ExtDecl>);
 
-#[derive(Debug)]
+/// identifier of a node, unique in the AST and assigned in parsing order
+///
+/// passes store their results in side tables keyed by the id instead of mutating the AST
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize)]
+pub struct NodeId(pub u32);
+
+#[derive(Debug, Serialize)]
 pub enum ExtDecl {
     Func(FuncDecl),
     Global(GlobalVarDecl),
+    StaticAssert(StaticAssert),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 pub struct FuncDecl {
-    pub return_type: DataType,
-    pub name: String,
+    pub id: NodeId,
+    pub span: Span,
+    pub return_type: Type,
+    pub name: Symbol,
     pub params: Vec<ParamDecl>,
+    /// parameters are declared, `(void)` for none, false for the unspecified `()` and the
+    /// parameter names of an old-style definition
+    pub prototype: bool,
+    /// declared `extern`, the default linkage of functions
+    pub external: bool,
+    /// declared `static`, the name is not visible to other objects
+    pub internal: bool,
+    /// the type specifier is omitted and defaults to int, K&R only
+    pub implicit_int: bool,
+    /// GNU attributes before and after the declarator
+    pub attrs: Vec<Attribute>,
     pub cmp_stmt: CmpStmt,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 pub struct ParamDecl {
-    pub data_type: DataType,
-    pub name: String,
+    pub id: NodeId,
+    pub span: Span,
+    pub data_type: Type,
+    pub name: Symbol,
+    /// the type specifier is omitted and defaults to int, K&R only
+    pub implicit_int: bool,
 }
 
 /// Compound statement
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 pub struct CmpStmt {
+    pub id: NodeId,
+    pub span: Span,
     pub stmts: Vec<Stmt>,
 }
 
-#[derive(Debug)]
-pub enum Stmt {
+#[derive(Debug, Serialize)]
+pub struct Stmt {
+    pub id: NodeId,
+    pub span: Span,
+    pub kind: StmtKind,
+}
+
+#[derive(Debug, Serialize)]
+pub enum StmtKind {
     Compound(CmpStmt),
     VarDecl(VarDecl),
-    Assignment(String, Expr),
+    Assignment(Symbol, Expr),
     Return(Option<Expr>),
     Expr(Expr),
+    StaticAssert(StaticAssert),
+    /// `asm("nop")`, the instructions are pasted into the output verbatim
+    Asm(Vec<u8>),
 }
 
-#[derive(Debug)]
-pub enum Expr {
+#[derive(Debug, Serialize)]
+pub struct Expr {
+    pub id: NodeId,
+    pub span: Span,
+    pub kind: ExprKind,
+}
+
+#[derive(Debug, Serialize)]
+pub enum ExprKind {
     IntConst(i64),
-    FunctionCall(String, Vec<Expr>),
-    VarRef(String),
+    /// string literal, bytes without the terminating null
+    StrLit(Vec<u8>),
+    FunctionCall(Symbol, Vec<Expr>),
+    VarRef(Symbol),
     Arith(Box<Expr>, ArithOp, Box<Expr>),
+    Unary(UnaryOp, Box<Expr>),
+    /// brace enclosed initializer, only the initializer of a declaration
+    InitList(Vec<InitItem>),
+}
+
+/// element of an initializer list, the designators select the initialized subobject
+#[derive(Debug, Serialize)]
+pub struct InitItem {
+    pub designators: Vec<Designator>,
+    pub value: Expr,
 }
 
-#[derive(Debug)]
-pub struct VarDecl(pub DataType, pub String, pub Option<Expr>);
+#[derive(Debug, Serialize)]
+pub enum Designator {
+    /// '[2] ='
+    Index(Expr),
+    /// '.x ='
+    Member(Symbol),
+}
 
-#[derive(Debug)]
-pub struct GlobalVarDecl(pub DataType, pub String, pub Option<Expr>);
+#[derive(Debug, Serialize)]
+pub struct VarDecl {
+    pub id: NodeId,
+    pub span: Span,
+    pub data_type: Type,
+    pub name: Symbol,
+    pub init: Option<Expr>,
+    /// GNU attributes before and after the declarator
+    pub attrs: Vec<Attribute>,
+}
 
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
+pub struct GlobalVarDecl {
+    pub id: NodeId,
+    pub span: Span,
+    pub data_type: Type,
+    pub name: Symbol,
+    pub init: Option<Expr>,
+    /// declared `extern`, without storage in this object unless initialized
+    pub external: bool,
+    /// declared `static`, the name is not visible to other objects
+    pub internal: bool,
+    /// the type specifier is omitted and defaults to int, K&R only
+    pub implicit_int: bool,
+    /// GNU attributes before and after the declarator
+    pub attrs: Vec<Attribute>,
+}
+
+impl GlobalVarDecl {
+    /// defines a variable, not a prototype or an extern declaration
+    pub fn has_storage(&self) -> bool {
+        let extern_decl = self.external && self.init.is_none();
+        !(matches!(self.data_type, Type::Function(_, _)) || extern_decl)
+    }
+}
+
+/// GNU attribute of a declaration, i.e `aligned(16)` in `__attribute__((aligned(16)))`
+#[derive(Debug, Serialize)]
+pub struct Attribute {
+    pub span: Span,
+    /// name without the surrounding underscores, `__noreturn__` is `noreturn`
+    pub name: Symbol,
+    pub args: Vec<Expr>,
+}
+
+/// `_Static_assert(cond, "message");` at file or block scope, checked by the semantic analysis
+#[derive(Debug, Serialize)]
+pub struct StaticAssert {
+    pub id: NodeId,
+    pub span: Span,
+    pub cond: Expr,
+    /// bytes of the string literal, optional since C23
+    pub message: Option<Vec<u8>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
 pub enum ArithOp {
     Add,
     Sub,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum UnaryOp {
+    /// '-x'
+    Neg,
+    /// '+x'
+    Plus,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
 pub enum DataType {
     Void,
     Char,
@@ -69,3 +195,335 @@ pub enum DataType {
     Float,
     Double,
 }
+
+/// declared type, the base type derived by pointer, array and function declarators
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub enum Type {
+    Base(DataType),
+    Pointer(Box<Type>),
+    /// element type and optional length
+    Array(Box<Type>, Option<u64>),
+    /// return type and parameter types, none if not specified by `()`
+    Function(Box<Type>, Option<Vec<Type>>),
+    /// qualified base type or pointer, i.e `volatile int` and `int *volatile`
+    Volatile(Box<Type>),
+}
+
+impl Type {
+    /// base type if the type is not derived
+    pub fn as_base(&self) -> Option<DataType> {
+        match self {
+            Type::Base(dt) => Some(*dt),
+            _ => None,
+        }
+    }
+
+    /// type without the qualifier of the outermost type, `int *volatile` is `int *`
+    pub fn unqualified(&self) -> &Type {
+        match self {
+            Type::Volatile(t) => t,
+            t => t,
+        }
+    }
+
+    /// accesses of objects of the type are side effects and must not be eliminated
+    pub fn is_volatile(&self) -> bool {
+        matches!(self, Type::Volatile(_))
+    }
+
+    /// type of the value in an expression, also the adjusted type of a parameter
+    ///
+    /// arrays decay to pointers to the element type and functions to function pointers
+    pub fn decay(&self) -> Type {
+        match self {
+            Type::Array(elem, _) => Type::Pointer(elem.clone()),
+            Type::Function(_, _) => Type::Pointer(Box::new(self.clone())),
+            t => t.clone(),
+        }
+    }
+}
+
+impl From<DataType> for Type {
+    fn from(dt: DataType) -> Self {
+        Type::Base(dt)
+    }
+}
+
+/// traverse the AST by reference
+///
+/// every method walks into the children by default, override the nodes of interest and call the
+/// matching `walk_*` function to keep traversing below them
+pub trait Visitor<'ast> {
+    fn visit_ast(&mut self, ast: &'ast Ast) {
+        walk_ast(self, ast)
+    }
+
+    fn visit_ext_decl(&mut self, ext: &'ast ExtDecl) {
+        walk_ext_decl(self, ext)
+    }
+
+    fn visit_func_decl(&mut self, func: &'ast FuncDecl) {
+        walk_func_decl(self, func)
+    }
+
+    fn visit_param_decl(&mut self, _param: &'ast ParamDecl) {}
+
+    fn visit_global_var_decl(&mut self, decl: &'ast GlobalVarDecl) {
+        walk_global_var_decl(self, decl)
+    }
+
+    fn visit_cmp_stmt(&mut self, cmp_stmt: &'ast CmpStmt) {
+        walk_cmp_stmt(self, cmp_stmt)
+    }
+
+    fn visit_stmt(&mut self, stmt: &'ast Stmt) {
+        walk_stmt(self, stmt)
+    }
+
+    fn visit_var_decl(&mut self, decl: &'ast VarDecl) {
+        walk_var_decl(self, decl)
+    }
+
+    fn visit_static_assert(&mut self, sa: &'ast StaticAssert) {
+        walk_static_assert(self, sa)
+    }
+
+    fn visit_expr(&mut self, expr: &'ast Expr) {
+        walk_expr(self, expr)
+    }
+}
+
+pub fn walk_ast<'ast, V: Visitor<'ast> + ?Sized>(v: &mut V, ast: &'ast Ast) {
+    ast.0.iter().for_each(|ext| v.visit_ext_decl(ext));
+}
+
+pub fn walk_ext_decl<'ast, V: Visitor<'ast> + ?Sized>(v: &mut V, ext: &'ast ExtDecl) {
+    match ext {
+        ExtDecl::Func(func) => v.visit_func_decl(func),
+        ExtDecl::Global(decl) => v.visit_global_var_decl(decl),
+        ExtDecl::StaticAssert(sa) => v.visit_static_assert(sa),
+    }
+}
+
+pub fn walk_func_decl<'ast, V: Visitor<'ast> + ?Sized>(v: &mut V, func: &'ast FuncDecl) {
+    func.params.iter().for_each(|p| v.visit_param_decl(p));
+    v.visit_cmp_stmt(&func.cmp_stmt);
+}
+
+pub fn walk_global_var_decl<'ast, V: Visitor<'ast> + ?Sized>(v: &mut V, decl: &'ast GlobalVarDecl) {
+    if let Some(e) = &decl.init {
+        v.visit_expr(e);
+    }
+}
+
+pub fn walk_cmp_stmt<'ast, V: Visitor<'ast> + ?Sized>(v: &mut V, cmp_stmt: &'ast CmpStmt) {
+    cmp_stmt.stmts.iter().for_each(|s| v.visit_stmt(s));
+}
+
+pub fn walk_stmt<'ast, V: Visitor<'ast> + ?Sized>(v: &mut V, stmt: &'ast Stmt) {
+    match &stmt.kind {
+        StmtKind::Compound(cmp_stmt) => v.visit_cmp_stmt(cmp_stmt),
+        StmtKind::VarDecl(decl) => v.visit_var_decl(decl),
+        StmtKind::Assignment(_, e) | StmtKind::Expr(e) | StmtKind::Return(Some(e)) => {
+            v.visit_expr(e)
+        }
+        StmtKind::StaticAssert(sa) => v.visit_static_assert(sa),
+        StmtKind::Return(None) | StmtKind::Asm(_) => {}
+    }
+}
+
+pub fn walk_var_decl<'ast, V: Visitor<'ast> + ?Sized>(v: &mut V, decl: &'ast VarDecl) {
+    if let Some(e) = &decl.init {
+        v.visit_expr(e);
+    }
+}
+
+pub fn walk_static_assert<'ast, V: Visitor<'ast> + ?Sized>(v: &mut V, sa: &'ast StaticAssert) {
+    v.visit_expr(&sa.cond);
+}
+
+pub fn walk_expr<'ast, V: Visitor<'ast> + ?Sized>(v: &mut V, expr: &'ast Expr) {
+    match &expr.kind {
+        ExprKind::IntConst(_) | ExprKind::StrLit(_) | ExprKind::VarRef(_) => {}
+        ExprKind::FunctionCall(_, args) => args.iter().for_each(|a| v.visit_expr(a)),
+        ExprKind::Arith(left, _, right) => {
+            v.visit_expr(left);
+            v.visit_expr(right);
+        }
+        ExprKind::Unary(_, e) => v.visit_expr(e),
+        ExprKind::InitList(items) => items.iter().for_each(|item| {
+            item.designators.iter().for_each(|d| {
+                if let Designator::Index(e) = d {
+                    v.visit_expr(e)
+                }
+            });
+            v.visit_expr(&item.value)
+        }),
+    }
+}
+
+/// traverse and modify the AST in place, the mutable version of `Visitor`
+pub trait VisitorMut {
+    fn visit_ast_mut(&mut self, ast: &mut Ast) {
+        walk_ast_mut(self, ast)
+    }
+
+    fn visit_ext_decl_mut(&mut self, ext: &mut ExtDecl) {
+        walk_ext_decl_mut(self, ext)
+    }
+
+    fn visit_func_decl_mut(&mut self, func: &mut FuncDecl) {
+        walk_func_decl_mut(self, func)
+    }
+
+    fn visit_param_decl_mut(&mut self, _param: &mut ParamDecl) {}
+
+    fn visit_global_var_decl_mut(&mut self, decl: &mut GlobalVarDecl) {
+        walk_global_var_decl_mut(self, decl)
+    }
+
+    fn visit_cmp_stmt_mut(&mut self, cmp_stmt: &mut CmpStmt) {
+        walk_cmp_stmt_mut(self, cmp_stmt)
+    }
+
+    fn visit_stmt_mut(&mut self, stmt: &mut Stmt) {
+        walk_stmt_mut(self, stmt)
+    }
+
+    fn visit_var_decl_mut(&mut self, decl: &mut VarDecl) {
+        walk_var_decl_mut(self, decl)
+    }
+
+    fn visit_static_assert_mut(&mut self, sa: &mut StaticAssert) {
+        walk_static_assert_mut(self, sa)
+    }
+
+    fn visit_expr_mut(&mut self, expr: &mut Expr) {
+        walk_expr_mut(self, expr)
+    }
+}
+
+pub fn walk_ast_mut<V: VisitorMut + ?Sized>(v: &mut V, ast: &mut Ast) {
+    ast.0.iter_mut().for_each(|ext| v.visit_ext_decl_mut(ext));
+}
+
+pub fn walk_ext_decl_mut<V: VisitorMut + ?Sized>(v: &mut V, ext: &mut ExtDecl) {
+    match ext {
+        ExtDecl::Func(func) => v.visit_func_decl_mut(func),
+        ExtDecl::Global(decl) => v.visit_global_var_decl_mut(decl),
+        ExtDecl::StaticAssert(sa) => v.visit_static_assert_mut(sa),
+    }
+}
+
+pub fn walk_func_decl_mut<V: VisitorMut + ?Sized>(v: &mut V, func: &mut FuncDecl) {
+    func.params
+        .iter_mut()
+        .for_each(|p| v.visit_param_decl_mut(p));
+    v.visit_cmp_stmt_mut(&mut func.cmp_stmt);
+}
+
+pub fn walk_global_var_decl_mut<V: VisitorMut + ?Sized>(v: &mut V, decl: &mut GlobalVarDecl) {
+    if let Some(e) = &mut decl.init {
+        v.visit_expr_mut(e);
+    }
+}
+
+pub fn walk_cmp_stmt_mut<V: VisitorMut + ?Sized>(v: &mut V, cmp_stmt: &mut CmpStmt) {
+    cmp_stmt.stmts.iter_mut().for_each(|s| v.visit_stmt_mut(s));
+}
+
+pub fn walk_stmt_mut<V: VisitorMut + ?Sized>(v: &mut V, stmt: &mut Stmt) {
+    match &mut stmt.kind {
+        StmtKind::Compound(cmp_stmt) => v.visit_cmp_stmt_mut(cmp_stmt),
+        StmtKind::VarDecl(decl) => v.visit_var_decl_mut(decl),
+        StmtKind::Assignment(_, e) | StmtKind::Expr(e) | StmtKind::Return(Some(e)) => {
+            v.visit_expr_mut(e)
+        }
+        StmtKind::StaticAssert(sa) => v.visit_static_assert_mut(sa),
+        StmtKind::Return(None) | StmtKind::Asm(_) => {}
+    }
+}
+
+pub fn walk_var_decl_mut<V: VisitorMut + ?Sized>(v: &mut V, decl: &mut VarDecl) {
+    if let Some(e) = &mut decl.init {
+        v.visit_expr_mut(e);
+    }
+}
+
+pub fn walk_static_assert_mut<V: VisitorMut + ?Sized>(v: &mut V, sa: &mut StaticAssert) {
+    v.visit_expr_mut(&mut sa.cond);
+}
+
+pub fn walk_expr_mut<V: VisitorMut + ?Sized>(v: &mut V, expr: &mut Expr) {
+    match &mut expr.kind {
+        ExprKind::IntConst(_) | ExprKind::StrLit(_) | ExprKind::VarRef(_) => {}
+        ExprKind::FunctionCall(_, args) => args.iter_mut().for_each(|a| v.visit_expr_mut(a)),
+        ExprKind::Arith(left, _, right) => {
+            v.visit_expr_mut(left);
+            v.visit_expr_mut(right);
+        }
+        ExprKind::Unary(_, e) => v.visit_expr_mut(e),
+        ExprKind::InitList(items) => items.iter_mut().for_each(|item| {
+            item.designators.iter_mut().for_each(|d| {
+                if let Designator::Index(e) = d {
+                    v.visit_expr_mut(e)
+                }
+            });
+            v.visit_expr_mut(&mut item.value)
+        }),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{parse, scan};
+
+    use super::{
+        printer::print_c, walk_expr, walk_expr_mut, Expr, ExprKind, Symbol, Visitor, VisitorMut,
+    };
+
+    /// collect names of called functions
+    #[derive(Default)]
+    struct Calls(Vec<Symbol>);
+
+    impl<'ast> Visitor<'ast> for Calls {
+        fn visit_expr(&mut self, expr: &'ast Expr) {
+            if let ExprKind::FunctionCall(name, _) = &expr.kind {
+                self.0.push(*name);
+            }
+            walk_expr(self, expr)
+        }
+    }
+
+    /// replace every integer constant with zero
+    struct Zero;
+
+    impl VisitorMut for Zero {
+        fn visit_expr_mut(&mut self, expr: &mut Expr) {
+            if let ExprKind::IntConst(v) = &mut expr.kind {
+                *v = 0;
+            }
+            walk_expr_mut(self, expr)
+        }
+    }
+
+    #[test]
+    fn test_visitor() {
+        let src = "int g = f(1); int main() { int a = g(h(2)); { return k(); } }";
+        let ast = parse(scan(src).unwrap()).unwrap();
+        let mut calls = Calls::default();
+        calls.visit_ast(&ast);
+        assert_eq!(calls.0, vec!["f", "g", "h", "k"]);
+    }
+
+    #[test]
+    fn test_visitor_mut() {
+        let src = "int g = 1; int main() { int a = f(2, 3); return 4; }";
+        let mut ast = parse(scan(src).unwrap()).unwrap();
+        Zero.visit_ast_mut(&mut ast);
+        assert_eq!(
+            print_c(&ast),
+            "int g = 0;\n\nint main() {\n    int a = f(0, 0);\n    return 0;\n}\n"
+        );
+    }
+}
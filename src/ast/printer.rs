@@ -0,0 +1,384 @@
+//! Render AST back to formatted C source
+
+use super::*;
+use crate::common::escape;
+
+/// spaces for each level of indentation
+const INDENT: &str = "    ";
+
+pub fn print_c(ast: &Ast) -> String {
+    let mut p = Printer::new();
+    p.print(ast);
+    p.str
+}
+
+struct Printer {
+    str: String,
+    level: usize,
+}
+
+impl Printer {
+    fn new() -> Self {
+        Printer {
+            str: String::new(),
+            level: 0,
+        }
+    }
+
+    fn print(&mut self, ast: &Ast) {
+        ast.0.iter().enumerate().for_each(|(i, ext)| {
+            match ext {
+                // blank line around function definitions
+                ExtDecl::Func(f) => {
+                    if i > 0 {
+                        self.str.push('\n');
+                    }
+                    self.print_func(f)
+                }
+                ExtDecl::Global(decl) => {
+                    let var = format_var(&decl.data_type, decl.name, &decl.init);
+                    let storage = match (decl.external, decl.internal) {
+                        (true, _) => "extern ",
+                        (_, true) => "static ",
+                        _ => "",
+                    };
+                    self.line(&format!("{}{}{}", format_attrs(&decl.attrs), storage, var))
+                }
+                ExtDecl::StaticAssert(sa) => self.line(&format_static_assert(sa)),
+            }
+        });
+    }
+
+    fn print_func(&mut self, func: &FuncDecl) {
+        if !func.prototype && !func.params.is_empty() {
+            return self.print_old_style_func(func);
+        }
+        let params: Vec<String> = func
+            .params
+            .iter()
+            .map(|p| declare(&p.data_type, p.name.to_string()))
+            .collect();
+        let params = match params.is_empty() {
+            true if func.prototype => String::from("void"),
+            _ => params.join(", "),
+        };
+        let decl = format!("{}({})", func.name, params);
+        let attrs = format_func_specifiers(func);
+        self.str
+            .push_str(&format!("{}{} ", attrs, declare(&func.return_type, decl)));
+        self.print_cmp_stmt(&func.cmp_stmt);
+    }
+
+    /// parameter names, then their declarations before the body, so calls stay unchecked
+    fn print_old_style_func(&mut self, func: &FuncDecl) {
+        let names: Vec<String> = func.params.iter().map(|p| p.name.to_string()).collect();
+        let decl = format!("{}({})", func.name, names.join(", "));
+        let attrs = format_func_specifiers(func);
+        self.line(&format!("{}{}", attrs, declare(&func.return_type, decl)));
+        func.params
+            .iter()
+            .for_each(|p| self.line(&format!("{};", declare(&p.data_type, p.name.to_string()))));
+        self.print_cmp_stmt(&func.cmp_stmt);
+    }
+
+    /// print block starting at the current position
+    fn print_cmp_stmt(&mut self, cmp_stmt: &CmpStmt) {
+        self.str.push_str("{\n");
+        self.level += 1;
+        cmp_stmt.stmts.iter().for_each(|s| self.print_stmt(s));
+        self.level -= 1;
+        self.line("}");
+    }
+
+    fn print_stmt(&mut self, stmt: &Stmt) {
+        match &stmt.kind {
+            StmtKind::Compound(st) => {
+                self.indent();
+                self.print_cmp_stmt(st)
+            }
+            StmtKind::VarDecl(d) => {
+                let var = format_var(&d.data_type, d.name, &d.init);
+                self.line(&format!("{}{}", format_attrs(&d.attrs), var))
+            }
+            StmtKind::Assignment(name, e) => self.line(&format!("{} = {};", name, format_expr(e))),
+            StmtKind::Return(Some(e)) => self.line(&format!("return {};", format_expr(e))),
+            StmtKind::Return(None) => self.line("return;"),
+            StmtKind::Expr(e) => self.line(&format!("{};", format_expr(e))),
+            StmtKind::StaticAssert(sa) => self.line(&format_static_assert(sa)),
+            StmtKind::Asm(code) => self.line(&format!("asm(\"{}\");", escape(code))),
+        }
+    }
+
+    fn indent(&mut self) {
+        (0..self.level).for_each(|_| self.str.push_str(INDENT));
+    }
+
+    /// push indented line
+    fn line(&mut self, str: &str) {
+        self.indent();
+        self.str.push_str(str);
+        self.str.push('\n');
+    }
+}
+
+fn format_var(ty: &Type, name: Symbol, expr: &Option<Expr>) -> String {
+    let decl = declare(ty, name.to_string());
+    match expr {
+        Some(e) => format!("{} = {};", decl, format_expr(e)),
+        None => format!("{};", decl),
+    }
+}
+
+/// attributes and storage class before the return type of the function
+fn format_func_specifiers(func: &FuncDecl) -> String {
+    let storage = match (func.external, func.internal) {
+        (true, _) => "extern ",
+        (_, true) => "static ",
+        _ => "",
+    };
+    format!("{}{}", format_attrs(&func.attrs), storage)
+}
+
+/// attributes before the declaration, i.e `__attribute__((aligned(8))) `
+fn format_attrs(attrs: &[Attribute]) -> String {
+    if attrs.is_empty() {
+        return String::new();
+    }
+    let attrs: Vec<String> = attrs
+        .iter()
+        .map(|a| match a.args.is_empty() {
+            true => a.name.to_string(),
+            false => {
+                let args: Vec<String> = a.args.iter().map(format_expr).collect();
+                format!("{}({})", a.name, args.join(", "))
+            }
+        })
+        .collect();
+    format!("__attribute__(({})) ", attrs.join(", "))
+}
+
+fn format_static_assert(sa: &StaticAssert) -> String {
+    match &sa.message {
+        Some(m) => format!(
+            "_Static_assert({}, \"{}\");",
+            format_expr(&sa.cond),
+            escape(m)
+        ),
+        None => format!("_Static_assert({});", format_expr(&sa.cond)),
+    }
+}
+
+/// C declaration of the type around the declarator, abstract if the declarator is empty
+///
+/// i.e pointer to function `int (*fp)(int)`
+pub fn declare(ty: &Type, declarator: String) -> String {
+    match ty {
+        // abstract declarator of an array, i.e `int[]`
+        Type::Base(dt) if declarator.is_empty() || declarator.starts_with('[') => {
+            format!("{}{}", type_name(dt), declarator)
+        }
+        Type::Base(dt) => format!("{} {}", type_name(dt), declarator),
+        Type::Pointer(t) => match t.as_ref() {
+            Type::Array(_, _) | Type::Function(_, _) => declare(t, format!("(*{})", declarator)),
+            _ => declare(t, format!("*{}", declarator)),
+        },
+        // the qualifier of a pointer follows the '*', i.e `int *volatile p`
+        Type::Volatile(t) => match t.as_ref() {
+            Type::Base(_) => format!("volatile {}", declare(t, declarator)),
+            _ if declarator.is_empty() => declare(t, String::from("volatile")),
+            _ => declare(t, format!("volatile {}", declarator)),
+        },
+        Type::Array(t, len) => {
+            let len = len.map(|l| l.to_string()).unwrap_or_default();
+            declare(t, format!("{}[{}]", declarator, len))
+        }
+        Type::Function(t, params) => {
+            let params = match params {
+                Some(params) if params.is_empty() => String::from("void"),
+                Some(params) => {
+                    let params: Vec<String> =
+                        params.iter().map(|p| declare(p, String::new())).collect();
+                    params.join(", ")
+                }
+                None => String::new(),
+            };
+            declare(t, format!("{}({})", declarator, params))
+        }
+    }
+}
+
+impl std::fmt::Display for Type {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", declare(self, String::new()))
+    }
+}
+
+/// format expression, right operands of arithmetic are parenthesized since the operators are
+/// left associative, so are arithmetic operands of unary operators
+fn format_expr(expr: &Expr) -> String {
+    match &expr.kind {
+        ExprKind::IntConst(v) => v.to_string(),
+        ExprKind::StrLit(s) => format!("\"{}\"", escape(s)),
+        ExprKind::InitList(items) => {
+            let items: Vec<String> = items
+                .iter()
+                .map(|item| {
+                    let designators: String = item
+                        .designators
+                        .iter()
+                        .map(|d| match d {
+                            Designator::Index(e) => format!("[{}]", format_expr(e)),
+                            Designator::Member(name) => format!(".{}", name),
+                        })
+                        .collect();
+                    match designators.is_empty() {
+                        true => format_expr(&item.value),
+                        false => format!("{} = {}", designators, format_expr(&item.value)),
+                    }
+                })
+                .collect();
+            format!("{{{}}}", items.join(", "))
+        }
+        ExprKind::VarRef(name) => name.to_string(),
+        ExprKind::FunctionCall(name, args) => {
+            let args: Vec<String> = args.iter().map(format_expr).collect();
+            format!("{}({})", name, args.join(", "))
+        }
+        ExprKind::Arith(left, op, right) => {
+            let op = match op {
+                ArithOp::Add => "+",
+                ArithOp::Sub => "-",
+            };
+            let right = match &right.kind {
+                ExprKind::Arith(_, _, _) => format!("({})", format_expr(right)),
+                _ => format_expr(right),
+            };
+            format!("{} {} {}", format_expr(left), op, right)
+        }
+        ExprKind::Unary(op, e) => {
+            let op = match op {
+                UnaryOp::Neg => "-",
+                UnaryOp::Plus => "+",
+            };
+            match &e.kind {
+                ExprKind::Arith(_, _, _) => format!("{}({})", op, format_expr(e)),
+                // '- -x' must not become '--x'
+                ExprKind::Unary(_, _) => format!("{} {}", op, format_expr(e)),
+                _ => format!("{}{}", op, format_expr(e)),
+            }
+        }
+    }
+}
+
+fn type_name(dt: &DataType) -> &'static str {
+    match dt {
+        DataType::Void => "void",
+        DataType::Char => "char",
+        DataType::Short => "short",
+        DataType::Int => "int",
+        DataType::Long => "long",
+        DataType::Float => "float",
+        DataType::Double => "double",
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use test_case::test_case;
+
+    use crate::{ast::*, common::Span, parse, parse_with, scan, Standard};
+
+    use super::print_c;
+
+    #[test_case("int main(){return 1;}", "int main() {\n    return 1;\n}\n")]
+    #[test_case(
+        "int g=1;int h;void foo(int x,int y){bar(x,2);{int a=y;return;}}",
+        "int g = 1;\nint h;\n\nvoid foo(int x, int y) {\n    bar(x, 2);\n    {\n        int a = y;\n        return;\n    }\n}\n"
+    )]
+    #[test_case("int a(){} int b(){}", "int a() {\n}\n\nint b() {\n}\n")]
+    #[test_case(
+        "int g=1-2+-3-f(-g,- -g,+-g)-a;",
+        "int g = 1 - 2 + -3 - f(-g, - -g, + -g) - a;\n"
+    )]
+    #[test_case(
+        r#"int main(){puts("a" "b\n\x01");}"#,
+        "int main() {\n    puts(\"ab\\n\\001\");\n}\n"
+    )]
+    #[test_case(
+        "int a[4]={1,[2]=7,};int main(){int m[2][2]={{1},[1][0]=2};}",
+        "int a[4] = {1, [2] = 7};\n\nint main() {\n    int m[2][2] = {{1}, [1][0] = 2};\n}\n"
+    )]
+    #[test_case(
+        "_Static_assert(1,\"a\" \"b\");int main(){_Static_assert(-1);}",
+        "_Static_assert(1, \"ab\");\n\nint main() {\n    _Static_assert(-1);\n}\n"
+    )]
+    #[test_case(
+        "void f() __attribute__((__noreturn__, used)) {} int g __attribute__((aligned(8))) = 1;",
+        "__attribute__((noreturn, used)) void f() {\n}\n__attribute__((aligned(8))) int g = 1;\n"
+    )]
+    #[test_case(
+        "int main(){__attribute__((aligned)) __attribute__(()) char c;}",
+        "int main() {\n    __attribute__((aligned)) char c;\n}\n"
+    )]
+    #[test_case(
+        "int main(){__asm__ volatile(\"nop\\n\\t\" \"wfi\");}",
+        "int main() {\n    asm(\"nop\\n\\twfi\");\n}\n"
+    )]
+    #[test_case(
+        "static int f(void);static int f(void){return 0;}static int n=1;",
+        "static int f(void);\n\nstatic int f(void) {\n    return 0;\n}\nstatic int n = 1;\n"
+    )]
+    #[test_case(
+        "extern int errno;extern char*environ[];__attribute__((used)) extern int f(void);",
+        "extern int errno;\nextern char *environ[];\n__attribute__((used)) extern int f(void);\n"
+    )]
+    fn test_print(src: &str, expected: &str) {
+        let ast = parse(scan(src).unwrap()).unwrap();
+        assert_eq!(print_c(&ast), expected);
+
+        // round trip
+        let again = parse(scan(expected).unwrap()).unwrap();
+        assert_eq!(print_c(&again), expected);
+    }
+
+    // K&R declarations get the explicit int, old-style definitions are kept
+    #[test_case("g;main(){}", "int g;\n\nint main() {\n}\n")]
+    #[test_case(
+        "f(a,s)char*s;{return a;}",
+        "int f(a, s)\nint a;\nchar *s;\n{\n    return a;\n}\n"
+    )]
+    fn test_print_knr(src: &str, expected: &str) {
+        let ast = parse_with(scan(src).unwrap(), Standard::Knr).unwrap();
+        assert_eq!(print_c(&ast), expected);
+
+        let again = parse_with(scan(expected).unwrap(), Standard::Knr).unwrap();
+        assert_eq!(print_c(&again), expected);
+    }
+
+    #[test]
+    fn test_arith() {
+        let (id, span) = (NodeId(0), Span::default());
+        let expr = |kind| Box::new(Expr { id, span, kind });
+        let one = || expr(ExprKind::IntConst(1));
+        let ast = Ast(vec![ExtDecl::Global(GlobalVarDecl {
+            id,
+            span,
+            data_type: Type::Base(DataType::Int),
+            name: Symbol::intern("g"),
+            external: false,
+            internal: false,
+            init: Some(Expr {
+                id,
+                span,
+                kind: ExprKind::Arith(
+                    expr(ExprKind::Arith(one(), ArithOp::Add, one())),
+                    ArithOp::Sub,
+                    expr(ExprKind::Arith(one(), ArithOp::Sub, one())),
+                ),
+            }),
+            implicit_int: false,
+            attrs: Vec::new(),
+        })]);
+        assert_eq!(print_c(&ast), "int g = 1 + 1 - (1 - 1);\n");
+    }
+}
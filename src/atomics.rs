@@ -0,0 +1,299 @@
+//! Atomic builtins of GCC, i.e `__sync_fetch_and_add(p, 1)` and `__atomic_load_n(p, order)`
+//!
+//! The builtins are calls of undeclared functions for the parser. The semantic analysis checks
+//! the arguments, the code generator lowers the calls to loops of exclusive loads and stores, or
+//! to the atomic instructions of ARMv8.1 (LSE) with '-march=armv8.1-a'
+
+use std::convert::TryFrom;
+
+/// memory orders of the `__atomic` builtins, predefined like in GCC
+pub const MEMORY_ORDERS: &[(&str, u64)] = &[
+    ("__ATOMIC_RELAXED", 0),
+    ("__ATOMIC_CONSUME", 1),
+    ("__ATOMIC_ACQUIRE", 2),
+    ("__ATOMIC_RELEASE", 3),
+    ("__ATOMIC_ACQ_REL", 4),
+    ("__ATOMIC_SEQ_CST", 5),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AtomicOp {
+    Load,
+    Store,
+    Exchange,
+    /// add and return the old value
+    FetchAdd,
+    FetchSub,
+    /// add and return the new value
+    AddFetch,
+    SubFetch,
+    /// store the desired value if the object holds the expected one, return the old value
+    CompareSwap,
+    Fence,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub enum MemoryOrder {
+    Relaxed,
+    /// treated as acquire
+    Consume,
+    Acquire,
+    Release,
+    AcqRel,
+    SeqCst,
+}
+
+impl MemoryOrder {
+    /// order of the value of an `__ATOMIC_*` macro
+    pub fn from_value(value: i64) -> Option<Self> {
+        let orders = [
+            MemoryOrder::Relaxed,
+            MemoryOrder::Consume,
+            MemoryOrder::Acquire,
+            MemoryOrder::Release,
+            MemoryOrder::AcqRel,
+            MemoryOrder::SeqCst,
+        ];
+        usize::try_from(value)
+            .ok()
+            .and_then(|i| orders.get(i).copied())
+    }
+
+    /// later accesses are not moved before the load
+    pub fn acquire(&self) -> bool {
+        !matches!(self, MemoryOrder::Relaxed | MemoryOrder::Release)
+    }
+
+    /// earlier accesses are not moved after the store
+    pub fn release(&self) -> bool {
+        matches!(
+            self,
+            MemoryOrder::Release | MemoryOrder::AcqRel | MemoryOrder::SeqCst
+        )
+    }
+}
+
+/// builtin and its memory order
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Atomic {
+    pub op: AtomicOp,
+    /// order of the `__sync` builtins, none if given by the last argument
+    pub order: Option<MemoryOrder>,
+}
+
+impl Atomic {
+    /// builtin of the name, none for other functions
+    pub fn of(name: &str) -> Option<Self> {
+        let (op, order) = match name {
+            "__sync_fetch_and_add" => (AtomicOp::FetchAdd, Some(MemoryOrder::SeqCst)),
+            "__sync_fetch_and_sub" => (AtomicOp::FetchSub, Some(MemoryOrder::SeqCst)),
+            "__sync_add_and_fetch" => (AtomicOp::AddFetch, Some(MemoryOrder::SeqCst)),
+            "__sync_sub_and_fetch" => (AtomicOp::SubFetch, Some(MemoryOrder::SeqCst)),
+            "__sync_val_compare_and_swap" => (AtomicOp::CompareSwap, Some(MemoryOrder::SeqCst)),
+            "__sync_lock_test_and_set" => (AtomicOp::Exchange, Some(MemoryOrder::Acquire)),
+            "__sync_synchronize" => (AtomicOp::Fence, Some(MemoryOrder::SeqCst)),
+            "__atomic_load_n" => (AtomicOp::Load, None),
+            "__atomic_store_n" => (AtomicOp::Store, None),
+            "__atomic_exchange_n" => (AtomicOp::Exchange, None),
+            "__atomic_fetch_add" => (AtomicOp::FetchAdd, None),
+            "__atomic_fetch_sub" => (AtomicOp::FetchSub, None),
+            "__atomic_add_fetch" => (AtomicOp::AddFetch, None),
+            "__atomic_sub_fetch" => (AtomicOp::SubFetch, None),
+            "__atomic_thread_fence" => (AtomicOp::Fence, None),
+            _ => return None,
+        };
+        Some(Atomic { op, order })
+    }
+
+    /// arguments before the memory order, the first is the pointer to the object
+    pub fn operands(&self) -> usize {
+        match self.op {
+            AtomicOp::Fence => 0,
+            AtomicOp::Load => 1,
+            AtomicOp::CompareSwap => 3,
+            _ => 2,
+        }
+    }
+
+    /// arguments including the memory order
+    pub fn arity(&self) -> usize {
+        self.operands() + self.order.is_none() as usize
+    }
+
+    /// the call has the type of the object, stores and fences are void
+    pub fn has_value(&self) -> bool {
+        !matches!(self.op, AtomicOp::Store | AtomicOp::Fence)
+    }
+
+    /// the order is allowed for the operation, a load does not release and a store does not
+    /// acquire
+    pub fn valid_order(&self, order: MemoryOrder) -> bool {
+        match self.op {
+            AtomicOp::Load => !order.release(),
+            AtomicOp::Store => matches!(
+                order,
+                MemoryOrder::Relaxed | MemoryOrder::Release | MemoryOrder::SeqCst
+            ),
+            _ => true,
+        }
+    }
+}
+
+/// instructions of the builtin on the object of `size` bytes at x0, the operands are in x1 and
+/// x2 and the value is left in x0 like the result of a call, `label` names the loops
+///
+/// x9 to x11 are clobbered. The bits above char and short are unspecified like for a call
+pub fn lower(op: AtomicOp, order: MemoryOrder, size: u32, lse: bool, label: &str) -> Vec<String> {
+    let (r, suffix) = match size {
+        1 => ("w", "b"),
+        2 => ("w", "h"),
+        4 => ("w", ""),
+        _ => ("x", ""),
+    };
+    let acq = if order.acquire() { "a" } else { "" };
+    let rel = if order.release() { "l" } else { "" };
+    let mut insts: Vec<String> = Vec::new();
+    let mut inst = |s: String| insts.push(s);
+    // register number of the value of the call
+    let mut value = "9";
+    match (op, lse) {
+        (AtomicOp::Fence, _) => {
+            if order != MemoryOrder::Relaxed {
+                inst("dmb ish".into());
+            }
+            return insts;
+        }
+        (AtomicOp::Load, _) => match order.acquire() {
+            true => inst(format!("ldar{} {}9, [x0]", suffix, r)),
+            false => inst(format!("ldr{} {}9, [x0]", suffix, r)),
+        },
+        (AtomicOp::Store, _) => {
+            match order.release() {
+                true => inst(format!("stlr{} {}1, [x0]", suffix, r)),
+                false => inst(format!("str{} {}1, [x0]", suffix, r)),
+            }
+            return insts;
+        }
+        (AtomicOp::CompareSwap, true) => {
+            inst(format!("mov {}9, {}1", r, r));
+            inst(format!("cas{}{}{} {}9, {}2, [x0]", acq, rel, suffix, r, r));
+        }
+        (AtomicOp::CompareSwap, false) => {
+            // the exclusive load zero extends, the expected value is compared as unsigned
+            let expected = match suffix {
+                "" => format!("{}1", r),
+                _ => {
+                    inst(format!("uxt{} w10, w1", suffix));
+                    "w10".to_string()
+                }
+            };
+            inst(format!("{}:", label));
+            inst(format!("ld{}xr{} {}9, [x0]", acq, suffix, r));
+            inst(format!("cmp {}9, {}", r, expected));
+            inst(format!("b.ne {}_done", label));
+            inst(format!("st{}xr{} w11, {}2, [x0]", rel, suffix, r));
+            inst(format!("cbnz w11, {}", label));
+            inst(format!("{}_done:", label));
+        }
+        (AtomicOp::Exchange, true) => {
+            inst(format!("swp{}{}{} {}1, {}9, [x0]", acq, rel, suffix, r, r))
+        }
+        (_, true) => {
+            let add = match op {
+                AtomicOp::FetchSub | AtomicOp::SubFetch => {
+                    inst(format!("neg {}10, {}1", r, r));
+                    "10"
+                }
+                _ => "1",
+            };
+            inst(format!(
+                "ldadd{}{}{} {}{}, {}9, [x0]",
+                acq, rel, suffix, r, add, r
+            ));
+            match op {
+                AtomicOp::AddFetch => inst(format!("add {}9, {}9, {}1", r, r, r)),
+                AtomicOp::SubFetch => inst(format!("sub {}9, {}9, {}1", r, r, r)),
+                _ => {}
+            }
+        }
+        (_, false) => {
+            // the new value in x10
+            let new = match op {
+                AtomicOp::Exchange => "1",
+                _ => "10",
+            };
+            if matches!(op, AtomicOp::AddFetch | AtomicOp::SubFetch) {
+                value = "10";
+            }
+            inst(format!("{}:", label));
+            inst(format!("ld{}xr{} {}9, [x0]", acq, suffix, r));
+            match op {
+                AtomicOp::FetchAdd | AtomicOp::AddFetch => {
+                    inst(format!("add {}10, {}9, {}1", r, r, r))
+                }
+                AtomicOp::FetchSub | AtomicOp::SubFetch => {
+                    inst(format!("sub {}10, {}9, {}1", r, r, r))
+                }
+                _ => {}
+            }
+            inst(format!("st{}xr{} w11, {}{}, [x0]", rel, suffix, r, new));
+            inst(format!("cbnz w11, {}", label));
+        }
+    }
+    inst(format!("mov {}0, {}{}", r, r, value));
+    insts
+}
+
+#[cfg(test)]
+mod test {
+    use test_case::test_case;
+
+    use super::{lower, Atomic, AtomicOp, MemoryOrder};
+
+    #[test_case("__sync_fetch_and_add" => Some((AtomicOp::FetchAdd, 2)))]
+    #[test_case("__sync_val_compare_and_swap" => Some((AtomicOp::CompareSwap, 3)))]
+    #[test_case("__atomic_load_n" => Some((AtomicOp::Load, 2)))]
+    #[test_case("__atomic_thread_fence" => Some((AtomicOp::Fence, 1)))]
+    #[test_case("__sync_synchronize" => Some((AtomicOp::Fence, 0)))]
+    #[test_case("sync_fetch_and_add" => None)]
+    fn builtins(name: &str) -> Option<(AtomicOp, usize)> {
+        Atomic::of(name).map(|a| (a.op, a.arity()))
+    }
+
+    #[test_case(AtomicOp::Load, MemoryOrder::Acquire => true)]
+    #[test_case(AtomicOp::Load, MemoryOrder::Release => false)]
+    #[test_case(AtomicOp::Store, MemoryOrder::Acquire => false)]
+    #[test_case(AtomicOp::Store, MemoryOrder::SeqCst => true)]
+    #[test_case(AtomicOp::Exchange, MemoryOrder::AcqRel => true)]
+    fn valid_order(op: AtomicOp, order: MemoryOrder) -> bool {
+        Atomic { op, order: None }.valid_order(order)
+    }
+
+    #[test_case(AtomicOp::FetchAdd, MemoryOrder::SeqCst, 4, false => vec![
+        "L:", "ldaxr w9, [x0]", "add w10, w9, w1", "stlxr w11, w10, [x0]", "cbnz w11, L", "mov w0, w9",
+    ]; "fetch add")]
+    #[test_case(AtomicOp::SubFetch, MemoryOrder::Relaxed, 8, false => vec![
+        "L:", "ldxr x9, [x0]", "sub x10, x9, x1", "stxr w11, x10, [x0]", "cbnz w11, L", "mov x0, x10",
+    ]; "sub fetch")]
+    #[test_case(AtomicOp::Exchange, MemoryOrder::Acquire, 1, false => vec![
+        "L:", "ldaxrb w9, [x0]", "stxrb w11, w1, [x0]", "cbnz w11, L", "mov w0, w9",
+    ]; "exchange")]
+    #[test_case(AtomicOp::CompareSwap, MemoryOrder::SeqCst, 2, false => vec![
+        "uxth w10, w1", "L:", "ldaxrh w9, [x0]", "cmp w9, w10", "b.ne L_done", "stlxrh w11, w2, [x0]", "cbnz w11, L", "L_done:", "mov w0, w9",
+    ]; "compare swap")]
+    #[test_case(AtomicOp::FetchSub, MemoryOrder::Release, 4, true => vec![
+        "neg w10, w1", "ldaddl w10, w9, [x0]", "mov w0, w9",
+    ]; "lse fetch sub")]
+    #[test_case(AtomicOp::AddFetch, MemoryOrder::SeqCst, 1, true => vec![
+        "ldaddalb w1, w9, [x0]", "add w9, w9, w1", "mov w0, w9",
+    ]; "lse add fetch")]
+    #[test_case(AtomicOp::CompareSwap, MemoryOrder::Acquire, 8, true => vec![
+        "mov x9, x1", "casa x9, x2, [x0]", "mov x0, x9",
+    ]; "lse compare swap")]
+    #[test_case(AtomicOp::Load, MemoryOrder::Consume, 4, true => vec!["ldar w9, [x0]", "mov w0, w9"]; "load")]
+    #[test_case(AtomicOp::Store, MemoryOrder::Relaxed, 2, false => vec!["strh w1, [x0]"]; "store")]
+    #[test_case(AtomicOp::Fence, MemoryOrder::Relaxed, 0, false => Vec::<String>::new(); "relaxed fence")]
+    fn lower_atomic(op: AtomicOp, order: MemoryOrder, size: u32, lse: bool) -> Vec<String> {
+        lower(op, order, size, lse, "L")
+    }
+}
@@ -0,0 +1,175 @@
+//! GNU attributes of declarations, i.e `__attribute__((noreturn))`
+//!
+//! Only `noreturn`, `used`, `aligned`, `no_instrument_function`, `weak` and `alias` change the
+//! generated code, other attributes are ignored with a warning
+
+use crate::{
+    ast::{Attribute, ExprKind},
+    common::CompileError,
+    consteval::{eval_int, is_constant},
+    intern::Symbol,
+};
+
+type AttrResult<T> = Result<T, CompileError>;
+
+/// alignment of `aligned` without the argument, the largest alignment of any type
+pub const MAX_ALIGN: u32 = 16;
+
+/// largest alignment accepted by `aligned(n)`
+const ALIGN_LIMIT: i64 = 1 << 28;
+
+/// declaration the attributes apply to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Subject {
+    Function,
+    Global,
+    Local,
+}
+
+/// the attribute is given
+pub fn has_attr(attrs: &[Attribute], name: &str) -> bool {
+    attrs.iter().any(|a| a.name == name)
+}
+
+/// alignment in bytes requested by `aligned(n)`, the largest if repeated
+pub fn requested_align(attrs: &[Attribute]) -> AttrResult<Option<u32>> {
+    let mut align = None;
+    for attr in attrs.iter().filter(|a| a.name == "aligned") {
+        let n = match attr.args.as_slice() {
+            [] => MAX_ALIGN,
+            [arg] if is_constant(arg) => {
+                let v = eval_int(arg)?.value;
+                if v <= 0 || v & (v - 1) != 0 || v > ALIGN_LIMIT {
+                    return Err(CompileError::at_span(
+                        "requested alignment is not a positive power of 2",
+                        arg.span,
+                    ));
+                }
+                v as u32
+            }
+            [arg] => {
+                return Err(CompileError::at_span(
+                    "requested alignment is not an integer constant",
+                    arg.span,
+                ))
+            }
+            _ => return Err(wrong_args(attr)),
+        };
+        align = align.max(Some(n));
+    }
+    Ok(align)
+}
+
+/// name aliased by `alias("name")`, the last if repeated
+pub fn alias_target(attrs: &[Attribute]) -> Option<Symbol> {
+    attrs
+        .iter()
+        .rev()
+        .filter(|a| a.name == "alias")
+        .filter_map(|a| match a.args.as_slice() {
+            [arg] => match &arg.kind {
+                ExprKind::StrLit(s) => Some(Symbol::intern(&String::from_utf8_lossy(s))),
+                _ => None,
+            },
+            _ => None,
+        })
+        .next()
+}
+
+/// check the arguments of the known attributes, return the warnings of the ignored ones
+pub fn check_attrs(attrs: &[Attribute], subject: Subject) -> AttrResult<Vec<CompileError>> {
+    requested_align(attrs)?;
+    let mut ignored = Vec::new();
+    for attr in attrs {
+        let applies = match attr.name.as_str() {
+            "aligned" => true,
+            "noreturn" | "no_instrument_function" => subject == Subject::Function,
+            "used" | "weak" | "alias" => subject != Subject::Local,
+            _ => {
+                let msg = format!("'{}' attribute directive ignored", attr.name);
+                ignored.push(CompileError::at_span(msg, attr.span));
+                continue;
+            }
+        };
+        match (attr.name.as_str(), attr.args.as_slice()) {
+            ("aligned", _) => {}
+            ("alias", [arg]) if !matches!(arg.kind, ExprKind::StrLit(_)) => {
+                return Err(CompileError::at_span(
+                    "alias argument not a string",
+                    arg.span,
+                ))
+            }
+            ("alias", [_]) => {}
+            ("alias", _) | (_, [_, ..]) => return Err(wrong_args(attr)),
+            (_, []) => {}
+        }
+        if !applies {
+            let msg = format!("'{}' attribute ignored", attr.name);
+            ignored.push(CompileError::at_span(msg, attr.span));
+        }
+    }
+    Ok(ignored)
+}
+
+fn wrong_args(attr: &Attribute) -> CompileError {
+    CompileError::at_span(
+        format!(
+            "wrong number of arguments specified for '{}' attribute",
+            attr.name
+        ),
+        attr.span,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use test_case::test_case;
+
+    use crate::{ast::ExtDecl, parse, scan};
+
+    use super::{alias_target, check_attrs, requested_align, Subject};
+
+    fn global_attrs<T>(src: &str, f: impl Fn(&[super::Attribute]) -> T) -> T {
+        let ast = parse(scan(src).unwrap()).unwrap();
+        match &ast.0[0] {
+            ExtDecl::Global(g) => f(&g.attrs),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test_case("int g;" => Ok(None))]
+    #[test_case("int g __attribute__((aligned(8)));" => Ok(Some(8)))]
+    #[test_case("int g __attribute__((aligned));" => Ok(Some(16)))]
+    #[test_case("__attribute__((aligned(4))) int g __attribute__((__aligned__(32)));" => Ok(Some(32)))]
+    #[test_case("int g __attribute__((aligned(6)));" => Err("requested alignment is not a positive power of 2".to_string()))]
+    #[test_case("int g __attribute__((aligned(-8)));" => Err("requested alignment is not a positive power of 2".to_string()))]
+    #[test_case("int g __attribute__((aligned(g)));" => Err("requested alignment is not an integer constant".to_string()))]
+    #[test_case("int g __attribute__((aligned(1, 2)));" => Err("wrong number of arguments specified for 'aligned' attribute".to_string()))]
+    fn align(src: &str) -> Result<Option<u32>, String> {
+        global_attrs(src, |attrs| requested_align(attrs).map_err(|e| e.msg))
+    }
+
+    #[test_case("int g __attribute__((used, aligned(4)));", Subject::Global => Ok(vec![]))]
+    #[test_case("int g __attribute__((noreturn));", Subject::Global => Ok(vec!["'noreturn' attribute ignored".to_string()]))]
+    #[test_case("int g __attribute__((no_instrument_function));", Subject::Global => Ok(vec!["'no_instrument_function' attribute ignored".to_string()]))]
+    #[test_case("int g __attribute__((used));", Subject::Local => Ok(vec!["'used' attribute ignored".to_string()]))]
+    #[test_case("int g __attribute__((hot, cold(1)));", Subject::Function => Ok(vec!["'hot' attribute directive ignored".to_string(), "'cold' attribute directive ignored".to_string()]))]
+    #[test_case("void f() __attribute__((noreturn(1)));", Subject::Function => Err("wrong number of arguments specified for 'noreturn' attribute".to_string()))]
+    #[test_case("int g __attribute__((weak, alias(\"h\")));", Subject::Global => Ok(vec![]))]
+    #[test_case("int g __attribute__((weak));", Subject::Local => Ok(vec!["'weak' attribute ignored".to_string()]))]
+    #[test_case("int g __attribute__((alias));", Subject::Global => Err("wrong number of arguments specified for 'alias' attribute".to_string()))]
+    #[test_case("int g __attribute__((alias(h)));", Subject::Global => Err("alias argument not a string".to_string()))]
+    fn check(src: &str, subject: Subject) -> Result<Vec<String>, String> {
+        global_attrs(src, |attrs| {
+            check_attrs(attrs, subject)
+                .map(|w| w.into_iter().map(|e| e.msg).collect())
+                .map_err(|e| e.msg)
+        })
+    }
+
+    #[test_case("int g;" => None)]
+    #[test_case("int g __attribute__((alias(\"f\"), alias(\"h\")));" => Some("h".to_string()))]
+    fn alias(src: &str) -> Option<String> {
+        global_attrs(src, |attrs| alias_target(attrs).map(|s| s.to_string()))
+    }
+}
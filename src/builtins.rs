@@ -0,0 +1,77 @@
+//! Builtin functions of the compiler, i.e `__builtin_expect(x, 1)` and `__builtin_trap()`
+//!
+//! The builtins are calls of undeclared functions for the parser. The registry gives the
+//! semantic analysis the signature of a builtin, the code generator lowers the call inline
+//! instead of branching to a function
+
+use crate::atomics::Atomic;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Builtin {
+    /// value of the first argument, the second is the expected value
+    Expect,
+    /// abnormal termination of the program
+    Trap,
+    /// the call is never reached
+    Unreachable,
+    /// `__sync` and `__atomic` builtins
+    Atomic(Atomic),
+}
+
+/// builtins by name, the atomics are named by `Atomic::of`
+const BUILTINS: &[(&str, Builtin)] = &[
+    ("__builtin_expect", Builtin::Expect),
+    ("__builtin_trap", Builtin::Trap),
+    ("__builtin_unreachable", Builtin::Unreachable),
+];
+
+impl Builtin {
+    /// builtin of the name, none for other functions
+    pub fn of(name: &str) -> Option<Self> {
+        BUILTINS
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, b)| *b)
+            .or_else(|| Atomic::of(name).map(Builtin::Atomic))
+    }
+
+    /// number of arguments
+    pub fn arity(&self) -> usize {
+        match self {
+            Builtin::Expect => 2,
+            Builtin::Trap | Builtin::Unreachable => 0,
+            Builtin::Atomic(a) => a.arity(),
+        }
+    }
+
+    /// control does not return from the call
+    pub fn is_noreturn(&self) -> bool {
+        matches!(self, Builtin::Trap | Builtin::Unreachable)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use test_case::test_case;
+
+    use crate::atomics::{Atomic, AtomicOp};
+
+    use super::Builtin;
+
+    #[test_case("__builtin_expect" => Some(Builtin::Expect))]
+    #[test_case("__builtin_unreachable" => Some(Builtin::Unreachable))]
+    #[test_case("__atomic_thread_fence" => Some(Builtin::Atomic(Atomic { op: AtomicOp::Fence, order: None })))]
+    #[test_case("__builtin_abort" => None)]
+    #[test_case("expect" => None)]
+    fn builtins(name: &str) -> Option<Builtin> {
+        Builtin::of(name)
+    }
+
+    #[test_case("__builtin_expect" => (2, false))]
+    #[test_case("__builtin_trap" => (0, true))]
+    #[test_case("__sync_fetch_and_add" => (2, false))]
+    fn signature(name: &str) -> (usize, bool) {
+        let b = Builtin::of(name).unwrap();
+        (b.arity(), b.is_noreturn())
+    }
+}
@@ -0,0 +1,199 @@
+//! Call graph of the translation unit
+//!
+//! the nodes are the declared and called functions in the order of appearance, the edges are
+//! the direct calls of the defined functions, the builtins are not functions. Render the DOT dump with
+//! `dot -Tpng calls.dot -o calls.png`
+
+use std::collections::HashMap;
+
+use crate::{
+    ast::{walk_expr, Ast, Expr, ExprKind, ExtDecl, FuncDecl, Type, Visitor},
+    builtins::Builtin,
+    common::Span,
+    intern::Symbol,
+};
+
+#[derive(Debug, Default)]
+pub struct CallGraph {
+    nodes: Vec<Function>,
+    by_name: HashMap<Symbol, usize>,
+}
+
+/// function declared, defined or only called
+#[derive(Debug)]
+pub struct Function {
+    pub name: Symbol,
+    /// span of the definition, the first declaration or the first call
+    pub span: Span,
+    pub defined: bool,
+    /// declared `static`
+    pub internal: bool,
+    /// callees in the order of the first call, without duplicates
+    pub calls: Vec<Symbol>,
+}
+
+impl CallGraph {
+    pub fn build(ast: &Ast) -> Self {
+        let mut g = CallGraph::default();
+        for ext in &ast.0 {
+            match ext {
+                ExtDecl::Func(f) => {
+                    let n = g.node(f.name, f.span);
+                    n.defined = true;
+                    n.internal |= f.internal;
+                    n.span = f.span;
+                    let mut calls = Calls::default();
+                    calls.visit_func_decl(f);
+                    calls.names.into_iter().for_each(|(callee, span)| {
+                        g.node(callee, span);
+                        g.add_call(f.name, callee);
+                    });
+                }
+                ExtDecl::Global(d) if matches!(d.data_type, Type::Function(_, _)) => {
+                    g.node(d.name, d.span).internal |= d.internal;
+                }
+                _ => {}
+            }
+        }
+        g
+    }
+
+    pub fn functions(&self) -> impl Iterator<Item = &Function> {
+        self.nodes.iter()
+    }
+
+    pub fn get(&self, name: Symbol) -> Option<&Function> {
+        self.by_name.get(&name).map(|i| &self.nodes[*i])
+    }
+
+    /// the function calls itself
+    pub fn is_recursive(&self, name: Symbol) -> bool {
+        self.get(name).is_some_and(|f| f.calls.contains(&name))
+    }
+
+    /// the function is called by another function
+    pub fn is_called(&self, name: Symbol) -> bool {
+        self.nodes
+            .iter()
+            .any(|f| f.name != name && f.calls.contains(&name))
+    }
+
+    /// defined `static` functions no other function calls, they are never executed
+    pub fn unused_static(&self) -> impl Iterator<Item = &Function> {
+        self.nodes
+            .iter()
+            .filter(move |f| f.defined && f.internal && !self.is_called(f.name))
+    }
+
+    /// Graphviz DOT graph, functions without definition are dashed
+    pub fn to_dot(&self) -> String {
+        let mut s = String::from("digraph calls {\n");
+        s.push_str("\tnode [shape=box, fontname=\"monospace\"];\n");
+        for (i, f) in self.nodes.iter().enumerate() {
+            let style = if f.defined { "" } else { ", style=dashed" };
+            s.push_str(&format!("\tn{} [label=\"{}\"{}];\n", i, f.name, style));
+        }
+        for (i, f) in self.nodes.iter().enumerate() {
+            for callee in &f.calls {
+                s.push_str(&format!("\tn{} -> n{};\n", i, self.by_name[callee]));
+            }
+        }
+        s.push_str("}\n");
+        s
+    }
+
+    fn node(&mut self, name: Symbol, span: Span) -> &mut Function {
+        let nodes = &mut self.nodes;
+        let i = *self.by_name.entry(name).or_insert_with(|| {
+            nodes.push(Function {
+                name,
+                span,
+                defined: false,
+                internal: false,
+                calls: Vec::new(),
+            });
+            nodes.len() - 1
+        });
+        &mut self.nodes[i]
+    }
+
+    fn add_call(&mut self, caller: Symbol, callee: Symbol) {
+        let calls = &mut self.nodes[self.by_name[&caller]].calls;
+        if !calls.contains(&callee) {
+            calls.push(callee);
+        }
+    }
+}
+
+/// names of the called functions in a body with the span of the call
+#[derive(Default)]
+struct Calls {
+    names: Vec<(Symbol, Span)>,
+}
+
+impl<'ast> Visitor<'ast> for Calls {
+    fn visit_func_decl(&mut self, func: &'ast FuncDecl) {
+        self.visit_cmp_stmt(&func.cmp_stmt);
+    }
+
+    fn visit_expr(&mut self, expr: &'ast Expr) {
+        match &expr.kind {
+            ExprKind::FunctionCall(name, _) if Builtin::of(name.as_str()).is_some() => {}
+            ExprKind::FunctionCall(name, _) => self.names.push((*name, expr.span)),
+            _ => {}
+        }
+        walk_expr(self, expr)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{intern::Symbol, parse, scan};
+
+    use super::CallGraph;
+
+    fn build(src: &str) -> CallGraph {
+        CallGraph::build(&parse(scan(src).unwrap()).unwrap())
+    }
+
+    #[test]
+    fn calls() {
+        let g = build("int puts(); int f(void) { return f(); } int main() { f(); puts(); f(); }");
+        let names: Vec<String> = g.functions().map(|f| f.name.to_string()).collect();
+        assert_eq!(names, ["puts", "f", "main"]);
+        let main = g.get(Symbol::from("main")).unwrap();
+        assert_eq!(main.calls, [Symbol::from("f"), Symbol::from("puts")]);
+        assert!(g.is_recursive(Symbol::from("f")));
+        assert!(!g.is_recursive(Symbol::from("main")));
+        assert!(!g.get(Symbol::from("puts")).unwrap().defined);
+    }
+
+    #[test]
+    fn builtins() {
+        let g =
+            build("int main() { __builtin_expect(1, 1); __sync_synchronize(); __builtin_trap(); }");
+        let names: Vec<String> = g.functions().map(|f| f.name.to_string()).collect();
+        assert_eq!(names, ["main"]);
+        assert!(g.get(Symbol::from("main")).unwrap().calls.is_empty());
+    }
+
+    #[test]
+    fn unused_static() {
+        let g = build(
+            "static int a(void) { return a(); } static int b(void); int b(void) { return 0; }
+             static int c(void) { return b(); } int main() { c(); }",
+        );
+        let unused: Vec<String> = g.unused_static().map(|f| f.name.to_string()).collect();
+        assert_eq!(unused, ["a"]);
+    }
+
+    #[test]
+    fn dot() {
+        let g = build("int main() { g(); }");
+        assert_eq!(
+            g.to_dot(),
+            "digraph calls {\n\tnode [shape=box, fontname=\"monospace\"];\n\
+             \tn0 [label=\"main\"];\n\tn1 [label=\"g\", style=dashed];\n\tn0 -> n1;\n}\n"
+        );
+    }
+}
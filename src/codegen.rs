@@ -1,273 +1,1956 @@
 //! Generate ARM assembly from AST
 
-use std::fmt::Display;
-
-use crate::{ast::*, util::TargetOs};
-
-/// register for frame pointer      
-const FP: Reg = Reg::X29;
-
-/// register for link pointer
-const LP: Reg = Reg::X30;
-
-/// registers for arguments
-static ARG_REGS: &[Reg] = &[
-    Reg::X0,
-    Reg::X1,
-    Reg::X2,
-    Reg::X3,
-    Reg::X4,
-    Reg::X5,
-    Reg::X6,
-    Reg::X7,
-];
-
-/// registers for local variables
-static TEMP_REGS: &[Reg] = &[
-    Reg::X9,
-    Reg::X10,
-    Reg::X11,
-    Reg::X12,
-    Reg::X13,
-    Reg::X14,
-    Reg::X15,
-];
-
-pub fn gen_asm(ast: &Ast, target: &TargetOs) -> String {
-    let mut g = ArmGen::new(ast, target);
-    g.gen();
-    g.str
-}
-
-struct ArmGen<'a> {
+use std::{
+    collections::{HashMap, HashSet},
+    convert::TryFrom,
+    fmt::Display,
+    io,
+};
+
+use crate::{
+    ast::*,
+    atomics::{lower, Atomic, MemoryOrder},
+    attrs::{alias_target, has_attr, requested_align},
+    builtins::Builtin,
+    common::{escape, CompileError, Span},
+    consteval::eval_int,
+    coverage::Coverage,
+    init::{flatten, FlatInit},
+    intern::Symbol,
+    sidetable::SideTable,
+    target::{
+        AsmSyntax, CallConv, ObjectFormat, Reg, RegClass, SectionKind, TargetBackend, TargetDesc,
+    },
+    timing::Phase,
+    util::{Arch, AsmDialect, OptLevel, RelocModel, TargetOs},
+};
+
+type GenResult<T> = Result<T, CompileError>;
+
+pub use gen_util::{layout_frame, Frame};
+
+/// constants taking more moves are loaded from the literal pool, a load and its 8 bytes in the
+/// pool are not larger than three moves
+const MAX_MOV_INSTS: usize = 2;
+
+/// bytes of code after the first load of a pending literal before the pool is emitted, half the
+/// 1MB range of `ldr` leaves room for the statement being generated
+const POOL_DISTANCE: u32 = 1 << 19;
+
+/// options for code generation
+#[derive(Debug, Clone, PartialEq)]
+pub struct GenOpts {
+    pub target: TargetOs,
+    pub opt_level: OptLevel,
+    /// instruction set, '-march=<arch>'
+    pub arch: Arch,
+    pub reloc: RelocModel,
+    /// call the profiling hooks at the entry and exit of functions, '-finstrument-functions'
+    pub instrument_functions: bool,
+    /// place tentative definitions in common symbols merged by the linker, '-fcommon'
+    pub common: bool,
+    /// source named by `.file`, the assembler records it instead of the path of the assembly
+    pub file: Option<String>,
+    /// spelling of the directives, the assembler of the target if none
+    pub dialect: Option<AsmDialect>,
+    /// each function in a section of its own, '-ffunction-sections'
+    pub function_sections: bool,
+    /// each variable in a section of its own, '-fdata-sections'
+    pub data_sections: bool,
+}
+
+impl GenOpts {
+    pub fn new(target: TargetOs) -> Self {
+        GenOpts {
+            target,
+            opt_level: OptLevel::default(),
+            arch: Arch::default(),
+            reloc: RelocModel::default(),
+            instrument_functions: false,
+            common: false,
+            file: None,
+            dialect: None,
+            function_sections: false,
+            data_sections: false,
+        }
+    }
+
+    pub fn dialect(&self) -> AsmDialect {
+        self.dialect.unwrap_or_else(|| AsmDialect::of(self.target))
+    }
+}
+
+/// static stack usage of a generated function, '-fstack-usage'
+#[derive(Debug, Clone, PartialEq)]
+pub struct StackUsage {
+    pub name: Symbol,
+    pub span: Span,
+    /// bytes reserved by the prologue for the parameters and the locals
+    pub frame: u32,
+    /// bytes of the frame saving the argument registers
+    pub spills: u32,
+    /// most bytes pushed below the frame to call other functions
+    pub outgoing: u32,
+}
+
+impl StackUsage {
+    /// deepest sp reached by the function itself
+    pub fn total(&self) -> u32 {
+        self.frame + self.outgoing
+    }
+
+    /// line of the `.su` file like gcc, the location of the function in the file then the
+    /// total bytes and the parts of it
+    pub fn report(&self, file: &str, src: &str, tab_width: u32) -> String {
+        let (line, col) = crate::scan::locate(src, self.span.start, tab_width);
+        format!(
+            "{}:{}:{}:{}\t{}\tstatic\tframe={}\tspills={}\toutgoing={}",
+            file,
+            line,
+            col,
+            self.name,
+            self.total(),
+            self.frame,
+            self.spills,
+            self.outgoing
+        )
+    }
+}
+
+/// generate ARM assembly for the AST into a string
+pub fn gen_asm(ast: &Ast, opts: &GenOpts) -> Result<String, CompileError> {
+    let mut buf = Vec::new();
+    write_asm(ast, opts, &mut buf)?;
+    Ok(String::from_utf8(buf).expect("assembly is not UTF-8"))
+}
+
+/// generate ARM assembly for the AST and stream it to the writer, return the stack usage of the
+/// functions
+///
+/// the output is written piece by piece, wrap files in `BufWriter`
+pub fn write_asm<W: io::Write>(
+    ast: &Ast,
+    opts: &GenOpts,
+    out: W,
+) -> Result<Vec<StackUsage>, CompileError> {
+    write(ast, opts, None, out)
+}
+
+/// generate ARM assembly counting the executed lines, see `coverage`
+pub fn write_asm_coverage<W: io::Write>(
+    ast: &Ast,
+    opts: &GenOpts,
+    coverage: &Coverage,
+    out: W,
+) -> Result<Vec<StackUsage>, CompileError> {
+    write(ast, opts, Some(coverage), out)
+}
+
+fn write<W: io::Write>(
+    ast: &Ast,
+    opts: &GenOpts,
+    coverage: Option<&Coverage>,
+    mut out: W,
+) -> Result<Vec<StackUsage>, CompileError> {
+    Arm64Backend::new(opts.clone()).write_asm(ast, coverage, &mut out)
+}
+
+/// generator of aarch64, the object format and the assembler depend on the options
+pub struct Arm64Backend {
+    opts: GenOpts,
+    desc: TargetDesc,
+}
+
+impl Arm64Backend {
+    pub fn new(opts: GenOpts) -> Self {
+        use Reg::*;
+        let args = vec![X0, X1, X2, X3, X4, X5, X6, X7];
+        let desc = TargetDesc {
+            arch: "aarch64",
+            os: opts.target,
+            reg_classes: vec![
+                RegClass {
+                    name: "argument",
+                    regs: args.clone(),
+                },
+                // registers for local variables
+                RegClass {
+                    name: "temporary",
+                    regs: vec![X9, X10, X11, X12, X13, X14, X15],
+                },
+            ],
+            call_conv: CallConv {
+                arg_regs: args,
+                ret_reg: X0,
+                frame_pointer: X29,
+                link_reg: X30,
+                stack_align: 16,
+            },
+            char_signed: opts.target.char_signed(),
+            object: ObjectFormat::of(opts.target),
+            syntax: AsmSyntax::of(opts.dialect()),
+        };
+        Arm64Backend { opts, desc }
+    }
+}
+
+impl TargetBackend for Arm64Backend {
+    fn desc(&self) -> &TargetDesc {
+        &self.desc
+    }
+
+    fn write_asm(
+        &self,
+        ast: &Ast,
+        coverage: Option<&Coverage>,
+        out: &mut dyn io::Write,
+    ) -> Result<Vec<StackUsage>, CompileError> {
+        let mut g = ArmGen::new(ast, &self.opts, &self.desc, out);
+        g.coverage = coverage;
+        g.gen()?;
+        match g.io_error {
+            Some(e) => Err(CompileError::new(format!(
+                "failed to write assembly: {}",
+                e
+            ))),
+            None => Ok(g.usage),
+        }
+    }
+}
+
+struct ArmGen<'a, W: io::Write> {
     ast: &'a Ast,
-    str: String,
-    target: TargetOs,
+    out: W,
+    /// first error of the writer, nothing is written after it
+    io_error: Option<io::Error>,
+    opts: &'a GenOpts,
+    /// symbols, relocations and directives of the target
+    desc: &'a TargetDesc,
+    /// registers of the calling convention of the target
+    regs: Regs,
+    /// label of the epilogue of the current function, returns before the end branch to it
+    epilogue: String,
+    /// a return branched to the epilogue
+    epilogue_used: bool,
+    /// return type of the current function
+    return_type: Type,
+    /// types of the global variables
+    globals: HashMap<Symbol, Type>,
+    /// functions and global variables with storage in this object
+    defined: HashSet<Symbol>,
+    /// offsets from sp of the parameters and locals of the current function
+    offsets: SideTable<u32>,
+    /// frame offsets and types of the visible parameters and locals, innermost block last
+    scopes: Vec<HashMap<Symbol, (u32, Type)>>,
+    /// bytes pushed below the frame while evaluating call arguments
+    pushed: u32,
+    /// most bytes pushed below the frame of the current function
+    max_pushed: u32,
+    /// stack usage of the generated functions
+    usage: Vec<StackUsage>,
+    /// access of the frame held back to pair it with the next one, '-O1' and above
+    pending: Option<SpAccess>,
+    /// constants loaded by `ldr` and not emitted yet
+    pool: LiteralPool,
+    /// bytes of code emitted so far, a line is counted as one instruction
+    text_bytes: u32,
+    /// see `POOL_DISTANCE`
+    pool_distance: u32,
+    /// layout of the data sections
+    sections: DataSections,
+    /// labels of the loops of the atomic builtins, a counter to keep them unique
+    atomic_labels: usize,
+    /// string literals in the order of use, emitted after the functions
+    strings: Vec<Vec<u8>>,
+    /// lines of the source with `--coverage`
+    coverage: Option<&'a Coverage>,
+    /// line of each coverage counter
+    counters: Vec<u32>,
+    /// line counted last, the following statements on the line are not counted again
+    counted_line: Option<u32>,
+}
+
+/// registers used by the generator, named by the description of the target
+struct Regs {
+    /// argument registers in order
+    args: Vec<Reg>,
+    /// register of the return value
+    ret: Reg,
+    /// first temporary register, values are computed in it before a store
+    temp: Reg,
+    /// frame pointer
+    fp: Reg,
+    /// link register
+    lp: Reg,
 }
 
-impl<'a> ArmGen<'a> {
+impl Regs {
+    fn of(desc: &TargetDesc) -> Self {
+        let conv = &desc.call_conv;
+        Regs {
+            args: conv.arg_regs.clone(),
+            ret: conv.ret_reg,
+            temp: *desc.regs("temporary").first().expect("temporary register"),
+            fp: conv.frame_pointer,
+            lp: conv.link_reg,
+        }
+    }
+}
+
+/// storage of a variable
+enum Storage {
+    /// offset from sp in the frame
+    Frame(u32),
+    Global,
+    /// defined in another object, the address is loaded from the GOT
+    Got,
+}
+
+impl<'a, W: io::Write> ArmGen<'a, W> {
     /// create new arm
-    fn new(ast: &'a Ast, target: &TargetOs) -> Self {
+    fn new(ast: &'a Ast, opts: &'a GenOpts, desc: &'a TargetDesc, out: W) -> Self {
         ArmGen {
             ast,
-            str: String::new(),
-            target: *target,
+            out,
+            io_error: None,
+            opts,
+            desc,
+            regs: Regs::of(desc),
+            epilogue: String::new(),
+            epilogue_used: false,
+            return_type: Type::Base(DataType::Void),
+            globals: HashMap::new(),
+            defined: HashSet::new(),
+            offsets: SideTable::new(),
+            scopes: Vec::new(),
+            pushed: 0,
+            max_pushed: 0,
+            usage: Vec::new(),
+            pending: None,
+            pool: LiteralPool::default(),
+            text_bytes: 0,
+            pool_distance: POOL_DISTANCE,
+            sections: DataSections::default(),
+            atomic_labels: 0,
+            strings: Vec::new(),
+            coverage: None,
+            counters: Vec::new(),
+            counted_line: None,
         }
     }
 
     /// generate ARM assembly for the AST
-    fn gen(&mut self) {
-        let begin = ".text";
-        self.ptab(begin);
-        self.ast.0.iter().for_each(|ext| match ext {
-            ExtDecl::Func(f) => self.gen_func(f),
-            ExtDecl::Global(g) => {}
+    fn gen(&mut self) -> GenResult<()> {
+        if let Some(file) = &self.opts.file {
+            self.ptab(format_args!(".file \"{}\"", escape(file.as_bytes())));
+        }
+        self.emit_section(SectionKind::Text.shared());
+        for ext in &self.ast.0 {
+            match ext {
+                // the type of the definition completes the extern declarations
+                ExtDecl::Global(g) if g.has_storage() => {
+                    self.globals.insert(g.name, g.data_type.clone());
+                    self.defined.insert(g.name);
+                }
+                ExtDecl::Global(g) if !self.defined.contains(&g.name) => {
+                    self.globals.insert(g.name, g.data_type.clone());
+                }
+                ExtDecl::Func(f) => {
+                    self.defined.insert(f.name);
+                }
+                _ => {}
+            }
+        }
+        let aliases = self.aliases();
+        self.defined.extend(aliases.iter().map(|(name, _)| *name));
+        for ext in &self.ast.0 {
+            if let ExtDecl::Func(f) = ext {
+                self.gen_func(f).map_err(|e| e.or_span(f.span))?;
+            }
+        }
+        if let Some(cov) = self.coverage.filter(|_| !self.counters.is_empty()) {
+            self.gen_coverage_dump(cov);
+        }
+
+        // string literals are read only
+        if !self.strings.is_empty() {
+            self.emit_section(self.desc.syntax.cstring_section);
+            for (i, s) in std::mem::take(&mut self.strings).iter().enumerate() {
+                // characters need no alignment
+                self.sections.place(1, s.len() as u32 + 1);
+                self.pln(format_args!("{}:", self.string_label(i)));
+                self.ptab(format_args!(".asciz \"{}\"", escape(s)));
+            }
+        }
+
+        // variables after the functions, prototypes and extern declarations have no storage
+        let (data, bss): (Vec<&GlobalVarDecl>, Vec<&GlobalVarDecl>) = self
+            .ast
+            .0
+            .iter()
+            .filter_map(|ext| match ext {
+                ExtDecl::Global(g) if self.is_definition(g) => Some(g),
+                _ => None,
+            })
+            .partition(|g| g.init.is_some());
+        data.into_iter()
+            .try_for_each(|g| self.gen_global(g).map_err(|e| e.or_span(g.span)))?;
+        let (common, bss): (Vec<_>, Vec<_>) = bss
+            .into_iter()
+            .partition(|g| self.opts.common && !self.is_internal(g.name));
+        common
+            .into_iter()
+            .try_for_each(|g| self.gen_common(g).map_err(|e| e.or_span(g.span)))?;
+        bss.into_iter()
+            .try_for_each(|g| self.gen_global(g).map_err(|e| e.or_span(g.span)))?;
+        if self.coverage.is_some() && !self.counters.is_empty() {
+            self.gen_coverage_data();
+        }
+        for (name, target) in aliases {
+            let symbol = self.to_symbol(name);
+            self.emit_global(name, &symbol);
+            self.ptab(format_args!(".set {}, {}", symbol, self.to_symbol(target)));
+        }
+        self.emit_weak_references();
+
+        // the sections are split at the symbols for the linker to strip them
+        let split = self.opts.function_sections || self.opts.data_sections;
+        if split && self.desc.object.subsections_via_symbols {
+            self.ptab(".subsections_via_symbols");
+        }
+        Ok(())
+    }
+
+    /// routine writing the counters to the report and the constructor registering it with
+    /// `atexit`
+    fn gen_coverage_dump(&mut self, cov: &Coverage) {
+        let [dump, init, lines, counters] = self.coverage_symbols();
+        let path = self.string_label(self.strings.len());
+        let mode = self.string_label(self.strings.len() + 1);
+        let format = self.string_label(self.strings.len() + 2);
+        self.strings.push(cov.report.as_bytes().to_vec());
+        self.strings.push(b"w".to_vec());
+        self.strings.push(cov.line_format().into_bytes());
+        let (next, done) = (
+            self.local_label("tncc_cov_next"),
+            self.local_label("tncc_cov_done"),
+        );
+
+        // the report is opened by fopen and closed by fclose, x19 and x20 are callee saved
+        self.enter_section(SectionKind::Text, &dump);
+        self.ptab(format_args!(".p2align 2"));
+        self.pln(format_args!("{}:", dump));
+        self.emit_push_frame(32);
+        self.ptab("stp x19, x20, [sp, #16]");
+        self.emit_symbol_addr(&path, Reg::X0);
+        self.emit_symbol_addr(&mode, Reg::X1);
+        self.ptab(format_args!(
+            "bl {}",
+            self.to_symbol(Symbol::intern("fopen"))
+        ));
+        self.ptab(format_args!("cbz x0, {}", done));
+        self.ptab("mov x19, x0");
+        self.ptab("mov x20, #0");
+        self.pln(format_args!("{}:", next));
+        self.emit_symbol_addr(&lines, Reg::X9);
+        self.ptab("ldr w2, [x9, x20, lsl #2]");
+        self.emit_symbol_addr(&counters, Reg::X9);
+        self.ptab("ldr x3, [x9, x20, lsl #3]");
+        self.ptab("mov x0, x19");
+        self.emit_symbol_addr(&format, Reg::X1);
+        // variadic arguments are passed on the stack on macOS, in registers on Linux
+        self.ptab("stp x2, x3, [sp, #-16]!");
+        self.ptab(format_args!(
+            "bl {}",
+            self.to_symbol(Symbol::intern("fprintf"))
+        ));
+        self.ptab("add sp, sp, #16");
+        self.ptab("add x20, x20, #1");
+        self.ptab(format_args!("mov x9, #{}", self.counters.len()));
+        self.ptab("cmp x20, x9");
+        self.ptab(format_args!("b.lt {}", next));
+        self.ptab("mov x0, x19");
+        self.ptab(format_args!(
+            "bl {}",
+            self.to_symbol(Symbol::intern("fclose"))
+        ));
+        self.pln(format_args!("{}:", done));
+        self.ptab("ldp x19, x20, [sp, #16]");
+        self.emit_pop_frame(32);
+        self.ptab("ret");
+        self.pln("");
+
+        self.ptab(format_args!(".p2align 2"));
+        self.pln(format_args!("{}:", init));
+        self.emit_push_frame(16);
+        self.emit_symbol_addr(&dump, Reg::X0);
+        self.ptab(format_args!(
+            "bl {}",
+            self.to_symbol(Symbol::intern("atexit"))
+        ));
+        self.emit_pop_frame(16);
+        self.ptab("ret");
+        self.pln("");
+    }
+
+    /// counters, their lines and the constructor entry
+    fn gen_coverage_data(&mut self) {
+        let [_, init, lines, counters] = self.coverage_symbols();
+        let count = self.counters.len() as u32;
+        self.emit_section(".data");
+        self.emit_object(8, count * 8);
+        self.pln(format_args!("{}:", counters));
+        self.ptab(format_args!(".zero {}", count * 8));
+        self.emit_object(4, count * 4);
+        self.pln(format_args!("{}:", lines));
+        for line in std::mem::take(&mut self.counters) {
+            self.ptab(format_args!(".word {}", line));
+        }
+        self.emit_section(self.desc.syntax.init_array_section);
+        self.emit_object(8, 8);
+        self.ptab(format_args!(".quad {}", init));
+    }
+
+    /// local symbols of the coverage routines and data, one set per object
+    fn coverage_symbols(&self) -> [String; 4] {
+        ["dump", "init", "lines", "counters"]
+            .map(|n| self.to_symbol(Symbol::intern(&format!("__tncc_cov_{}", n))))
+    }
+
+    /// increment the counter of the line at the offset, once for the statements on a line
+    fn emit_counter(&mut self, offset: usize) {
+        let line = match self.coverage {
+            Some(cov) => cov.line(offset),
+            None => return,
+        };
+        if self.counted_line == Some(line) {
+            return;
+        }
+        self.counted_line = Some(line);
+        let [_, _, _, counters] = self.coverage_symbols();
+        self.emit_symbol_addr(&counters, Reg::X9);
+        self.ptab(format_args!("mov x10, #{}", self.counters.len()));
+        self.ptab("ldr x11, [x9, x10, lsl #3]");
+        self.ptab("add x11, x11, #1");
+        self.ptab("str x11, [x9, x10, lsl #3]");
+        self.counters.push(line);
+    }
+
+    /// global variable with the folded initializer, zero if not initialized
+    fn gen_global(&mut self, decl: &GlobalVarDecl) -> GenResult<()> {
+        let flat = match &decl.init {
+            Some(e) => Some(flatten(&decl.data_type, e)?),
+            None => None,
+        };
+        let ty = flat.as_ref().map_or(&decl.data_type, |f| &f.ty);
+        let size = ty.get_size()?;
+
+        let symbol = self.to_symbol(decl.name);
+        let kind = match decl.init {
+            Some(_) => SectionKind::Data,
+            None => SectionKind::Bss,
+        };
+        self.enter_section(kind, &symbol);
+        self.emit_global(decl.name, &symbol);
+        self.emit_used(decl.name, &symbol);
+        let align = ty.get_align()?.max(self.requested_align(decl.name)?);
+        self.emit_object(align, size);
+        self.pln(format_args!("{}:", symbol));
+        match flat {
+            Some(flat) => self.gen_data(&flat, size)?,
+            None => self.ptab(format_args!(".zero {}", size)),
+        }
+        Ok(())
+    }
+
+    /// switch to the data section, the objects are placed after the ones emitted before
+    fn emit_section(&mut self, directive: &str) {
+        self.ptab(directive);
+        self.sections.current = directive.to_string();
+    }
+
+    /// switch to the section of the symbol, a section of its own with '-ffunction-sections' or
+    /// '-fdata-sections' if the object format has them
+    fn enter_section(&mut self, kind: SectionKind, symbol: &str) {
+        let own = match kind {
+            SectionKind::Text => self.opts.function_sections,
+            SectionKind::Data | SectionKind::Bss => self.opts.data_sections,
+        };
+        let directive = own
+            .then(|| self.desc.object.symbol_section(kind, symbol))
+            .flatten()
+            .unwrap_or_else(|| kind.shared().to_string());
+        if self.sections.current != directive {
+            self.emit_section(&directive);
+        }
+    }
+
+    /// start an object of the current data section at its alignment, the padding after the
+    /// previous object is zero
+    fn emit_object(&mut self, align: u32, size: u32) {
+        let padding = self.sections.place(align, size);
+        if padding > 0 {
+            self.ptab(format_args!(".zero {}", padding));
+        }
+        self.ptab(format_args!(".p2align {}", align.trailing_zeros()));
+    }
+
+    /// the declaration defines the variable, tentative definitions are merged into the
+    /// initialized one or the first of them
+    fn is_definition(&self, decl: &GlobalVarDecl) -> bool {
+        if !decl.has_storage()
+            || self
+                .decl_attrs(decl.name)
+                .any(|a| alias_target(a).is_some())
+        {
+            return false;
+        }
+        let mut defs = self.ast.0.iter().filter_map(|ext| match ext {
+            ExtDecl::Global(g) if g.name == decl.name && g.has_storage() => Some(g),
+            _ => None,
         });
+        let def = match defs.clone().find(|g| g.init.is_some()) {
+            Some(g) => g,
+            None => defs.next().expect("declaration with storage"),
+        };
+        def.id == decl.id
+    }
+
+    /// tentative definition as a common symbol, the linker keeps one of the symbols with the
+    /// name in all objects
+    fn gen_common(&mut self, decl: &GlobalVarDecl) -> GenResult<()> {
+        let size = decl.data_type.get_size()?;
+        let symbol = self.to_symbol(decl.name);
+        self.emit_used(decl.name, &symbol);
+        let align = decl
+            .data_type
+            .get_align()?
+            .max(self.requested_align(decl.name)?);
+        // the alignment of the Darwin assembler is a power of 2
+        let align = match self.desc.syntax.comm_align_log2 {
+            true => align.trailing_zeros(),
+            false => align,
+        };
+        self.ptab(format_args!(".comm {}, {}, {}", symbol, size, align));
+        Ok(())
+    }
+
+    /// initialized scalars in order, the gaps between them are zero
+    fn gen_data(&mut self, flat: &FlatInit, size: u32) -> GenResult<()> {
+        let scalar = flat.scalar.get_size()?;
+        let directive = data_directive(scalar).ok_or_else(|| not_supported(&flat.scalar))?;
+        let mut end = 0;
+        for (i, e) in &flat.values {
+            let at = *i as u32 * scalar;
+            if at > end {
+                self.ptab(format_args!(".zero {}", at - end));
+            }
+            // the value is truncated to the size of the scalar
+            let v = eval_int(e)?.value;
+            self.ptab(format_args!("{} {}", directive, truncate(v, scalar)));
+            end = at + scalar;
+        }
+        if size > end {
+            self.ptab(format_args!(".zero {}", size - end));
+        }
+        Ok(())
     }
 
-    fn gen_func(&mut self, func: &FuncDecl) {
+    fn gen_func(&mut self, func: &FuncDecl) -> GenResult<()> {
         // pre computation
-        debug!("gen function: {}", func.name);
+        debug!(target: Phase::Codegen.name(), "gen function: {}", func.name);
+        if func.params.len() > self.regs.args.len() {
+            return Err(CompileError::at_span(
+                format!(
+                    "tncc does not yet support more than {} parameters in '{}'",
+                    self.regs.args.len(),
+                    func.name
+                ),
+                func.span,
+            ));
+        }
 
         // decl
-        self.ptab(&format!(".global {}", self.to_symbol(&func.name)));
-        self.ptab(".p2align 2");
-        self.pln(&format!("{}:", self.to_symbol(&func.name)));
+        let symbol = self.to_symbol(func.name);
+        self.enter_section(SectionKind::Text, &symbol);
+        self.emit_global(func.name, &symbol);
+        self.emit_used(func.name, &symbol);
+        let align = self.requested_align(func.name)?.max(4);
+        self.ptab(format_args!(".p2align {}", align.trailing_zeros()));
+        self.pln(format_args!("{}:", symbol));
 
         // calculate space needed for arguments and local variables
-        let size: u32 = gen_util::size_args_local(func);
+        let frame = gen_util::layout_frame(func)?;
+        let sp_offset = frame.size;
 
-        let sp_offset: u32 = gen_util::get_sp_offset(size);
         // save sp
         if sp_offset > 0 {
-            self.ptab(&format!("sub sp, sp, #{}", sp_offset));
+            self.ptab(format_args!("sub sp, sp, #{}", sp_offset));
         }
+        self.max_pushed = 0;
 
         // emit args
-        let mut arg_offset = sp_offset;
-        func.params
+        for (arg, reg) in func.params.iter().zip(self.regs.args.clone()) {
+            let offset = *frame.offsets.get(arg.id).unwrap();
+            let size = arg.data_type.decay().get_size()?;
+            self.emit_sp_access(SpAccess::store(reg, size, offset));
+        }
+
+        // parameters share the scope with the outermost block of the body
+        let params = func
+            .params
             .iter()
-            .take(ARG_REGS.len())
-            .enumerate()
-            .for_each(|(i, arg)| {
-                arg_offset = arg_offset - arg.data_type.get_size();
-                let reg = ARG_REGS.get(i).unwrap();
-                self.ptab(&format!("str {}, [sp, #{}]", *reg, arg_offset));
-            });
+            .map(|p| {
+                (
+                    p.name,
+                    (*frame.offsets.get(p.id).unwrap(), p.data_type.decay()),
+                )
+            })
+            .collect();
+        self.scopes = vec![params];
+        self.offsets = frame.offsets;
+
+        // the parameters are saved before the hooks clobber the argument registers
+        let instrument = self.opts.instrument_functions
+            && !self
+                .decl_attrs(func.name)
+                .any(|a| has_attr(a, "no_instrument_function"));
+        if instrument {
+            self.emit_profile_hook("__cyg_profile_func_enter", func.name);
+        }
+        self.counted_line = None;
+        self.emit_counter(func.span.start);
+
+        // body with statement, the last one falls through to the epilogue
+        self.epilogue = self.local_label(&format!("{}_epilogue", func.name));
+        self.epilogue_used = false;
+        self.return_type = func.return_type.clone();
+        self.emit_cmp_stmt(&func.cmp_stmt, true)?;
+        self.usage.push(StackUsage {
+            name: func.name,
+            span: func.span,
+            frame: frame.size,
+            spills: frame.spills,
+            outgoing: self.max_pushed,
+        });
+
+        // noreturn function without returns and a body ending with a trap have no epilogue
+        let noreturn = self.decl_attrs(func.name).any(|a| has_attr(a, "noreturn"))
+            || ends_with_noreturn(&func.cmp_stmt);
+        if noreturn && !self.epilogue_used && !ends_with_return(&func.cmp_stmt) {
+            self.emit_pool();
+            self.pln("");
+            return Ok(());
+        }
 
-        // body with statement
-        self.emit_cmp_stmt(&func.cmp_stmt);
+        // reaching the end of main returns 0
+        if func.name == "main" && !ends_with_return(&func.cmp_stmt) {
+            let ret = self.regs.ret;
+            self.ptab(format_args!("mov {}, #0", ret));
+        }
+
+        // epilogue shared by all returns
+        if self.epilogue_used {
+            let label = format!("{}:", self.epilogue);
+            self.pln(label);
+        }
+        if instrument {
+            self.emit_profile_hook("__cyg_profile_func_exit", func.name);
+        }
 
         // restore sp
         if sp_offset > 0 {
-            self.ptab(&format!("add sp, sp, #{}", sp_offset));
+            self.ptab(format_args!("add sp, sp, #{}", sp_offset));
         }
 
         // finish function
         self.ptab("ret");
+        self.emit_pool();
 
         // empty new line
         self.pln("");
+        Ok(())
     }
 
-    /// emit compound statement
-    fn emit_cmp_stmt(&mut self, cmp_stmt: &CmpStmt) {
-        cmp_stmt.stmts.iter().for_each(|stmt| self.emit_stmt(stmt))
+    /// attributes of all declarations of the global name, prototypes pass them to the definition
+    fn decl_attrs(&self, name: Symbol) -> impl Iterator<Item = &'a [Attribute]> {
+        self.ast.0.iter().filter_map(move |ext| match ext {
+            ExtDecl::Func(f) if f.name == name => Some(f.attrs.as_slice()),
+            ExtDecl::Global(g) if g.name == name => Some(g.attrs.as_slice()),
+            _ => None,
+        })
+    }
+
+    /// the largest alignment requested by the declarations of the global name, 1 if none
+    fn requested_align(&self, name: Symbol) -> GenResult<u32> {
+        self.decl_attrs(name)
+            .map(requested_align)
+            .try_fold(1, |align, a| Ok(a?.unwrap_or(1).max(align)))
+    }
+
+    /// call the profiling hook with the address of the function and the call site, the return
+    /// value in x0 is kept
+    fn emit_profile_hook(&mut self, hook: &str, func: Symbol) {
+        let (ret, lp, site) = (self.regs.ret, self.regs.lp, self.regs.args[1]);
+        self.emit_push_frame(32);
+        self.max_pushed = self.max_pushed.max(self.pushed + 32);
+        self.ptab(format_args!("str {}, [sp, #16]", ret));
+        let storage = match self.is_got(func) {
+            true => Storage::Got,
+            false => Storage::Global,
+        };
+        self.emit_addr(func, storage, self.regs.args[0]);
+        self.ptab(format_args!("mov {}, {}", site, lp));
+        self.ptab(format_args!("bl {}", self.to_symbol(Symbol::intern(hook))));
+        self.ptab(format_args!("ldr {}, [sp, #16]", ret));
+        self.emit_pop_frame(32);
+    }
+
+    /// push the frame record of fp and lr in the `size` bytes below sp, fp points to it
+    fn emit_push_frame(&mut self, size: u32) {
+        let (fp, lp) = (self.regs.fp, self.regs.lp);
+        self.ptab(format_args!("stp {}, {}, [sp, #-{}]!", fp, lp, size));
+        self.ptab(format_args!("mov {}, sp", fp));
+    }
+
+    /// pop the frame record of `emit_push_frame`
+    fn emit_pop_frame(&mut self, size: u32) {
+        let (fp, lp) = (self.regs.fp, self.regs.lp);
+        self.ptab(format_args!("ldp {}, {}, [sp], #{}", fp, lp, size));
+    }
+
+    /// export the symbol unless a declaration of the name is `static`, a weak symbol may be
+    /// replaced by the definition of another object
+    fn emit_global(&mut self, name: Symbol, symbol: &str) {
+        if self.is_internal(name) {
+            return;
+        }
+        let object = &self.desc.object;
+        let weak = self.is_weak(name);
+        if !weak || object.weak_exported {
+            self.ptab(format_args!("{} {}", self.desc.syntax.global, symbol));
+        }
+        if weak {
+            self.ptab(format_args!("{} {}", object.weak_definition, symbol));
+        }
+    }
+
+    /// a declaration of the name is `weak`
+    fn is_weak(&self, name: Symbol) -> bool {
+        self.decl_attrs(name).any(|a| has_attr(a, "weak"))
+    }
+
+    /// names declared `alias("target")` and their targets in the order of declaration
+    fn aliases(&self) -> Vec<(Symbol, Symbol)> {
+        let mut aliases: Vec<(Symbol, Symbol)> = Vec::new();
+        for ext in &self.ast.0 {
+            let (name, attrs) = match ext {
+                ExtDecl::Func(f) => (f.name, &f.attrs),
+                ExtDecl::Global(g) => (g.name, &g.attrs),
+                ExtDecl::StaticAssert(_) => continue,
+            };
+            match alias_target(attrs) {
+                Some(target) if !aliases.iter().any(|(n, _)| *n == name) => {
+                    aliases.push((name, target))
+                }
+                _ => {}
+            }
+        }
+        aliases
+    }
+
+    /// weak declarations without definition, the references are 0 unless another object
+    /// defines the name
+    fn emit_weak_references(&mut self) {
+        let mut names: Vec<Symbol> = Vec::new();
+        for ext in &self.ast.0 {
+            if let ExtDecl::Global(g) = ext {
+                let undefined = !self.defined.contains(&g.name) && !names.contains(&g.name);
+                if undefined && self.is_weak(g.name) {
+                    names.push(g.name);
+                }
+            }
+        }
+        for name in names {
+            let symbol = self.to_symbol(name);
+            self.ptab(format_args!(
+                "{} {}",
+                self.desc.object.weak_reference, symbol
+            ));
+        }
+    }
+
+    /// the name has internal linkage, the first declaration decides in C
+    fn is_internal(&self, name: Symbol) -> bool {
+        self.ast.0.iter().any(|ext| match ext {
+            ExtDecl::Func(f) => f.name == name && f.internal,
+            ExtDecl::Global(g) => g.name == name && g.internal,
+            ExtDecl::StaticAssert(_) => false,
+        })
+    }
+
+    /// keep the symbol marked `used` from dead stripping by the linker, only Mach-O strips
+    /// unreferenced symbols by default
+    fn emit_used(&mut self, name: Symbol, symbol: &str) {
+        let used = self.decl_attrs(name).any(|a| has_attr(a, "used"));
+        match self.desc.object.no_dead_strip {
+            Some(directive) if used => self.ptab(format_args!("{} {}", directive, symbol)),
+            _ => {}
+        }
+    }
+
+    /// emit compound statement, `tail` if nothing follows it in the function
+    fn emit_cmp_stmt(&mut self, cmp_stmt: &CmpStmt, tail: bool) -> GenResult<()> {
+        let last = cmp_stmt.stmts.len().saturating_sub(1);
+        cmp_stmt
+            .stmts
+            .iter()
+            .enumerate()
+            .try_for_each(|(i, stmt)| self.emit_stmt(stmt, tail && i == last))
     }
 
     /// emit statement
-    fn emit_stmt(&mut self, stmt: &Stmt) {
-        match stmt {
-            Stmt::Return(opt) => {
+    fn emit_stmt(&mut self, stmt: &Stmt, tail: bool) -> GenResult<()> {
+        // the pool goes between the statements, branched over, before its loads are out of range
+        let far = self
+            .pool
+            .first_use
+            .is_some_and(|at| self.text_bytes - at > self.pool_distance);
+        if far {
+            let skip = self.local_label(&format!("pool{}", self.pool.labels));
+            self.ptab(format_args!("b {}", skip));
+            self.emit_pool();
+            self.pln(format_args!("{}:", skip));
+        }
+        // lines without code are not counted
+        let code = match &stmt.kind {
+            StmtKind::Compound(_) | StmtKind::StaticAssert(_) => false,
+            StmtKind::VarDecl(decl) => decl.init.is_some(),
+            _ => true,
+        };
+        if code {
+            self.emit_counter(stmt.span.start);
+        }
+        match &stmt.kind {
+            StmtKind::Return(opt) => {
                 if let Some(expr) = opt {
-                    self.emit_expr(expr, Some(Reg::X0));
+                    self.emit_expr(expr, Some(self.regs.ret))?;
+                    if self.return_type.as_base() != Some(DataType::Void) {
+                        let size = self.return_type.get_size()?;
+                        self.emit_extend(expr, self.regs.ret, size)?;
+                    }
+                }
+                // ret inst is emitted by the function after the epilogue
+                if !tail {
+                    let branch = format!("b {}", self.epilogue);
+                    self.ptab(branch);
+                    self.epilogue_used = true;
                 }
-                // ret inst is emitted by the function
             }
-            Stmt::Expr(e) => self.emit_expr(e, None),
-            _ => panic!("not supported: {:?}", stmt),
+            StmtKind::Expr(e) => self.emit_expr(e, None)?,
+            StmtKind::Compound(c) => {
+                self.scopes.push(HashMap::new());
+                self.emit_cmp_stmt(c, tail)?;
+                self.scopes.pop();
+            }
+            // the storage is reserved by the frame
+            StmtKind::VarDecl(decl) => {
+                let offset = *self.offsets.get(decl.id).unwrap();
+                let ty = match &decl.init {
+                    Some(e) => {
+                        let flat = flatten(&decl.data_type, e)?;
+                        self.emit_init(&flat, offset)?;
+                        flat.ty
+                    }
+                    None => decl.data_type.clone(),
+                };
+                let scope = self.scopes.last_mut().expect("open scope");
+                scope.insert(decl.name, (offset, ty));
+            }
+            // checked by the semantic analysis
+            StmtKind::StaticAssert(_) => {}
+            StmtKind::Asm(code) => self.ptab(String::from_utf8_lossy(code)),
+            _ => return Err(not_supported(stmt)),
         }
+        Ok(())
     }
 
     /// emit expression and return value to reg
-    fn emit_expr(&mut self, expr: &Expr, dst_reg: Option<Reg>) {
-        match expr {
-            Expr::IntConst(v) => {
-                dst_reg.map(|r| self.ptab(&format!("mov {}, #{}", r, v)));
-            }
-            Expr::FunctionCall(name, args) => {
-                // push fp, lr
-                self.ptab(&format!("stp {}, {}, [sp, #-16]!", FP, LP));
-
-                // update fp
-                self.ptab(&format!("mov {}, sp", FP));
-
-                // move arguments to registers (8)
-                args.iter()
-                    .take(ARG_REGS.len())
-                    .enumerate()
-                    .for_each(|(i, arg)| {
-                        let reg = ARG_REGS.get(i).unwrap();
-                        self.emit_expr(arg, Some(*reg));
-                    });
+    fn emit_expr(&mut self, expr: &Expr, dst_reg: Option<Reg>) -> GenResult<()> {
+        match &expr.kind {
+            ExprKind::IntConst(v) => {
+                if let Some(r) = dst_reg {
+                    self.emit_const(r, *v)
+                }
+            }
+            // address of the literal in the read only data
+            ExprKind::StrLit(s) => {
+                if let Some(r) = dst_reg {
+                    let label = self.string_label(self.strings.len());
+                    self.strings.push(s.clone());
+                    self.emit_symbol_addr(&label, r);
+                }
+            }
+            ExprKind::FunctionCall(name, args) if Builtin::of(name.as_str()).is_some() => {
+                let builtin = Builtin::of(name.as_str()).expect("builtin");
+                self.emit_builtin(expr, *name, builtin, args, dst_reg)?
+            }
+            // the arguments passed on the stack are not implemented
+            ExprKind::FunctionCall(name, args) if args.len() > self.regs.args.len() => {
+                return Err(CompileError::at_span(
+                    format!(
+                        "tncc does not yet support more than {} arguments in the call to '{}'",
+                        self.regs.args.len(),
+                        name
+                    ),
+                    expr.span,
+                ))
+            }
+            ExprKind::FunctionCall(name, args) => {
+                // push fp, lr and update fp
+                self.emit_push_frame(16);
+                self.pushed += 16;
+                self.max_pushed = self.max_pushed.max(self.pushed);
+
+                // move arguments to registers (8), extended to int at least like the promotion
+                // of the arguments without prototype
+                let params = self.param_types(*name);
+                let sizes = (0..args.len())
+                    .map(|i| match params.as_ref().and_then(|p| p.get(i)) {
+                        Some(ty) => Ok(ty.decay().get_size()?.max(4)),
+                        None => Ok(4),
+                    })
+                    .collect::<GenResult<Vec<_>>>()?;
+                self.emit_args(args, &sizes)?;
 
                 // call
-                self.ptab(&format!("bl {}", self.to_symbol(name)));
+                self.ptab(format_args!("bl {}", self.to_symbol(*name)));
 
                 // pop fp, lr
-                self.ptab(&format!("ldp {}, {}, [sp], #16", FP, LP));
+                self.emit_pop_frame(16);
+                self.pushed -= 16;
 
                 // return value (in x0) to reg
-                self.util_move_reg(dst_reg, Reg::X0);
+                self.util_move_reg(dst_reg, self.regs.ret);
+            }
+            // discarded value, only the calls in the operands and the reads of volatile objects
+            // are evaluated
+            ExprKind::VarRef(name) if dst_reg.is_none() => match self.lookup(*name) {
+                Some((storage, ty)) if ty.is_volatile() => {
+                    let size = ty.get_size()?;
+                    self.emit_load(*name, storage, size, self.regs.temp);
+                }
+                _ => {}
+            },
+            // arrays decay to the address of the first element
+            ExprKind::VarRef(name) => match (self.lookup(*name), dst_reg) {
+                (Some((storage, Type::Array(_, _))), Some(r)) => self.emit_addr(*name, storage, r),
+                _ => return Err(not_supported(expr)),
+            },
+            ExprKind::Unary(_, e) if dst_reg.is_none() => self.emit_expr(e, None)?,
+            ExprKind::Arith(l, _, r) if dst_reg.is_none() => {
+                self.emit_expr(l, None)?;
+                self.emit_expr(r, None)?;
+            }
+            _ => return Err(not_supported(expr)),
+        }
+        Ok(())
+    }
+
+    /// store every scalar of the local variable, zero if not initialized
+    fn emit_init(&mut self, flat: &FlatInit, offset: u32) -> GenResult<()> {
+        let size = flat.scalar.get_size()?;
+        for i in 0..flat.count {
+            let reg = match flat.values.get(&i) {
+                // constants are folded, truncated to the size of the scalar
+                Some(e) => {
+                    match eval_int(e) {
+                        Ok(c) => self.emit_const(self.regs.temp, truncate(c.value, size)),
+                        Err(_) => {
+                            self.emit_expr(e, Some(self.regs.temp))?;
+                            self.emit_extend(e, self.regs.temp, size)?;
+                        }
+                    }
+                    self.regs.temp
+                }
+                None => Reg::Xzr,
+            };
+            let offset = offset + self.pushed + i as u32 * size;
+            self.emit_sp_access(SpAccess::store(reg, size, offset));
+        }
+        Ok(())
+    }
+
+    /// extend the value of the expression in reg to `size` bytes, the bits above the type of a
+    /// call result are unspecified by the ABI, other values are already extended
+    fn emit_extend(&mut self, expr: &Expr, reg: Reg, size: u32) -> GenResult<()> {
+        let ty = match &expr.kind {
+            ExprKind::FunctionCall(name, args) => self.call_type(*name, args)?,
+            _ => None,
+        };
+        let signed = self.desc.char_signed;
+        if let Some(inst) = ty
+            .map(|t| extension(&t, size, signed))
+            .transpose()?
+            .flatten()
+        {
+            // writing the word register clears the upper half
+            let dst = match inst.starts_with("uxt") {
+                true => reg.sized(4),
+                false => reg.sized(size),
+            };
+            self.ptab(format_args!("{} {}, {}", inst, dst, reg.sized(4)));
+        }
+        Ok(())
+    }
+
+    /// evaluate the arguments to the argument registers, each extended to its size in bytes
+    ///
+    /// a call in an argument clobbers the registers of the others, so the arguments with calls
+    /// are evaluated first and all but the last of them wait on the stack
+    fn emit_args(&mut self, args: &[Expr], sizes: &[u32]) -> GenResult<()> {
+        let (calls, others): (Vec<usize>, Vec<usize>) =
+            (0..args.len()).partition(|i| has_call(&args[*i]));
+        for (n, &i) in calls.iter().enumerate() {
+            let reg = self.regs.args[i];
+            self.emit_expr(&args[i], Some(reg))?;
+            self.emit_extend(&args[i], reg, sizes[i])?;
+            if n + 1 < calls.len() {
+                self.ptab(format_args!("str {}, [sp, #-16]!", reg));
+                self.pushed += 16;
+                self.max_pushed = self.max_pushed.max(self.pushed);
+            }
+        }
+        for &i in calls.iter().rev().skip(1) {
+            let reg = self.regs.args[i];
+            self.ptab(format_args!("ldr {}, [sp], #16", reg));
+            self.pushed -= 16;
+        }
+        for i in others {
+            let reg = self.regs.args[i];
+            self.emit_expr(&args[i], Some(reg))?;
+            self.emit_extend(&args[i], reg, sizes[i])?;
+        }
+        Ok(())
+    }
+
+    /// builtin inline instead of a call
+    fn emit_builtin(
+        &mut self,
+        expr: &Expr,
+        name: Symbol,
+        builtin: Builtin,
+        args: &[Expr],
+        dst_reg: Option<Reg>,
+    ) -> GenResult<()> {
+        if args.len() != builtin.arity() {
+            return Err(not_supported(expr));
+        }
+        match builtin {
+            Builtin::Atomic(atomic) => self.emit_atomic(expr, name, atomic, args, dst_reg)?,
+            // the hint does not weigh any branch yet, only its side effects are kept
+            Builtin::Expect => {
+                self.emit_expr(&args[1], None)?;
+                self.emit_expr(&args[0], dst_reg)?;
+                if let Some(r) = dst_reg {
+                    self.emit_extend(&args[0], r, 8)?;
+                }
+            }
+            // the immediate of GCC
+            Builtin::Trap => self.ptab("brk #1000"),
+            Builtin::Unreachable => {}
+        }
+        Ok(())
+    }
+
+    /// atomic builtin inline, the operands are evaluated to the argument registers like the
+    /// arguments of a call
+    fn emit_atomic(
+        &mut self,
+        expr: &Expr,
+        name: Symbol,
+        atomic: Atomic,
+        args: &[Expr],
+        dst_reg: Option<Reg>,
+    ) -> GenResult<()> {
+        let (operands, order) = args.split_at(atomic.operands());
+        let order = match (atomic.order, order) {
+            (Some(order), _) => order,
+            (None, [arg]) => MemoryOrder::from_value(eval_int(arg)?.value).ok_or_else(|| {
+                CompileError::at_span(
+                    format!("invalid memory model argument of '{}'", name),
+                    arg.span,
+                )
+            })?,
+            _ => return Err(not_supported(expr)),
+        };
+        let size = match operands.first() {
+            Some(p) => self.pointee(p)?.get_size()?,
+            None => 0,
+        };
+        let sizes: Vec<u32> = (0..operands.len())
+            .map(|i| if i == 0 { 8 } else { size.max(4) })
+            .collect();
+        self.emit_args(operands, &sizes)?;
+        let label = self.local_label(&format!("atomic{}", self.atomic_labels));
+        self.atomic_labels += 1;
+        for inst in lower(atomic.op, order, size, self.opts.arch.lse(), &label) {
+            match inst.ends_with(':') {
+                true => self.pln(inst),
+                false => self.ptab(inst),
+            }
+        }
+        if atomic.has_value() {
+            self.util_move_reg(dst_reg, self.regs.ret);
+        }
+        Ok(())
+    }
+
+    /// type of the object the value of the pointer expression points to
+    fn pointee(&self, expr: &Expr) -> GenResult<Type> {
+        let ty = match &expr.kind {
+            ExprKind::StrLit(_) => Some(Type::Base(DataType::Char)),
+            ExprKind::VarRef(name) => match self.lookup(*name) {
+                Some((_, Type::Array(elem, _))) => Some((**elem).clone()),
+                _ => None,
+            },
+            ExprKind::FunctionCall(name, args) => match self.call_type(*name, args)? {
+                Some(Type::Pointer(ty)) => Some(*ty),
+                _ => None,
+            },
+            _ => None,
+        };
+        ty.map(|t| t.unqualified().clone())
+            .ok_or_else(|| not_supported(expr))
+    }
+
+    /// type of the value of the call, the object of an atomic builtin, none if the function is
+    /// not declared
+    fn call_type(&self, name: Symbol, args: &[Expr]) -> GenResult<Option<Type>> {
+        match Builtin::of(name.as_str()) {
+            Some(Builtin::Atomic(atomic)) if atomic.has_value() => match args.first() {
+                Some(p) => self.pointee(p).map(Some),
+                None => Ok(None),
+            },
+            Some(Builtin::Expect) => Ok(Some(Type::Base(DataType::Long))),
+            Some(_) => Ok(Some(Type::Base(DataType::Void))),
+            None => Ok(self.return_type_of(name)),
+        }
+    }
+
+    /// declared return type of the function, none if it is not declared
+    fn return_type_of(&self, name: Symbol) -> Option<Type> {
+        self.ast.0.iter().find_map(|ext| match ext {
+            ExtDecl::Func(f) if f.name == name => Some(f.return_type.clone()),
+            ExtDecl::Global(g) if g.name == name => match &g.data_type {
+                Type::Function(ret, _) => Some((**ret).clone()),
+                _ => None,
+            },
+            _ => None,
+        })
+    }
+
+    /// parameter types of the prototype of the function, none without prototype
+    fn param_types(&self, name: Symbol) -> Option<Vec<Type>> {
+        self.ast.0.iter().find_map(|ext| match ext {
+            ExtDecl::Func(f) if f.name == name && f.prototype => {
+                Some(f.params.iter().map(|p| p.data_type.clone()).collect())
             }
-            _ => panic!("not supported: {:?}", expr),
+            ExtDecl::Global(g) if g.name == name => match &g.data_type {
+                Type::Function(_, params) => params.clone(),
+                _ => None,
+            },
+            _ => None,
+        })
+    }
+
+    /// storage and declared type of the variable visible by the name
+    fn lookup(&self, name: Symbol) -> Option<(Storage, &Type)> {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|s| s.get(&name))
+            .map(|(offset, ty)| (Storage::Frame(*offset), ty))
+            .or_else(|| {
+                let storage = match self.is_got(name) {
+                    true => Storage::Got,
+                    false => Storage::Global,
+                };
+                self.globals.get(&name).map(|ty| (storage, ty))
+            })
+    }
+
+    /// the global may be out of reach or interposed, i.e defined in another shared library
+    ///
+    /// macOS has no copy relocations for the static model and no interposition of the globals
+    /// of the same image
+    fn is_got(&self, name: Symbol) -> bool {
+        let defined = self.defined.contains(&name);
+        if defined && self.is_internal(name) {
+            return false;
         }
+        let object = &self.desc.object;
+        match self.opts.reloc {
+            RelocModel::Pic if object.interposition => true,
+            RelocModel::Static if object.copy_relocations => false,
+            _ => !defined,
+        }
+    }
+
+    /// load the address of the variable to reg
+    fn emit_addr(&mut self, name: Symbol, storage: Storage, reg: Reg) {
+        match storage {
+            Storage::Frame(offset) => {
+                self.ptab(format_args!("add {}, sp, #{}", reg, offset + self.pushed))
+            }
+            Storage::Global => self.emit_symbol_addr(&self.to_symbol(name), reg),
+            Storage::Got => self.emit_got_addr(&self.to_symbol(name), reg),
+        }
+    }
+
+    /// load the value of the scalar variable to reg
+    fn emit_load(&mut self, name: Symbol, storage: Storage, size: u32, reg: Reg) {
+        let inst = load_inst(size);
+        match storage {
+            Storage::Frame(offset) => {
+                self.emit_sp_access(SpAccess::load(reg, size, offset + self.pushed))
+            }
+            // the offset in the page is folded into the load
+            Storage::Global => {
+                let page_off = self.emit_page(&self.to_symbol(name), reg);
+                self.ptab(format_args!(
+                    "{} {}, [{}, {}]",
+                    inst,
+                    reg.sized(size),
+                    reg,
+                    page_off
+                ));
+            }
+            Storage::Got => {
+                self.emit_got_addr(&self.to_symbol(name), reg);
+                self.ptab(format_args!("{} {}, [{}]", inst, reg.sized(size), reg));
+            }
+        }
+    }
+
+    /// move the constant to reg, from the literal pool if it takes more than `MAX_MOV_INSTS`
+    /// moves
+    fn emit_const(&mut self, reg: Reg, value: i64) {
+        if let Some(insts) = mov_sequence(reg, value) {
+            return insts.into_iter().for_each(|i| self.ptab(i));
+        }
+        let label = match self.pool.entries.iter().find(|(_, v)| *v == value) {
+            Some((label, _)) => label.clone(),
+            None => {
+                let label = self.local_label(&format!("lit{}", self.pool.labels));
+                self.pool.labels += 1;
+                self.pool.entries.push((label.clone(), value));
+                label
+            }
+        };
+        self.pool.first_use.get_or_insert(self.text_bytes);
+        self.ptab(format_args!("ldr {}, {}", reg, label));
+    }
+
+    /// the pending constants of the literal pool, after code which is not executed
+    fn emit_pool(&mut self) {
+        if self.pool.entries.is_empty() {
+            return;
+        }
+        self.ptab(".p2align 3");
+        for (label, value) in std::mem::take(&mut self.pool.entries) {
+            self.pln(format_args!("{}:", label));
+            self.ptab(format_args!(".xword {}", value));
+        }
+        self.pool.first_use = None;
+    }
+
+    /// load or store a slot of the frame, above '-O0' two adjacent slots of the same size are
+    /// accessed by one `ldp` or `stp`
+    fn emit_sp_access(&mut self, access: SpAccess) {
+        if self.opts.opt_level == OptLevel::O0 {
+            return self.ptab(access.single());
+        }
+        match self.pending.take() {
+            Some(prev) => match prev.pair(&access) {
+                Some(inst) => self.ptab(inst),
+                None => {
+                    self.ptab(prev.single());
+                    self.pending = Some(access);
+                }
+            },
+            None => self.pending = Some(access),
+        }
+    }
+
+    /// load the address of the symbol to reg, the page then the offset in the page
+    fn emit_symbol_addr(&mut self, symbol: &str, reg: Reg) {
+        let page_off = self.emit_page(symbol, reg);
+        self.ptab(format_args!("add {}, {}, {}", reg, reg, page_off));
+    }
+
+    /// load the address of the symbol to reg from its GOT entry
+    fn emit_got_addr(&mut self, symbol: &str, reg: Reg) {
+        let (page, page_off) = &self.desc.object.got_page;
+        let (page, page_off) = (page.apply(symbol), page_off.apply(symbol));
+        self.ptab(format_args!("adrp {}, {}", reg, page));
+        self.ptab(format_args!("ldr {}, [{}, {}]", reg, reg, page_off));
+    }
+
+    /// load the address of the page of the symbol to reg, return the offset in the page
+    fn emit_page(&mut self, symbol: &str, reg: Reg) -> String {
+        let (page, page_off) = &self.desc.object.page;
+        let (page, page_off) = (page.apply(symbol), page_off.apply(symbol));
+        self.ptab(format_args!("adrp {}, {}", reg, page));
+        page_off
+    }
+
+    /// label of the n-th string literal, private to the object file like the C compilers
+    fn string_label(&self, n: usize) -> String {
+        format!("{}{}", self.desc.object.string_prefix, n)
     }
 
     /// util move to reg with optimization
     fn util_move_reg(&mut self, dst: Option<Reg>, src: Reg) {
-        dst.map(|r| {
+        if let Some(r) = dst {
             if src != r {
-                self.ptab(&format!("mov {}, {}", r, src))
+                self.ptab(format_args!("mov {}, {}", r, src))
             }
-        });
+        }
     }
 
-    /// gen symbol name based on os
-    fn to_symbol(&self, name: &str) -> String {
-        match self.target {
-            TargetOs::MacOs => format!("_{}", name),
-            _ => String::from(name),
-        }
+    /// assembler local label, not visible in the object file
+    fn local_label(&self, name: &str) -> String {
+        format!("{}{}", self.desc.object.local_prefix, name)
+    }
+
+    /// symbol of the C name in the object format
+    fn to_symbol(&self, name: Symbol) -> String {
+        format!("{}{}", self.desc.object.symbol_prefix, name)
     }
 }
 
 trait Render {
     /// push with tab and new line
-    fn ptab(&mut self, str: &str);
+    fn ptab<D: Display>(&mut self, str: D);
 
     /// push with new line
-    fn pln(&mut self, str: &str);
+    fn pln<D: Display>(&mut self, str: D);
+}
+
+impl<'a, W: io::Write> Render for ArmGen<'a, W> {
+    fn ptab<D: Display>(&mut self, str: D) {
+        self.text_bytes += 4;
+        self.pln(format_args!("\t{}", str));
+    }
+
+    fn pln<D: Display>(&mut self, str: D) {
+        // the held back access is not paired with anything but the next access
+        if let Some(access) = self.pending.take() {
+            self.ptab(access.single());
+        }
+        if self.io_error.is_none() {
+            self.io_error = writeln!(self.out, "{}", str).err();
+        }
+    }
+}
+
+/// offsets of the objects in the data sections of the object
+///
+/// `.p2align` before an object only pads to its alignment, the padding is emitted explicitly
+/// to keep the layout visible and the same for every assembler. The sections are aligned to
+/// the largest alignment of their objects
+#[derive(Debug, Default)]
+struct DataSections {
+    /// directive of the current section
+    current: String,
+    /// bytes of each section so far
+    ends: HashMap<String, u32>,
+}
+
+impl DataSections {
+    /// place an object in the current section, return the padding before it
+    fn place(&mut self, align: u32, size: u32) -> u32 {
+        let end = self.ends.entry(self.current.clone()).or_default();
+        let start = end.next_multiple_of(align.max(1));
+        let padding = start - *end;
+        *end = start + size;
+        padding
+    }
+}
+
+/// constants loaded by `ldr` from the text section, emitted after the function or between its
+/// statements if the first load would be out of range
+#[derive(Debug, Default)]
+struct LiteralPool {
+    /// labels and values not emitted yet
+    entries: Vec<(String, i64)>,
+    /// labels created in the object, a counter to keep them unique
+    labels: usize,
+    /// offset in the code of the first load of the entries
+    first_use: Option<u32>,
+}
+
+/// instructions moving the constant to reg, none if it takes more than `MAX_MOV_INSTS`
+///
+/// `mov` is a single `movz`, `movn` or `orr` of a bitmask, otherwise the 16 bit chunks which are
+/// not zero, or not ones if there are more of them, are set by `movk`
+fn mov_sequence(reg: Reg, value: i64) -> Option<Vec<String>> {
+    let bits = value as u64;
+    let chunks: Vec<u64> = (0..4).map(|i| (bits >> (16 * i)) & 0xffff).collect();
+    let zeros = chunks.iter().filter(|c| **c == 0).count();
+    let ones = chunks.iter().filter(|c| **c == 0xffff).count();
+    if zeros >= 3 || ones >= 3 || is_bitmask_imm(bits) {
+        return Some(vec![format!("mov {}, #{}", reg, value)]);
+    }
+    let fill = if ones > zeros { 0xffff } else { 0 };
+    let patches: Vec<(usize, u64)> = chunks
+        .into_iter()
+        .enumerate()
+        .filter(|(_, c)| *c != fill)
+        .collect();
+    if patches.len() > MAX_MOV_INSTS {
+        return None;
+    }
+    let insts = patches
+        .into_iter()
+        .enumerate()
+        .map(|(n, (i, c))| match (n, fill) {
+            (0, 0) => format!("movz {}, #{}, lsl #{}", reg, c, 16 * i),
+            (0, _) => format!("movn {}, #{}, lsl #{}", reg, !c & 0xffff, 16 * i),
+            _ => format!("movk {}, #{}, lsl #{}", reg, c, 16 * i),
+        })
+        .collect();
+    Some(insts)
+}
+
+/// the value is a logical immediate of `orr`: a rotated run of ones repeated in elements of 2 to
+/// 64 bits
+fn is_bitmask_imm(value: u64) -> bool {
+    if value == 0 || value == u64::MAX {
+        return false;
+    }
+    // the smallest element repeated to the value
+    let mut size = 64;
+    while size > 2 {
+        let half = size / 2;
+        let mask = (1u64 << half) - 1;
+        if value & mask != (value >> half) & mask {
+            break;
+        }
+        size = half;
+    }
+    let mask = match size {
+        64 => u64::MAX,
+        _ => (1u64 << size) - 1,
+    };
+    let elem = value & mask;
+    (0..size).any(|r| {
+        let rotated = match r {
+            0 => elem,
+            _ => ((elem >> r) | (elem << (size - r))) & mask,
+        };
+        rotated & rotated.wrapping_add(1) == 0
+    })
+}
+
+/// load or store of a register from a slot addressed from sp
+#[derive(Debug, Clone, Copy)]
+struct SpAccess {
+    load: bool,
+    reg: Reg,
+    /// bytes of the slot
+    size: u32,
+    /// offset from sp
+    offset: u32,
+}
+
+impl SpAccess {
+    fn load(reg: Reg, size: u32, offset: u32) -> Self {
+        SpAccess {
+            load: true,
+            reg,
+            size,
+            offset,
+        }
+    }
+
+    fn store(reg: Reg, size: u32, offset: u32) -> Self {
+        SpAccess {
+            load: false,
+            reg,
+            size,
+            offset,
+        }
+    }
+
+    /// `ldr` or `str` of the slot
+    fn single(&self) -> String {
+        let inst = match self.load {
+            true => load_inst(self.size),
+            false => store_inst(self.size),
+        };
+        format!(
+            "{} {}, [sp, #{}]",
+            inst,
+            self.reg.sized(self.size),
+            self.offset
+        )
+    }
+
+    /// `ldp` or `stp` of both slots if they are adjacent words or double words, the scaled
+    /// offset of the lower slot fits in 7 bits and two loads have different destinations
+    fn pair(&self, next: &SpAccess) -> Option<String> {
+        let (lo, hi) = match self.offset < next.offset {
+            true => (self, next),
+            false => (next, self),
+        };
+        let size = lo.size;
+        let pairable = lo.load == hi.load
+            && size == hi.size
+            && (size == 4 || size == 8)
+            && hi.offset == lo.offset + size
+            && lo.offset % size == 0
+            && lo.offset / size <= 63
+            && !(lo.load && lo.reg == hi.reg);
+        pairable.then(|| {
+            format!(
+                "{} {}, {}, [sp, #{}]",
+                if lo.load { "ldp" } else { "stp" },
+                lo.reg.sized(size),
+                hi.reg.sized(size),
+                lo.offset
+            )
+        })
+    }
+}
+
+/// the last statement is a return, also in nested blocks
+fn ends_with_return(cmp_stmt: &CmpStmt) -> bool {
+    match cmp_stmt.stmts.last().map(|s| &s.kind) {
+        Some(StmtKind::Return(_)) => true,
+        Some(StmtKind::Compound(c)) => ends_with_return(c),
+        _ => false,
+    }
+}
+
+/// the evaluation of the expression calls a function or a builtin using the argument registers
+fn has_call(expr: &Expr) -> bool {
+    match &expr.kind {
+        ExprKind::FunctionCall(_, _) => true,
+        ExprKind::Arith(l, _, r) => has_call(l) || has_call(r),
+        ExprKind::Unary(_, e) => has_call(e),
+        _ => false,
+    }
+}
+
+/// the last statement is a call of a builtin not returning, i.e `__builtin_unreachable()`
+fn ends_with_noreturn(cmp_stmt: &CmpStmt) -> bool {
+    match cmp_stmt.stmts.last().map(|s| &s.kind) {
+        Some(StmtKind::Expr(Expr {
+            kind: ExprKind::FunctionCall(name, _),
+            ..
+        })) => Builtin::of(name.as_str()).is_some_and(|b| b.is_noreturn()),
+        Some(StmtKind::Compound(c)) => ends_with_noreturn(c),
+        _ => false,
+    }
+}
+
+/// instruction extending a value of the type to `size` bytes, none if the value is as wide
+///
+/// the integer types are signed, plain `char` is unsigned unless `char_signed`
+fn extension(ty: &Type, size: u32, char_signed: bool) -> GenResult<Option<&'static str>> {
+    if ty.as_base() == Some(DataType::Void) {
+        return Ok(None);
+    }
+    match ty.get_size()? {
+        1 if size > 1 && !char_signed => Ok(Some("uxtb")),
+        1 if size > 1 => Ok(Some("sxtb")),
+        2 if size > 2 => Ok(Some("sxth")),
+        4 if size > 4 => Ok(Some("sxtw")),
+        _ => Ok(None),
+    }
+}
+
+/// store instruction of a scalar of the size in bytes
+fn store_inst(size: u32) -> &'static str {
+    match size {
+        1 => "strb",
+        2 => "strh",
+        _ => "str",
+    }
+}
+
+/// load instruction of a scalar of the size in bytes, zero extended
+fn load_inst(size: u32) -> &'static str {
+    match size {
+        1 => "ldrb",
+        2 => "ldrh",
+        _ => "ldr",
+    }
+}
+
+/// data directive of a scalar of the size in bytes
+fn data_directive(size: u32) -> Option<&'static str> {
+    match size {
+        1 => Some(".byte"),
+        2 => Some(".hword"),
+        4 => Some(".word"),
+        8 => Some(".xword"),
+        _ => None,
+    }
+}
+
+/// signed value of the lowest bytes of the value
+fn truncate(value: i64, size: u32) -> i64 {
+    let shift = 64 - size * 8;
+    (value << shift) >> shift
+}
+
+/// error for constructs which can not be generated yet, at the node if it has a location
+fn not_supported<T: Construct>(node: &T) -> CompileError {
+    let msg = format!("tncc does not yet support {}", node.construct());
+    match node.span() {
+        Some(span) => CompileError::at_span(msg, span),
+        None => CompileError::new(msg),
+    }
+}
+
+/// node of the AST named in the errors of the code generator
+trait Construct {
+    /// description after "tncc does not yet support"
+    fn construct(&self) -> String;
+
+    /// source range of the node, types have none
+    fn span(&self) -> Option<Span> {
+        None
+    }
 }
 
-impl<'a> Render for ArmGen<'a> {
-    fn ptab(&mut self, str: &str) {
-        self.str.push('\t');
-        self.pln(str);
+impl Construct for Expr {
+    fn construct(&self) -> String {
+        match &self.kind {
+            ExprKind::IntConst(_) | ExprKind::StrLit(_) => String::from("constants here"),
+            ExprKind::FunctionCall(name, _) => format!("the call to '{}' here", name),
+            ExprKind::VarRef(name) => format!("reading the value of '{}'", name),
+            ExprKind::Arith(_, op, _) => {
+                let op = match op {
+                    ArithOp::Add => "+",
+                    ArithOp::Sub => "-",
+                };
+                format!("the value of '{}'", op)
+            }
+            ExprKind::Unary(op, _) => {
+                let op = match op {
+                    UnaryOp::Neg => "-",
+                    UnaryOp::Plus => "+",
+                };
+                format!("the value of unary '{}'", op)
+            }
+            ExprKind::InitList(_) => String::from("initializer lists here"),
+        }
     }
 
-    fn pln(&mut self, str: &str) {
-        self.str.push_str(str);
-        self.str.push('\n');
+    fn span(&self) -> Option<Span> {
+        Some(self.span)
+    }
+}
+
+impl Construct for Stmt {
+    fn construct(&self) -> String {
+        match &self.kind {
+            StmtKind::Assignment(name, _) => format!("assignment to '{}'", name),
+            _ => String::from("this statement"),
+        }
+    }
+
+    fn span(&self) -> Option<Span> {
+        Some(self.span)
+    }
+}
+
+impl Construct for Type {
+    fn construct(&self) -> String {
+        format!("the type '{}'", self)
+    }
+}
+
+impl Construct for DataType {
+    fn construct(&self) -> String {
+        Type::Base(*self).construct()
     }
 }
 
 trait AddrSize {
-    fn get_size(&self) -> u32;
+    fn get_size(&self) -> GenResult<u32>;
+
+    /// alignment in bytes, the size of scalars
+    fn get_align(&self) -> GenResult<u32> {
+        self.get_size()
+    }
 }
 
 impl AddrSize for DataType {
-    fn get_size(&self) -> u32 {
+    fn get_size(&self) -> GenResult<u32> {
         match self {
-            DataType::Int => 4,
-            _ => panic!("not supported: {:?}", self),
+            DataType::Char => Ok(1),
+            DataType::Int => Ok(4),
+            // LP64 on Linux and macOS, only the values of builtins are long
+            DataType::Long => Ok(8),
+            _ => Err(not_supported(self)),
         }
     }
 }
+
+impl AddrSize for Type {
+    fn get_size(&self) -> GenResult<u32> {
+        match self {
+            Type::Base(dt) => dt.get_size(),
+            Type::Pointer(_) => Ok(8),
+            Type::Volatile(t) => t.get_size(),
+            Type::Array(elem, Some(len)) => u32::try_from(*len)
+                .ok()
+                .and_then(|len| elem.get_size().ok()?.checked_mul(len))
+                .ok_or_else(|| not_supported(self)),
+            _ => Err(not_supported(self)),
+        }
+    }
+
+    fn get_align(&self) -> GenResult<u32> {
+        match self {
+            Type::Array(elem, _) => elem.get_align(),
+            _ => self.get_size(),
+        }
+    }
+}
+
 mod gen_util {
-    use crate::ast::FuncDecl;
+    use crate::{
+        ast::{FuncDecl, Type, VarDecl, Visitor},
+        attrs::{requested_align, MAX_ALIGN},
+        common::CompileError,
+        init::flatten,
+        sidetable::SideTable,
+    };
 
-    use super::AddrSize;
+    use super::{AddrSize, GenResult};
 
-    pub fn size_args_local(func: &FuncDecl) -> u32 {
-        func.params.iter().map(|p| p.data_type.get_size()).sum()
+    /// stack frame of a function
+    pub struct Frame {
+        /// size of the frame, aligned to 16 bytes
+        pub size: u32,
+        /// offset from sp of parameters and local variables
+        pub offsets: SideTable<u32>,
+        /// bytes of the slots saving the argument registers at the top of the frame
+        pub spills: u32,
     }
 
-    pub fn get_sp_offset(size: u32) -> u32 {
-        16 * (size / 16 + (if size % 16 == 0 { 0 } else { 1 }))
+    /// place parameters then local variables from the top of the frame
+    pub fn layout_frame(func: &FuncDecl) -> GenResult<Frame> {
+        let mut locals = Locals(Vec::new());
+        locals.visit_cmp_stmt(&func.cmp_stmt);
+        // array parameters are passed as pointers
+        let vars = func
+            .params
+            .iter()
+            .map(|p| {
+                let ty = p.data_type.decay();
+                Ok((p.id, ty.get_align()?, ty))
+            })
+            .chain(locals.0.iter().map(|v| {
+                let ty = local_type(v)?;
+                Ok((v.id, local_align(v, &ty)?, ty))
+            }))
+            .collect::<GenResult<Vec<_>>>()?;
+
+        // depth of each variable below the top of the frame, aligned to its type
+        let mut depths = Vec::with_capacity(vars.len());
+        let mut depth = 0;
+        let mut spills = 0;
+        for (i, (id, align, ty)) in vars.iter().enumerate() {
+            let align = *align;
+            depth += ty.get_size()?;
+            depth = depth.div_ceil(align) * align;
+            depths.push((*id, depth));
+            if i + 1 == func.params.len() {
+                spills = depth;
+            }
+        }
+        let size = get_sp_offset(depth);
+        let mut offsets = SideTable::new();
+        for (id, depth) in depths {
+            offsets.insert(id, size - depth);
+        }
+        Ok(Frame {
+            size,
+            offsets,
+            spills,
+        })
     }
-}
 
-/// register
-#[derive(Debug, PartialEq, PartialOrd, Clone, Copy)]
-enum Reg {
-    X0,
-    X1,
-    X2,
-    X3,
-    X4,
-    X5,
-    X6,
-    X7,
-    X9,
-    X10,
-    X11,
-    X12,
-    X13,
-    X14,
-    X15,
-    X29,
-    X30,
-}
+    /// declared type, the length of an array may be given by the initializer
+    fn local_type(decl: &VarDecl) -> GenResult<Type> {
+        match (&decl.data_type, &decl.init) {
+            (Type::Array(_, None), Some(e)) => Ok(flatten(&decl.data_type, e)?.ty),
+            _ => Ok(decl.data_type.clone()),
+        }
+    }
+
+    /// alignment of the type or the larger one requested by `aligned(n)`, at most the alignment
+    /// of the stack since sp is not realigned
+    fn local_align(decl: &VarDecl, ty: &Type) -> GenResult<u32> {
+        let align = ty.get_align()?;
+        match requested_align(&decl.attrs)? {
+            Some(n) if n > MAX_ALIGN => Err(CompileError::at_span(
+                format!(
+                    "alignment of local variable '{}' is larger than the stack alignment {}",
+                    decl.name, MAX_ALIGN
+                ),
+                decl.span,
+            )),
+            Some(n) => Ok(align.max(n)),
+            None => Ok(align),
+        }
+    }
+
+    /// collect local variables of the function body
+    struct Locals<'a>(Vec<&'a VarDecl>);
+
+    impl<'a> Visitor<'a> for Locals<'a> {
+        fn visit_var_decl(&mut self, decl: &'a VarDecl) {
+            self.0.push(decl);
+        }
+    }
 
-impl Display for Reg {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let name = format!("{:?}", self);
-        write!(f, "{}", name.to_lowercase())
+    pub fn get_sp_offset(size: u32) -> u32 {
+        16 * (size / 16 + (if size.is_multiple_of(16) { 0 } else { 1 }))
     }
 }
 
 #[cfg(test)]
 mod test {
-    use crate::{parse, scan, util::TargetOs};
+    use crate::{
+        ast::ExtDecl,
+        common::Span,
+        golden, parse, scan,
+        util::{Arch, AsmDialect, TargetOs},
+    };
     use test_case::test_case;
 
-    use super::{gen_asm, gen_util};
+    use super::{
+        extension, gen_asm, gen_util, is_bitmask_imm, mov_sequence, truncate, write_asm,
+        write_asm_coverage, Arm64Backend, ArmGen, Coverage, DataSections, DataType, GenOpts,
+        OptLevel, Reg, RelocModel, SpAccess, TargetBackend, Type,
+    };
 
     #[test]
     fn expect_header_linux() {
-        let v = gen_asm(&parse(scan("int main(){return 1;}")), &TargetOs::Linux);
-        vec![
+        let v = gen_asm(
+            &parse(scan("int main(){return 1;}").unwrap()).unwrap(),
+            &GenOpts::new(TargetOs::Linux),
+        )
+        .unwrap();
+        [
             ".text",
             ".global main",
             "main:",
@@ -283,10 +1966,34 @@ mod test {
         });
     }
 
+    #[test_case("int f(int a, int b, int c, int d, int e, int f, int g, int h, int i) { return 0; }" => "tncc does not yet support more than 8 parameters in 'f'")]
+    #[test_case("int f(); int main() { return f(1, 2, 3, 4, 5, 6, 7, 8, 9); }" => "tncc does not yet support more than 8 arguments in the call to 'f'")]
+    fn too_many_args(src: &str) -> String {
+        gen_asm(
+            &parse(scan(src).unwrap()).unwrap(),
+            &GenOpts::new(TargetOs::Linux),
+        )
+        .unwrap_err()
+        .to_string()
+    }
+
+    #[test]
+    fn eight_args() {
+        let src = "int f(int a, int b, int c, int d, int e, int f, int g, int h) { return 0; }
+                   int main() { return f(1, 2, 3, 4, 5, 6, 7, 8); }";
+        let v = gen_asm(
+            &parse(scan(src).unwrap()).unwrap(),
+            &GenOpts::new(TargetOs::Linux),
+        )
+        .unwrap();
+        assert!(v.contains("str w7, [sp, #0]"), "{}", v);
+        assert!(v.contains("mov x7, #8"), "{}", v);
+    }
+
     // single function -> emit directives
     #[test_case("int main(){return 1;}", vec![
         ".text",
-        ".global _main",
+        ".globl _main",
         ".p2align 2",
         "_main:",
         "mov x0, #1",
@@ -295,8 +2002,8 @@ mod test {
     // function with arguments
     #[test_case("int foo(int x, int y) {}", vec![
         "sub sp, sp, #16",
-        "str x0, [sp, #12]",
-        "str x1, [sp, #8]",
+        "str w0, [sp, #12]",
+        "str w1, [sp, #8]",
         "add sp, sp, #16",
         "ret",
     ])]
@@ -309,8 +2016,69 @@ mod test {
         "bl _foo",
         "ldp x29, x30, [sp], #16"
     ])]
+    // global array passed by the address of its page
+    #[test_case("int a[2]; int f(int *p); int main() { return f(a); }", vec![
+        "adrp x0, _a@PAGE",
+        "add x0, x0, _a@PAGEOFF",
+        ".p2align 2",
+        ".zero 8",
+    ])]
+    // static assertions generate no code
+    #[test_case("_Static_assert(1, \"x\"); int main() { _Static_assert(1); return 2; }", vec![
+        "mov x0, #2",
+    ])]
     fn test_function_with_args(src: &str, vec: Vec<&str>) {
-        let v = gen_asm(&parse(scan(src)), &TargetOs::MacOs);
+        let v = gen_asm(
+            &parse(scan(src).unwrap()).unwrap(),
+            &GenOpts::new(TargetOs::MacOs),
+        )
+        .unwrap();
+        vec.iter().for_each(|i| {
+            if !v.contains(i) {
+                panic!("'{}' is not generated", i)
+            }
+        });
+    }
+
+    // full output compared with tests/golden/<name>.expected.s
+    #[test_case("main", TargetOs::Linux)]
+    #[test_case("function_call", TargetOs::MacOs)]
+    #[test_case("call_args", TargetOs::Linux)]
+    #[test_case("main_implicit_return", TargetOs::Linux)]
+    #[test_case("early_return", TargetOs::MacOs)]
+    #[test_case("discard_value", TargetOs::Linux)]
+    #[test_case("globals", TargetOs::MacOs)]
+    #[test_case("array_decay", TargetOs::Linux)]
+    #[test_case("hello", TargetOs::Linux)]
+    #[test_case("hello_macos", TargetOs::MacOs)]
+    #[test_case("designated_init", TargetOs::Linux)]
+    #[test_case("mixed_globals", TargetOs::Linux)]
+    fn golden_asm(name: &str, target: TargetOs) {
+        let src = golden::read_source(name);
+        let v = gen_asm(&parse(scan(&src).unwrap()).unwrap(), &GenOpts::new(target)).unwrap();
+        golden::assert_golden(name, &v);
+    }
+
+    #[test_case(300, 1 => 44)]
+    #[test_case(-1, 4 => -1)]
+    #[test_case(2147483648, 4 => -2147483648)]
+    #[test_case(i64::MIN, 8 => i64::MIN)]
+    fn test_truncate(value: i64, size: u32) -> i64 {
+        truncate(value, size)
+    }
+
+    // char parameters are stored as bytes
+    #[test_case("int f(char c, int n, char *s) {}", vec![
+        "strb w0, [sp, #15]",
+        "str w1, [sp, #8]",
+        "str x2, [sp, #0]",
+    ])]
+    fn test_store_params(src: &str, vec: Vec<&str>) {
+        let v = gen_asm(
+            &parse(scan(src).unwrap()).unwrap(),
+            &GenOpts::new(TargetOs::Linux),
+        )
+        .unwrap();
         vec.iter().for_each(|i| {
             if !v.contains(i) {
                 panic!("'{}' is not generated", i)
@@ -318,6 +2086,726 @@ mod test {
         });
     }
 
+    // discarded reads of volatile objects are performed
+    #[test_case("volatile int g; int main() { volatile char c; g; -c; return 0; }", TargetOs::Linux, vec![
+        "adrp x9, g",
+        "ldr w9, [x9, :lo12:g]",
+        "ldrb w9, [sp, #15]",
+    ])]
+    #[test_case("int main(void) { int *volatile p; p; }", TargetOs::MacOs, vec![
+        "ldr x9, [sp, #8]",
+    ])]
+    #[test_case("volatile int g; int f(int); int main() { f(1) + g; }", TargetOs::MacOs, vec![
+        "adrp x9, _g@PAGE",
+        "ldr w9, [x9, _g@PAGEOFF]",
+    ])]
+    fn test_volatile_reads(src: &str, target: TargetOs, vec: Vec<&str>) {
+        let v = gen_asm(&parse(scan(src).unwrap()).unwrap(), &GenOpts::new(target)).unwrap();
+        vec.iter().for_each(|i| {
+            if !v.contains(i) {
+                panic!("'{}' is not generated", i)
+            }
+        });
+    }
+
+    // params then locals placed from the top of the frame
+    #[test_case("int f(int x, int y) { int a; { int b; } }", 16, vec![12, 8, 4, 0])]
+    #[test_case("int f(int a, int b, int c, int d, int e) {}", 32, vec![28, 24, 20, 16, 12])]
+    #[test_case("int f() {}", 0, vec![])]
+    // array parameters are pointers, aligned to 8 bytes
+    #[test_case("int f(int n, int a[]) { int b[3]; }", 32, vec![28, 16, 4])]
+    // the length of the array is given by the initializer
+    #[test_case("int f() { int a[] = {1, [2] = 3}; char c = 1; }", 16, vec![4, 3])]
+    // requested alignment of locals
+    #[test_case("int f(char x) { char c __attribute__((aligned(8))); }", 16, vec![15, 8])]
+    fn test_layout_frame(src: &str, size: u32, offsets: Vec<u32>) {
+        let ast = parse(scan(src).unwrap()).unwrap();
+        let func = match &ast.0[0] {
+            ExtDecl::Func(f) => f,
+            _ => unreachable!(),
+        };
+        let frame = gen_util::layout_frame(func).unwrap();
+        assert_eq!(frame.size, size);
+        let mut v: Vec<u32> = frame.offsets.iter().map(|(_, o)| *o).collect();
+        v.sort_unstable_by(|a, b| b.cmp(a));
+        assert_eq!(v, offsets);
+    }
+
+    #[test_case(0 => Some(vec!["mov x9, #0".to_string()]))]
+    #[test_case(65536 => Some(vec!["mov x9, #65536".to_string()]); "movz")]
+    #[test_case(-5 => Some(vec!["mov x9, #-5".to_string()]); "movn")]
+    #[test_case(0x5555_5555_5555_5555 => Some(vec!["mov x9, #6148914691236517205".to_string()]); "bitmask")]
+    #[test_case(0x1234_5678 => Some(vec!["movz x9, #22136, lsl #0".to_string(), "movk x9, #4660, lsl #16".to_string()]))]
+    #[test_case(-100000 => Some(vec!["movn x9, #34463, lsl #0".to_string(), "movk x9, #65534, lsl #16".to_string()]); "negative")]
+    #[test_case(0x0001_0000_0000_0002 => Some(vec!["movz x9, #2, lsl #0".to_string(), "movk x9, #1, lsl #48".to_string()]))]
+    #[test_case(0x0123_4567_89ab_cdef => None; "literal pool")]
+    fn test_mov_sequence(value: i64) -> Option<Vec<String>> {
+        mov_sequence(Reg::X9, value)
+    }
+
+    #[test_case(0x5555_5555_5555_5555 => true)]
+    #[test_case(0x00ff_00ff_00ff_00ff => true)]
+    #[test_case(0xf000_0000_0000_000f => true; "rotated")]
+    #[test_case(0x0000_0000_ffff_0000 => true)]
+    #[test_case(0x0000_0000_5555_5555 => false)]
+    #[test_case(0 => false)]
+    #[test_case(u64::MAX => false)]
+    #[test_case(0x1234 => false)]
+    fn test_bitmask_imm(value: u64) -> bool {
+        is_bitmask_imm(value)
+    }
+
+    // constants of more than two moves are loaded from a pool after the function, once each
+    #[test]
+    fn literal_pool() {
+        let src = "void h(int); int main() { h(81985529216486895); h(81985529216486895); }";
+        let v = gen_asm(
+            &parse(scan(src).unwrap()).unwrap(),
+            &GenOpts::new(TargetOs::Linux),
+        )
+        .unwrap();
+        assert_eq!(v.matches("ldr x0, .Llit0").count(), 2);
+        assert!(v.contains("\tret\n\t.p2align 3\n.Llit0:\n\t.xword 81985529216486895\n"));
+        let v = gen_asm(
+            &parse(scan(src).unwrap()).unwrap(),
+            &GenOpts::new(TargetOs::MacOs),
+        )
+        .unwrap();
+        assert!(v.contains("ldr x0, Llit0\n"));
+    }
+
+    // the pool is branched over between the statements before the loads are out of range
+    #[test]
+    fn literal_pool_in_range() {
+        let src = "void h(int); int main() { h(81985529216486895); h(1); h(81985529216486896); }";
+        let ast = parse(scan(src).unwrap()).unwrap();
+        let opts = GenOpts::new(TargetOs::Linux);
+        let backend = Arm64Backend::new(opts.clone());
+        let mut out = Vec::new();
+        let mut g = ArmGen::new(&ast, &opts, backend.desc(), &mut out);
+        g.pool_distance = 16;
+        g.gen().unwrap();
+        let v = String::from_utf8(out).unwrap();
+        let pools: Vec<&str> = v
+            .lines()
+            .filter(|l| l.starts_with(".L") || l.starts_with("\tb ") || l.contains(".xword"))
+            .collect();
+        assert_eq!(
+            pools,
+            [
+                "\tb .Lpool1",
+                ".Llit0:",
+                "\t.xword 81985529216486895",
+                ".Lpool1:",
+                ".Llit1:",
+                "\t.xword 81985529216486896",
+            ]
+        );
+    }
+
+    // the registers are those of the description of the target
+    #[test]
+    fn registers_of_desc() {
+        let src = "void h(int, int); void f(void) { int i = 1; h(2, 3); }";
+        let ast = parse(scan(src).unwrap()).unwrap();
+        let opts = GenOpts::new(TargetOs::Linux);
+        let mut desc = Arm64Backend::new(opts.clone()).desc().clone();
+        desc.call_conv.arg_regs = vec![Reg::X1, Reg::X0];
+        let temps = desc.reg_classes.iter_mut().find(|c| c.name == "temporary");
+        temps.unwrap().regs = vec![Reg::X10];
+        let mut out = Vec::new();
+        ArmGen::new(&ast, &opts, &desc, &mut out).gen().unwrap();
+        let v = String::from_utf8(out).unwrap();
+        assert!(v.contains("\tmov x10, #1\n\tstr w10, [sp, #12]\n"), "{}", v);
+        assert!(v.contains("\tmov x1, #2\n\tmov x0, #3\n\tbl h\n"), "{}", v);
+    }
+
+    // padding before each object, the sections are laid out separately
+    #[test]
+    fn place_objects() {
+        let mut s = DataSections {
+            current: ".data".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(s.place(1, 1), 0);
+        assert_eq!(s.place(4, 4), 3);
+        assert_eq!(s.place(8, 8), 0);
+        assert_eq!(s.place(1, 3), 0);
+        s.current = ".bss".to_string();
+        assert_eq!(s.place(4, 4), 0);
+        s.current = ".data".to_string();
+        assert_eq!(s.place(8, 8), 5);
+        assert_eq!(s.ends[".data"], 32);
+    }
+
+    // char call results are extended where they are used as int, plain char is signed on Darwin
+    #[test_case(TargetOs::Linux, "char c(void); int f(void) { return c(); }" => vec!["bl c", "uxtb w0, w0"]; "return int")]
+    #[test_case(TargetOs::MacOs, "char c(void); int f(void) { return c(); }" => vec!["bl _c", "sxtb w0, w0"]; "return int darwin")]
+    #[test_case(TargetOs::Linux, "char c(void); char f(void) { return c(); }" => vec!["bl c"]; "return char")]
+    #[test_case(TargetOs::Linux, "char c(void); void f(void) { int i = c(); }" => vec!["bl c", "mov x9, x0", "uxtb w9, w9", "str w9, [sp, #12]"]; "init")]
+    #[test_case(TargetOs::Linux, "char c(void); void f(void) { char d = c(); }" => vec!["bl c", "mov x9, x0", "strb w9, [sp, #15]"]; "init char")]
+    #[test_case(TargetOs::Linux, "char c(void); void h(int, char); void f(void) { h(c(), c()); }" => vec!["bl c", "uxtb w0, w0", "str x0, [sp, #-16]!", "bl c", "mov x1, x0", "uxtb w1, w1", "ldr x0, [sp], #16", "bl h"]; "args")]
+    #[test_case(TargetOs::Linux, "int c(void); void h(int, int); void f(void) { h(1, c()); }" => vec!["bl c", "mov x1, x0", "mov x0, #1", "bl h"]; "args after call")]
+    #[test_case(TargetOs::Linux, "char c(void); void f(void) { g(c()); }" => vec!["bl c", "uxtb w0, w0", "bl g"]; "promoted without prototype")]
+    #[test_case(TargetOs::Linux, "int i(void); void h(int); void f(void) { h(i()); }" => vec!["bl i", "bl h"]; "int")]
+    fn extend_call_results(target: TargetOs, src: &str) -> Vec<String> {
+        let v = gen_asm(&parse(scan(src).unwrap()).unwrap(), &GenOpts::new(target)).unwrap();
+        v.lines()
+            .skip_while(|l| !l.ends_with("f:"))
+            .map(|l| l.trim())
+            // the calls, the moves and stores of their results and the arguments kept meanwhile
+            .filter(|l| {
+                [
+                    "bl ",
+                    "sxt",
+                    "uxt",
+                    "mov x1,",
+                    "mov x9,",
+                    "mov x0, #",
+                    "str",
+                    "ldr x0,",
+                ]
+                .iter()
+                .any(|p| l.starts_with(p))
+            })
+            .map(String::from)
+            .collect()
+    }
+
+    #[test_case(Type::Base(DataType::Char), 4, false => Some("uxtb"))]
+    #[test_case(Type::Base(DataType::Char), 4, true => Some("sxtb"))]
+    #[test_case(Type::Base(DataType::Char), 8, true => Some("sxtb"))]
+    #[test_case(Type::Base(DataType::Char), 1, false => None)]
+    #[test_case(Type::Base(DataType::Int), 4, false => None)]
+    #[test_case(Type::Base(DataType::Int), 8, false => Some("sxtw"))]
+    #[test_case(Type::Pointer(Box::new(Type::Base(DataType::Char))), 8, true => None)]
+    #[test_case(Type::Base(DataType::Void), 4, true => None)]
+    fn test_extension(ty: Type, size: u32, char_signed: bool) -> Option<&'static str> {
+        extension(&ty, size, char_signed).unwrap()
+    }
+
+    // adjacent words or double words of the frame are paired above -O0
+    #[test_case("int f(int a, int b, char *p, char *q) {}", OptLevel::O1 => vec![
+        "sub sp, sp, #32", "stp w1, w0, [sp, #24]", "stp x3, x2, [sp, #8]", "add sp, sp, #32",
+    ]; "params")]
+    #[test_case("int f(int a, int b, int c) {}", OptLevel::O2 => vec![
+        "sub sp, sp, #16", "stp w1, w0, [sp, #8]", "str w2, [sp, #4]", "add sp, sp, #16",
+    ]; "odd params")]
+    #[test_case("int f(char c, int a) {}", OptLevel::Os => vec![
+        "sub sp, sp, #16", "strb w0, [sp, #15]", "str w1, [sp, #8]", "add sp, sp, #16",
+    ]; "different sizes")]
+    #[test_case("int f() { int a[3] = {1}; }", OptLevel::O1 => vec![
+        "sub sp, sp, #16", "mov x9, #1", "stp w9, wzr, [sp, #4]", "str wzr, [sp, #12]",
+        "add sp, sp, #16",
+    ]; "zeroed elements")]
+    #[test_case("int f(int a, int b) {}", OptLevel::O0 => vec![
+        "sub sp, sp, #16", "str w0, [sp, #12]", "str w1, [sp, #8]", "add sp, sp, #16",
+    ]; "no pairs at O0")]
+    fn pair_frame_accesses(src: &str, opt_level: OptLevel) -> Vec<String> {
+        let opts = GenOpts {
+            opt_level,
+            ..GenOpts::new(TargetOs::Linux)
+        };
+        let v = gen_asm(&parse(scan(src).unwrap()).unwrap(), &opts).unwrap();
+        v.lines()
+            .skip_while(|l| *l != "f:")
+            .skip(1)
+            .take_while(|l| *l != "\tret")
+            .map(|l| l.trim().to_string())
+            .collect()
+    }
+
+    #[test_case(SpAccess::store(Reg::X0, 8, 16), SpAccess::store(Reg::X1, 8, 8) => Some("stp x1, x0, [sp, #8]".to_string()))]
+    #[test_case(SpAccess::load(Reg::X9, 4, 0), SpAccess::load(Reg::X10, 4, 4) => Some("ldp w9, w10, [sp, #0]".to_string()))]
+    #[test_case(SpAccess::load(Reg::X9, 8, 0), SpAccess::load(Reg::X9, 8, 8) => None; "same destination")]
+    #[test_case(SpAccess::load(Reg::X9, 8, 0), SpAccess::store(Reg::X10, 8, 8) => None; "load and store")]
+    #[test_case(SpAccess::store(Reg::X0, 8, 0), SpAccess::store(Reg::X1, 8, 16) => None; "gap")]
+    #[test_case(SpAccess::store(Reg::X0, 8, 4), SpAccess::store(Reg::X1, 8, 12) => None; "misaligned")]
+    #[test_case(SpAccess::store(Reg::X0, 8, 504), SpAccess::store(Reg::X1, 8, 512) => Some("stp x0, x1, [sp, #504]".to_string()))]
+    #[test_case(SpAccess::store(Reg::X0, 8, 512), SpAccess::store(Reg::X1, 8, 520) => None; "out of range")]
+    #[test_case(SpAccess::store(Reg::X0, 2, 0), SpAccess::store(Reg::X1, 2, 2) => None; "half words")]
+    fn pair_access(a: SpAccess, b: SpAccess) -> Option<String> {
+        a.pair(&b)
+    }
+
+    // frame size, spilled argument registers and the pushes of nested calls
+    #[test_case(false => vec![
+        "a.c:1:1:g\t32\tstatic\tframe=32\tspills=16\toutgoing=0",
+        "a.c:2:1:main\t32\tstatic\tframe=0\tspills=0\toutgoing=32",
+    ])]
+    // the profiling hooks push 32 bytes
+    #[test_case(true => vec![
+        "a.c:1:1:g\t64\tstatic\tframe=32\tspills=16\toutgoing=32",
+        "a.c:2:1:main\t32\tstatic\tframe=0\tspills=0\toutgoing=32",
+    ])]
+    fn stack_usage(instrument: bool) -> Vec<String> {
+        let src = "int g(int a, char *s) { int b; return 0; }\n\
+                   int main() { g(1, \"x\"); return g(g(1, \"a\"), \"b\"); }";
+        let opts = GenOpts {
+            instrument_functions: instrument,
+            ..GenOpts::new(TargetOs::Linux)
+        };
+        let usage = write_asm(&parse(scan(src).unwrap()).unwrap(), &opts, std::io::sink());
+        usage
+            .unwrap()
+            .iter()
+            .map(|u| u.report("a.c", src, 8))
+            .collect()
+    }
+
+    // directives of the sections, Mach-O splits the shared sections at the symbols instead
+    #[test_case(TargetOs::Linux, true, false => vec![
+        ".text",
+        ".section .text.f,\"ax\",@progbits",
+        ".section .text.main,\"ax\",@progbits",
+        ".data",
+        ".bss",
+    ]; "function sections")]
+    #[test_case(TargetOs::Linux, false, true => vec![
+        ".text",
+        ".section .data.a,\"aw\",@progbits",
+        ".section .data.b,\"aw\",@progbits",
+        ".section .bss.c,\"aw\",@nobits",
+    ]; "data sections")]
+    #[test_case(TargetOs::MacOs, true, true => vec![".text", ".data", ".bss", ".subsections_via_symbols"]; "macos")]
+    fn symbol_sections(
+        target: TargetOs,
+        function_sections: bool,
+        data_sections: bool,
+    ) -> Vec<String> {
+        let src = "int a = 1; int b = 2; int c; void f(void) {} int main() { return 0; }";
+        let opts = GenOpts {
+            function_sections,
+            data_sections,
+            ..GenOpts::new(target)
+        };
+        let asm = gen_asm(&parse(scan(src).unwrap()).unwrap(), &opts).unwrap();
+        asm.lines()
+            .map(str::trim)
+            .filter(|l| {
+                l.starts_with(".section")
+                    || [".text", ".data", ".bss", ".subsections_via_symbols"].contains(l)
+            })
+            .map(String::from)
+            .collect()
+    }
+
+    // weak definitions and references, aliases are set to their target after the definitions
+    #[test_case(TargetOs::Linux => vec![
+        ".weak probe",
+        "probe:",
+        "impl:",
+        ".global main",
+        "main:",
+        "bl hook",
+        "bl api",
+        "bl probe",
+        ".global counter",
+        "counter:",
+        ".global count",
+        ".set count, counter",
+        ".weak api",
+        ".set api, impl",
+        ".weak hook",
+    ])]
+    #[test_case(TargetOs::MacOs => vec![
+        ".globl _probe",
+        ".weak_definition _probe",
+        "_probe:",
+        "_impl:",
+        ".globl _main",
+        "_main:",
+        "bl _hook",
+        "bl _api",
+        "bl _probe",
+        ".globl _counter",
+        "_counter:",
+        ".globl _count",
+        ".set _count, _counter",
+        ".globl _api",
+        ".weak_definition _api",
+        ".set _api, _impl",
+        ".weak_reference _hook",
+    ])]
+    fn weak_and_alias(target: TargetOs) -> Vec<String> {
+        let src = "int counter = 3;
+                   extern int count __attribute__((alias(\"counter\")));
+                   int hook(void) __attribute__((weak));
+                   int probe(int x) __attribute__((weak));
+                   int probe(int x) { return 0; }
+                   static int impl(void) { return 1; }
+                   int api(void) __attribute__((weak, alias(\"impl\")));
+                   int main() { hook(); return probe(api()); }";
+        let asm = gen_asm(&parse(scan(src).unwrap()).unwrap(), &GenOpts::new(target)).unwrap();
+        asm.lines()
+            .map(str::trim)
+            .filter(|l| {
+                [".glob", ".weak", ".set", "bl "]
+                    .iter()
+                    .any(|p| l.starts_with(p))
+                    || l.ends_with(':') && !l.starts_with('.') && !l.starts_with('L')
+            })
+            .map(String::from)
+            .collect()
+    }
+
+    // exclusive loops on ARMv8.0, single instructions with LSE, sub-word results are extended
+    #[test_case(Arch::Armv8 => vec![
+        ".Latomic0:",
+        "ldaxr w9, [x0]",
+        "add w10, w9, w1",
+        "stlxr w11, w10, [x0]",
+        "cbnz w11, .Latomic0",
+        "mov w0, w9",
+        "dmb ish",
+        "add x0, sp, #11",
+        "ldarb w9, [x0]",
+        "mov w0, w9",
+        "uxtb w0, w0",
+    ])]
+    #[test_case(Arch::Armv8Lse => vec![
+        "ldaddal w1, w9, [x0]",
+        "mov w0, w9",
+        "dmb ish",
+        "add x0, sp, #11",
+        "ldarb w9, [x0]",
+        "mov w0, w9",
+        "uxtb w0, w0",
+    ])]
+    fn atomic_builtins(arch: Arch) -> Vec<String> {
+        let src = "int main() {
+                       int c[1];
+                       char f[1];
+                       __sync_fetch_and_add(c, 1);
+                       __sync_synchronize();
+                       return __atomic_load_n(f, __ATOMIC_ACQUIRE);
+                   }";
+        let opts = GenOpts {
+            arch,
+            ..GenOpts::new(TargetOs::Linux)
+        };
+        let asm = gen_asm(&parse(scan(src).unwrap()).unwrap(), &opts).unwrap();
+        asm.lines()
+            .map(str::trim)
+            .skip_while(|l| !l.starts_with("mov x1"))
+            .skip(1)
+            .take_while(|l| !l.starts_with("add sp"))
+            .map(String::from)
+            .collect()
+    }
+
+    // a trap ends the function without epilogue, the hint is the extended value
+    #[test]
+    fn builtins() {
+        let src = "char g(void); void fail(void) { __builtin_trap(); }
+                   int main() { return __builtin_expect(g(), 0); }";
+        let asm = gen_asm(
+            &parse(scan(src).unwrap()).unwrap(),
+            &GenOpts::new(TargetOs::Linux),
+        )
+        .unwrap();
+        assert!(asm.contains("fail:\n\tbrk #1000\n\n"), "{}", asm);
+        assert!(
+            asm.contains("\tbl g\n\tldp x29, x30, [sp], #16\n\tuxtb w0, w0\n"),
+            "{}",
+            asm
+        );
+    }
+
+    // the construct is named at its location, not dumped
+    #[test_case("int main() { int a; return a; }" => ("tncc does not yet support reading the value of 'a'".to_string(), Some(Span::new(27, 28))))]
+    #[test_case("int main() { return -1; }" => ("tncc does not yet support the value of unary '-'".to_string(), Some(Span::new(20, 22))))]
+    #[test_case("int main() { return 1 + 2; }" => ("tncc does not yet support the value of '+'".to_string(), Some(Span::new(20, 25))))]
+    fn not_supported(src: &str) -> (String, Option<Span>) {
+        let ast = parse(scan(src).unwrap()).unwrap();
+        let e = gen_asm(&ast, &GenOpts::new(TargetOs::Linux)).unwrap_err();
+        (e.msg, e.span)
+    }
+
+    #[test_case("int main() { char c __attribute__((aligned(32))); }" => "alignment of local variable 'c' is larger than the stack alignment 16")]
+    fn invalid_frame(src: &str) -> String {
+        let ast = parse(scan(src).unwrap()).unwrap();
+        let func = match &ast.0[0] {
+            ExtDecl::Func(f) => f,
+            _ => unreachable!(),
+        };
+        gen_util::layout_frame(func).err().unwrap().msg
+    }
+
+    // noreturn functions have no epilogue, aligned and used apply to prototypes and definitions
+    #[test_case("void g(int);\nvoid f(void) __attribute__((noreturn)) { int a; g(1); }", TargetOs::Linux => "\
+\t.global f
+\t.p2align 2
+f:
+\tsub sp, sp, #16
+\tstp x29, x30, [sp, #-16]!
+\tmov x29, sp
+\tmov x0, #1
+\tbl g
+\tldp x29, x30, [sp], #16
+")]
+    #[test_case("__attribute__((used, aligned(16))) int f(void);\nint f(void) { return 1; }", TargetOs::MacOs => "\
+\t.globl _f
+\t.no_dead_strip _f
+\t.p2align 4
+_f:
+\tmov x0, #1
+\tret
+")]
+    #[test_case("static int f(void);\nint f(void) { return 1; }", TargetOs::Linux => "\
+\t.p2align 2
+f:
+\tmov x0, #1
+\tret
+")]
+    #[test_case("char c __attribute__((aligned(8), used)) = 1;", TargetOs::Linux => "\
+\t.global c
+\t.p2align 3
+c:
+\t.byte 1
+")]
+    fn test_attributes(src: &str, target: TargetOs) -> String {
+        let v = gen_asm(&parse(scan(src).unwrap()).unwrap(), &GenOpts::new(target)).unwrap();
+        v.lines()
+            .skip_while(|l| !l.contains(".glob") && !l.contains(".p2align"))
+            .take_while(|l| !l.is_empty())
+            .map(|l| format!("{}\n", l))
+            .collect()
+    }
+
+    // extern data is addressed through the GOT, always on macOS, unless it is defined here
+    #[test_case(TargetOs::Linux, RelocModel::Pie => "\
+\tadrp x9, :got:flag
+\tldr x9, [x9, :got_lo12:flag]
+\tldr w9, [x9]
+\tadrp x0, :got:environ
+\tldr x0, [x0, :got_lo12:environ]
+\tadrp x0, a
+\tadd x0, x0, :lo12:a
+")]
+    #[test_case(TargetOs::Linux, RelocModel::Static => "\
+\tadrp x9, flag
+\tldr w9, [x9, :lo12:flag]
+\tadrp x0, environ
+\tadd x0, x0, :lo12:environ
+\tadrp x0, a
+\tadd x0, x0, :lo12:a
+")]
+    #[test_case(TargetOs::MacOs, RelocModel::Static => "\
+\tadrp x9, _flag@GOTPAGE
+\tldr x9, [x9, _flag@GOTPAGEOFF]
+\tldr w9, [x9]
+\tadrp x0, _environ@GOTPAGE
+\tldr x0, [x0, _environ@GOTPAGEOFF]
+\tadrp x0, _a@PAGE
+\tadd x0, x0, _a@PAGEOFF
+")]
+    #[test_case(TargetOs::Linux, RelocModel::Pic => "\
+\tadrp x9, :got:flag
+\tldr x9, [x9, :got_lo12:flag]
+\tldr w9, [x9]
+\tadrp x0, :got:environ
+\tldr x0, [x0, :got_lo12:environ]
+\tadrp x0, :got:a
+\tldr x0, [x0, :got_lo12:a]
+")]
+    fn test_got(target: TargetOs, reloc: RelocModel) -> String {
+        let src = "extern char *environ[]; extern volatile int flag; int a[2]; extern int a[];
+            char **e(void) { flag; return environ; } int *f(void) { return a; }";
+        got_access(src, target, reloc)
+    }
+
+    // static globals can not be interposed
+    #[test]
+    fn test_got_static() {
+        let src = "static int s[2]; int *f(void) { return s; }";
+        let v = got_access(src, TargetOs::Linux, RelocModel::Pic);
+        assert_eq!(v, "\tadrp x0, s\n\tadd x0, x0, :lo12:s\n");
+    }
+
+    // the dialect spells the directives, the target names the symbols
+    #[test_case(TargetOs::Linux, AsmDialect::Darwin => vec![
+        "\t.globl f", "\t.section __TEXT,__cstring,cstring_literals", "\t.comm c, 8, 2",
+    ])]
+    #[test_case(TargetOs::MacOs, AsmDialect::Gnu => vec![
+        "\t.global _f", "\t.section .rodata", "\t.comm _c, 8, 4",
+    ])]
+    fn test_dialect(target: TargetOs, dialect: AsmDialect) -> Vec<String> {
+        let src = "int c[2]; char *f(void) { return \"s\"; }";
+        let opts = GenOpts {
+            common: true,
+            dialect: Some(dialect),
+            ..GenOpts::new(target)
+        };
+        let v = gen_asm(&parse(scan(src).unwrap()).unwrap(), &opts).unwrap();
+        v.lines()
+            .filter(|l| {
+                l.starts_with("\t.glob") || l.starts_with("\t.section") || l.starts_with("\t.comm")
+            })
+            .map(String::from)
+            .collect()
+    }
+
+    // the source name is escaped like string literals
+    #[test_case(None => "\t.text\n")]
+    #[test_case(Some("a.c") => "\t.file \"a.c\"\n\t.text\n")]
+    #[test_case(Some("dir \"x\"\\b.c") => "\t.file \"dir \\\"x\\\"\\\\b.c\"\n\t.text\n"; "escaped")]
+    fn test_file(file: Option<&str>) -> String {
+        let opts = GenOpts {
+            file: file.map(String::from),
+            ..GenOpts::new(TargetOs::Linux)
+        };
+        let v = gen_asm(&parse(scan("int main() {}").unwrap()).unwrap(), &opts).unwrap();
+        v.lines()
+            .take_while(|l| *l != "\t.text")
+            .chain(["\t.text"])
+            .map(|l| format!("{}\n", l))
+            .collect()
+    }
+
+    // tentative definitions merge into the initialized definition or the first of them
+    #[test_case(TargetOs::Linux, false => "\
+\t.data
+\t.global a
+\t.p2align 2
+a:
+\t.word 3
+\t.bss
+\t.global b
+\t.p2align 2
+b:
+\t.zero 16
+\t.p2align 0
+s:
+\t.zero 1
+")]
+    #[test_case(TargetOs::Linux, true => "\
+\t.data
+\t.global a
+\t.p2align 2
+a:
+\t.word 3
+\t.comm b, 16, 4
+\t.bss
+\t.p2align 0
+s:
+\t.zero 1
+")]
+    #[test_case(TargetOs::MacOs, true => "\
+\t.data
+\t.globl _a
+\t.p2align 2
+_a:
+\t.word 3
+\t.comm _b, 16, 2
+\t.bss
+\t.p2align 0
+_s:
+\t.zero 1
+")]
+    fn test_tentative(target: TargetOs, common: bool) -> String {
+        let src = "int a; int b[4]; int a = 3; static char s; int b[4]; int a;";
+        let opts = GenOpts {
+            common,
+            ..GenOpts::new(target)
+        };
+        let v = gen_asm(&parse(scan(src).unwrap()).unwrap(), &opts).unwrap();
+        v.lines()
+            .skip_while(|l| *l != "\t.data")
+            .filter(|l| !l.is_empty())
+            .map(|l| format!("{}\n", l))
+            .collect()
+    }
+
+    fn got_access(src: &str, target: TargetOs, reloc: RelocModel) -> String {
+        let opts = GenOpts {
+            reloc,
+            ..GenOpts::new(target)
+        };
+        let v = gen_asm(&parse(scan(src).unwrap()).unwrap(), &opts).unwrap();
+        assert!(!v.contains("environ:") && !v.contains("flag:"), "{}", v);
+        assert!(!v.contains(".global s"), "{}", v);
+        v.lines()
+            .filter(|l| l.starts_with("\tadrp") || l.starts_with("\tldr") || l.starts_with("\tadd"))
+            .map(|l| format!("{}\n", l))
+            .collect()
+    }
+
+    // the hooks keep the parameters and the return value, the hooks themselves are excluded
+    #[test_case("int f(int a) { return 1; }", TargetOs::Linux => "\
+f:
+\tsub sp, sp, #16
+\tstr w0, [sp, #12]
+\tstp x29, x30, [sp, #-32]!
+\tmov x29, sp
+\tstr x0, [sp, #16]
+\tadrp x0, f
+\tadd x0, x0, :lo12:f
+\tmov x1, x30
+\tbl __cyg_profile_func_enter
+\tldr x0, [sp, #16]
+\tldp x29, x30, [sp], #32
+\tmov x0, #1
+\tstp x29, x30, [sp, #-32]!
+\tmov x29, sp
+\tstr x0, [sp, #16]
+\tadrp x0, f
+\tadd x0, x0, :lo12:f
+\tmov x1, x30
+\tbl __cyg_profile_func_exit
+\tldr x0, [sp, #16]
+\tldp x29, x30, [sp], #32
+\tadd sp, sp, #16
+\tret
+")]
+    #[test_case("void f(void) __attribute__((no_instrument_function)) {}", TargetOs::MacOs => "\
+_f:
+\tret
+")]
+    fn test_instrument_functions(src: &str, target: TargetOs) -> String {
+        let opts = GenOpts {
+            instrument_functions: true,
+            ..GenOpts::new(target)
+        };
+        let v = gen_asm(&parse(scan(src).unwrap()).unwrap(), &opts).unwrap();
+        v.lines()
+            .skip_while(|l| !l.ends_with("f:"))
+            .take_while(|l| !l.is_empty())
+            .map(|l| format!("{}\n", l))
+            .collect()
+    }
+
+    // a counter per line with code, statements on the same line share the counter
+    #[test]
+    fn test_coverage() {
+        let src = "int main(void) {\n  int a;\n  f(); f();\n  return 0;\n}";
+        let cov = Coverage::new(src, "m.c");
+        let mut out = Vec::new();
+        let opts = GenOpts::new(TargetOs::Linux);
+        write_asm_coverage(&parse(scan(src).unwrap()).unwrap(), &opts, &cov, &mut out).unwrap();
+        let v = String::from_utf8(out).unwrap();
+        assert_eq!(v.matches("add x11, x11, #1").count(), 3, "{}", v);
+        assert!(
+            v.contains("__tncc_cov_lines:\n\t.word 1\n\t.word 3\n\t.word 4\n"),
+            "{}",
+            v
+        );
+        assert!(v.contains(".asciz \"m.c.cov\""), "{}", v);
+        assert!(v.contains(".init_array,\"aw\"\n\t.p2align 3\n\t.quad __tncc_cov_init"));
+    }
+
+    // the string is pasted between the instructions of the function
+    #[test]
+    fn test_asm_stmt() {
+        let src = "void f(void) { asm volatile(\"dsb sy\\n\\tisb\"); __asm__(\"wfi\"); }";
+        let v = gen_asm(
+            &parse(scan(src).unwrap()).unwrap(),
+            &GenOpts::new(TargetOs::Linux),
+        )
+        .unwrap();
+        assert!(v.contains("f:\n\tdsb sy\n\tisb\n\twfi\n\tret\n"), "{}", v);
+    }
+
     #[test_case(10, 16)]
     #[test_case(16, 16)]
     #[test_case(20, 32)]
@@ -326,4 +2814,30 @@ mod test {
     fn test_get_sp_offset(size: u32, expected: u32) {
         assert_eq!(gen_util::get_sp_offset(size), expected);
     }
+
+    /// writer accepting a limited number of bytes
+    struct Full(usize);
+
+    impl std::io::Write for Full {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            if self.0 < buf.len() {
+                return Err(std::io::Error::other("disk full"));
+            }
+            self.0 -= buf.len();
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test_case(usize::MAX => None)]
+    #[test_case(16 => Some(String::from("failed to write assembly: disk full")))]
+    fn write_asm_stream(limit: usize) -> Option<String> {
+        let ast = parse(scan("int main(){return 1;}").unwrap()).unwrap();
+        write_asm(&ast, &GenOpts::new(TargetOs::Linux), Full(limit))
+            .err()
+            .map(|e| e.to_string())
+    }
 }
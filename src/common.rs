@@ -1,32 +1,136 @@
 use std::fmt::{self, Display};
 
+use serde::Serialize;
+
+use crate::intern::Symbol;
+
 /// Token for ANSI C grammar
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Token {
     /// token type with optional value (for id, number)
     pub tok: TokType,
     /// location (line,column) starting from 1
     pub loc: (u32, u32),
+    /// byte offsets in the source
+    pub span: Span,
+    /// whitespace and comments before the token, only kept with `Lexer::keep_trivia`
+    pub leading: Vec<Trivia>,
+}
+
+/// source text between tokens
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Trivia {
+    pub kind: TriviaKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum TriviaKind {
+    /// spaces, tabs and line breaks
+    Whitespace,
+    /// '// ...' until the end of line
+    LineComment,
+    /// '/* ... */'
+    BlockComment,
+}
+
+/// range of byte offsets in the source, end is exclusive
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub const fn new(start: usize, end: usize) -> Self {
+        Span { start, end }
+    }
+
+    /// span covering both spans
+    pub fn to(self, other: Span) -> Span {
+        Span::new(self.start.min(other.start), self.end.max(other.end))
+    }
+
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start >= self.end
+    }
 }
 
 /// Token type with attached value
-#[derive(Debug, PartialEq, PartialOrd)]
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
 pub enum TokType {
-    KeywordVoid,   // 'void'
-    KeywordInt,    // 'int'
-    KeywordReturn, // 'return'
-    ID(String),    // Identifier
-    NumInt(u64),   // 0, 1
-    NumReal(f64),  // 0.1, 1.1
-    ParentOpen,    // (
-    ParentClose,   // )
-    BracketOpen,   // {
-    BracketClose,  // }
-    Semicolon,     // ;
-    Minus,         // -
-    Plus,          // +
-    Assign,        // =
-    Comma,        // ,
+    KeywordVoid,         // 'void'
+    KeywordInt,          // 'int'
+    KeywordChar,         // 'char'
+    KeywordReturn,       // 'return'
+    KeywordStaticAssert, // '_Static_assert'
+    KeywordVolatile,     // 'volatile'
+    KeywordAttribute,    // '__attribute__'
+    KeywordAsm,          // 'asm'
+    KeywordExtern,       // 'extern'
+    KeywordStatic,       // 'static'
+    ID(Symbol),          // Identifier
+    NumInt(u64),         // 0, 1
+    NumReal(f64),        // 0.1, 1.1
+    Str(Vec<u8>),        // "abc", bytes after escapes without the terminating null
+    ParentOpen,          // (
+    ParentClose,         // )
+    BracketOpen,         // {
+    BracketClose,        // }
+    Semicolon,           // ;
+    Colon,               // :
+    Minus,               // -
+    Arrow,               // ->
+    Dot,                 // .
+    Plus,                // +
+    Assign,              // =
+    Comma,               // ,
+    Star,                // *
+    SquareOpen,          // [
+    SquareClose,         // ]
+    Unknown,             // invalid input, reported by the scanner
+}
+
+impl TokType {
+    /// name of the token type without value, i.e 'identifier'
+    pub fn kind(&self) -> &'static str {
+        match self {
+            TokType::KeywordVoid => "keyword_void",
+            TokType::KeywordInt => "keyword_int",
+            TokType::KeywordChar => "keyword_char",
+            TokType::KeywordReturn => "keyword_return",
+            TokType::KeywordStaticAssert => "keyword_static_assert",
+            TokType::KeywordVolatile => "keyword_volatile",
+            TokType::KeywordAttribute => "keyword_attribute",
+            TokType::KeywordAsm => "keyword_asm",
+            TokType::KeywordExtern => "keyword_extern",
+            TokType::KeywordStatic => "keyword_static",
+            TokType::ID(_) => "identifier",
+            TokType::NumInt(_) => "num_int",
+            TokType::NumReal(_) => "num_real",
+            TokType::Str(_) => "string",
+            TokType::ParentOpen => "paren_open",
+            TokType::ParentClose => "paren_close",
+            TokType::BracketOpen => "brace_open",
+            TokType::BracketClose => "brace_close",
+            TokType::Semicolon => "semicolon",
+            TokType::Colon => "colon",
+            TokType::Minus => "minus",
+            TokType::Arrow => "arrow",
+            TokType::Dot => "dot",
+            TokType::Plus => "plus",
+            TokType::Assign => "assign",
+            TokType::Comma => "comma",
+            TokType::Star => "star",
+            TokType::SquareOpen => "square_open",
+            TokType::SquareClose => "square_close",
+            TokType::Unknown => "unknown",
+        }
+    }
 }
 
 impl Display for Token {
@@ -40,17 +144,325 @@ impl Display for TokType {
         let s: &str = match self {
             TokType::KeywordVoid => "void",
             TokType::KeywordInt => "int",
+            TokType::KeywordChar => "char",
             TokType::KeywordReturn => "return",
+            TokType::KeywordStaticAssert => "_Static_assert",
+            TokType::KeywordVolatile => "volatile",
+            TokType::KeywordAttribute => "__attribute__",
+            TokType::KeywordAsm => "asm",
+            TokType::KeywordExtern => "extern",
+            TokType::KeywordStatic => "static",
             TokType::ParentOpen => "(",
             TokType::ParentClose => ")",
             TokType::BracketOpen => "{",
             TokType::BracketClose => "}",
             TokType::Assign => "=",
             TokType::Semicolon => ";",
+            TokType::Colon => ":",
             TokType::Comma => ",",
+            TokType::Minus => "-",
+            TokType::Arrow => "->",
+            TokType::Dot => ".",
+            TokType::Plus => "+",
+            TokType::Star => "*",
+            TokType::SquareOpen => "[",
+            TokType::SquareClose => "]",
+            TokType::Unknown => "invalid token",
             TokType::ID(id) => return write!(f, "identifier '{}'", id),
-            _ => return write!(f, "{:?}", self),
+            TokType::NumInt(v) => return write!(f, "number '{}'", v),
+            TokType::NumReal(v) => return write!(f, "number '{}'", v),
+            TokType::Str(v) => return write!(f, "string \"{}\"", escape(v)),
         };
         write!(f, "{}", s)
     }
 }
+
+/// bytes of a string literal in C syntax without quotes, also accepted by the assemblers
+///
+/// other than the common escapes, bytes out of printable ASCII are three octal digits
+pub fn escape(bytes: &[u8]) -> String {
+    let mut s = String::with_capacity(bytes.len());
+    for b in bytes {
+        match b {
+            b'\\' => s.push_str("\\\\"),
+            b'"' => s.push_str("\\\""),
+            b'\n' => s.push_str("\\n"),
+            b'\t' => s.push_str("\\t"),
+            b'\r' => s.push_str("\\r"),
+            b' '..=b'~' => s.push(*b as char),
+            _ => s.push_str(&format!("\\{:03o}", b)),
+        }
+    }
+    s
+}
+
+/// error reported by a compiler phase, i.e unexpected token
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompileError {
+    /// message including the location if available
+    pub msg: String,
+    /// location (line,column) of the error
+    pub loc: Option<(u32, u32)>,
+    /// source range to underline
+    pub span: Option<Span>,
+    /// suggested edits fixing the error, safe to apply without review
+    pub fixits: Vec<FixIt>,
+    /// related locations reported after the error, i.e the previous declaration
+    pub notes: Vec<CompileError>,
+}
+
+/// replacement of a source range suggested by a diagnostic, an insertion if the span is empty
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FixIt {
+    pub span: Span,
+    pub replacement: String,
+}
+
+impl FixIt {
+    /// insert the text before the byte offset
+    pub fn insert<S: Into<String>>(offset: usize, text: S) -> Self {
+        FixIt {
+            span: Span::new(offset, offset),
+            replacement: text.into(),
+        }
+    }
+
+    /// replace the source range with the text
+    pub fn replace<S: Into<String>>(span: Span, text: S) -> Self {
+        FixIt {
+            span,
+            replacement: text.into(),
+        }
+    }
+}
+
+impl CompileError {
+    pub fn new<S: Into<String>>(msg: S) -> Self {
+        CompileError {
+            msg: msg.into(),
+            loc: None,
+            span: None,
+            fixits: Vec::new(),
+            notes: Vec::new(),
+        }
+    }
+
+    pub fn at<S: Into<String>>(msg: S, loc: (u32, u32)) -> Self {
+        CompileError {
+            msg: msg.into(),
+            loc: Some(loc),
+            span: None,
+            fixits: Vec::new(),
+            notes: Vec::new(),
+        }
+    }
+
+    /// error at the source range of a node, the location is filled by `locate`
+    pub fn at_span<S: Into<String>>(msg: S, span: Span) -> Self {
+        CompileError {
+            msg: msg.into(),
+            loc: None,
+            span: Some(span),
+            fixits: Vec::new(),
+            notes: Vec::new(),
+        }
+    }
+
+    /// error at the token
+    pub fn at_token<S: Into<String>>(msg: S, t: &Token) -> Self {
+        CompileError {
+            msg: msg.into(),
+            loc: Some(t.loc),
+            span: Some(t.span),
+            fixits: Vec::new(),
+            notes: Vec::new(),
+        }
+    }
+
+    /// the source range of the node reporting the error if the error has no location
+    pub fn or_span(mut self, span: Span) -> Self {
+        if self.loc.is_none() && self.span.is_none() {
+            self.span = Some(span);
+        }
+        self
+    }
+
+    /// suggest the edit with the error
+    pub fn with_fixit(mut self, fixit: FixIt) -> Self {
+        self.fixits.push(fixit);
+        self
+    }
+
+    /// attach a note at the source range
+    pub fn with_note<S: Into<String>>(mut self, msg: S, span: Span) -> Self {
+        self.notes.push(CompileError::at_span(msg, span));
+        self
+    }
+
+    /// add the location of the span to the message and the notes if it is not known yet
+    pub fn locate(mut self, src: &str, tab_width: u32) -> Self {
+        self.notes = self
+            .notes
+            .into_iter()
+            .map(|n| n.locate(src, tab_width))
+            .collect();
+        if let (None, Some(span)) = (self.loc, self.span) {
+            let (line, col) = crate::scan::locate(src, span.start, tab_width);
+            self.msg = format!("{} at {}:{}", self.msg, line, col);
+            self.loc = Some((line, col));
+        }
+        self
+    }
+
+    /// source line of the error with the span underlined and the replacement text of each
+    /// fix-it under it, empty without span
+    pub fn snippet(&self, src: &str) -> String {
+        let span = match self.span {
+            Some(s) => s,
+            None => return String::new(),
+        };
+        let mut s = render_snippet(src, span);
+        let (line_start, _, _) = source_line(src, span.start);
+        for f in &self.fixits {
+            let (start, end, line_no) = source_line(src, f.span.start);
+            if start != line_start {
+                s.push_str(&format!("{:>5} | {}\n", line_no, &src[start..end]));
+            }
+            let pad = padding(&src[start..f.span.start.clamp(start, end)]);
+            s.push_str(&format!("      | {}{}\n", pad, f.replacement));
+        }
+        s
+    }
+}
+
+impl Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.msg)
+    }
+}
+
+impl std::error::Error for CompileError {}
+
+/// render the first source line of the span with '^' under the span
+///
+/// ```text
+///     1 | int main() { return 1 }
+///       |                       ^
+/// ```
+pub fn render_snippet(src: &str, span: Span) -> String {
+    let start = span.start.min(src.len());
+    let (line_start, line_end, line_no) = source_line(src, start);
+    let line = &src[line_start..line_end];
+    let end = span.end.clamp(start, line_end);
+    let width = src[start..end].chars().count().max(1);
+    format!(
+        "{:>5} | {}\n      | {}{}\n",
+        line_no,
+        line,
+        padding(&src[line_start..start]),
+        "^".repeat(width)
+    )
+}
+
+/// byte offsets of the start and end of the line with the offset and the line number from 1
+fn source_line(src: &str, offset: usize) -> (usize, usize, usize) {
+    let offset = offset.min(src.len());
+    let line_start = src[..offset]
+        .rfind(['\n', '\r'])
+        .map(|i| i + 1)
+        .unwrap_or(0);
+    let line_end = src[offset..]
+        .find(['\n', '\r'])
+        .map(|i| offset + i)
+        .unwrap_or(src.len());
+
+    // '\r\n' is a single line break
+    let before = &src[..line_start];
+    let line_no = before.matches(['\n', '\r']).count() - before.matches("\r\n").count() + 1;
+    (line_start, line_end, line_no)
+}
+
+/// blanks as wide as the text, tabs are kept so markers line up with the source
+fn padding(text: &str) -> String {
+    text.chars()
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect()
+}
+
+/// errors reported while compiling a translation unit
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Diagnostics(pub Vec<CompileError>);
+
+impl Diagnostics {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn errors(&self) -> &[CompileError] {
+        &self.0
+    }
+
+    /// add the locations of the errors reported by span only
+    pub fn locate(self, src: &str, tab_width: u32) -> Self {
+        Diagnostics(
+            self.0
+                .into_iter()
+                .map(|e| e.locate(src, tab_width))
+                .collect(),
+        )
+    }
+}
+
+impl From<CompileError> for Diagnostics {
+    fn from(e: CompileError) -> Self {
+        Diagnostics(vec![e])
+    }
+}
+
+impl Display for Diagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let msgs: Vec<String> = self.0.iter().map(|e| e.to_string()).collect();
+        write!(f, "{}", msgs.join("\n"))
+    }
+}
+
+impl std::error::Error for Diagnostics {}
+
+#[cfg(test)]
+mod test {
+    use test_case::test_case;
+
+    use super::{render_snippet, CompileError, FixIt, Span};
+
+    #[test_case("int main() { return 1 }", 22, 23 => "    1 | int main() { return 1 }\n      |                       ^\n")]
+    #[test_case("int a;\n\tfoo(1, 2);\n", 8, 17 => "    2 | \tfoo(1, 2);\n      | \t^^^^^^^^^\n")]
+    #[test_case("int a", 5, 5 => "    1 | int a\n      |      ^\n")]
+    #[test_case("int a;\r\n\r\nint b;\r\n", 14, 15 => "    3 | int b;\n      |     ^\n"; "crlf")]
+    fn test_snippet(src: &str, start: usize, end: usize) -> String {
+        render_snippet(src, Span::new(start, end))
+    }
+
+    #[test_case("int main() { return 1 }", 22, 21 => "    1 | int main() { return 1 }\n      |                       ^\n      |                      ;\n")]
+    #[test_case("int g = 1\nint main", 10, 9 => "    2 | int main\n      | ^\n    1 | int g = 1\n      |          ;\n"; "previous line")]
+    fn test_fixit_snippet(src: &str, start: usize, offset: usize) -> String {
+        CompileError::at_span("expected ;", Span::new(start, start + 1))
+            .with_fixit(FixIt::insert(offset, ";"))
+            .snippet(src)
+    }
+
+    #[test]
+    fn test_locate_notes() {
+        let src = "int g;\nchar g;";
+        let e = CompileError::at_span("conflicting types for g", Span::new(12, 13))
+            .with_note("previous declaration of 'g' is here", Span::new(0, 6))
+            .locate(src, 8);
+        assert_eq!(e.msg, "conflicting types for g at 2:6");
+        assert_eq!(e.notes[0].msg, "previous declaration of 'g' is here at 1:1");
+        assert_eq!(e.notes[0].snippet(src), "    1 | int g;\n      | ^^^^^^\n");
+    }
+
+    #[test]
+    fn test_span_to() {
+        assert_eq!(Span::new(4, 6).to(Span::new(1, 2)), Span::new(1, 6));
+    }
+}
@@ -0,0 +1,79 @@
+//! Generate compilation database (compile_commands.json) for clangd and other tools
+//!
+//! See https://clang.llvm.org/docs/JSONCompilationDatabase.html
+
+use serde::{Deserialize, Serialize};
+
+/// default file name of the compilation database
+pub const COMPDB_FILE: &str = "compile_commands.json";
+
+/// compile command for one translation unit
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CompileCommand {
+    /// working directory of the compilation
+    pub directory: String,
+    /// main source file
+    pub file: String,
+    /// compile command as argv
+    pub arguments: Vec<String>,
+    /// name of the output created by this compilation
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>,
+}
+
+/// merge new commands into the existing database, entries of the same file are replaced
+///
+/// invalid existing database is discarded
+pub fn merge(existing: &str, commands: Vec<CompileCommand>) -> Vec<CompileCommand> {
+    let mut db: Vec<CompileCommand> = serde_json::from_str(existing).unwrap_or_default();
+    db.retain(|old| {
+        !commands
+            .iter()
+            .any(|c| c.directory == old.directory && c.file == old.file)
+    });
+    db.extend(commands);
+    db
+}
+
+/// render the database as pretty JSON
+pub fn gen_compdb(commands: &[CompileCommand]) -> String {
+    serde_json::to_string_pretty(commands).expect("failed to serialize compile commands") + "\n"
+}
+
+#[cfg(test)]
+mod test {
+    use test_case::test_case;
+
+    use super::{gen_compdb, merge, CompileCommand};
+
+    fn cmd(dir: &str, file: &str) -> CompileCommand {
+        CompileCommand {
+            directory: dir.into(),
+            file: file.into(),
+            arguments: vec!["tncc".into(), "-c".into(), file.into()],
+            output: None,
+        }
+    }
+
+    #[test_case("", vec!["a.c"])]
+    #[test_case("not json", vec!["a.c"])]
+    #[test_case(r#"[{"directory":"/p","file":"b.c","arguments":["tncc","-c","b.c"]}]"#, vec!["b.c", "a.c"])]
+    #[test_case(r#"[{"directory":"/p","file":"a.c","arguments":["tncc","-O1","a.c"]}]"#, vec!["a.c"])]
+    #[test_case(r#"[{"directory":"/q","file":"a.c","arguments":["tncc","-c","a.c"]}]"#, vec!["a.c", "a.c"])]
+    fn test_merge(existing: &str, files: Vec<&str>) {
+        let db = merge(existing, vec![cmd("/p", "a.c")]);
+        assert_eq!(
+            db.iter().map(|c| c.file.as_str()).collect::<Vec<_>>(),
+            files
+        );
+        assert!(db.contains(&cmd("/p", "a.c")));
+    }
+
+    #[test]
+    fn test_gen_compdb() {
+        let v = gen_compdb(&[cmd("/p", "a.c")]);
+        assert_eq!(merge(&v, vec![]), vec![cmd("/p", "a.c")]);
+        assert!(v.contains("\"directory\": \"/p\""));
+        assert!(!v.contains("output"));
+    }
+}
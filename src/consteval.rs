@@ -0,0 +1,134 @@
+//! Evaluate integer constant expressions
+//!
+//! Shared by the phases needing a value at compile time, i.e array sizes and global initializers.
+//! Operands are converted to the larger of their types (`int` or `long`) and signed overflow is an
+//! error instead of wrapping
+
+use std::convert::TryFrom;
+
+use crate::{
+    ast::{ArithOp, DataType, Designator, Expr, ExprKind, UnaryOp},
+    common::CompileError,
+};
+
+/// value of an integer constant expression with its type
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IntConst {
+    pub value: i64,
+    /// `Int` or `Long`
+    pub ty: DataType,
+}
+
+impl IntConst {
+    /// constant of the type, none if the value is out of range
+    fn checked(value: i64, ty: DataType) -> Option<IntConst> {
+        let fits = match ty {
+            DataType::Int => i32::try_from(value).is_ok(),
+            _ => true,
+        };
+        match fits {
+            true => Some(IntConst { value, ty }),
+            false => None,
+        }
+    }
+}
+
+/// evaluate the integer constant expression, references and calls are not constant
+pub fn eval_int(expr: &Expr) -> Result<IntConst, CompileError> {
+    let overflow = || CompileError::at_span("integer overflow in constant expression", expr.span);
+    match &expr.kind {
+        // decimal constants take the first type fitting the value, the scanned value above
+        // i64::MAX is negative
+        ExprKind::IntConst(v) => IntConst::checked(*v, DataType::Int)
+            .or_else(|| IntConst::checked(*v, DataType::Long))
+            .filter(|_| *v >= 0)
+            .ok_or_else(|| {
+                CompileError::at_span("integer constant is too large for its type", expr.span)
+            }),
+        ExprKind::Unary(op, e) => {
+            let c = eval_int(e)?;
+            match op {
+                UnaryOp::Plus => Ok(c),
+                UnaryOp::Neg => c
+                    .value
+                    .checked_neg()
+                    .and_then(|v| IntConst::checked(v, c.ty))
+                    .ok_or_else(overflow),
+            }
+        }
+        ExprKind::Arith(l, op, r) => {
+            let (l, r) = (eval_int(l)?, eval_int(r)?);
+            let ty = match (l.ty, r.ty) {
+                (DataType::Int, DataType::Int) => DataType::Int,
+                _ => DataType::Long,
+            };
+            let value = match op {
+                ArithOp::Add => l.value.checked_add(r.value),
+                ArithOp::Sub => l.value.checked_sub(r.value),
+            };
+            value
+                .and_then(|v| IntConst::checked(v, ty))
+                .ok_or_else(overflow)
+        }
+        ExprKind::StrLit(_)
+        | ExprKind::VarRef(_)
+        | ExprKind::FunctionCall(_, _)
+        | ExprKind::InitList(_) => Err(CompileError::at_span(
+            "expression is not an integer constant",
+            expr.span,
+        )),
+    }
+}
+
+/// the expression has no references or calls, its value is known at compile time
+pub fn is_constant(expr: &Expr) -> bool {
+    match &expr.kind {
+        ExprKind::IntConst(_) => true,
+        ExprKind::Unary(_, e) => is_constant(e),
+        ExprKind::Arith(l, _, r) => is_constant(l) && is_constant(r),
+        ExprKind::InitList(items) => items.iter().all(|item| {
+            is_constant(&item.value)
+                && item.designators.iter().all(|d| match d {
+                    Designator::Index(e) => is_constant(e),
+                    Designator::Member(_) => true,
+                })
+        }),
+        ExprKind::StrLit(_) | ExprKind::VarRef(_) | ExprKind::FunctionCall(_, _) => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use test_case::test_case;
+
+    use crate::{
+        ast::{DataType, ExtDecl},
+        parse, scan,
+    };
+
+    use super::{eval_int, IntConst};
+
+    fn eval(src: &str) -> Result<IntConst, String> {
+        let ast = parse(scan(&format!("int g = {};", src)).unwrap()).unwrap();
+        match &ast.0[0] {
+            ExtDecl::Global(g) => eval_int(g.init.as_ref().unwrap()).map_err(|e| e.msg),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test_case("1 + 2 - -3" => Ok((6, DataType::Int)))]
+    #[test_case("-(2 - +5)" => Ok((3, DataType::Int)))]
+    #[test_case("2147483647" => Ok((2147483647, DataType::Int)))]
+    #[test_case("2147483648" => Ok((2147483648, DataType::Long)))]
+    #[test_case("-2147483647 - 1" => Ok((-2147483648, DataType::Int)))]
+    #[test_case("2147483648 - 1" => Ok((2147483647, DataType::Long)); "long operand")]
+    #[test_case("2147483647 + 1" => Err("integer overflow in constant expression".to_string()))]
+    #[test_case("-2147483647 - 2" => Err("integer overflow in constant expression".to_string()))]
+    #[test_case("9223372036854775807 + 1" => Err("integer overflow in constant expression".to_string()))]
+    #[test_case("9223372036854775808" => Err("integer constant is too large for its type".to_string()))]
+    #[test_case("1 + a" => Err("expression is not an integer constant".to_string()))]
+    #[test_case("f()" => Err("expression is not an integer constant".to_string()))]
+    fn eval_expr(src: &str) -> Result<(i64, DataType), String> {
+        eval(src).map(|c| (c.value, c.ty))
+    }
+}
@@ -0,0 +1,62 @@
+//! Line coverage of `--coverage`
+//!
+//! every line with statements gets a counter, incremented by the generated code when the line
+//! is reached. A routine registered with `atexit` writes the counts to the report, one
+//! `<file>:<line>: <count>` per line
+
+/// lines of the source and the report written by the compiled program
+#[derive(Debug, Clone)]
+pub struct Coverage {
+    /// source named in the report
+    pub file: String,
+    /// path of the report, relative to the working directory of the program
+    pub report: String,
+    /// byte offsets where the lines start
+    line_starts: Vec<usize>,
+}
+
+impl Coverage {
+    /// coverage of the source written to `<file>.cov`
+    pub fn new(src: &str, file: &str) -> Self {
+        let line_starts = std::iter::once(0)
+            .chain(src.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        Coverage {
+            file: file.to_string(),
+            report: format!("{}.cov", file),
+            line_starts,
+        }
+    }
+
+    /// line of the byte offset, from 1
+    pub fn line(&self, offset: usize) -> u32 {
+        self.line_starts.partition_point(|s| *s <= offset) as u32
+    }
+
+    /// format of a line of the report, `%` in the file name is escaped
+    pub fn line_format(&self) -> String {
+        format!("{}:%u: %lu\n", self.file.replace('%', "%%"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use test_case::test_case;
+
+    use super::Coverage;
+
+    #[test_case(0 => 1)]
+    #[test_case(3 => 1)]
+    #[test_case(4 => 2)]
+    #[test_case(9 => 3)]
+    fn line(offset: usize) -> u32 {
+        Coverage::new("int\nmain\n{}", "a.c").line(offset)
+    }
+
+    #[test]
+    fn report() {
+        let cov = Coverage::new("", "50%.c");
+        assert_eq!(cov.report, "50%.c.cov");
+        assert_eq!(cov.line_format(), "50%%.c:%u: %lu\n");
+    }
+}
@@ -0,0 +1,147 @@
+//! Differential testing: run a program built by tncc and by the system C compiler and compare
+//!
+//! The reference is compiled by `$CC` or `cc`. On aarch64 hosts the assembly of tncc is linked by
+//! the same compiler and run, other hosts and programs beyond the code generation are interpreted
+//! instead. Without a C compiler the comparison is skipped
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use crate::{
+    analyse, gen_asm, interpret, parse, scan,
+    util::{find_in_path, TargetOs, TempDir},
+    GenOpts,
+};
+
+/// directory of the programs compared by the tests
+pub fn diff_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("diff")
+}
+
+/// observable behavior of a program
+#[derive(Debug, PartialEq)]
+pub struct Outcome {
+    /// exit status, the low 8 bits of the value returned by main
+    pub code: i32,
+    pub stdout: String,
+}
+
+/// C compiler of `$CC`, otherwise `cc` on PATH
+pub fn reference_cc() -> Option<PathBuf> {
+    std::env::var_os("CC")
+        .map(PathBuf::from)
+        .or_else(|| find_in_path("cc"))
+}
+
+/// compare the program built by both compilers, ok if no C compiler is found
+pub fn compare(src: &str) -> Result<(), String> {
+    let cc = match reference_cc() {
+        Some(cc) => cc,
+        None => {
+            eprintln!("skip differential test, no C compiler found");
+            return Ok(());
+        }
+    };
+    let dir = TempDir::new().map_err(|e| e.to_string())?;
+    let expected = run_reference(&cc, src, &dir)?;
+    let actual = run_tncc(&cc, src, &dir)?;
+    match expected == actual {
+        true => Ok(()),
+        false => Err(format!(
+            "cc: {:?}\ntncc: {:?}\nprogram:\n{}",
+            expected, actual, src
+        )),
+    }
+}
+
+/// compare the program `tests/diff/<name>.c`
+pub fn compare_file(name: &str) -> Result<(), String> {
+    let path = diff_dir().join(format!("{}.c", name));
+    let src = fs::read_to_string(&path).map_err(|e| format!("{:?}: {}", path, e))?;
+    compare(&src)
+}
+
+/// compile and run with the C compiler
+pub fn run_reference(cc: &Path, src: &str, dir: &TempDir) -> Result<Outcome, String> {
+    let input = dir.new_file(Path::new("reference"), "c");
+    fs::write(&input, src).map_err(|e| e.to_string())?;
+    let exe = dir.new_file(Path::new("reference"), "out");
+    build(Command::new(cc).arg("-w").arg("-o").arg(&exe).arg(&input))?;
+    execute(&exe)
+}
+
+/// link and run the assembly of tncc on aarch64, otherwise interpret the program
+///
+/// programs not supported by the code generation yet are interpreted as well
+pub fn run_tncc(cc: &Path, src: &str, dir: &TempDir) -> Result<Outcome, String> {
+    let ast = parse(scan(src).map_err(|e| e.to_string())?).map_err(|e| e.to_string())?;
+    let decor = analyse(&ast).map_err(|e| e.to_string())?;
+    let asm = match std::env::consts::ARCH {
+        "aarch64" => gen_asm(&ast, &GenOpts::new(TargetOs::current())).ok(),
+        _ => None,
+    };
+    let asm = match asm {
+        Some(asm) => asm,
+        None => {
+            let code = interpret(&ast, &decor).map_err(|e| e.to_string())?;
+            return Ok(Outcome {
+                code: (code & 0xff) as i32,
+                stdout: String::new(),
+            });
+        }
+    };
+
+    let input = dir.new_file(Path::new("tncc"), "s");
+    fs::write(&input, asm).map_err(|e| e.to_string())?;
+    let exe = dir.new_file(Path::new("tncc"), "out");
+    build(Command::new(cc).arg("-o").arg(&exe).arg(&input))?;
+    execute(&exe)
+}
+
+fn build(cmd: &mut Command) -> Result<(), String> {
+    let out = cmd.output().map_err(|e| format!("{:?}: {}", cmd, e))?;
+    match out.status.success() {
+        true => Ok(()),
+        false => Err(format!(
+            "{:?} failed:\n{}",
+            cmd,
+            String::from_utf8_lossy(&out.stderr)
+        )),
+    }
+}
+
+fn execute(exe: &Path) -> Result<Outcome, String> {
+    let out = Command::new(exe)
+        .output()
+        .map_err(|e| format!("{:?}: {}", exe, e))?;
+    let code = out
+        .status
+        .code()
+        .ok_or_else(|| format!("{:?} was terminated by a signal", exe))?;
+    Ok(Outcome {
+        code,
+        stdout: String::from_utf8_lossy(&out.stdout).into_owned(),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use test_case::test_case;
+
+    use super::compare_file;
+
+    #[test_case("return_value")]
+    #[test_case("calls")]
+    #[test_case("scopes")]
+    #[test_case("wrap_exit_code")]
+    fn differential(name: &str) {
+        if let Err(e) = compare_file(name) {
+            panic!("{} differs\n{}", name, e)
+        }
+    }
+}
@@ -0,0 +1,215 @@
+//! Generate Graphviz DOT graph from AST
+//!
+//! Render with `dot -Tpng ast.dot -o ast.png`
+
+use crate::{ast::*, common::escape, intern::Symbol};
+
+pub fn gen_dot(ast: &Ast) -> String {
+    let mut g = DotGen::new();
+    g.gen(ast);
+    g.str
+}
+
+struct DotGen {
+    str: String,
+    count: usize,
+}
+
+impl DotGen {
+    fn new() -> Self {
+        DotGen {
+            str: String::new(),
+            count: 0,
+        }
+    }
+
+    fn gen(&mut self, ast: &Ast) {
+        self.str.push_str("digraph ast {\n");
+        self.str
+            .push_str("\tnode [shape=box, fontname=\"monospace\"];\n");
+        let root = self.node("translation unit");
+        ast.0.iter().for_each(|ext| {
+            let id = match ext {
+                ExtDecl::Func(f) => self.gen_func(f),
+                ExtDecl::Global(d) => self.gen_var("global", &d.data_type, d.name, &d.init),
+                ExtDecl::StaticAssert(sa) => self.gen_static_assert(sa),
+            };
+            self.edge(root, id);
+        });
+        self.str.push_str("}\n");
+    }
+
+    fn gen_func(&mut self, func: &FuncDecl) -> usize {
+        let id = self.node(&format!(
+            "function {} {}",
+            type_label(&func.return_type),
+            func.name
+        ));
+        func.params.iter().for_each(|p| {
+            let param = self.node(&format!("param {} {}", type_label(&p.data_type), p.name));
+            self.edge(id, param);
+        });
+        let body = self.gen_cmp_stmt(&func.cmp_stmt);
+        self.edge(id, body);
+        id
+    }
+
+    fn gen_cmp_stmt(&mut self, cmp_stmt: &CmpStmt) -> usize {
+        let id = self.node("{ }");
+        cmp_stmt.stmts.iter().for_each(|stmt| {
+            let child = self.gen_stmt(stmt);
+            self.edge(id, child);
+        });
+        id
+    }
+
+    fn gen_stmt(&mut self, stmt: &Stmt) -> usize {
+        match &stmt.kind {
+            StmtKind::Compound(st) => self.gen_cmp_stmt(st),
+            StmtKind::VarDecl(d) => self.gen_var("var", &d.data_type, d.name, &d.init),
+            StmtKind::Assignment(name, expr) => {
+                let id = self.node(&format!("{} =", name));
+                let child = self.gen_expr(expr);
+                self.edge(id, child);
+                id
+            }
+            StmtKind::Return(expr) => {
+                let id = self.node("return");
+                if let Some(e) = expr {
+                    let child = self.gen_expr(e);
+                    self.edge(id, child);
+                }
+                id
+            }
+            StmtKind::Expr(e) => self.gen_expr(e),
+            StmtKind::StaticAssert(sa) => self.gen_static_assert(sa),
+            StmtKind::Asm(code) => self.node(&format!("asm \"{}\"", escape(code))),
+        }
+    }
+
+    fn gen_static_assert(&mut self, sa: &StaticAssert) -> usize {
+        let id = match &sa.message {
+            Some(m) => self.node(&format!("static assert \"{}\"", escape(m))),
+            None => self.node("static assert"),
+        };
+        let child = self.gen_expr(&sa.cond);
+        self.edge(id, child);
+        id
+    }
+
+    fn gen_var(&mut self, kind: &str, dt: &Type, name: Symbol, expr: &Option<Expr>) -> usize {
+        let id = self.node(&format!("{} {} {}", kind, type_label(dt), name));
+        if let Some(e) = expr {
+            let child = self.gen_expr(e);
+            self.edge(id, child);
+        }
+        id
+    }
+
+    fn gen_expr(&mut self, expr: &Expr) -> usize {
+        match &expr.kind {
+            ExprKind::IntConst(v) => self.node(&v.to_string()),
+            ExprKind::StrLit(s) => self.node(&format!("\"{}\"", escape(s))),
+            ExprKind::VarRef(name) => self.node(name.as_str()),
+            ExprKind::FunctionCall(name, args) => {
+                let id = self.node(&format!("call {}", name));
+                args.iter().for_each(|a| {
+                    let child = self.gen_expr(a);
+                    self.edge(id, child);
+                });
+                id
+            }
+            ExprKind::Arith(left, op, right) => {
+                let id = self.node(&format!("{:?}", op));
+                let l = self.gen_expr(left);
+                let r = self.gen_expr(right);
+                self.edge(id, l);
+                self.edge(id, r);
+                id
+            }
+            ExprKind::Unary(op, e) => {
+                let id = self.node(&format!("{:?}", op));
+                let child = self.gen_expr(e);
+                self.edge(id, child);
+                id
+            }
+            // designators are chained from the list down to the value
+            ExprKind::InitList(items) => {
+                let id = self.node("init list");
+                for item in items {
+                    let mut parent = id;
+                    for d in &item.designators {
+                        let child = match d {
+                            Designator::Index(e) => {
+                                let child = self.node("[]");
+                                let index = self.gen_expr(e);
+                                self.edge(child, index);
+                                child
+                            }
+                            Designator::Member(name) => self.node(&format!(".{}", name)),
+                        };
+                        self.edge(parent, child);
+                        parent = child;
+                    }
+                    let value = self.gen_expr(&item.value);
+                    self.edge(parent, value);
+                }
+                id
+            }
+        }
+    }
+
+    /// emit new node and return its id
+    fn node(&mut self, label: &str) -> usize {
+        let id = self.count;
+        self.count += 1;
+        let label = label.replace('\\', "\\\\").replace('"', "\\\"");
+        self.str
+            .push_str(&format!("\tn{} [label=\"{}\"];\n", id, label));
+        id
+    }
+
+    fn edge(&mut self, from: usize, to: usize) {
+        self.str.push_str(&format!("\tn{} -> n{};\n", from, to));
+    }
+}
+
+/// base types keep the variant name, derived types use the C syntax
+fn type_label(ty: &Type) -> String {
+    match ty {
+        Type::Base(dt) => format!("{:?}", dt),
+        _ => ty.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use test_case::test_case;
+
+    use crate::{parse, scan};
+
+    use super::gen_dot;
+
+    #[test_case("int main() { return 1; }", vec![
+        "digraph ast {",
+        "n0 [label=\"translation unit\"];",
+        "n1 [label=\"function Int main\"];",
+        "n0 -> n1;",
+        "n3 [label=\"return\"];",
+        "n4 [label=\"1\"];",
+        "n3 -> n4;",
+    ])]
+    #[test_case("int g = 1; void foo(int x) { bar(x); }", vec![
+        "[label=\"global Int g\"];",
+        "[label=\"param Int x\"];",
+        "[label=\"call bar\"];",
+    ])]
+    fn test_dot(src: &str, vec: Vec<&str>) {
+        let v = gen_dot(&parse(scan(src).unwrap()).unwrap());
+        vec.iter().for_each(|i| {
+            if !v.contains(i) {
+                panic!("'{}' is not generated", i)
+            }
+        });
+    }
+}
@@ -0,0 +1,90 @@
+//! Golden testing: compare full output with the expected file checked in the repo
+//!
+//! Run tests with `TNCC_UPDATE_GOLDEN=1` to write the expected files from the current output,
+//! then review the changes as a diff
+
+use std::{fs, path::PathBuf};
+
+/// environment variable to enable update mode
+const UPDATE_ENV: &str = "TNCC_UPDATE_GOLDEN";
+
+/// directory of the golden files
+pub fn golden_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("golden")
+}
+
+/// read the source of the golden test
+pub fn read_source(name: &str) -> String {
+    let path = golden_dir().join(format!("{}.c", name));
+    fs::read_to_string(&path).unwrap_or_else(|e| panic!("failed to read {:?}: {}", path, e))
+}
+
+/// compare the output to `<name>.expected.s`, or write it in update mode
+pub fn assert_golden(name: &str, actual: &str) {
+    let path = golden_dir().join(format!("{}.expected.s", name));
+    let actual = normalize(actual);
+    if std::env::var_os(UPDATE_ENV).is_some() {
+        fs::write(&path, &actual).unwrap_or_else(|e| panic!("failed to write {:?}: {}", path, e));
+        return;
+    }
+
+    let expected = match fs::read_to_string(&path) {
+        Ok(s) => normalize(&s),
+        Err(e) => panic!(
+            "failed to read {:?}: {}, run with {}=1 to create it",
+            path, e, UPDATE_ENV
+        ),
+    };
+    if expected != actual {
+        panic!(
+            "output of '{}' differs from {:?}, run with {}=1 to update\n{}",
+            name,
+            path,
+            UPDATE_ENV,
+            diff(&expected, &actual)
+        );
+    }
+}
+
+/// unify line endings and drop trailing whitespace
+fn normalize(s: &str) -> String {
+    let mut lines: Vec<&str> = s.lines().map(|l| l.trim_end()).collect();
+    while lines.last() == Some(&"") {
+        lines.pop();
+    }
+    lines.iter().map(|l| format!("{}\n", l)).collect()
+}
+
+/// line by line diff, mark removed lines with '-' and added lines with '+'
+fn diff(expected: &str, actual: &str) -> String {
+    let (exp, act): (Vec<&str>, Vec<&str>) = (expected.lines().collect(), actual.lines().collect());
+    (0..exp.len().max(act.len()))
+        .map(|i| match (exp.get(i), act.get(i)) {
+            (Some(e), Some(a)) if e == a => format!(" {}\n", e),
+            (e, a) => {
+                e.map(|e| format!("-{}\n", e)).unwrap_or_default()
+                    + &a.map(|a| format!("+{}\n", a)).unwrap_or_default()
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use test_case::test_case;
+
+    use super::{diff, normalize};
+
+    #[test_case("a \r\nb\n\n\n" => "a\nb\n")]
+    #[test_case("a\n\tb" => "a\n\tb\n")]
+    fn test_normalize(s: &str) -> String {
+        normalize(s)
+    }
+
+    #[test]
+    fn test_diff() {
+        assert_eq!(diff("a\nb\n", "a\nc\nd\n"), " a\n-b\n+c\n+d\n");
+    }
+}
@@ -0,0 +1,264 @@
+//! Layout of initializers, the values of the scalars of the initialized object
+//!
+//! Arrays are initialized in order, a designator `[i] =` moves to the element and the next values
+//! continue after it. Nested arrays may omit their braces, scalars not mentioned are zero
+
+use std::collections::BTreeMap;
+
+use crate::{
+    ast::{Designator, Expr, ExprKind, InitItem, Type},
+    common::{CompileError, Span},
+    consteval::eval_int,
+};
+
+type InitResult<T> = Result<T, CompileError>;
+
+/// values of an initializer by the index of the scalar in the object
+#[derive(Debug)]
+pub struct FlatInit<'a> {
+    /// declared type completed by the initializer, i.e the length of `int a[] = {1, 2}`
+    pub ty: Type,
+    /// type of every scalar of the object
+    pub scalar: Type,
+    /// number of scalars of the object
+    pub count: u64,
+    /// initialized scalars, a later designator overrides the value
+    pub values: BTreeMap<u64, &'a Expr>,
+}
+
+/// values of the initializer of an object of the type
+pub fn flatten<'a>(ty: &Type, init: &'a Expr) -> InitResult<FlatInit<'a>> {
+    let mut values = BTreeMap::new();
+    let ty = match ty {
+        Type::Array(elem, len) => {
+            let used = init_array(elem, *len, init, 0, &mut values)?;
+            Type::Array(elem.clone(), Some(len.unwrap_or(used)))
+        }
+        _ => {
+            init_scalar(init, 0, &mut values)?;
+            ty.clone()
+        }
+    };
+    let (scalar, count) = scalars(&ty, init.span)?;
+    Ok(FlatInit {
+        ty,
+        scalar,
+        count,
+        values,
+    })
+}
+
+/// scalar type and the number of scalars of a complete type
+fn scalars(ty: &Type, span: Span) -> InitResult<(Type, u64)> {
+    match ty {
+        Type::Array(elem, Some(len)) => {
+            let (scalar, count) = scalars(elem, span)?;
+            Ok((scalar, count * len))
+        }
+        Type::Array(_, None) => Err(CompileError::at_span(
+            "array type has incomplete element type",
+            span,
+        )),
+        _ => Ok((ty.clone(), 1)),
+    }
+}
+
+fn list(init: &Expr) -> InitResult<&[InitItem]> {
+    match &init.kind {
+        ExprKind::InitList(items) => Ok(items),
+        _ => Err(CompileError::at_span("invalid initializer", init.span)),
+    }
+}
+
+/// scalar at the index, braces around a single value are allowed
+fn init_scalar<'a>(
+    init: &'a Expr,
+    at: u64,
+    values: &mut BTreeMap<u64, &'a Expr>,
+) -> InitResult<()> {
+    match &init.kind {
+        ExprKind::InitList(items) => match items.as_slice() {
+            [] => Ok(()),
+            [item] if item.designators.is_empty() => init_scalar(&item.value, at, values),
+            [item] => Err(designator_error(&item.designators[0], &item.value)),
+            [_, extra, ..] => Err(CompileError::at_span(
+                "excess elements in scalar initializer",
+                extra.value.span,
+            )),
+        },
+        _ => {
+            values.insert(at, init);
+            Ok(())
+        }
+    }
+}
+
+/// elements of the array from the scalar index `base`, return the number of elements used
+fn init_array<'a>(
+    elem: &Type,
+    len: Option<u64>,
+    init: &'a Expr,
+    base: u64,
+    values: &mut BTreeMap<u64, &'a Expr>,
+) -> InitResult<u64> {
+    let items = list(init)?;
+    let per_elem = scalars(elem, init.span)?.1;
+    let capacity = len.map(|l| l * per_elem);
+    // next scalar relative to the array and the end of the initialized scalars
+    let mut pos = 0;
+    let mut end = 0;
+    for item in items {
+        let (at, target) = match item.designators.split_first() {
+            Some((first, rest)) => designate(elem, len, per_elem, first, rest, &item.value)?,
+            // a braced value starts the next element
+            None if is_list(&item.value) && is_aggregate(elem) => {
+                (round_up(pos, per_elem), elem.clone())
+            }
+            None if is_aggregate(elem) => (pos, scalars(elem, init.span)?.0),
+            None => (pos, elem.clone()),
+        };
+        if capacity.is_some_and(|c| at >= c) {
+            return Err(CompileError::at_span(
+                "excess elements in array initializer",
+                item.value.span,
+            ));
+        }
+        pos = at + init_object(&target, &item.value, base + at, values)?;
+        end = end.max(pos);
+    }
+    Ok(round_up(end, per_elem) / per_elem)
+}
+
+/// the object of the type at the scalar index, return its number of scalars
+fn init_object<'a>(
+    ty: &Type,
+    init: &'a Expr,
+    at: u64,
+    values: &mut BTreeMap<u64, &'a Expr>,
+) -> InitResult<u64> {
+    match ty {
+        Type::Array(elem, len) if is_list(init) => {
+            init_array(elem, *len, init, at, values)?;
+            Ok(scalars(ty, init.span)?.1)
+        }
+        // without braces the value initializes the first scalar of the array
+        Type::Array(_, _) => {
+            values.insert(at, init);
+            Ok(1)
+        }
+        _ => init_scalar(init, at, values).map(|_| 1),
+    }
+}
+
+/// scalar index relative to the array and the type of the designated subobject
+fn designate(
+    elem: &Type,
+    len: Option<u64>,
+    per_elem: u64,
+    first: &Designator,
+    rest: &[Designator],
+    value: &Expr,
+) -> InitResult<(u64, Type)> {
+    let index = match first {
+        Designator::Index(e) => e,
+        d => return Err(designator_error(d, value)),
+    };
+    let i = match eval_int(index) {
+        Ok(c) => c.value,
+        Err(_) => {
+            return Err(CompileError::at_span(
+                "nonconstant array index in initializer",
+                index.span,
+            ))
+        }
+    };
+    if i < 0 || len.is_some_and(|l| i as u64 >= l) {
+        return Err(CompileError::at_span(
+            "array index in initializer exceeds array bounds",
+            index.span,
+        ));
+    }
+    let at = i as u64 * per_elem;
+    match (rest.split_first(), elem) {
+        (None, _) => Ok((at, elem.clone())),
+        (Some((next, rest)), Type::Array(inner, inner_len)) => {
+            let per_inner = scalars(inner, value.span)?.1;
+            let (inner_at, ty) = designate(inner, *inner_len, per_inner, next, rest, value)?;
+            Ok((at + inner_at, ty))
+        }
+        (Some((next, _)), _) => Err(designator_error(next, value)),
+    }
+}
+
+fn designator_error(d: &Designator, value: &Expr) -> CompileError {
+    match d {
+        Designator::Index(e) => {
+            CompileError::at_span("array index in non-array initializer", e.span)
+        }
+        Designator::Member(_) => {
+            CompileError::at_span("field name not in record or union initializer", value.span)
+        }
+    }
+}
+
+fn is_list(e: &Expr) -> bool {
+    matches!(e.kind, ExprKind::InitList(_))
+}
+
+fn is_aggregate(ty: &Type) -> bool {
+    matches!(ty, Type::Array(_, _))
+}
+
+fn round_up(v: u64, n: u64) -> u64 {
+    v.div_ceil(n.max(1)) * n.max(1)
+}
+
+#[cfg(test)]
+mod test {
+    use test_case::test_case;
+
+    use crate::{ast::ExtDecl, consteval::eval_int, parse, scan, CompileError};
+
+    use super::{flatten, FlatInit};
+
+    fn flatten_global(src: &str, f: impl Fn(FlatInit) -> String) -> Result<String, CompileError> {
+        let ast = parse(scan(src).unwrap()).unwrap();
+        match &ast.0[0] {
+            ExtDecl::Global(g) => flatten(&g.data_type, g.init.as_ref().unwrap()).map(f),
+            _ => unreachable!(),
+        }
+    }
+
+    // completed type and the initialized scalars by index
+    #[test_case("int a[] = {1, 2};" => "int[2] 0:1 1:2")]
+    #[test_case("int a[] = {[4] = 1, 2};" => "int[6] 4:1 5:2")]
+    #[test_case("int a[3] = {1, [0] = 5};" => "int[3] 0:5")]
+    #[test_case("int m[][2] = {1, 2, 3};" => "int[2][2] 0:1 1:2 2:3"; "brace elision")]
+    #[test_case("int m[2][2] = {{1}, {2, 3}};" => "int[2][2] 0:1 2:2 3:3")]
+    #[test_case("int m[2][3] = {[0][1] = 4, 5};" => "int[2][3] 1:4 2:5")]
+    #[test_case("int m[2][3] = {[1] = 4, 5};" => "int[2][3] 3:4 4:5")]
+    #[test_case("int n = {7};" => "int 0:7")]
+    #[test_case("int a[2] = {};" => "int[2]")]
+    fn flat_values(src: &str) -> String {
+        flatten_global(src, |flat| {
+            let values = flat
+                .values
+                .iter()
+                .map(|(i, e)| format!(" {}:{}", i, eval_int(e).unwrap().value));
+            std::iter::once(flat.ty.to_string()).chain(values).collect()
+        })
+        .unwrap()
+    }
+
+    #[test_case("int a[2][2] = {{1, 2, 3}};" => "excess elements in array initializer")]
+    #[test_case("int a[2] = {[1][0] = 1};" => "array index in non-array initializer")]
+    #[test_case("int a[] = 1;" => "invalid initializer")]
+    #[test_case("int n = {1, 2};" => "excess elements in scalar initializer")]
+    #[test_case("int n = {[0] = 1};" => "array index in non-array initializer")]
+    #[test_case("int a[][] = {1};" => "array type has incomplete element type")]
+    fn flat_errors(src: &str) -> String {
+        flatten_global(src, |_| String::new())
+            .unwrap_err()
+            .to_string()
+    }
+}
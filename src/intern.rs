@@ -0,0 +1,93 @@
+//! Interning of identifiers
+//!
+//! Identifiers are interned once while scanning and passed around as `Symbol`, a copyable id
+//! which compares and hashes as an integer
+
+use std::{
+    collections::HashMap,
+    fmt::{self, Debug, Display},
+    sync::{Mutex, OnceLock},
+};
+
+use serde::{Serialize, Serializer};
+
+/// interned string
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Symbol(u32);
+
+impl Symbol {
+    pub fn intern(s: &str) -> Self {
+        let mut interner = interner().lock().unwrap();
+        if let Some(sym) = interner.map.get(s) {
+            return *sym;
+        }
+
+        // strings live as long as the process, like the identifiers of a compilation
+        let s: &'static str = Box::leak(s.to_string().into_boxed_str());
+        let sym = Symbol(interner.strings.len() as u32);
+        interner.strings.push(s);
+        interner.map.insert(s, sym);
+        sym
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        interner().lock().unwrap().strings[self.0 as usize]
+    }
+}
+
+impl From<&str> for Symbol {
+    fn from(s: &str) -> Self {
+        Symbol::intern(s)
+    }
+}
+
+impl PartialEq<&str> for Symbol {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.as_str())
+    }
+}
+
+impl Serialize for Symbol {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+#[derive(Default)]
+struct Interner {
+    map: HashMap<&'static str, Symbol>,
+    strings: Vec<&'static str>,
+}
+
+fn interner() -> &'static Mutex<Interner> {
+    static INTERNER: OnceLock<Mutex<Interner>> = OnceLock::new();
+    INTERNER.get_or_init(|| Mutex::new(Interner::default()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::Symbol;
+
+    #[test]
+    fn test_intern() {
+        let a = Symbol::intern("main");
+        assert_eq!(a, Symbol::intern("main"));
+        assert_ne!(a, Symbol::intern("foo"));
+        assert_eq!(a.as_str(), "main");
+        assert_eq!(a, "main");
+        assert_eq!(format!("{} {:?}", a, a), "main \"main\"");
+        assert_eq!(serde_json::to_string(&a).unwrap(), "\"main\"");
+    }
+}
@@ -0,0 +1,406 @@
+//! Interpret the analysed AST without the assembler and linker
+//!
+//! Values are 64 bit integers wrapped to the type of the expression, the exit code is the value
+//! returned by `main`
+
+use std::collections::HashMap;
+
+use crate::{
+    ast::{
+        ArithOp, Ast, CmpStmt, DataType, Expr, ExprKind, ExtDecl, FuncDecl, NodeId, Stmt, StmtKind,
+        Type, UnaryOp,
+    },
+    attrs::alias_target,
+    builtins::Builtin,
+    callgraph::CallGraph,
+    common::{CompileError, Span},
+    consteval::eval_int,
+    init::flatten,
+    intern::Symbol,
+    semantics::Decorations,
+    symtable::DeclKind,
+};
+
+type EvalResult<T> = Result<T, CompileError>;
+
+/// default maximum nesting of interpreted calls, deeper recursion is reported instead of
+/// overflowing the stack
+pub const MAX_CALL_DEPTH: usize = 10_000;
+
+/// stack of the interpreter thread, enough for the deepest calls in debug builds
+const STACK_SIZE: usize = 256 << 20;
+
+/// run `main` of the analysed translation unit and return its exit code
+pub fn interpret(ast: &Ast, decor: &Decorations) -> EvalResult<i64> {
+    interpret_with(ast, decor, MAX_CALL_DEPTH)
+}
+
+/// run `main` with calls nested up to `max_depth`, set by '-fconstexpr-depth=<n>'
+///
+/// each interpreted call takes several native frames, so the program runs on a thread with a
+/// stack growing with the depth
+pub fn interpret_with(ast: &Ast, decor: &Decorations, max_depth: usize) -> EvalResult<i64> {
+    let stack_size = (STACK_SIZE / MAX_CALL_DEPTH)
+        .saturating_mul(max_depth)
+        .max(STACK_SIZE);
+    std::thread::scope(|s| {
+        std::thread::Builder::new()
+            .name("interpreter".into())
+            .stack_size(stack_size)
+            .spawn_scoped(s, || run_main(ast, decor, max_depth))
+            .expect("failed to spawn interpreter thread")
+            .join()
+            .expect("interpreter panicked")
+    })
+}
+
+fn run_main(ast: &Ast, decor: &Decorations, max_depth: usize) -> EvalResult<i64> {
+    let mut interp = Interpreter {
+        decor,
+        calls: CallGraph::build(ast),
+        max_depth,
+        funcs: HashMap::new(),
+        aliases: HashMap::new(),
+        globals: HashMap::new(),
+        frames: Vec::new(),
+    };
+    for ext in &ast.0 {
+        match ext {
+            ExtDecl::Global(g) if alias_target(&g.attrs).is_some() => {
+                let target = alias_target(&g.attrs).expect("alias");
+                interp.aliases.entry(g.name).or_insert(target);
+            }
+            ExtDecl::Func(f) => {
+                interp.funcs.insert(f.name, f);
+            }
+            // prototypes and extern declarations have no storage
+            ExtDecl::Global(g) if g.has_storage() => {
+                // arrays are not values of the interpreter, scalars may be braced
+                let value = match (&g.init, &g.data_type) {
+                    (Some(e), ty) if !matches!(ty, Type::Array(_, _)) => {
+                        match flatten(ty, e)?.values.get(&0) {
+                            Some(v) => eval_int(v)?.value,
+                            None => 0,
+                        }
+                    }
+                    _ => 0,
+                };
+                interp
+                    .globals
+                    .insert(g.id, wrap(value, &g.data_type, decor.char_signed));
+            }
+            ExtDecl::Global(_) | ExtDecl::StaticAssert(_) => {}
+        }
+    }
+
+    let main = *interp
+        .funcs
+        .get(&Symbol::from("main"))
+        .ok_or_else(|| CompileError::new("undefined reference to 'main'"))?;
+    interp.call(main, Vec::new(), main.span)
+}
+
+/// continue with the next statement or leave the function
+enum Flow {
+    Next,
+    Return(i64),
+}
+
+struct Interpreter<'a> {
+    decor: &'a Decorations,
+    /// names the direct recursion when the calls nest too deep
+    calls: CallGraph,
+    max_depth: usize,
+    /// function definitions by name
+    funcs: HashMap<Symbol, &'a FuncDecl>,
+    /// names declared `alias("target")` and their targets, the first declaration wins like
+    /// in the assembly
+    aliases: HashMap<Symbol, Symbol>,
+    /// values of global variables by declaration id
+    globals: HashMap<NodeId, i64>,
+    /// values of parameters and local variables of the active calls, missing if not initialized
+    frames: Vec<HashMap<NodeId, i64>>,
+}
+
+impl<'a> Interpreter<'a> {
+    fn call(&mut self, func: &'a FuncDecl, args: Vec<i64>, span: Span) -> EvalResult<i64> {
+        if self.frames.len() >= self.max_depth {
+            let msg = match self.calls.is_recursive(func.name) {
+                true => format!(
+                    "recursion of '{}' nested deeper than {} in interpreted program",
+                    func.name, self.max_depth
+                ),
+                false => format!(
+                    "calls nested deeper than {} in interpreted program",
+                    self.max_depth
+                ),
+            };
+            return Err(CompileError::at_span(msg, span));
+        }
+
+        // arguments missing for `()` leave the parameters uninitialized
+        let frame = func
+            .params
+            .iter()
+            .zip(args)
+            .map(|(p, v)| (p.id, wrap(v, &p.data_type, self.decor.char_signed)))
+            .collect();
+        self.frames.push(frame);
+        let flow = self.exec_cmp_stmt(&func.cmp_stmt);
+        self.frames.pop();
+
+        // reaching the end of a function returns 0, required for main only
+        match flow? {
+            Flow::Return(v) => Ok(wrap(v, &func.return_type, self.decor.char_signed)),
+            Flow::Next => Ok(0),
+        }
+    }
+
+    fn exec_cmp_stmt(&mut self, cmp_stmt: &'a CmpStmt) -> EvalResult<Flow> {
+        for stmt in &cmp_stmt.stmts {
+            if let Flow::Return(v) = self.exec_stmt(stmt)? {
+                return Ok(Flow::Return(v));
+            }
+        }
+        Ok(Flow::Next)
+    }
+
+    fn exec_stmt(&mut self, stmt: &'a Stmt) -> EvalResult<Flow> {
+        match &stmt.kind {
+            StmtKind::Compound(c) => return self.exec_cmp_stmt(c),
+            StmtKind::VarDecl(decl) => {
+                if let Some(e) = &decl.init {
+                    let v = wrap(self.eval(e)?, &decl.data_type, self.decor.char_signed);
+                    self.frame().insert(decl.id, v);
+                }
+            }
+            StmtKind::Assignment(name, e) => {
+                let v = self.eval(e)?;
+                let id = self.target(stmt.id, *name, stmt.span)?;
+                let ty = self.decor.table.decl(id).map(|d| d.data_type());
+                let v = ty.map_or(v, |t| wrap(v, &t, self.decor.char_signed));
+                match self.globals.get_mut(&id) {
+                    Some(g) => *g = v,
+                    None => {
+                        self.frame().insert(id, v);
+                    }
+                }
+            }
+            StmtKind::Return(e) => {
+                let v = match e {
+                    Some(e) => self.eval(e)?,
+                    None => 0,
+                };
+                return Ok(Flow::Return(v));
+            }
+            StmtKind::Expr(e) => {
+                self.eval(e)?;
+            }
+            // checked at compile time
+            StmtKind::StaticAssert(_) => {}
+            StmtKind::Asm(_) => {
+                return Err(CompileError::at_span(
+                    "asm statements are not supported by the interpreter",
+                    stmt.span,
+                ))
+            }
+        }
+        Ok(Flow::Next)
+    }
+
+    fn eval(&mut self, expr: &'a Expr) -> EvalResult<i64> {
+        let value = match &expr.kind {
+            ExprKind::IntConst(v) => *v,
+            ExprKind::StrLit(_) => {
+                return Err(CompileError::at_span(
+                    "string literals are not supported by the interpreter",
+                    expr.span,
+                ))
+            }
+            ExprKind::InitList(_) => {
+                return Err(CompileError::at_span(
+                    "initializer lists are not supported by the interpreter",
+                    expr.span,
+                ))
+            }
+            ExprKind::VarRef(name) => {
+                let id = self.target(expr.id, *name, expr.span)?;
+                self.globals
+                    .get(&id)
+                    .or_else(|| self.frames.last().and_then(|f| f.get(&id)))
+                    .copied()
+                    .ok_or_else(|| {
+                        CompileError::at_span(
+                            format!("'{}' is used uninitialized", name),
+                            expr.span,
+                        )
+                    })?
+            }
+            ExprKind::FunctionCall(name, args) if Builtin::of(name.as_str()).is_some() => {
+                match Builtin::of(name.as_str()).expect("builtin") {
+                    Builtin::Expect => {
+                        let value = self.eval(&args[0])?;
+                        self.eval(&args[1])?;
+                        value
+                    }
+                    Builtin::Trap => return Err(CompileError::at_span("trap executed", expr.span)),
+                    Builtin::Unreachable => {
+                        return Err(CompileError::at_span("unreachable code reached", expr.span))
+                    }
+                    Builtin::Atomic(_) => {
+                        return Err(CompileError::at_span(
+                            "atomic builtins are not supported by the interpreter",
+                            expr.span,
+                        ))
+                    }
+                }
+            }
+            ExprKind::FunctionCall(name, args) => {
+                let args = args
+                    .iter()
+                    .map(|a| self.eval(a))
+                    .collect::<EvalResult<Vec<_>>>()?;
+                let target = self.aliases.get(name).unwrap_or(name);
+                let func = *self.funcs.get(target).ok_or_else(|| {
+                    CompileError::at_span(format!("undefined reference to '{}'", name), expr.span)
+                })?;
+                self.call(func, args, expr.span)?
+            }
+            ExprKind::Arith(l, op, r) => {
+                let (l, r) = (self.eval(l)?, self.eval(r)?);
+                match op {
+                    ArithOp::Add => l.wrapping_add(r),
+                    ArithOp::Sub => l.wrapping_sub(r),
+                }
+            }
+            ExprKind::Unary(op, e) => {
+                let v = self.eval(e)?;
+                match op {
+                    UnaryOp::Plus => v,
+                    UnaryOp::Neg => v.wrapping_neg(),
+                }
+            }
+        };
+        Ok(self
+            .decor
+            .types
+            .get(expr.id)
+            .map_or(value, |t| wrap(value, t, self.decor.char_signed)))
+    }
+
+    /// declaration of the variable resolved by the analysis
+    fn target(&self, id: NodeId, name: Symbol, span: Span) -> EvalResult<NodeId> {
+        let table = &self.decor.table;
+        let mut decl = self.decor.symbols.get(id).copied();
+        let mut kind = decl.and_then(|d| table.decl(d)).map(|d| &d.kind);
+        // an extern declaration or a tentative definition refers to the definition in the
+        // translation unit
+        if let Some(DeclKind::Extern(_) | DeclKind::Tentative(_)) = kind {
+            let name = *self.aliases.get(&name).unwrap_or(&name);
+            let def = table.global_scope().and_then(|s| table.resolve(s, name));
+            decl = def.map(|d| d.id);
+            kind = def.map(|d| &d.kind);
+        }
+        match (decl, kind) {
+            (Some(_), Some(DeclKind::Extern(_))) => Err(CompileError::at_span(
+                format!("undefined reference to '{}'", name),
+                span,
+            )),
+            (
+                Some(d),
+                Some(
+                    DeclKind::GlobalVar(_)
+                    | DeclKind::Tentative(_)
+                    | DeclKind::Var(_)
+                    | DeclKind::Param(_),
+                ),
+            ) => Ok(d),
+            (Some(_), _) => Err(CompileError::at_span(
+                format!("'{}' is not a variable", name),
+                span,
+            )),
+            (None, _) => Err(CompileError::at_span(
+                format!("use of undeclared identifier '{}'", name),
+                span,
+            )),
+        }
+    }
+
+    fn frame(&mut self) -> &mut HashMap<NodeId, i64> {
+        self.frames.last_mut().expect("active call")
+    }
+}
+
+/// value converted to the integer type, wrapping around like the target, plain `char` is
+/// unsigned unless `char_signed`
+fn wrap(value: i64, ty: &Type, char_signed: bool) -> i64 {
+    match ty {
+        Type::Base(DataType::Char) if !char_signed => value as u8 as i64,
+        Type::Base(DataType::Char) => value as i8 as i64,
+        Type::Base(DataType::Short) => value as i16 as i64,
+        Type::Base(DataType::Int) => value as i32 as i64,
+        Type::Volatile(t) => wrap(value, t, char_signed),
+        _ => value,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use test_case::test_case;
+
+    use crate::{analyse, analyse_with, parse, scan, util::TargetOs};
+
+    use super::{interpret, interpret_with};
+
+    fn run(src: &str) -> Result<i64, String> {
+        let ast = parse(scan(src).unwrap()).unwrap();
+        let decor = analyse(&ast).unwrap();
+        interpret(&ast, &decor).map_err(|e| e.msg)
+    }
+
+    #[test_case("int main() { return 42; }" => Ok(42))]
+    #[test_case("int main() {}" => Ok(0); "falls off main")]
+    #[test_case("_Static_assert(1); int main() { _Static_assert(1, \"x\"); return 3; }" => Ok(3))]
+    #[test_case("int add(int a, int b) { return a + b; } int main() { return add(40, 2) - -1; }" => Ok(43))]
+    #[test_case("int g = 5; int main() { int g = 2; { int g = 3; } return g; }" => Ok(2))]
+    #[test_case("int g = 5; int main() { { return g; } return 1; }" => Ok(5))]
+    #[test_case("int f(int); int main() { return f(1); } int f(int a) { return a + 1; }" => Ok(2))]
+    #[test_case("int main() { return 2147483647 + 1; }" => Ok(-2147483648); "wraps int")]
+    #[test_case("int f(int n) { return n + f(n); } int main() { return f(1); }" => Err("recursion of 'f' nested deeper than 10000 in interpreted program".to_string()))]
+    #[test_case("int g(int n); int f(int n) { return g(n); } int g(int n) { return f(n); } int main() { return f(1); }" => Err("calls nested deeper than 10000 in interpreted program".to_string()); "mutual recursion")]
+    #[test_case("int main() { int a; return a; }" => Err("'a' is used uninitialized".to_string()))]
+    #[test_case("int main() { return b; }" => Err("use of undeclared identifier 'b'".to_string()))]
+    #[test_case("int puts(); int main() { puts(); }" => Err("undefined reference to 'puts'".to_string()))]
+    #[test_case("extern int g; int main() { return g + 1; } int g = 4;" => Ok(5))]
+    #[test_case("int g; int main() { return g; } int g = 6; int g;" => Ok(6); "tentative definition")]
+    #[test_case("extern int errno; int main() { return errno; }" => Err("undefined reference to 'errno'".to_string()))]
+    #[test_case("int main() { asm(\"nop\"); return 0; }" => Err("asm statements are not supported by the interpreter".to_string()))]
+    #[test_case("int main() { return __builtin_expect(3 - 1, 0); }" => Ok(2))]
+    #[test_case("static int f(void) { return 7; } int g(void) __attribute__((alias(\"f\"))); int main() { return g(); }" => Ok(7); "function alias")]
+    #[test_case("int counter = 3; extern int count __attribute__((alias(\"counter\"))); int main() { return count + 1; }" => Ok(4); "variable alias")]
+    #[test_case("int main() { __builtin_trap(); }" => Err("trap executed".to_string()))]
+    #[test_case("int main() { __builtin_unreachable(); }" => Err("unreachable code reached".to_string()))]
+    fn interpret_program(src: &str) -> Result<i64, String> {
+        run(src)
+    }
+
+    // plain char wraps like the target, unsigned on Linux and signed on macOS
+    #[test_case(TargetOs::Linux, "int main() { char c = 200; return c; }" => Ok(200))]
+    #[test_case(TargetOs::MacOs, "int main() { char c = 200; return c; }" => Ok(-56))]
+    #[test_case(TargetOs::Linux, "char f(void) { return -1; } int main() { int i = f(); return i; }" => Ok(255))]
+    #[test_case(TargetOs::MacOs, "char f(void) { return -1; } int main() { int i = f(); return i; }" => Ok(-1))]
+    fn plain_char(target: TargetOs, src: &str) -> Result<i64, String> {
+        let ast = parse(scan(src).unwrap()).unwrap();
+        let decor = analyse_with(&ast, target).unwrap();
+        interpret(&ast, &decor).map_err(|e| e.msg)
+    }
+
+    #[test_case(4 => Ok(1))]
+    #[test_case(3 => Err("calls nested deeper than 3 in interpreted program".to_string()))]
+    fn max_depth(depth: usize) -> Result<i64, String> {
+        let src = "int c() { return 1; } int b() { return c(); } int a() { return b(); } int main() { return a(); }";
+        let ast = parse(scan(src).unwrap()).unwrap();
+        let decor = analyse(&ast).unwrap();
+        interpret_with(&ast, &decor, depth).map_err(|e| e.msg)
+    }
+}
@@ -0,0 +1,798 @@
+//! Experimental JIT, run the generated code in memory without the assembler and linker
+//!
+//! The assembly of the code generator is encoded by a small assembler for the instructions it
+//! emits, copied to executable memory and `main` is called directly on aarch64 hosts. Only
+//! functions of the translation unit can be called, the data section is not mapped
+
+use std::collections::HashMap;
+
+use crate::{
+    ast::Ast,
+    codegen::{gen_asm, GenOpts},
+    common::CompileError,
+    util::TargetOs,
+};
+
+/// machine code of the text section
+#[derive(Debug, PartialEq)]
+pub struct Image {
+    /// instructions in order
+    pub code: Vec<u32>,
+    /// offset in instructions of the labels
+    pub labels: HashMap<String, usize>,
+}
+
+/// generate, encode and run `main` of the translation unit, return its exit code
+pub fn run_main(ast: &Ast, opts: &GenOpts) -> Result<i32, CompileError> {
+    let image = assemble(&gen_asm(ast, opts)?)?;
+    let entry = match opts.target {
+        TargetOs::MacOs => "_main",
+        _ => "main",
+    };
+    let offset = image
+        .labels
+        .get(entry)
+        .ok_or_else(|| CompileError::new("undefined reference to 'main'"))?;
+    execute(&image, *offset)
+}
+
+/// instruction or label of the text section
+enum Line<'a> {
+    Label(&'a str),
+    Inst(&'a str, Vec<&'a str>),
+}
+
+/// encode the text section of the assembly, labels are resolved in a second pass
+pub fn assemble(asm: &str) -> Result<Image, CompileError> {
+    let mut lines = Vec::new();
+    let mut aliases = Vec::new();
+    let mut text = true;
+    for line in asm.lines().map(str::trim).filter(|l| !l.is_empty()) {
+        match line {
+            ".text" => text = true,
+            // aliases follow the data, they name the address of the target
+            _ if line.starts_with(".set ") => {
+                if let [name, target] = split_operands(&line[5..])[..] {
+                    aliases.push((name, target));
+                }
+            }
+            ".data" | ".bss" => text = false,
+            // the literal pool and its alignment are part of the code
+            _ if text && (line.starts_with(".xword") || line.starts_with(".p2align")) => {
+                let (directive, ops) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+                lines.push(Line::Inst(directive, split_operands(ops)))
+            }
+            _ if !text || line.starts_with('.') && !line.ends_with(':') => {}
+            _ => lines.push(match line.strip_suffix(':') {
+                Some(label) => Line::Label(label),
+                None => {
+                    let (mnemonic, ops) =
+                        line.split_once(char::is_whitespace).unwrap_or((line, ""));
+                    Line::Inst(mnemonic, split_operands(ops))
+                }
+            }),
+        }
+    }
+
+    // the size of instructions does not depend on the labels
+    let mut labels = HashMap::new();
+    let mut pc = 0;
+    for line in &lines {
+        match line {
+            Line::Label(l) => {
+                labels.insert(l.to_string(), pc);
+            }
+            Line::Inst(m, ops) => pc += encode(m, ops, pc, &|_| Some(0)).map_or(1, |c| c.len()),
+        }
+    }
+    // the aliases of data are not in the code
+    for (name, target) in aliases {
+        if let Some(&at) = labels.get(target) {
+            labels.insert(name.to_string(), at);
+        }
+    }
+
+    let mut code = Vec::new();
+    for line in &lines {
+        if let Line::Inst(m, ops) = line {
+            let words = encode(m, ops, code.len(), &|l| labels.get(l).copied()).map_err(|e| {
+                CompileError::new(format!("jit: {} in '{} {}'", e, m, ops.join(", ")))
+            })?;
+            code.extend(words);
+        }
+    }
+    Ok(Image { code, labels })
+}
+
+/// operands separated by commas outside of brackets
+fn split_operands(ops: &str) -> Vec<&str> {
+    let mut res = Vec::new();
+    let (mut depth, mut start) = (0, 0);
+    for (i, c) in ops.char_indices() {
+        match c {
+            '[' => depth += 1,
+            ']' => depth -= 1,
+            ',' if depth == 0 => {
+                res.push(ops[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    if !ops[start..].trim().is_empty() {
+        res.push(ops[start..].trim());
+    }
+    res
+}
+
+type Encoded = Result<Vec<u32>, String>;
+
+/// encode the instruction at the offset `pc`, `resolve` gives the offset of a label
+fn encode(
+    mnemonic: &str,
+    ops: &[&str],
+    pc: usize,
+    resolve: &dyn Fn(&str) -> Option<usize>,
+) -> Encoded {
+    match (mnemonic, ops) {
+        ("ret", []) => Ok(vec![0xd65f_03c0]),
+        ("b", [label]) => branch(0x1400_0000, label, pc, resolve),
+        ("bl", [label]) => branch(0x9400_0000, label, pc, resolve),
+        ("mov", [rd, imm]) if imm.starts_with('#') => Ok(mov_imm(reg(rd)?, imm_value(imm)?)),
+        ("movz", [rd, imm, shift]) => mov_wide(0xd280_0000, rd, imm, shift),
+        ("movn", [rd, imm, shift]) => mov_wide(0x9280_0000, rd, imm, shift),
+        ("movk", [rd, imm, shift]) => mov_wide(0xf280_0000, rd, imm, shift),
+        // moves from or to sp are additions
+        ("mov", [rd, rn]) if *rd == "sp" || *rn == "sp" => add_imm(0x9100_0000, rd, rn, "#0"),
+        ("mov", [rd, rm]) => arith_reg(0x2a00_03e0, rd, "wzr", rm),
+        ("add", [rd, rn, rm]) if is_reg(rm) => arith_reg(0x0b00_0000, rd, rn, rm),
+        ("sub", [rd, rn, rm]) if is_reg(rm) => arith_reg(0x4b00_0000, rd, rn, rm),
+        ("neg", [rd, rm]) => arith_reg(0x4b00_0000, rd, "wzr", rm),
+        ("cmp", [rn, rm]) => arith_reg(0x6b00_0000, "wzr", rn, rm),
+        ("uxtb", [rd, rn]) if rd.starts_with('w') => {
+            Ok(vec![0x5300_1c00 | reg_w(rn)? << 5 | reg_w(rd)?])
+        }
+        ("uxth", [rd, rn]) if rd.starts_with('w') => {
+            Ok(vec![0x5300_3c00 | reg_w(rn)? << 5 | reg_w(rd)?])
+        }
+        ("b.ne", [label]) => cond_branch(0x5400_0001, label, pc, resolve),
+        ("cbnz", [rt, label]) => {
+            let op = match rt.starts_with('w') {
+                true => 0x3500_0000,
+                false => 0xb500_0000,
+            };
+            cond_branch(op | reg_w(rt)?, label, pc, resolve)
+        }
+        ("dmb", ["ish"]) => Ok(vec![0xd503_3bbf]),
+        ("brk", [imm]) => match imm_value(imm)? {
+            v @ 0..=0xffff => Ok(vec![0xd420_0000 | (v as u32) << 5]),
+            _ => Err(format!("invalid immediate '{}'", imm)),
+        },
+        (
+            "ldxr" | "ldxrb" | "ldxrh" | "ldaxr" | "ldaxrb" | "ldaxrh" | "ldar" | "ldarb" | "ldarh"
+            | "stlr" | "stlrb" | "stlrh",
+            [rt, mem],
+        ) => exclusive(mnemonic, "wzr", rt, mem),
+        ("stxr" | "stxrb" | "stxrh" | "stlxr" | "stlxrb" | "stlxrh", [rs, rt, mem]) => {
+            exclusive(mnemonic, rs, rt, mem)
+        }
+        (m, [rs, rt, mem])
+            if m.starts_with("ldadd") || m.starts_with("swp") || m.starts_with("cas") =>
+        {
+            atomic(m, rs, rt, mem)
+        }
+        ("sxtb" | "sxth" | "sxtw", [rd, rn]) => sign_extend(mnemonic, rd, rn),
+        ("add", [rd, rn, imm]) => add_imm(0x9100_0000, rd, rn, imm),
+        ("sub", [rd, rn, imm]) => add_imm(0xd100_0000, rd, rn, imm),
+        ("stp" | "ldp", [rt, rt2, mem]) => pair(mnemonic == "ldp", rt, rt2, mem, None),
+        ("stp" | "ldp", [rt, rt2, mem, imm]) => pair(mnemonic == "ldp", rt, rt2, mem, Some(imm)),
+        ("ldr", [rt, label]) if !label.starts_with('[') => literal(rt, label, pc, resolve),
+        ("str" | "ldr", [rt, mem, imm]) => post_index(mnemonic, rt, mem, imm),
+        ("str" | "strb" | "strh" | "ldr" | "ldrb" | "ldrh", [rt, mem]) => {
+            load_store(mnemonic, rt, mem)
+        }
+        // data of the literal pool, little endian
+        (".xword", [value]) => {
+            let v = value
+                .parse::<i64>()
+                .map_err(|_| format!("invalid value '{}'", value))?;
+            Ok(vec![v as u32, (v >> 32) as u32])
+        }
+        // padded with nop
+        (".p2align", [n]) => {
+            let align = n
+                .parse::<u32>()
+                .ok()
+                .filter(|n| (2..=12).contains(n))
+                .map(|n| 1usize << (n - 2))
+                .ok_or_else(|| format!("invalid alignment '{}'", n))?;
+            Ok(vec![0xd503_201f; (align - pc % align) % align])
+        }
+        _ => Err("unsupported instruction".into()),
+    }
+}
+
+/// `b` or `bl` to the label
+fn branch(op: u32, label: &str, pc: usize, resolve: &dyn Fn(&str) -> Option<usize>) -> Encoded {
+    let target = resolve(label).ok_or_else(|| format!("undefined reference to '{}'", label))?;
+    let offset = target as i64 - pc as i64;
+    match (-(1 << 25)..(1 << 25)).contains(&offset) {
+        true => Ok(vec![op | (offset as u32 & 0x03ff_ffff)]),
+        false => Err("branch out of range".into()),
+    }
+}
+
+/// conditional branch or `cbnz` to the label, within 1MB
+fn cond_branch(
+    op: u32,
+    label: &str,
+    pc: usize,
+    resolve: &dyn Fn(&str) -> Option<usize>,
+) -> Encoded {
+    let target = resolve(label).ok_or_else(|| format!("undefined reference to '{}'", label))?;
+    let offset = target as i64 - pc as i64;
+    match (-(1 << 18)..(1 << 18)).contains(&offset) {
+        true => Ok(vec![op | (offset as u32 & 0x7ffff) << 5]),
+        false => Err("branch out of range".into()),
+    }
+}
+
+/// `orr`, `add`, `sub` or `subs` of registers of the same size, `mov`, `neg` and `cmp` use the
+/// zero register
+fn arith_reg(op: u32, rd: &str, rn: &str, rm: &str) -> Encoded {
+    let sf = match rm.starts_with('x') {
+        true => 0x8000_0000,
+        false => 0,
+    };
+    Ok(vec![
+        op | sf | reg_w(rm)? << 16 | reg_w(rn)? << 5 | reg_w(rd)?,
+    ])
+}
+
+/// size field of the load and store instructions, from the suffix `b` or `h` or the register
+fn access_size(mnemonic: &str, rt: &str) -> u32 {
+    match (mnemonic.chars().last(), rt.starts_with('x')) {
+        (Some('b'), _) => 0,
+        (Some('h'), _) => 1,
+        (_, false) => 2,
+        (_, true) => 3,
+    }
+}
+
+/// base register of `[base]`
+fn base_only(mem: &str) -> Result<u32, String> {
+    match memory(mem)? {
+        (base, 0, false) => Ok(base),
+        _ => Err("unsupported addressing mode".into()),
+    }
+}
+
+/// exclusive loads and stores, `ldar` and `stlr`, `rs` receives the status of the exclusive
+/// stores
+fn exclusive(mnemonic: &str, rs: &str, rt: &str, mem: &str) -> Encoded {
+    let op = match mnemonic.trim_end_matches(['b', 'h']) {
+        "ldxr" => 0x085f_7c00,
+        "ldaxr" => 0x085f_fc00,
+        "ldar" => 0x08df_fc00,
+        "stlr" => 0x089f_fc00,
+        "stxr" => 0x0800_7c00,
+        _ => 0x0800_fc00,
+    };
+    let size = access_size(mnemonic, rt);
+    Ok(vec![
+        op | size << 30 | reg_w(rs)? << 16 | base_only(mem)? << 5 | reg_w(rt)?,
+    ])
+}
+
+/// `ldadd`, `swp` and `cas` of ARMv8.1 with the acquire `a` and release `l` suffixes
+fn atomic(mnemonic: &str, rs: &str, rt: &str, mem: &str) -> Encoded {
+    let (op, rest, acquire, release) = match mnemonic {
+        m if m.starts_with("ldadd") => (0x3820_0000, &m[5..], 0x0080_0000, 0x0040_0000),
+        m if m.starts_with("swp") => (0x3820_8000, &m[3..], 0x0080_0000, 0x0040_0000),
+        m => (0x08a0_7c00, &m[3..], 0x0040_0000, 0x8000),
+    };
+    let (op, rest) = match rest.strip_prefix('a') {
+        Some(r) => (op | acquire, r),
+        None => (op, rest),
+    };
+    let (op, rest) = match rest.strip_prefix('l') {
+        Some(r) => (op | release, r),
+        None => (op, rest),
+    };
+    if !["", "b", "h"].contains(&rest) {
+        return Err("unsupported instruction".into());
+    }
+    let size = access_size(mnemonic, rt);
+    Ok(vec![
+        op | size << 30 | reg_w(rs)? << 16 | base_only(mem)? << 5 | reg_w(rt)?,
+    ])
+}
+
+/// `movz` or `movn` followed by `movk` for the other 16 bit chunks
+fn mov_imm(rd: u32, value: i64) -> Vec<u32> {
+    let fill = if value < 0 { 0xffff } else { 0 };
+    let chunk = |i: u32| (value >> (16 * i)) as u32 & 0xffff;
+    let mut chunks = (0..4).filter(|i| chunk(*i) != fill).peekable();
+    let first = chunks.peek().copied().unwrap_or(0);
+    let head = match value < 0 {
+        true => 0x9280_0000 | first << 21 | (!chunk(first) & 0xffff) << 5 | rd,
+        false => 0xd280_0000 | first << 21 | chunk(first) << 5 | rd,
+    };
+    std::iter::once(head)
+        .chain(
+            chunks
+                .skip(1)
+                .map(|i| 0xf280_0000 | i << 21 | chunk(i) << 5 | rd),
+        )
+        .collect()
+}
+
+/// `sxtb`, `sxth` and `sxtw` of the word register to a word or double word, aliases of `sbfm`
+fn sign_extend(mnemonic: &str, rd: &str, rn: &str) -> Encoded {
+    let bits = match mnemonic {
+        "sxtb" => 8,
+        "sxth" => 16,
+        _ => 32,
+    };
+    let op = match (rd.starts_with('w'), bits) {
+        (true, 32) => return Err(format!("invalid register '{}'", rd)),
+        (true, _) => 0x1300_0000,
+        (false, _) => 0x9340_0000,
+    };
+    let (rd, rn) = (
+        reg(&rd.replacen('w', "x", 1))?,
+        reg(&rn.replacen('w', "x", 1))?,
+    );
+    Ok(vec![op | (bits - 1) << 10 | rn << 5 | rd])
+}
+
+/// `movz`, `movn` or `movk` of a 16 bit chunk shifted by `lsl #n`
+fn mov_wide(op: u32, rd: &str, imm: &str, shift: &str) -> Encoded {
+    let hw = shift
+        .strip_prefix("lsl ")
+        .and_then(|s| imm_value(s).ok())
+        .filter(|s| [0, 16, 32, 48].contains(s))
+        .ok_or_else(|| format!("invalid shift '{}'", shift))?;
+    match imm_value(imm)? {
+        v @ 0..=0xffff => Ok(vec![
+            op | (hw as u32 / 16) << 21 | (v as u32) << 5 | reg(rd)?,
+        ]),
+        _ => Err("immediate out of range".into()),
+    }
+}
+
+/// `ldr` of a word or double word at the label, within 1MB
+fn literal(rt: &str, label: &str, pc: usize, resolve: &dyn Fn(&str) -> Option<usize>) -> Encoded {
+    let op = match rt.starts_with('w') {
+        true => 0x1800_0000,
+        false => 0x5800_0000,
+    };
+    let rt = reg(&rt.replacen('w', "x", 1))?;
+    let target = resolve(label).ok_or_else(|| format!("undefined reference to '{}'", label))?;
+    let offset = target as i64 - pc as i64;
+    match (-(1 << 18)..(1 << 18)).contains(&offset) {
+        true => Ok(vec![op | (offset as u32 & 0x7ffff) << 5 | rt]),
+        false => Err("literal out of range".into()),
+    }
+}
+
+/// `add` or `sub` with 12 bit immediate
+fn add_imm(op: u32, rd: &str, rn: &str, imm: &str) -> Encoded {
+    match imm_value(imm)? {
+        v @ 0..=4095 => Ok(vec![op | (v as u32) << 10 | reg(rn)? << 5 | reg(rd)?]),
+        _ => Err("immediate out of range".into()),
+    }
+}
+
+/// `stp` and `ldp` of words or double words at an offset, pre-indexed or post-indexed, by a
+/// multiple of the size
+fn pair(load: bool, rt: &str, rt2: &str, mem: &str, post: Option<&str>) -> Encoded {
+    let (base, offset, pre) = memory(mem)?;
+    let (mode, offset) = match (pre, post) {
+        (false, None) => (0x0100_0000, offset),
+        (true, None) => (0x0180_0000, offset),
+        (false, Some(imm)) if offset == 0 => (0x0080_0000, imm_value(imm)?),
+        _ => return Err("unsupported addressing mode".into()),
+    };
+    let (size, op) = match rt.starts_with('w') {
+        true => (4, 0x2800_0000),
+        false => (8, 0xa800_0000),
+    };
+    let op = match load {
+        true => op | mode | 0x0040_0000,
+        false => op | mode,
+    };
+    let (rt, rt2) = (
+        reg(&rt.replacen('w', "x", 1))?,
+        reg(&rt2.replacen('w', "x", 1))?,
+    );
+    match offset % size == 0 && (-64 * size..64 * size).contains(&offset) {
+        true => {
+            let imm7 = (offset / size) as u32 & 0x7f;
+            Ok(vec![op | imm7 << 15 | rt2 << 10 | base << 5 | rt])
+        }
+        false => Err("offset out of range".into()),
+    }
+}
+
+/// `str`, `strb`, `strh` and the `ldr` forms with scaled offset, the unscaled `stur` and `ldur`
+/// forms for offsets not a multiple of the size
+///
+/// `str wN` stores the low 4 bytes of the register, `ldrb` and `ldrh` zero extend
+fn load_store(mnemonic: &str, rt: &str, mem: &str) -> Encoded {
+    let (base, offset, pre) = memory(mem)?;
+    // the size follows 'ld' or 'st'
+    let (size, op) = match (&mnemonic[2..], rt.starts_with('w')) {
+        ("rb", true) => (1, 0x3800_0000),
+        ("rh", true) => (2, 0x7800_0000),
+        ("r", true) => (4, 0xb800_0000),
+        ("r", false) => (8, 0xf800_0000),
+        _ => return Err(format!("invalid register '{}'", rt)),
+    };
+    let op = match mnemonic.starts_with("ld") {
+        true => op | 0x0040_0000,
+        false => op,
+    };
+    let rt = reg(&rt.replacen('w', "x", 1))?;
+    match offset {
+        -256..=255 if pre => Ok(vec![
+            op | (offset as u32 & 0x1ff) << 12 | 0xc00 | base << 5 | rt,
+        ]),
+        _ if pre => Err("offset out of range".into()),
+        0.. if offset % size == 0 && offset / size < 4096 => Ok(vec![
+            op | 0x0100_0000 | ((offset / size) as u32) << 10 | base << 5 | rt,
+        ]),
+        -256..=255 => Ok(vec![op | (offset as u32 & 0x1ff) << 12 | base << 5 | rt]),
+        _ => Err("offset out of range".into()),
+    }
+}
+
+/// `ldr xN, [base], #imm` and `str` adding the offset to the base after the access
+fn post_index(mnemonic: &str, rt: &str, mem: &str, imm: &str) -> Encoded {
+    let offset = imm_value(imm)?;
+    let op = match (mnemonic, rt.starts_with('x')) {
+        ("str", true) => 0xf800_0400,
+        ("ldr", true) => 0xf840_0400,
+        _ => return Err(format!("invalid register '{}'", rt)),
+    };
+    match (base_only(mem)?, offset) {
+        (base, -256..=255) => Ok(vec![
+            op | (offset as u32 & 0x1ff) << 12 | base << 5 | reg(rt)?,
+        ]),
+        _ => Err("offset out of range".into()),
+    }
+}
+
+/// base register, offset and pre-index of `[base, #offset]!`
+fn memory(mem: &str) -> Result<(u32, i64, bool), String> {
+    let (inner, pre) = match mem.strip_suffix('!') {
+        Some(m) => (m, true),
+        None => (mem, false),
+    };
+    let inner = inner
+        .strip_prefix('[')
+        .and_then(|m| m.strip_suffix(']'))
+        .ok_or_else(|| format!("invalid memory operand '{}'", mem))?;
+    match split_operands(inner).as_slice() {
+        [base] => Ok((reg(base)?, 0, pre)),
+        [base, imm] => Ok((reg(base)?, imm_value(imm)?, pre)),
+        _ => Err(format!("invalid memory operand '{}'", mem)),
+    }
+}
+
+/// number of a 64 bit register, 31 is `sp` or `xzr` depending on the instruction
+fn reg(name: &str) -> Result<u32, String> {
+    match name {
+        "sp" | "xzr" => Ok(31),
+        _ => name
+            .strip_prefix('x')
+            .and_then(|n| n.parse().ok())
+            .filter(|n| *n <= 30)
+            .ok_or_else(|| format!("invalid register '{}'", name)),
+    }
+}
+
+/// number of a word or double word register, `wzr` and `xzr` are 31
+fn reg_w(name: &str) -> Result<u32, String> {
+    match name {
+        "wzr" => Ok(31),
+        _ => reg(&name.replacen('w', "x", 1)),
+    }
+}
+
+/// general purpose register, not an immediate or a relocation
+fn is_reg(op: &str) -> bool {
+    reg_w(op).is_ok()
+}
+
+fn imm_value(imm: &str) -> Result<i64, String> {
+    imm.strip_prefix('#')
+        .and_then(|v| v.parse().ok())
+        .ok_or_else(|| format!("invalid immediate '{}'", imm))
+}
+
+#[cfg(all(unix, target_arch = "aarch64"))]
+fn execute(image: &Image, entry: usize) -> Result<i32, CompileError> {
+    let buf = exec::ExecBuf::new(&image.code)
+        .map_err(|e| CompileError::new(format!("jit: failed to map code: {}", e)))?;
+    // safety: the code is generated for the C calling convention and returns from main
+    let main: extern "C" fn() -> i32 = unsafe { std::mem::transmute(buf.addr(entry)) };
+    Ok(main())
+}
+
+#[cfg(not(all(unix, target_arch = "aarch64")))]
+fn execute(_image: &Image, _entry: usize) -> Result<i32, CompileError> {
+    Err(CompileError::new(format!(
+        "jit runs on aarch64 hosts only, not {}",
+        std::env::consts::ARCH
+    )))
+}
+
+#[cfg(unix)]
+#[cfg_attr(not(target_arch = "aarch64"), allow(dead_code))]
+mod exec {
+    use std::{io, ptr};
+
+    /// copy of the code in executable memory, unmapped when dropped
+    pub struct ExecBuf {
+        ptr: *mut libc::c_void,
+        len: usize,
+    }
+
+    impl ExecBuf {
+        /// map writable memory for the code, then make it executable and read only
+        pub fn new(code: &[u32]) -> io::Result<Self> {
+            let bytes: Vec<u8> = code.iter().flat_map(|w| w.to_le_bytes()).collect();
+            let len = bytes.len().max(1);
+            // safety: a fresh private mapping is only accessed within its length
+            unsafe {
+                let ptr = libc::mmap(
+                    ptr::null_mut(),
+                    len,
+                    libc::PROT_READ | libc::PROT_WRITE,
+                    libc::MAP_PRIVATE | libc::MAP_ANON,
+                    -1,
+                    0,
+                );
+                if ptr == libc::MAP_FAILED {
+                    return Err(io::Error::last_os_error());
+                }
+                let buf = ExecBuf { ptr, len };
+                ptr::copy_nonoverlapping(bytes.as_ptr(), ptr as *mut u8, bytes.len());
+                flush_icache(ptr as usize, len);
+                if libc::mprotect(ptr, len, libc::PROT_READ | libc::PROT_EXEC) != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(buf)
+            }
+        }
+
+        /// address of the instruction at the offset
+        pub fn addr(&self, offset: usize) -> *const u8 {
+            (self.ptr as *const u8).wrapping_add(offset * 4)
+        }
+    }
+
+    impl Drop for ExecBuf {
+        fn drop(&mut self) {
+            // safety: the mapping is owned by the buffer
+            unsafe {
+                libc::munmap(self.ptr, self.len);
+            }
+        }
+    }
+
+    /// make the written instructions visible to the instruction fetch
+    #[cfg(target_arch = "aarch64")]
+    unsafe fn flush_icache(start: usize, len: usize) {
+        use std::arch::asm;
+
+        let ctr: u64;
+        asm!("mrs {}, ctr_el0", out(reg) ctr);
+        let dline = 4usize << ((ctr >> 16) & 0xf);
+        let iline = 4usize << (ctr & 0xf);
+        let end = start + len;
+
+        let mut addr = start & !(dline - 1);
+        while addr < end {
+            asm!("dc cvau, {}", in(reg) addr);
+            addr += dline;
+        }
+        asm!("dsb ish");
+        let mut addr = start & !(iline - 1);
+        while addr < end {
+            asm!("ic ivau, {}", in(reg) addr);
+            addr += iline;
+        }
+        asm!("dsb ish", "isb");
+    }
+
+    /// other hosts do not run the code
+    #[cfg(not(target_arch = "aarch64"))]
+    unsafe fn flush_icache(_start: usize, _len: usize) {}
+}
+
+#[cfg(test)]
+mod test {
+    use test_case::test_case;
+
+    use crate::{
+        golden, parse, scan,
+        util::{Arch, OptLevel, TargetOs},
+        GenOpts,
+    };
+
+    use super::{assemble, encode};
+
+    #[test_case("ret", &[] => Ok(vec![0xd65f03c0]))]
+    #[test_case("mov", &["x0", "#1"] => Ok(vec![0xd2800020]))]
+    #[test_case("mov", &["x0", "#-1"] => Ok(vec![0x92800000]))]
+    #[test_case("mov", &["x0", "#65536"] => Ok(vec![0xd2a00020]))]
+    #[test_case("mov", &["x1", "#-2147483648"] => Ok(vec![0x929fffe1, 0xf2b00001]); "min int")]
+    #[test_case("mov", &["x29", "sp"] => Ok(vec![0x910003fd]))]
+    #[test_case("mov", &["x1", "x0"] => Ok(vec![0xaa0003e1]))]
+    #[test_case("sub", &["sp", "sp", "#16"] => Ok(vec![0xd10043ff]))]
+    #[test_case("add", &["sp", "sp", "#16"] => Ok(vec![0x910043ff]))]
+    #[test_case("stp", &["x29", "x30", "[sp, #-16]!"] => Ok(vec![0xa9bf7bfd]))]
+    #[test_case("ldp", &["x29", "x30", "[sp]", "#16"] => Ok(vec![0xa8c17bfd]))]
+    #[test_case("stp", &["w1", "w0", "[sp, #24]"] => Ok(vec![0x290303e1]); "word pair")]
+    #[test_case("stp", &["wzr", "wzr", "[sp, #8]"] => Ok(vec![0x29017fff]); "zero pair")]
+    #[test_case("stp", &["x3", "x2", "[sp, #504]"] => Ok(vec![0xa91f8be3]))]
+    #[test_case("stp", &["x3", "x2", "[sp, #512]"] => Err("offset out of range".to_string()))]
+    #[test_case("ldp", &["x9", "x10", "[sp, #16]"] => Ok(vec![0xa9412be9]))]
+    #[test_case("ldp", &["w9", "w10", "[sp, #0]"] => Ok(vec![0x29402be9]); "word load pair")]
+    #[test_case("str", &["x0", "[sp, #8]"] => Ok(vec![0xf90007e0]))]
+    #[test_case("str", &["x0", "[sp, #12]"] => Ok(vec![0xf800c3e0]); "unscaled")]
+    #[test_case("str", &["w1", "[sp, #12]"] => Ok(vec![0xb9000fe1]); "word")]
+    #[test_case("str", &["w0", "[sp, #2]"] => Ok(vec![0xb80023e0]); "unscaled word")]
+    #[test_case("str", &["wzr", "[sp, #4]"] => Ok(vec![0xb90007ff]); "zero word")]
+    #[test_case("strb", &["w2", "[sp, #15]"] => Ok(vec![0x39003fe2]))]
+    #[test_case("strh", &["w0", "[sp, #6]"] => Ok(vec![0x79000fe0]))]
+    #[test_case("strb", &["x0", "[sp]"] => Err("invalid register 'x0'".to_string()))]
+    #[test_case("ldr", &["w9", "[sp, #12]"] => Ok(vec![0xb9400fe9]))]
+    #[test_case("ldr", &["x9", "[sp, #12]"] => Ok(vec![0xf840c3e9]); "unscaled load")]
+    #[test_case("ldrb", &["w9", "[sp, #3]"] => Ok(vec![0x39400fe9]))]
+    #[test_case("str", &["x0", "[sp, #-16]!"] => Ok(vec![0xf81f0fe0]); "pre-index")]
+    #[test_case("ldr", &["x1", "[sp]", "#16"] => Ok(vec![0xf84107e1]); "post-index")]
+    #[test_case("ldr", &["w1", "[sp]", "#16"] => Err("invalid register 'w1'".to_string()); "post-index word")]
+    #[test_case("ldrh", &["w9", "[sp, #6]"] => Ok(vec![0x79400fe9]))]
+    #[test_case("sxtb", &["w0", "w0"] => Ok(vec![0x13001c00]))]
+    #[test_case("sxtb", &["x1", "w1"] => Ok(vec![0x93401c21]); "byte to double word")]
+    #[test_case("sxth", &["w9", "w9"] => Ok(vec![0x13003d29]))]
+    #[test_case("sxtw", &["x0", "w0"] => Ok(vec![0x93407c00]))]
+    #[test_case("sxtw", &["w0", "w0"] => Err("invalid register 'w0'".to_string()))]
+    #[test_case("movz", &["x9", "#22136", "lsl #0"] => Ok(vec![0xd28acf09]))]
+    #[test_case("movk", &["x9", "#4660", "lsl #16"] => Ok(vec![0xf2a24689]))]
+    #[test_case("movn", &["x9", "#34463", "lsl #0"] => Ok(vec![0x9290d3e9]))]
+    #[test_case("movk", &["x0", "#1", "lsl #48"] => Ok(vec![0xf2e00020]))]
+    #[test_case("movk", &["x0", "#1", "lsl #8"] => Err("invalid shift 'lsl #8'".to_string()))]
+    #[test_case("ldr", &["x0", "end"] => Ok(vec![0x58000040]); "literal")]
+    #[test_case("ldr", &["w0", "start"] => Ok(vec![0x18ffffe0]); "word literal")]
+    #[test_case(".xword", &["81985529216486895"] => Ok(vec![0x89abcdef, 0x01234567]))]
+    #[test_case(".p2align", &["3"] => Ok(vec![0xd503201f]))]
+    #[test_case(".p2align", &["2"] => Ok(vec![]))]
+    #[test_case("b", &["end"] => Ok(vec![0x14000002]))]
+    #[test_case("bl", &["start"] => Ok(vec![0x97ffffff]))]
+    #[test_case("bl", &["puts"] => Err("undefined reference to 'puts'".to_string()))]
+    #[test_case("ldr", &["x0", "[sp]"] => Ok(vec![0xf94003e0]))]
+    #[test_case("mul", &["x0", "x1", "x2"] => Err("unsupported instruction".to_string()))]
+    #[test_case("mov", &["w0", "w9"] => Ok(vec![0x2a0903e0]); "word move")]
+    #[test_case("add", &["w10", "w9", "w1"] => Ok(vec![0x0b01012a]))]
+    #[test_case("sub", &["x10", "x9", "x1"] => Ok(vec![0xcb01012a]))]
+    #[test_case("neg", &["w10", "w1"] => Ok(vec![0x4b0103ea]))]
+    #[test_case("cmp", &["w9", "w10"] => Ok(vec![0x6b0a013f]))]
+    #[test_case("uxtb", &["w10", "w1"] => Ok(vec![0x53001c2a]))]
+    #[test_case("uxth", &["w10", "w1"] => Ok(vec![0x53003c2a]))]
+    #[test_case("b.ne", &["end"] => Ok(vec![0x54000041]))]
+    #[test_case("cbnz", &["w11", "start"] => Ok(vec![0x35ffffeb]))]
+    #[test_case("dmb", &["ish"] => Ok(vec![0xd5033bbf]))]
+    #[test_case("brk", &["#1000"] => Ok(vec![0xd4207d00]))]
+    #[test_case("brk", &["#65536"] => Err("invalid immediate '#65536'".to_string()))]
+    #[test_case("ldaxr", &["w9", "[x0]"] => Ok(vec![0x885ffc09]))]
+    #[test_case("ldxr", &["x9", "[x0]"] => Ok(vec![0xc85f7c09]))]
+    #[test_case("ldaxrb", &["w9", "[x0]"] => Ok(vec![0x085ffc09]))]
+    #[test_case("stlxr", &["w11", "w10", "[x0]"] => Ok(vec![0x880bfc0a]))]
+    #[test_case("stxrh", &["w11", "w1", "[x0]"] => Ok(vec![0x480b7c01]))]
+    #[test_case("ldar", &["w9", "[x0]"] => Ok(vec![0x88dffc09]))]
+    #[test_case("stlrb", &["w1", "[x0]"] => Ok(vec![0x089ffc01]))]
+    #[test_case("ldaddal", &["w1", "w9", "[x0]"] => Ok(vec![0xb8e10009]))]
+    #[test_case("ldadd", &["x10", "x9", "[x0]"] => Ok(vec![0xf82a0009]))]
+    #[test_case("swpa", &["w1", "w9", "[x0]"] => Ok(vec![0xb8a18009]))]
+    #[test_case("casalb", &["w9", "w2", "[x0]"] => Ok(vec![0x08e9fc02]))]
+    #[test_case("casa", &["x9", "x2", "[x0]"] => Ok(vec![0xc8e97c02]))]
+    #[test_case("ldaddx", &["w1", "w9", "[x0]"] => Err("unsupported instruction".to_string()))]
+    fn encode_inst(mnemonic: &str, ops: &[&str]) -> Result<Vec<u32>, String> {
+        let resolve = |l: &str| match l {
+            "start" => Some(0),
+            "end" => Some(3),
+            _ => None,
+        };
+        encode(mnemonic, ops, 1, &resolve)
+    }
+
+    // every instruction of the golden outputs is encoded, branches resolve to their labels
+    #[test_case("function_call", TargetOs::MacOs, "_main", 6)]
+    #[test_case("early_return", TargetOs::MacOs, "Lfoo_epilogue", 3)]
+    #[test_case("globals", TargetOs::Linux, "main", 0)]
+    fn assemble_golden(name: &str, target: TargetOs, label: &str, offset: usize) {
+        let src = golden::read_source(name);
+        let asm = crate::gen_asm(&parse(scan(&src).unwrap()).unwrap(), &GenOpts::new(target));
+        let image = assemble(&asm.unwrap()).unwrap();
+        assert_eq!(image.labels.get(label), Some(&offset));
+    }
+
+    // paired accesses of the frame above -O0
+    #[test]
+    fn assemble_pairs() {
+        let src =
+            "int f(int a, int b) { int x[3] = {1}; return 0; } int main() { return f(1, 2); }";
+        let opts = GenOpts {
+            opt_level: OptLevel::O1,
+            ..GenOpts::new(TargetOs::Linux)
+        };
+        let asm = crate::gen_asm(&parse(scan(src).unwrap()).unwrap(), &opts).unwrap();
+        assert!(asm.contains("stp w1, w0, [sp, #24]"), "{}", asm);
+        assert!(assemble(&asm).is_ok());
+    }
+
+    // the pool after the function is aligned to 8 bytes
+    #[test]
+    fn assemble_literal_pool() {
+        let src = "void h(int a) {} int main() { h(81985529216486895); }";
+        let asm = crate::gen_asm(
+            &parse(scan(src).unwrap()).unwrap(),
+            &GenOpts::new(TargetOs::Linux),
+        )
+        .unwrap();
+        let image = assemble(&asm).unwrap();
+        let at = image.labels[".Llit0"];
+        assert_eq!(at % 2, 0);
+        assert_eq!(image.code[at..at + 2], [0x89abcdef, 0x01234567]);
+    }
+
+    // exclusive loops branch back to their label, LSE replaces them
+    #[test_case(Arch::Armv8)]
+    #[test_case(Arch::Armv8Lse)]
+    fn assemble_atomics(arch: Arch) {
+        let src = "int main() {
+                       int counter[1];
+                       char flag[4];
+                       __sync_fetch_and_add(counter, 1);
+                       __sync_val_compare_and_swap(flag, 1, 2);
+                       __atomic_store_n(flag, 0, __ATOMIC_RELEASE);
+                       __sync_synchronize();
+                       return __atomic_exchange_n(counter, 3, __ATOMIC_ACQ_REL);
+                   }";
+        let opts = GenOpts {
+            arch,
+            ..GenOpts::new(TargetOs::Linux)
+        };
+        let asm = crate::gen_asm(&parse(scan(src).unwrap()).unwrap(), &opts).unwrap();
+        assert!(assemble(&asm).is_ok(), "{}", asm);
+    }
+
+    // a call of an alias branches to the function of its target
+    #[test_case(TargetOs::Linux, "g", "f")]
+    #[test_case(TargetOs::MacOs, "_g", "_f")]
+    fn assemble_alias(target: TargetOs, alias: &str, func: &str) {
+        let src = "static int f(void) { return 7; } int g(void) __attribute__((alias(\"f\")));
+                   int main() { return g(); }";
+        let asm = crate::gen_asm(&parse(scan(src).unwrap()).unwrap(), &GenOpts::new(target));
+        let image = assemble(&asm.unwrap()).unwrap();
+        assert!(image.labels.contains_key(func));
+        assert_eq!(image.labels.get(alias), image.labels.get(func));
+    }
+
+    #[test]
+    fn undefined_function() {
+        let e = assemble("\tbl _puts\n\tret\n").unwrap_err();
+        assert_eq!(e.msg, "jit: undefined reference to '_puts' in 'bl _puts'");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn map_code() {
+        let buf = super::exec::ExecBuf::new(&[0xd65f03c0]).unwrap();
+        assert!(!buf.addr(0).is_null());
+    }
+}
@@ -0,0 +1,139 @@
+//! Toy C compiler targets ARM on Linux and macOS
+//!
+//! The library exposes the compiler phases: scanning -> parsing -> semantics analysis -> code
+//! generation (ARM ASM). Assembling and linking is done by the `tncc` binary with the system tools
+
+#[macro_use]
+extern crate log;
+
+pub mod ast;
+pub mod atomics;
+pub mod attrs;
+pub mod builtins;
+pub mod callgraph;
+pub mod codegen;
+pub mod common;
+pub mod compdb;
+pub mod consteval;
+pub mod coverage;
+#[cfg(test)]
+mod difftest;
+pub mod dot;
+#[cfg(test)]
+mod golden;
+pub mod init;
+pub mod intern;
+pub mod interp;
+pub mod jit;
+pub mod lsp;
+pub mod parse;
+pub mod progen;
+pub mod scan;
+pub mod semantics;
+pub mod sidetable;
+pub mod symbols;
+pub mod symtable;
+pub mod target;
+pub mod timing;
+pub mod typenames;
+pub mod util;
+
+pub use ast::{Ast, NodeId};
+pub use codegen::{gen_asm, GenOpts};
+pub use common::{CompileError, Diagnostics, Span, Token, Trivia, TriviaKind};
+pub use interp::{interpret, interpret_with};
+pub use parse::{parse, parse_stream, parse_stream_with, parse_with};
+pub use scan::{scan, scan_recover, LexError, LexErrorKind, Lexer};
+pub use semantics::{analyse, analyse_with, Decorations};
+pub use util::{AsmDialect, OptLevel, RelocModel, Standard, TargetOs as Target, Warning};
+
+/// scan arbitrary bytes into tokens, invalid UTF-8 is reported as error
+///
+/// never panics, entry point for fuzzing the scanner
+pub fn try_scan(input: &[u8]) -> Result<Vec<Token>, Diagnostics> {
+    tokenize(from_utf8(input)?)
+}
+
+/// scan and parse arbitrary bytes into AST
+///
+/// never panics, entry point for fuzzing the parser
+pub fn try_parse(input: &[u8]) -> Result<Ast, Diagnostics> {
+    parse_source(from_utf8(input)?)
+}
+
+fn from_utf8(input: &[u8]) -> Result<&str, CompileError> {
+    std::str::from_utf8(input)
+        .map_err(|e| CompileError::new(format!("invalid UTF-8 at byte {}", e.valid_up_to())))
+}
+
+/// scan the source into tokens, every lexical error is reported
+pub fn tokenize(source: &str) -> Result<Vec<Token>, Diagnostics> {
+    let (tokens, errors) = scan_recover(source);
+    match errors.is_empty() {
+        true => Ok(tokens),
+        false => Err(Diagnostics(
+            errors.into_iter().map(CompileError::from).collect(),
+        )),
+    }
+}
+
+/// scan and parse the source into AST without semantics analysis
+pub fn parse_source(source: &str) -> Result<Ast, Diagnostics> {
+    parse_stream(Lexer::new(source))
+}
+
+/// parse and type check the source, return the AST with the results of semantics analysis
+pub fn check(source: &str) -> Result<(Ast, Decorations), Diagnostics> {
+    let ast = parse_source(source)?;
+    let decor =
+        analyse(&ast).map_err(|e| Diagnostics::from(e).locate(source, scan::DEFAULT_TAB_WIDTH))?;
+    Ok((ast, decor))
+}
+
+/// compile the source to ARM assembly for the target with default options
+pub fn compile_to_asm(source: &str, target: Target) -> Result<String, Diagnostics> {
+    compile_to_asm_with(source, &GenOpts::new(target))
+}
+
+/// compile the source to ARM assembly with the code generation options
+pub fn compile_to_asm_with(source: &str, opts: &GenOpts) -> Result<String, Diagnostics> {
+    let (ast, _) = check(source)?;
+    Ok(gen_asm(&ast, opts)?)
+}
+
+#[cfg(test)]
+mod test {
+    use test_case::test_case;
+
+    use super::{check, compile_to_asm, try_parse, try_scan, Target};
+
+    #[test]
+    fn test_compile_to_asm() {
+        let asm = compile_to_asm("int main() { return 2; }", Target::Linux).unwrap();
+        assert!(asm.contains("main:"));
+        assert!(asm.contains("mov x0, #2"));
+    }
+
+    #[test_case("int main() { return $; }" => "unexpected char: $ at 1:21")]
+    #[test_case("int main() { int a = #; return $; }" => "unexpected char: # at 1:22\nunexpected char: $ at 1:32")]
+    #[test_case("int main() { return 1 }" => "expected ; but } at 1:23")]
+    #[test_case("int main() { int a; int a; }" => "a is already define as local variable at 1:21")]
+    #[test_case("int g = 1;\r\n\tint g = 2;" => "g is already define as global variable at 2:9")]
+    fn test_diagnostics(src: &str) -> String {
+        check(src).unwrap_err().to_string()
+    }
+
+    #[test_case(b"int \xff main" => "invalid UTF-8 at byte 4")]
+    #[test_case(b"int main() { return 99999999999999999999; }" => "number is too large at 1:21")]
+    fn test_try_scan(input: &[u8]) -> String {
+        try_scan(input).unwrap_err().to_string()
+    }
+
+    #[test_case(b"int main() { return f(1, 2); }", true)]
+    #[test_case(b"", true)]
+    #[test_case(b"((((", false)]
+    #[test_case(b"int f() { {{{{{ }", false)]
+    fn test_try_parse(input: &[u8], ok: bool) {
+        assert_eq!(try_parse(input).is_ok(), ok);
+    }
+}
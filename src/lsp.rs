@@ -0,0 +1,509 @@
+//! Minimal language server over stdio
+//!
+//! Supports diagnostics on open/change with the notes as related information and quick fixes
+//! from the fix-its, go-to-definition and hover with resolved types. Documents are synchronized
+//! in full on every change
+
+use std::{
+    collections::HashMap,
+    io::{self, BufRead, Write},
+};
+
+use serde_json::{json, Value};
+
+use crate::{
+    ast::{printer::declare, walk_expr, Ast, Expr, ExprKind, Visitor},
+    check,
+    common::{CompileError, Span},
+    Decorations,
+};
+
+/// JSON-RPC error code of unknown methods
+const METHOD_NOT_FOUND: i64 = -32601;
+
+/// serve requests from the input until 'exit' or end of input
+pub fn serve<R: BufRead, W: Write>(mut input: R, mut output: W) -> io::Result<()> {
+    let mut server = Server::default();
+    while let Some(msg) = read_message(&mut input)? {
+        let method = msg["method"].as_str().unwrap_or_default();
+        debug!("lsp <- {}", method);
+        if method == "exit" {
+            break;
+        }
+        for out in server.handle(method, &msg) {
+            write_message(&mut output, &out)?;
+        }
+    }
+    Ok(())
+}
+
+/// read message framed with 'Content-Length' header, none at the end of input
+fn read_message<R: BufRead>(input: &mut R) -> io::Result<Option<Value>> {
+    let mut len: Option<usize> = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(v) = line.strip_prefix("Content-Length:") {
+            len = v.trim().parse().ok();
+        }
+    }
+    let len = len.ok_or_else(|| invalid_data("missing Content-Length header"))?;
+    let mut body = vec![0; len];
+    input.read_exact(&mut body)?;
+    serde_json::from_slice(&body)
+        .map(Some)
+        .map_err(|e| invalid_data(&e.to_string()))
+}
+
+fn write_message<W: Write>(output: &mut W, msg: &Value) -> io::Result<()> {
+    let body = msg.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+/// open documents by uri
+#[derive(Default)]
+struct Server {
+    docs: HashMap<String, String>,
+}
+
+impl Server {
+    /// handle request or notification, return messages to send
+    fn handle(&mut self, method: &str, msg: &Value) -> Vec<Value> {
+        let id = msg.get("id").cloned();
+        let params = &msg["params"];
+        let uri = params["textDocument"]["uri"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string();
+        let result = match method {
+            "initialize" => json!({
+                "capabilities": {
+                    "textDocumentSync": 1,
+                    "definitionProvider": true,
+                    "hoverProvider": true,
+                    "codeActionProvider": { "codeActionKinds": ["quickfix"] },
+                },
+                "serverInfo": { "name": "tncc", "version": env!("CARGO_PKG_VERSION") },
+            }),
+            "shutdown" => Value::Null,
+            "textDocument/didOpen" => {
+                let text = params["textDocument"]["text"].as_str().unwrap_or_default();
+                self.docs.insert(uri.clone(), text.to_string());
+                return vec![self.publish_diagnostics(&uri)];
+            }
+            "textDocument/didChange" => {
+                let changes = params["contentChanges"].as_array();
+                if let Some(text) = changes
+                    .and_then(|c| c.last())
+                    .and_then(|c| c["text"].as_str())
+                {
+                    self.docs.insert(uri.clone(), text.to_string());
+                }
+                return vec![self.publish_diagnostics(&uri)];
+            }
+            "textDocument/didClose" => {
+                self.docs.remove(&uri);
+                return vec![notification(
+                    "textDocument/publishDiagnostics",
+                    json!({ "uri": uri, "diagnostics": [] }),
+                )];
+            }
+            "textDocument/definition" => self.definition(&uri, &params["position"]),
+            "textDocument/hover" => self.hover(&uri, &params["position"]),
+            "textDocument/codeAction" => self.code_actions(&uri, &params["range"]),
+            _ => {
+                // notifications without handler are ignored
+                return match id {
+                    Some(id) => vec![json!({
+                        "jsonrpc": "2.0",
+                        "id": id,
+                        "error": {
+                            "code": METHOD_NOT_FOUND,
+                            "message": format!("method not found: {}", method),
+                        },
+                    })],
+                    None => vec![],
+                };
+            }
+        };
+        match id {
+            Some(id) => vec![json!({ "jsonrpc": "2.0", "id": id, "result": result })],
+            None => vec![],
+        }
+    }
+
+    fn publish_diagnostics(&self, uri: &str) -> Value {
+        let text = self.docs.get(uri).map(String::as_str).unwrap_or_default();
+        let diagnostics: Vec<Value> = match check(text) {
+            Ok(_) => vec![],
+            Err(d) => d
+                .errors()
+                .iter()
+                .map(|e| diagnostic(uri, text, e))
+                .collect(),
+        };
+        notification(
+            "textDocument/publishDiagnostics",
+            json!({ "uri": uri, "diagnostics": diagnostics }),
+        )
+    }
+
+    /// quick fixes of the errors in the range, one per fix-it
+    fn code_actions(&self, uri: &str, requested: &Value) -> Value {
+        let text = self.docs.get(uri).map(String::as_str).unwrap_or_default();
+        let errors = match check(text) {
+            Ok(_) => return json!([]),
+            Err(d) => d,
+        };
+        let start = offset_of(text, &requested["start"]);
+        let end = offset_of(text, &requested["end"]);
+        let actions: Vec<Value> = errors
+            .errors()
+            .iter()
+            .flat_map(|e| e.fixits.iter().map(move |f| (e, f)))
+            .filter(|(e, f)| {
+                let span = e.span.unwrap_or(f.span);
+                span.start <= end && start <= span.end
+            })
+            .map(|(e, f)| {
+                let title = match f.span.is_empty() {
+                    true => format!("insert '{}'", f.replacement),
+                    false => format!("replace with '{}'", f.replacement),
+                };
+                let edit = json!({ "range": range(text, f.span), "newText": f.replacement });
+                json!({
+                    "title": title,
+                    "kind": "quickfix",
+                    "diagnostics": [diagnostic(uri, text, e)],
+                    "isPreferred": true,
+                    "edit": { "changes": { uri: [edit] } },
+                })
+            })
+            .collect();
+        json!(actions)
+    }
+
+    /// location of the declaration referred at the position
+    fn definition(&self, uri: &str, pos: &Value) -> Value {
+        let text = match self.docs.get(uri) {
+            Some(t) => t,
+            None => return Value::Null,
+        };
+        let (ast, decor) = match check(text) {
+            Ok(v) => v,
+            Err(_) => return Value::Null,
+        };
+        let offset = offset_of(text, pos);
+        let index = Index::build(&ast, offset);
+        index
+            .expr
+            .and_then(|e| decor.symbols.get(e.id))
+            .and_then(|decl| decor.table.decl(*decl))
+            .map(|decl| json!({ "uri": uri, "range": range(text, decl.span) }))
+            .unwrap_or(Value::Null)
+    }
+
+    /// type of the expression at the position
+    fn hover(&self, uri: &str, pos: &Value) -> Value {
+        let text = match self.docs.get(uri) {
+            Some(t) => t,
+            None => return Value::Null,
+        };
+        let (ast, decor) = match check(text) {
+            Ok(v) => v,
+            Err(_) => return Value::Null,
+        };
+        let index = Index::build(&ast, offset_of(text, pos));
+        index
+            .expr
+            .and_then(|e| hover_text(e, &decor).map(|h| (e.span, h)))
+            .map(|(span, h)| {
+                json!({
+                    "contents": { "kind": "markdown", "value": h },
+                    "range": range(text, span),
+                })
+            })
+            .unwrap_or(Value::Null)
+    }
+}
+
+fn notification(method: &str, params: Value) -> Value {
+    json!({ "jsonrpc": "2.0", "method": method, "params": params })
+}
+
+fn hover_text(expr: &Expr, decor: &Decorations) -> Option<String> {
+    let typ = decor.types.get(expr.id)?;
+    let text = match &expr.kind {
+        ExprKind::VarRef(name) => format!("```c\n{}\n```", declare(typ, name.to_string())),
+        ExprKind::FunctionCall(name, _) => format!("```c\n{} {}(...)\n```", typ, name),
+        _ => format!("```c\n{}\n```", typ),
+    };
+    Some(text)
+}
+
+/// the innermost expression at the offset
+struct Index<'a> {
+    offset: usize,
+    expr: Option<&'a Expr>,
+}
+
+impl<'a> Index<'a> {
+    fn build(ast: &'a Ast, offset: usize) -> Self {
+        let mut index = Index { offset, expr: None };
+        index.visit_ast(ast);
+        index
+    }
+}
+
+impl<'a> Visitor<'a> for Index<'a> {
+    fn visit_expr(&mut self, expr: &'a Expr) {
+        // children are visited later so the innermost expression wins
+        if expr.span.start <= self.offset && self.offset < expr.span.end {
+            self.expr = Some(expr);
+        }
+        walk_expr(self, expr);
+    }
+}
+
+/// diagnostic of the error, the notes are the related information
+fn diagnostic(uri: &str, text: &str, e: &CompileError) -> Value {
+    let related: Vec<Value> = e
+        .notes
+        .iter()
+        .map(|n| {
+            json!({
+                "location": { "uri": uri, "range": error_range(text, n) },
+                "message": n.msg,
+            })
+        })
+        .collect();
+    json!({
+        "range": error_range(text, e),
+        "severity": 1,
+        "source": "tncc",
+        "message": e.msg,
+        "relatedInformation": related,
+    })
+}
+
+/// range of the error, empty range at the location without span
+fn error_range(text: &str, e: &CompileError) -> Value {
+    match (e.span, e.loc) {
+        (Some(span), _) => range(text, span),
+        (None, Some((line, col))) => {
+            let pos = json!({ "line": line.saturating_sub(1), "character": col.saturating_sub(1) });
+            json!({ "start": pos, "end": pos })
+        }
+        (None, None) => json!({ "start": position(text, 0), "end": position(text, 0) }),
+    }
+}
+
+fn range(text: &str, span: Span) -> Value {
+    json!({ "start": position(text, span.start), "end": position(text, span.end) })
+}
+
+/// LSP position of the byte offset, character is counted in UTF-16 code units
+fn position(text: &str, offset: usize) -> Value {
+    let offset = offset.min(text.len());
+    let line_start = text[..offset].rfind('\n').map(|i| i + 1).unwrap_or(0);
+    let line = text[..line_start].matches('\n').count();
+    let character: usize = text[line_start..offset].chars().map(char::len_utf16).sum();
+    json!({ "line": line, "character": character })
+}
+
+/// byte offset of the LSP position, clamped to the end of line or text
+fn offset_of(text: &str, pos: &Value) -> usize {
+    let line = pos["line"].as_u64().unwrap_or_default() as usize;
+    let character = pos["character"].as_u64().unwrap_or_default() as usize;
+    let line_start = match line {
+        0 => 0,
+        n => match text.match_indices('\n').nth(n - 1) {
+            Some((i, _)) => i + 1,
+            None => return text.len(),
+        },
+    };
+    let mut units = 0;
+    for (i, c) in text[line_start..].char_indices() {
+        if units >= character || c == '\n' {
+            return line_start + i;
+        }
+        units += c.len_utf16();
+    }
+    text.len()
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::{json, Value};
+    use test_case::test_case;
+
+    use super::{offset_of, position, serve};
+
+    /// run the server with the messages and return the messages sent back
+    fn run(msgs: &[Value]) -> Vec<Value> {
+        let input: String = msgs
+            .iter()
+            .map(|m| {
+                let body = m.to_string();
+                format!("Content-Length: {}\r\n\r\n{}", body.len(), body)
+            })
+            .collect();
+        let mut output = Vec::new();
+        serve(input.as_bytes(), &mut output).unwrap();
+
+        let mut out = Vec::new();
+        let mut rest = String::from_utf8(output).unwrap();
+        while let Some(i) = rest.find("\r\n\r\n") {
+            let len: usize = rest[..i]
+                .trim_start_matches("Content-Length: ")
+                .parse()
+                .unwrap();
+            let body = rest[i + 4..i + 4 + len].to_string();
+            out.push(serde_json::from_str(&body).unwrap());
+            rest = rest[i + 4 + len..].to_string();
+        }
+        out
+    }
+
+    fn open(text: &str) -> Value {
+        json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didOpen",
+            "params": { "textDocument": { "uri": "file:///a.c", "text": text } },
+        })
+    }
+
+    fn request(id: u32, method: &str, line: u32, character: u32) -> Value {
+        json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": method,
+            "params": {
+                "textDocument": { "uri": "file:///a.c" },
+                "position": { "line": line, "character": character },
+            },
+        })
+    }
+
+    #[test]
+    fn test_initialize() {
+        let out = run(&[
+            json!({ "jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {} }),
+            json!({ "jsonrpc": "2.0", "method": "initialized", "params": {} }),
+            json!({ "jsonrpc": "2.0", "id": 2, "method": "foo" }),
+            json!({ "jsonrpc": "2.0", "id": 3, "method": "shutdown" }),
+            json!({ "jsonrpc": "2.0", "method": "exit" }),
+            json!({ "jsonrpc": "2.0", "id": 4, "method": "shutdown" }),
+        ]);
+        assert_eq!(out.len(), 3);
+        assert_eq!(out[0]["result"]["capabilities"]["hoverProvider"], true);
+        assert_eq!(out[1]["error"]["code"], -32601);
+        assert_eq!(out[2]["id"], 3);
+    }
+
+    #[test]
+    fn test_diagnostics() {
+        let out = run(&[open("int main() {\n  return 1 }")]);
+        let diag = &out[0]["params"]["diagnostics"][0];
+        assert_eq!(out[0]["method"], "textDocument/publishDiagnostics");
+        assert_eq!(diag["message"], "expected ; but } at 2:12");
+        assert_eq!(
+            diag["range"]["start"],
+            json!({ "line": 1, "character": 11 })
+        );
+    }
+
+    #[test]
+    fn test_related_information() {
+        let out = run(&[open("int g;\nchar g;")]);
+        let related = &out[0]["params"]["diagnostics"][0]["relatedInformation"][0];
+        assert_eq!(
+            related["message"],
+            "previous declaration of 'g' is here at 1:1"
+        );
+        assert_eq!(related["location"]["uri"], "file:///a.c");
+        assert_eq!(
+            related["location"]["range"]["end"],
+            json!({ "line": 0, "character": 6 })
+        );
+    }
+
+    #[test]
+    fn test_code_actions() {
+        let out = run(&[
+            open("int main() {\n  return 1\n}"),
+            json!({
+                "jsonrpc": "2.0",
+                "id": 2,
+                "method": "textDocument/codeAction",
+                "params": {
+                    "textDocument": { "uri": "file:///a.c" },
+                    "range": {
+                        "start": { "line": 2, "character": 0 },
+                        "end": { "line": 2, "character": 0 },
+                    },
+                    "context": { "diagnostics": [] },
+                },
+            }),
+        ]);
+        let action = &out[1]["result"][0];
+        assert_eq!(action["title"], "insert ';'");
+        assert_eq!(action["kind"], "quickfix");
+        assert_eq!(
+            action["edit"]["changes"]["file:///a.c"][0],
+            json!({
+                "range": {
+                    "start": { "line": 1, "character": 10 },
+                    "end": { "line": 1, "character": 10 },
+                },
+                "newText": ";",
+            })
+        );
+    }
+
+    #[test]
+    fn test_definition_and_hover() {
+        let src = "int g = 1;\nvoid foo() {}\nint main() {\n  foo();\n  return g;\n}";
+        let out = run(&[
+            open(src),
+            request(1, "textDocument/definition", 4, 9),
+            request(2, "textDocument/hover", 3, 3),
+            request(3, "textDocument/definition", 0, 0),
+        ]);
+        assert_eq!(out[0]["params"]["diagnostics"], json!([]));
+        assert_eq!(
+            out[1]["result"]["range"],
+            json!({ "start": { "line": 0, "character": 0 }, "end": { "line": 0, "character": 10 } })
+        );
+        assert_eq!(
+            out[2]["result"]["contents"]["value"],
+            "```c\nvoid foo(...)\n```"
+        );
+        assert_eq!(out[3]["result"], Value::Null);
+    }
+
+    #[test_case("ab\ncd", 1, 1 => 4)]
+    #[test_case("ab\ncd", 1, 9 => 5)]
+    #[test_case("ab\ncd", 5, 0 => 5)]
+    #[test_case("é😀x", 0, 3 => 6; "utf16")]
+    fn test_offset_of(text: &str, line: u32, character: u32) -> usize {
+        offset_of(text, &json!({ "line": line, "character": character }))
+    }
+
+    #[test_case("ab\ncd", 4 => json!({ "line": 1, "character": 1 }))]
+    #[test_case("é😀x", 6 => json!({ "line": 0, "character": 3 }); "utf16")]
+    fn test_position(text: &str, offset: usize) -> Value {
+        position(text, offset)
+    }
+}
@@ -1,207 +1,893 @@
 //! Toy C compiler targets ARM on Linux and macOS
 //!
-//! Use system assembler and linker to assemble and link the executable file
+//! Driver over the tncc library, use system assembler and linker to assemble and link the
+//! executable file
 
 #[macro_use]
 extern crate log;
 
-use std::{fs, path::PathBuf, process::Command};
-use std::{fs::File, io::prelude::*};
+use std::{
+    ffi::OsString,
+    fmt::Display,
+    fs,
+    path::{Path, PathBuf},
+    process::{Command, ExitStatus},
+};
+use std::{fs::File, io::prelude::*, io::BufWriter};
 
-use clap::{App, Arg};
-use codegen::gen_asm;
+use clap::{App, Arg, ArgMatches};
 use env_logger::{Builder, Env};
-use parse::parse;
-use scan::scan;
-use semantics::analyse;
-use util::*;
-
-mod ast;
-mod codegen;
-mod common;
-mod parse;
-mod scan;
-mod semantics;
-mod symtable;
-mod util;
+use log::LevelFilter;
+use tncc::{
+    analyse_with,
+    ast::printer::print_c,
+    callgraph::CallGraph,
+    compdb::{self, CompileCommand, COMPDB_FILE},
+    coverage::Coverage,
+    dot::gen_dot,
+    interp::{interpret_with, MAX_CALL_DEPTH},
+    jit, parse_stream_with, parse_with,
+    scan::{token_infos, DEFAULT_TAB_WIDTH},
+    semantics::defines_main,
+    symbols::{format_symbols, list_symbols},
+    target::{self, ObjectFormat},
+    timing::{Phase, Timings},
+    util::*,
+    CompileError, Diagnostics, GenOpts, Lexer,
+};
+
+mod watch;
+
+/// default system assembler, override with '--as-path' or TNCC_AS
+const DEFAULT_AS: &str = "/usr/bin/as";
+
+/// default system linker, override with '--ld-path' or TNCC_LD
+const DEFAULT_LD: &str = "/usr/bin/ld";
 
 fn main() {
-    let opts = parse_opts();
+    match run() {
+        Ok(0) => (),
+        Ok(code) => std::process::exit(code),
+        Err(e) => {
+            eprintln!("tncc: error: {}", e);
+            std::process::exit(e.exit_code());
+        }
+    }
+}
+
+/// parse options and run the compiler pipeline, return the process exit code
+fn run() -> Result<i32, DriverError> {
+    let opts = parse_opts()?;
     init_logger(&opts);
+    Warning::enabled(&opts.warnings)
+        .1
+        .iter()
+        .for_each(|w| warn!("ignoring unknown warning option '-W{}'", w));
+    opts.features
+        .iter()
+        .for_each(|f| warn!("ignoring unknown option '-f{}'", f));
 
+    // language server on stdin/stdout
+    if opts.lsp {
+        let stdin = std::io::stdin();
+        return tncc::lsp::serve(stdin.lock(), std::io::stdout())
+            .map(|_| 0)
+            .map_err(|e| DriverError::Io("language server".into(), e));
+    }
+
+    // print information about the compiler only
+    if let Some(query) = opts.query {
+        print_query(&opts, query);
+        return Ok(0);
+    }
+
+    // recompile on change until interrupted
+    if opts.watch {
+        return exec_watch(&opts);
+    }
+
+    let timings = Timings::new();
+    let result = exec(&opts, &timings);
+    if opts.time_report {
+        eprint!("{}", timings);
+    }
+    result
+}
+
+/// print version or configuration for build systems probing the compiler
+fn print_query(opts: &Opts, query: Query) {
+    let version = env!("CARGO_PKG_VERSION");
+    match query {
+        Query::DumpVersion => println!("{}", version),
+        Query::DumpMachine => println!("{}", opts.target.triple()),
+        Query::Version => {
+            println!("tncc {} (git {})", version, env!("TNCC_GIT_REV"));
+            println!("Target: {}", opts.target.triple());
+            println!("Assembler: {}", opts.as_path.display());
+            println!("Linker: {}", opts.ld_path.display());
+        }
+    }
+}
+
+/// compile now and again whenever an input file changes, errors are printed but do not stop
+fn exec_watch(opts: &Opts) -> Result<i32, DriverError> {
+    if opts.files.iter().any(|f| is_stdin(f)) {
+        return Err(DriverError::Usage("can not watch standard input".into()));
+    }
+    watch::watch_files(&opts.files, || {
+        let timings = Timings::new();
+        match exec(opts, &timings) {
+            Ok(_) => eprintln!(
+                "tncc: compiled {} file(s) in {:.1} ms, watching for changes...",
+                opts.files.len(),
+                timings.total().as_secs_f64() * 1000.0
+            ),
+            Err(e) => eprintln!("tncc: error: {}", e),
+        }
+    })
+    .map_err(|e| DriverError::Usage(format!("failed to watch input files: {}", e)))?;
+    Ok(0)
+}
+
+/// execute all stages for the inputs, temp files are removed before returning
+///
+/// return the exit code of the program with 'run', otherwise 0
+fn exec(opts: &Opts, timings: &Timings) -> Result<i32, DriverError> {
     // validate
-    ensure_input_exist(&opts.files);
+    ensure_input_exist(&opts.files)?;
+    if opts.shared && opts.run.is_some() {
+        return Err(DriverError::Usage("can not run a shared library".into()));
+    }
+
+    // dump front-end phases only
+    if let Some(emit) = opts.emit {
+        return exec_emit(opts, emit).map(|_| 0);
+    }
+
+    // run in the compiler process instead of producing files
+    if opts.interpret || opts.jit {
+        return exec_in_process(opts);
+    }
+
+    // record how each source is compiled for tooling
+    if opts.compile_commands {
+        write_compile_commands(opts)?;
+    }
+
+    // classify inputs by extension
+    let inputs: Vec<Input> = opts.files.iter().map(|f| Input::from_path(f)).collect();
+
+    // unique temp dir for intermediate files and the executable of 'run'
+    let temps = if opts.compile_only || (opts.save_temps && opts.run.is_none()) {
+        None
+    } else {
+        Some(TempDir::new().map_err(|e| DriverError::Io("failed to create temp dir".into(), e))?)
+    };
+
+    // intermediate files are kept next to the sources with --save-temps
+    let intermediates = if opts.save_temps {
+        None
+    } else {
+        temps.as_ref()
+    };
 
     // always execute front-end to emit asm
     let target = TargetOs::current();
-    let asm_paths = exec_cc1(&opts);
+    let all_sources = inputs.iter().all(|i| i.kind == InputKind::Source);
+    let mut has_main = false;
+    let inputs = exec_cc1(opts, timings, intermediates, inputs, &mut has_main)?;
+
+    // main can only come from the sources without objects or libraries
+    let linking = !opts.compile_only && !opts.complie_as_only;
+    let only_sources =
+        linking && all_sources && opts.libs.is_empty() && opts.linker_args.is_empty();
+    // the entry point may be supplied without the startup files, i.e '_start'
+    let needs_main = !opts.shared && !opts.no_stdlib && !opts.no_start_files;
+    if only_sources && !has_main && needs_main {
+        return Err(DriverError::Usage(
+            "undefined reference to 'main', use -c or -S to compile without linking".into(),
+        ));
+    }
 
     // stop if -S
     if opts.compile_only {
-        return;
+        warn_unused_inputs(opts);
+        return Ok(0);
     }
 
     // check arch
-    check_target(&target);
+    check_target(&target)?;
 
     // run assembler
-    let obj_paths = run_assembler(&opts, &asm_paths);
+    let inputs = timings.time(Phase::Assemble, || {
+        run_assembler(opts, intermediates, inputs)
+    })?;
 
     // stop if -c
     if opts.complie_as_only {
-        return;
+        warn_unused_inputs(opts);
+        return Ok(0);
     }
 
-    // run linker
-    let out = run_linker(&opts, &obj_paths);
+    // run linker, the executable of 'run' goes to the temp dir without '-o'
+    let first = opts.files.first().unwrap();
+    let out = match (&opts.output, &opts.run, &temps) {
+        (Some(o), _, _) => PathBuf::from(o),
+        (None, Some(_), Some(t)) => t.new_file(&new_output_executable(first), ""),
+        _ if opts.shared => new_output_shared(first, opts.target),
+        _ => new_output_executable(first),
+    };
+    timings.time(Phase::Link, || run_linker(opts, &inputs, &out))?;
     info!("ouput at {:?}", out);
+
+    // execute the program
+    match &opts.run {
+        Some(args) => run_program(&out, args),
+        None => Ok(0),
+    }
+}
+
+/// run the compiled program with inherited stdio and return its exit code
+fn run_program(path: &Path, args: &[String]) -> Result<i32, DriverError> {
+    info!("run {:?} {:?}", path, args);
+    let status = Command::new(path)
+        .args(args)
+        .status()
+        .map_err(|e| DriverError::Io(format!("failed to run {}", path.display()), e))?;
+    Ok(exit_code_of(&status))
+}
+
+/// exit code of the process, 128 + signal like shells if killed by a signal
+fn exit_code_of(status: &ExitStatus) -> i32 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(sig) = status.signal() {
+            return 128 + sig;
+        }
+    }
+    status.code().unwrap_or(1)
 }
 
 /// compiler front-end to emit assembly code
 /// phases: scanning -> parsing -> semantics analysis -> code generation (ARM ASM)
-fn exec_cc1(opts: &Opts) -> Vec<PathBuf> {
+///
+/// C sources are replaced by the generated asm files, other inputs are kept as is. `has_main` is
+/// set if a source defines main
+fn exec_cc1(
+    opts: &Opts,
+    timings: &Timings,
+    temps: Option<&TempDir>,
+    inputs: Vec<Input>,
+    has_main: &mut bool,
+) -> Result<Vec<Input>, DriverError> {
     info!("execute core cc1");
-    opts.files
-        .iter()
-        .map(|f| {
-            let contents = fs::read_to_string(f).unwrap();
+    let (warnings, _) = Warning::enabled(&opts.warnings);
+    inputs
+        .into_iter()
+        .map(|input| {
+            if input.kind != InputKind::Source {
+                return Ok(input);
+            }
 
-            // scan to tokens
-            debug!("start scanning...");
-            let toks = scan(&contents);
+            let f = &input.path;
+            let contents = read_source(f)?;
+            let to_err = |e: CompileError| compile_error(f, &contents, e.into(), opts);
 
-            // parse to ast
-            debug!("start parsing...");
-            let mut ast = parse(toks);
-            debug!("{:#?}", ast);
+            // parse to ast, tokens are scanned on demand so the scanning time is part of parsing
+            debug!(target: Phase::Parse.name(), "start parsing {:?}", f);
+            let ast = timings
+                .time(Phase::Parse, || {
+                    parse_stream_with(opts.lexer(&contents), opts.std)
+                })
+                .map_err(|d| compile_error(f, &contents, d, opts))?;
+            debug!(target: Phase::Parse.name(), "{} external declarations", ast.0.len());
+            trace!(target: Phase::Parse.name(), "{:#?}", ast);
+            *has_main |= defines_main(&ast);
 
             // semantics analysis and type checking
-            debug!("start semantics analysis");
-            analyse(&mut ast);
-
-            // generate asm
-            debug!("start code generation...");
-            let asm = gen_asm(&ast, &opts.target);
-            debug!("\n{}", asm);
+            debug!(target: Phase::Semantics.name(), "start semantics analysis");
+            let decor = timings
+                .time(Phase::Semantics, || analyse_with(&ast, opts.target))
+                .map_err(to_err)?;
+            report_warnings(f, &contents, &decor.warnings, &warnings, opts.tab_width);
 
-            // write to output
-            write_asm_file(&asm, opts, f)
+            // generate asm straight to the output file
+            debug!(target: Phase::Codegen.name(), "start code generation");
+            let path = asm_output_path(opts, temps, f)?;
+            let io_err = |e| DriverError::Io(format!("{}", path.display()), e);
+            let mut fout = BufWriter::new(create_output(&path)?);
+            // the directories of the source are host dependent
+            let name = match opts.reproducible {
+                true => reproducible_name(f),
+                false => source_name(f),
+            };
+            let gen_opts = GenOpts {
+                file: opts.reproducible.then(|| name.clone()),
+                ..opts.gen_opts()
+            };
+            let cov = opts.coverage.then(|| Coverage::new(&contents, &name));
+            let res = timings.time(Phase::Codegen, || {
+                target::backend(&gen_opts).write_asm(&ast, cov.as_ref(), &mut fout)
+            });
+            let usage = match res {
+                Ok(usage) => usage,
+                Err(e) => {
+                    drop(fout);
+                    if !is_stdout(&path) {
+                        let _ = fs::remove_file(&path);
+                    }
+                    return Err(to_err(e));
+                }
+            };
+            fout.flush().map_err(io_err)?;
+            if opts.stack_usage {
+                let report: String = usage
+                    .iter()
+                    .map(|u| u.report(&name, &contents, opts.tab_width) + "\n")
+                    .collect();
+                let su = stack_usage_path(opts, f)?;
+                fs::write(&su, report)
+                    .map_err(|e| DriverError::Io(format!("{}", su.display()), e))?;
+            }
+            info!(target: Phase::Codegen.name(), "generate asm to {:?}", path);
+            Ok(Input::new(path, InputKind::Asm))
         })
         .collect()
 }
 
-/// write ARM assembly file into new file
-fn write_asm_file(asm: &String, opts: &Opts, p: &PathBuf) -> PathBuf {
-    let path = if opts.compile_only {
-        opts.output
-            .as_ref()
-            .map(|s| PathBuf::from(s))
-            .unwrap_or_else(|| new_output_asm(p, false))
-    } else {
-        new_output_asm(p, true)
+/// interpret or JIT compile the single source and return the exit code of its main
+fn exec_in_process(opts: &Opts) -> Result<i32, DriverError> {
+    let mode = if opts.jit { "--jit" } else { "--interpret" };
+    let f = match opts.files.as_slice() {
+        [f] if Input::from_path(f).kind == InputKind::Source => f,
+        _ => {
+            return Err(DriverError::Usage(format!(
+                "'{}' requires a single C source file",
+                mode
+            )))
+        }
+    };
+    let contents = read_source(f)?;
+    let to_err = |e: CompileError| compile_error(f, &contents, e.into(), opts);
+    let ast = parse_stream_with(opts.lexer(&contents), opts.std)
+        .map_err(|d| compile_error(f, &contents, d, opts))?;
+    let decor = analyse_with(&ast, opts.target).map_err(to_err)?;
+    let (warnings, _) = Warning::enabled(&opts.warnings);
+    report_warnings(f, &contents, &decor.warnings, &warnings, opts.tab_width);
+    let code = match opts.jit {
+        true => jit::run_main(&ast, &opts.gen_opts()).map_err(to_err)?,
+        false => interpret_with(&ast, &decor, opts.max_depth).map_err(to_err)? as i32,
+    };
+    info!("main returned {} with '{}'", code, mode);
+    Ok(code)
+}
+
+/// stop after scanning or parsing and dump the result to stdout or the output file
+fn exec_emit(opts: &Opts, emit: Emit) -> Result<(), DriverError> {
+    let mut out: Box<dyn Write> = match &opts.output {
+        Some(p) => create_output(Path::new(p))?,
+        None => Box::new(std::io::stdout()),
     };
+    for f in opts
+        .files
+        .iter()
+        .filter(|f| Input::from_path(f).kind == InputKind::Source)
+    {
+        let contents = read_source(f)?;
+        let to_err = |e: CompileError| compile_error(f, &contents, e.into(), opts);
+        let toks = Lexer::new(&contents)
+            .tab_width(opts.tab_width)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| to_err(e.into()))?;
+        let dump = match emit {
+            Emit::Tokens => toks
+                .iter()
+                .map(|t| format!("{}:{}\t{}\n", t.loc.0, t.loc.1, t.tok))
+                .collect::<String>(),
+            Emit::TokensJson => {
+                serde_json::to_string_pretty(&token_infos(&contents, &toks))
+                    .expect("failed to serialize tokens")
+                    + "\n"
+            }
+            Emit::Ast => format!("{:#?}\n", parse_with(toks, opts.std).map_err(to_err)?),
+            Emit::Dot => gen_dot(&parse_with(toks, opts.std).map_err(to_err)?),
+            Emit::CallGraph => {
+                CallGraph::build(&parse_with(toks, opts.std).map_err(to_err)?).to_dot()
+            }
+            Emit::C => print_c(&parse_with(toks, opts.std).map_err(to_err)?),
+            Emit::AstJson => {
+                serde_json::to_string_pretty(&parse_with(toks, opts.std).map_err(to_err)?)
+                    .expect("failed to serialize AST")
+                    + "\n"
+            }
+            Emit::Symbols | Emit::SymbolsJson => {
+                let ast = parse_with(toks, opts.std).map_err(to_err)?;
+                analyse_with(&ast, opts.target).map_err(to_err)?;
+                let scopes = list_symbols(&ast);
+                match emit {
+                    Emit::Symbols => format_symbols(&scopes),
+                    _ => {
+                        serde_json::to_string_pretty(&scopes).expect("failed to serialize symbols")
+                            + "\n"
+                    }
+                }
+            }
+        };
+        if opts.in_place && !is_stdin(f) {
+            fs::write(f, dump).map_err(|e| DriverError::Io(format!("{}", f.display()), e))?;
+            continue;
+        }
+        out.write_all(dump.as_bytes())
+            .map_err(|e| DriverError::Io("failed to write output".into(), e))?;
+    }
+    Ok(())
+}
 
-    let mut fout = File::create(&path).expect("failed to create file");
-    fout.write_all(asm.as_bytes()).expect("wrote asm to file");
-    info!("generate asm to {:?}", path);
-    path
+/// write or update compile_commands.json in the current directory with an entry per C source
+fn write_compile_commands(opts: &Opts) -> Result<(), DriverError> {
+    let dir = std::env::current_dir()
+        .map_err(|e| DriverError::Io("failed to get current directory".into(), e))?;
+    let commands = opts
+        .files
+        .iter()
+        .filter(|f| !is_stdin(f) && Input::from_path(f).kind == InputKind::Source)
+        .map(|f| {
+            let output = new_output_in_dir(Path::new(""), f, "o");
+            CompileCommand {
+                directory: dir.display().to_string(),
+                file: f.display().to_string(),
+                arguments: compile_command_args(opts, f, &output),
+                output: Some(output.display().to_string()),
+            }
+        })
+        .collect();
+    let existing = fs::read_to_string(COMPDB_FILE).unwrap_or_default();
+    let db = compdb::merge(&existing, commands);
+    fs::write(COMPDB_FILE, compdb::gen_compdb(&db))
+        .map_err(|e| DriverError::Io(COMPDB_FILE.into(), e))
 }
 
-/// use system assembler (GNU as) to assemble asm code to object code
-fn run_assembler(opts: &Opts, paths: &[PathBuf]) -> Vec<PathBuf> {
-    info!("invoke assembler");
-    paths
+/// arguments to compile a single source to an object file with the current options
+fn compile_command_args(opts: &Opts, file: &Path, output: &Path) -> Vec<String> {
+    let mut args = vec![String::from("tncc"), String::from("-c")];
+    if opts.opt_level != OptLevel::O0 {
+        args.push(opts.opt_level.flag().into());
+    }
+    if opts.arch != Arch::default() {
+        args.push(opts.arch.flag().into());
+    }
+    if opts.std != Standard::default() {
+        args.push(opts.std.flag().into());
+    }
+    if let Some(dialect) = opts.dialect {
+        args.push(format!("--asm-dialect={}", dialect.name()));
+    }
+    if opts.reloc != RelocModel::default() {
+        args.push(opts.reloc.flag().into());
+    }
+    if opts.freestanding {
+        args.push(String::from("-ffreestanding"));
+    }
+    if opts.instrument_functions {
+        args.push(String::from("-finstrument-functions"));
+    }
+    if opts.common {
+        args.push(String::from("-fcommon"));
+    }
+    if opts.stack_usage {
+        args.push(String::from("-fstack-usage"));
+    }
+    if opts.function_sections {
+        args.push(String::from("-ffunction-sections"));
+    }
+    if opts.data_sections {
+        args.push(String::from("-fdata-sections"));
+    }
+    if opts.coverage {
+        args.push(String::from("--coverage"));
+    }
+    if opts.reproducible {
+        args.push(String::from("--reproducible"));
+    }
+    if let Some(sysroot) = &opts.sysroot {
+        args.push(format!("--sysroot={}", sysroot.display()));
+    }
+    args.extend(opts.warnings.iter().map(|w| format!("-W{}", w)));
+    args.extend(opts.features.iter().map(|f| format!("-f{}", f)));
+    args.push(String::from("-o"));
+    args.push(output.display().to_string());
+    args.push(file.display().to_string());
+    args
+}
+
+/// read C source from file or standard input
+fn read_source(path: &Path) -> Result<String, DriverError> {
+    if is_stdin(path) {
+        let mut contents = String::new();
+        std::io::stdin()
+            .read_to_string(&mut contents)
+            .map_err(|e| DriverError::Io("failed to read from standard input".into(), e))?;
+        Ok(contents)
+    } else {
+        fs::read_to_string(path).map_err(|e| DriverError::Io(format!("{}", path.display()), e))
+    }
+}
+
+/// warn about inputs which are not used because the pipeline stops early with -S or -c
+fn warn_unused_inputs(opts: &Opts) {
+    opts.files
         .iter()
-        .map(|p| {
-            let output_path = if opts.complie_as_only {
-                opts.output
-                    .as_ref()
-                    .map(|s| PathBuf::from(s))
-                    .unwrap_or_else(|| new_output_obj(p, false))
-            } else {
-                new_output_obj(p, true)
+        .map(|f| Input::from_path(f))
+        .filter(|i| match i.kind {
+            InputKind::Source => false,
+            InputKind::Asm => opts.compile_only,
+            InputKind::Object => true,
+        })
+        .for_each(|i| warn!("{:?}: input file unused because linking not done", i.path));
+}
+
+/// file or standard output for '-o -' to write an output to
+fn create_output(path: &Path) -> Result<Box<dyn Write>, DriverError> {
+    match is_stdout(path) {
+        true => Ok(Box::new(std::io::stdout())),
+        false => {
+            Ok(Box::new(File::create(path).map_err(|e| {
+                DriverError::Io(format!("{}", path.display()), e)
+            })?))
+        }
+    }
+}
+
+/// output of '-S' or '-c' like gcc: the '-o' file (`-` for the standard output), inside the '-o'
+/// directory (created if missing) or in the current directory
+fn final_output(opts: &Opts, p: &Path, ext: &str) -> Result<PathBuf, DriverError> {
+    match (&opts.output, opts.output_dir()) {
+        (_, Some(dir)) => {
+            fs::create_dir_all(&dir)
+                .map_err(|e| DriverError::Io(format!("{}", dir.display()), e))?;
+            Ok(new_output_in_dir(&dir, p, ext))
+        }
+        (Some(o), None) => Ok(PathBuf::from(o)),
+        (None, None) => Ok(new_output_in_dir(Path::new(""), p, ext)),
+    }
+}
+
+/// path of the assembly file for the source, the final output with -S
+fn asm_output_path(opts: &Opts, temps: Option<&TempDir>, p: &Path) -> Result<PathBuf, DriverError> {
+    if opts.compile_only {
+        final_output(opts, p, "s")
+    } else {
+        Ok(new_output_asm(p, temps))
+    }
+}
+
+/// report of '-fstack-usage' named like the output of '-S' or '-c', in the current directory
+/// when linking or writing to the standard output
+fn stack_usage_path(opts: &Opts, p: &Path) -> Result<PathBuf, DriverError> {
+    let output = match opts.compile_only || opts.complie_as_only {
+        true => Some(final_output(opts, p, "su")?),
+        false => None,
+    };
+    match output {
+        Some(o) if !is_stdout(&o) => Ok(o.with_extension("su")),
+        _ => Ok(new_output_in_dir(Path::new(""), p, "su")),
+    }
+}
+
+/// use system assembler (GNU as) to assemble asm code to object code
+///
+/// asm files are replaced by the object files, other inputs are kept as is
+fn run_assembler(
+    opts: &Opts,
+    temps: Option<&TempDir>,
+    inputs: Vec<Input>,
+) -> Result<Vec<Input>, DriverError> {
+    info!(target: Phase::Assemble.name(), "invoke assembler");
+    inputs
+        .into_iter()
+        .map(|input| {
+            if input.kind != InputKind::Asm {
+                return Ok(input);
+            }
+
+            let p = &input.path;
+            let final_path = match opts.complie_as_only {
+                true => Some(final_output(opts, p, "o")?),
+                false => None,
+            };
+            // the assembler writes a file, the object is copied to the standard output
+            let output_path = match &final_path {
+                Some(o) if !is_stdout(o) => o.clone(),
+                _ => new_output_obj(p, temps),
             };
 
-            let mut cmd = Command::new("/usr/bin/as");
+            let mut cmd = Command::new(&opts.as_path);
             cmd.arg(p.as_os_str()).arg("-o").arg(&output_path);
             if opts.debug {
                 cmd.arg("-v");
             }
-            info!("{:?}", cmd);
-            ensure_success(&mut cmd, "failed to assembler with asm files");
-            output_path
+            info!(target: Phase::Assemble.name(), "{:?}", cmd);
+            ensure_success(&mut cmd, "assembler")?;
+            if final_path.as_deref().is_some_and(is_stdout) {
+                let io_err = |e| DriverError::Io(format!("{}", output_path.display()), e);
+                let mut obj = File::open(&output_path).map_err(io_err)?;
+                std::io::copy(&mut obj, &mut std::io::stdout()).map_err(io_err)?;
+            }
+            Ok(Input::new(output_path, InputKind::Object))
         })
         .collect()
 }
 
 /// use system linker (GNU ld) to link object code to machine code (ELF)
-fn run_linker(opts: &Opts, paths: &[PathBuf]) -> PathBuf {
-    info!("invoke linker");
-
-    let output_path = opts
-        .output
-        .as_ref()
-        .map(|s| PathBuf::from(s))
-        .unwrap_or_else(|| new_output_executable(opts.files.first().unwrap()));
-
-    // build command
-    let mut cmd = Command::new("/usr/bin/ld");
-    paths.iter().for_each(|p| {
-        cmd.arg(p);
+fn run_linker(opts: &Opts, inputs: &[Input], output_path: &Path) -> Result<(), DriverError> {
+    info!(target: Phase::Link.name(), "invoke linker");
+    let mut cmd = linker_command(opts, inputs, output_path)?;
+    info!(target: Phase::Link.name(), "{:?}", cmd);
+    ensure_success(&mut cmd, "linker")
+}
+
+/// command of the system linker for the target, the startup files and the C library are
+/// linked unless '-nostdlib' or '-nostartfiles'
+fn linker_command(
+    opts: &Opts,
+    inputs: &[Input],
+    output_path: &Path,
+) -> Result<Command, DriverError> {
+    let mut cmd = Command::new(&opts.ld_path);
+    inputs.iter().for_each(|i| {
+        cmd.arg(&i.path);
     });
-    cmd.arg("-o").arg(&output_path);
+    cmd.arg("-o").arg(output_path);
+
+    // user library search paths and libraries
+    opts.lib_dirs.iter().for_each(|d| {
+        let mut arg = OsString::from("-L");
+        arg.push(d);
+        cmd.arg(arg);
+    });
+    opts.libs.iter().for_each(|l| {
+        cmd.arg(format!("-l{}", l));
+    });
+
+    // Xcode ld records the modification time of archive members and objects
+    if opts.reproducible && opts.target == TargetOs::MacOs {
+        cmd.env("ZERO_AR_DATE", "1");
+    }
 
     // run on macOS using Xcode ld
     if opts.target == TargetOs::MacOs {
-        cmd
-        .arg("-dynamic")
-        .arg("-arch")
-        .arg("arm64")
-        .arg("-syslibroot")
-        .arg("/Applications/Xcode.app/Contents/Developer/Platforms/MacOSX.platform/Developer/SDKs/MacOSX.sdk")
-        .arg("-lSystem");
+        let sdk = opts
+            .sysroot
+            .clone()
+            .or_else(find_macos_sdk)
+            .ok_or_else(|| {
+                DriverError::Unsupported(
+                    "failed to detect macOS SDK with 'xcrun --show-sdk-path', try '--sysroot <dir>'"
+                        .into(),
+                )
+            })?;
+        // executables without libSystem are static and start at '_start'
+        let kind = match (opts.shared, opts.no_stdlib) {
+            (true, _) => "-dylib",
+            (false, true) => "-static",
+            (false, false) => "-dynamic",
+        };
+        cmd.arg(kind)
+            .arg("-arch")
+            .arg("arm64")
+            .arg("-syslibroot")
+            .arg(sdk);
+        match (opts.no_stdlib, opts.shared) {
+            (false, _) => cmd.arg("-lSystem"),
+            (true, false) => cmd.arg("-e").arg("__start"),
+            (true, true) => &mut cmd,
+        };
     } else {
-        cmd.arg("/usr/lib/aarch64-linux-gnu/crt1.o")
-            .arg("/usr/lib/aarch64-linux-gnu/crti.o")
-            .arg("/usr/lib/aarch64-linux-gnu/crtn.o")
-            .arg("-L/usr/lib/aarch64-linux-gnu")
-            .arg("-lc");
+        let root = opts.sysroot.clone().unwrap_or_else(|| PathBuf::from("/"));
+        let lib = root.join("usr/lib/aarch64-linux-gnu");
+        let mut lib_arg = OsString::from("-L");
+        lib_arg.push(&lib);
+        if opts.shared {
+            cmd.arg("-shared");
+        }
+        // shared libraries have no entry point, ld starts at '_start' without the startup files
+        if !opts.no_stdlib && !opts.no_start_files {
+            if !opts.shared {
+                cmd.arg(lib.join("crt1.o"));
+            }
+            cmd.arg(lib.join("crti.o")).arg(lib.join("crtn.o"));
+        }
+        if !opts.no_stdlib {
+            cmd.arg(lib_arg).arg("-lc");
+        }
     }
 
-    // execute
-    info!("{:?}", cmd);
-    ensure_success(&mut cmd, "failed to run linker with object files");
-    output_path
+    // map file and stripping, Xcode ld strips the debug and the local symbols separately
+    if let Some(map) = &opts.map_file {
+        let flag = match opts.target {
+            TargetOs::MacOs => "-map",
+            _ => "-Map",
+        };
+        cmd.arg(flag).arg(map);
+    }
+    if opts.strip {
+        match opts.target {
+            TargetOs::MacOs => cmd.arg("-S").arg("-x"),
+            _ => cmd.arg("-s"),
+        };
+    }
+
+    // the sections of the functions and variables not referenced are removed
+    if opts.function_sections || opts.data_sections {
+        cmd.arg(ObjectFormat::of(opts.target).gc_sections);
+    }
+
+    // extra options from '-Wl,' and '-Xlinker'
+    cmd.args(&opts.linker_args);
+    Ok(cmd)
 }
 
 /// ensure command is succes
-fn ensure_success(cmd: &mut Command, msg: &str) {
+fn ensure_success(cmd: &mut Command, tool: &str) -> Result<(), DriverError> {
     match cmd.status() {
-        Ok(status) if status.success() => (),
-        Ok(_) => panic!("{}", msg),
-        Err(e) => panic!("Failed to execute, error: {:?}", e),
+        Ok(status) if status.success() => Ok(()),
+        Ok(status) => Err(DriverError::Tool(tool.to_string(), status.code())),
+        Err(e) => Err(DriverError::Io(format!("failed to execute {}", tool), e)),
     }
 }
 
 /// support macos arm and linux arm only
-fn check_target(target: &TargetOs) {
-    match (target, util::is_aarch64()) {
-        (TargetOs::MacOs, true) => (),
-        (TargetOs::Linux, true) => (),
-        (os, _) => panic!(
+fn check_target(target: &TargetOs) -> Result<(), DriverError> {
+    match (target, is_aarch64()) {
+        (TargetOs::MacOs, true) => Ok(()),
+        (TargetOs::Linux, true) => Ok(()),
+        (os, _) => Err(DriverError::Unsupported(format!(
             "{:?} ({}) is not yet supported, try macos or linux (aarch64) instead",
             os,
             std::env::consts::ARCH
-        ),
+        ))),
+    }
+}
+
+fn ensure_input_exist(files: &[PathBuf]) -> Result<(), DriverError> {
+    if let Some(f) = files.iter().find(|f| !is_stdin(f) && !f.is_file()) {
+        return Err(DriverError::Usage(format!(
+            "invalid input file '{}'",
+            f.display()
+        )));
+    }
+    if files.iter().filter(|f| is_stdin(f)).count() > 1 {
+        return Err(DriverError::Usage(format!(
+            "standard input '{}' can only be used once",
+            STDIN_PATH
+        )));
     }
+    Ok(())
 }
 
-fn ensure_input_exist(files: &[PathBuf]) {
-    files.iter().for_each(|f| {
-        if !f.is_file() {
-            panic!("invalid input file '{}'", f.to_str().unwrap())
+/// error of the driver, reported as a single line
+#[derive(Debug)]
+enum DriverError {
+    /// invalid options or input files
+    Usage(String),
+    /// errors in the C source file with the source line and the notes of each error
+    Compile(PathBuf, Vec<(CompileError, String)>),
+    /// host, target or platform SDK is not supported
+    Unsupported(String),
+    /// failed to read or write a file
+    Io(String, std::io::Error),
+    /// assembler or linker failed with optional exit code
+    Tool(String, Option<i32>),
+}
+
+/// errors of the source file with the snippet and the notes under each error, the errors after
+/// the limit are dropped
+fn compile_error(path: &Path, src: &str, diags: Diagnostics, opts: &Opts) -> DriverError {
+    let mut errors: Vec<(CompileError, String)> = diags
+        .locate(src, opts.tab_width)
+        .0
+        .into_iter()
+        .map(|e| {
+            let details = details(path, src, &e);
+            (e, details)
+        })
+        .collect();
+    if opts.error_limit > 0 && errors.len() > opts.error_limit {
+        errors.truncate(opts.error_limit);
+        let e = CompileError::new("too many errors emitted, stopping now [-ferror-limit=]");
+        errors.push((e, String::new()));
+    }
+    DriverError::Compile(path.to_path_buf(), errors)
+}
+
+/// print the enabled warnings of the source with the snippet and the notes under each warning
+fn report_warnings(
+    path: &Path,
+    src: &str,
+    found: &[(Warning, CompileError)],
+    enabled: &[Warning],
+    tab_width: u32,
+) {
+    for (w, e) in found.iter().filter(|(w, _)| enabled.contains(w)) {
+        let e = e.clone().locate(src, tab_width);
+        eprintln!(
+            "tncc: warning: {}: {} [-W{}]",
+            source_name(path),
+            e,
+            w.name()
+        );
+        let details = details(path, src, &e);
+        if !details.is_empty() {
+            eprintln!("{}", details);
         }
-    });
+    }
+}
+
+/// lines under the first line of a located diagnostic, the snippet followed by each note with
+/// its snippet
+fn details(path: &Path, src: &str, e: &CompileError) -> String {
+    let mut lines = vec![e.snippet(src).trim_end().to_string()];
+    for n in &e.notes {
+        lines.push(format!("tncc: note: {}: {}", source_name(path), n));
+        lines.push(n.snippet(src).trim_end().to_string());
+    }
+    lines.retain(|l| !l.is_empty());
+    lines.join("\n")
+}
+
+/// name of the source in diagnostics
+fn source_name(path: &Path) -> String {
+    match is_stdin(path) {
+        true => String::from("<stdin>"),
+        false => path.display().to_string(),
+    }
+}
+
+/// name of the source without the directories, '--reproducible'
+fn reproducible_name(path: &Path) -> String {
+    match path.file_name() {
+        Some(name) if !is_stdin(path) => name.to_string_lossy().into_owned(),
+        _ => source_name(path),
+    }
+}
+
+impl DriverError {
+    /// process exit code, the exit code of a failed tool is forwarded
+    fn exit_code(&self) -> i32 {
+        match self {
+            DriverError::Usage(_) | DriverError::Compile(_, _) | DriverError::Unsupported(_) => 1,
+            DriverError::Io(_, _) => 74, // EX_IOERR
+            DriverError::Tool(_, code) => code.unwrap_or(1),
+        }
+    }
+}
+
+impl Display for DriverError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DriverError::Usage(msg) | DriverError::Unsupported(msg) => write!(f, "{}", msg),
+            DriverError::Compile(p, errors) => {
+                let name = source_name(p);
+                for (i, (e, details)) in errors.iter().enumerate() {
+                    // the first error is prefixed by the caller
+                    if i > 0 {
+                        write!(f, "\ntncc: error: ")?;
+                    }
+                    write!(f, "{}: {}", name, e)?;
+                    if !details.is_empty() {
+                        write!(f, "\n{}", details)?;
+                    }
+                }
+                Ok(())
+            }
+            DriverError::Io(msg, e) => write!(f, "{}: {}", msg, e),
+            DriverError::Tool(tool, Some(code)) => {
+                write!(f, "{} failed with exit code {}", tool, code)
+            }
+            DriverError::Tool(tool, None) => write!(f, "{} was terminated by a signal", tool),
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -212,12 +898,183 @@ struct Opts {
     complie_as_only: bool,
     debug: bool,
     verbose: bool,
+    /// level of the log records per phase, '--log=<phase>[=<level>],...'
+    log: Vec<(Phase, LevelFilter)>,
     target: TargetOs,
+    sysroot: Option<PathBuf>,
+    as_path: PathBuf,
+    ld_path: PathBuf,
+    lib_dirs: Vec<PathBuf>,
+    libs: Vec<String>,
+    linker_args: Vec<String>,
+    warnings: Vec<String>,
+    emit: Option<Emit>,
+    run: Option<Vec<String>>,
+    query: Option<Query>,
+    watch: bool,
+    save_temps: bool,
+    in_place: bool,
+    lsp: bool,
+    /// evaluate the AST of the source instead of compiling
+    interpret: bool,
+    /// run the generated code in memory, aarch64 hosts only
+    jit: bool,
+    compile_commands: bool,
+    time_report: bool,
+    /// columns between tab stops in reported locations, '-ftabstop=<n>'
+    tab_width: u32,
+    /// maximum nesting of calls with '--interpret', '-fconstexpr-depth=<n>'
+    max_depth: usize,
+    /// errors reported per source before stopping, 0 for no limit, '-ferror-limit=<n>'
+    error_limit: usize,
+    features: Vec<String>,
+    opt_level: OptLevel,
+    /// instruction set, '-march=<arch>'
+    arch: Arch,
+    std: Standard,
+    reloc: RelocModel,
+    /// link a shared library instead of an executable, '-shared'
+    shared: bool,
+    /// link without the startup files and the C library, '-nostdlib'
+    no_stdlib: bool,
+    /// link without the startup files, '-nostartfiles'
+    no_start_files: bool,
+    /// `__STDC_HOSTED__` is 0, '-ffreestanding'
+    freestanding: bool,
+    /// map of the linked sections and symbols written by the linker, '--emit-map=<file>'
+    map_file: Option<PathBuf>,
+    /// strip the symbols from the output of the linker, '-s'
+    strip: bool,
+    /// call the profiling hooks in every function, '-finstrument-functions'
+    instrument_functions: bool,
+    /// count the executed lines and write `<source>.cov` at exit, '--coverage'
+    coverage: bool,
+    /// tentative definitions are common symbols, '-fcommon'
+    common: bool,
+    /// write the stack usage of the functions to `<source>.su`, '-fstack-usage'
+    stack_usage: bool,
+    /// each function in a section of its own and unused sections removed by the linker,
+    /// '-ffunction-sections'
+    function_sections: bool,
+    /// each variable in a section of its own and unused sections removed by the linker,
+    /// '-fdata-sections'
+    data_sections: bool,
+    /// same output for the same sources on any host, '--reproducible'
+    reproducible: bool,
+    /// spelling of the directives, '--asm-dialect=<gnu|darwin>'
+    dialect: Option<AsmDialect>,
 }
 
-fn parse_opts() -> Opts {
+impl Opts {
+    /// '-o' is a directory if it ends with a separator or exists as directory
+    fn output_dir(&self) -> Option<PathBuf> {
+        self.output
+            .as_ref()
+            .filter(|o| {
+                o.ends_with(std::path::MAIN_SEPARATOR) || o.ends_with('/') || Path::new(o).is_dir()
+            })
+            .map(PathBuf::from)
+    }
+
+    /// lexer of a source to compile
+    fn lexer<'a>(&self, contents: &'a str) -> Lexer<'a> {
+        Lexer::new(contents)
+            .tab_width(self.tab_width)
+            .hosted(!self.freestanding)
+    }
+
+    /// options passed to code generation
+    fn gen_opts(&self) -> GenOpts {
+        GenOpts {
+            target: self.target,
+            opt_level: self.opt_level,
+            arch: self.arch,
+            reloc: self.reloc,
+            instrument_functions: self.instrument_functions,
+            common: self.common,
+            file: None,
+            dialect: self.dialect,
+            function_sections: self.function_sections,
+            data_sections: self.data_sections,
+        }
+    }
+}
+
+/// information to print instead of compiling
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Query {
+    /// --version
+    Version,
+    /// -dumpversion
+    DumpVersion,
+    /// -dumpmachine
+    DumpMachine,
+}
+
+/// front-end phase to dump instead of compiling
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Emit {
+    Tokens,
+    TokensJson,
+    Ast,
+    AstJson,
+    Dot,
+    CallGraph,
+    C,
+    Symbols,
+    SymbolsJson,
+}
+
+fn parse_opts() -> Result<Opts, DriverError> {
+    parse_opts_from(std::env::args())
+}
+
+/// options of the command line, the first argument is the program name
+fn parse_opts_from<I: IntoIterator<Item = String>>(args: I) -> Result<Opts, DriverError> {
     let app = create_arg_app();
-    let args = app.get_matches();
+    let matches = app.get_matches_from(normalize_args(args));
+
+    // 'run' subcommand accepts the same options followed by the program arguments
+    let (args, run) = match matches.subcommand() {
+        Some(("run", sub)) => {
+            let prog_args = sub
+                .values_of("args")
+                .map(|v| v.map(String::from).collect())
+                .unwrap_or_default();
+            (sub, Some(prog_args))
+        }
+        Some(("fmt", sub)) => (sub, None),
+        Some(("lsp", _)) => (&matches, None),
+        _ => (&matches, None),
+    };
+
+    // 'fmt' subcommand is the same as '--emit=c'
+    let fmt = matches.subcommand_matches("fmt");
+
+    // runtime checks of '-fsanitize=<name>,...', there are no '/', '%' or shift operators to
+    // check yet
+    let sanitizers = args
+        .values_of("feature")
+        .map(|v| {
+            v.filter_map(|f| f.strip_prefix("sanitize="))
+                .map(Sanitizer::parse_list)
+                .collect::<Result<Vec<_>, _>>()
+        })
+        .transpose()
+        .map_err(|n| {
+            DriverError::Usage(format!(
+                "unrecognized argument to -fsanitize= option: '{}'",
+                n
+            ))
+        })?
+        .map(|v| v.concat())
+        .unwrap_or_default();
+    if let Some(s) = sanitizers.first() {
+        return Err(DriverError::Unsupported(format!(
+            "'-fsanitize={}' is not supported, tncc does not yet support the checked operators",
+            s.name()
+        )));
+    }
 
     // load options
     let opts = Opts {
@@ -226,74 +1083,633 @@ fn parse_opts() -> Opts {
         complie_as_only: args.is_present("arg-c"),
         debug: args.is_present("debug"),
         verbose: args.is_present("verbose"),
+        log: parse_log(
+            args.is_present("debug"),
+            args.values_of("log").into_iter().flatten(),
+        )?,
         output: args.value_of("output").map(String::from),
+        sysroot: args.value_of("sysroot").map(PathBuf::from),
+        as_path: resolve_tool(args.value_of("as-path"), "TNCC_AS", DEFAULT_AS),
+        ld_path: resolve_tool(args.value_of("ld-path"), "TNCC_LD", DEFAULT_LD),
+        lib_dirs: args
+            .values_of("lib-dir")
+            .map(|v| v.map(PathBuf::from).collect())
+            .unwrap_or_default(),
+        libs: args
+            .values_of("lib")
+            .map(|v| v.map(String::from).collect())
+            .unwrap_or_default(),
+        linker_args: linker_args(args),
+        run,
+        warnings: args
+            .values_of("warning")
+            .map(|v| {
+                v.filter(|w| !w.starts_with("l,"))
+                    .map(String::from)
+                    .collect()
+            })
+            .unwrap_or_default(),
+        save_temps: args.is_present("save-temps"),
+        shared: args.is_present("shared"),
+        no_stdlib: args.is_present("nostdlib"),
+        map_file: args.value_of("emit-map").map(PathBuf::from),
+        strip: args.is_present("strip"),
+        coverage: args.is_present("coverage"),
+        reproducible: args.is_present("reproducible"),
+        arch: args
+            .value_of("march")
+            .map(|a| {
+                Arch::parse(a).ok_or_else(|| {
+                    DriverError::Usage(format!("unknown value '{}' for '-march'", a))
+                })
+            })
+            .transpose()?
+            .unwrap_or_default(),
+        instrument_functions: args
+            .values_of("feature")
+            .map(|mut v| v.any(|f| f == "instrument-functions"))
+            .unwrap_or_default(),
+        common: args
+            .values_of("feature")
+            .and_then(|mut v| v.rfind(|f| *f == "common" || *f == "no-common"))
+            == Some("common"),
+        stack_usage: args
+            .values_of("feature")
+            .map(|mut v| v.any(|f| f == "stack-usage"))
+            .unwrap_or_default(),
+        function_sections: args
+            .values_of("feature")
+            .map(|mut v| v.any(|f| f == "function-sections"))
+            .unwrap_or_default(),
+        data_sections: args
+            .values_of("feature")
+            .map(|mut v| v.any(|f| f == "data-sections"))
+            .unwrap_or_default(),
+        no_start_files: args.is_present("nostartfiles"),
+        freestanding: args
+            .values_of("feature")
+            .and_then(|mut v| v.rfind(|f| *f == "freestanding" || *f == "hosted"))
+            == Some("freestanding"),
+        compile_commands: args.is_present("emit-compile-commands"),
+        watch: args.is_present("watch"),
+        query: if args.is_present("version") {
+            Some(Query::Version)
+        } else if args.is_present("dumpversion") {
+            Some(Query::DumpVersion)
+        } else if args.is_present("dumpmachine") {
+            Some(Query::DumpMachine)
+        } else {
+            None
+        },
+        opt_level: args
+            .values_of("opt-level")
+            .and_then(|mut v| v.next_back())
+            .and_then(OptLevel::parse)
+            .unwrap_or_default(),
+        std: args
+            .value_of("std")
+            .and_then(Standard::parse)
+            .unwrap_or_default(),
+        dialect: args.value_of("asm-dialect").and_then(AsmDialect::parse),
+        reloc: args
+            .values_of("feature")
+            .and_then(|v| v.filter_map(RelocModel::from_feature).next_back())
+            .unwrap_or_default(),
+        time_report: args
+            .values_of("feature")
+            .map(|mut v| v.any(|f| f == "time-report"))
+            .unwrap_or_default(),
+        tab_width: args
+            .values_of("feature")
+            .and_then(|v| v.filter_map(|f| f.strip_prefix("tabstop=")).next_back())
+            .map(|w| {
+                w.parse()
+                    .ok()
+                    .filter(|w| *w > 0)
+                    .ok_or_else(|| DriverError::Usage(format!("invalid tab stop '{}'", w)))
+            })
+            .transpose()?
+            .unwrap_or(DEFAULT_TAB_WIDTH),
+        max_depth: args
+            .values_of("feature")
+            .and_then(|v| {
+                v.filter_map(|f| f.strip_prefix("constexpr-depth="))
+                    .next_back()
+            })
+            .map(|d| {
+                d.parse()
+                    .ok()
+                    .filter(|d| *d > 0)
+                    .ok_or_else(|| DriverError::Usage(format!("invalid constexpr depth '{}'", d)))
+            })
+            .transpose()?
+            .unwrap_or(MAX_CALL_DEPTH),
+        error_limit: args
+            .values_of("feature")
+            .and_then(|v| v.filter_map(|f| f.strip_prefix("error-limit=")).next_back())
+            .map(|n| {
+                n.parse()
+                    .map_err(|_| DriverError::Usage(format!("invalid error limit '{}'", n)))
+            })
+            .transpose()?
+            .unwrap_or(ERROR_LIMIT),
+        features: args
+            .values_of("feature")
+            .map(|v| {
+                v.filter(|f| {
+                    !KNOWN_FEATURES.contains(f)
+                        && !f.starts_with("tabstop=")
+                        && !f.starts_with("constexpr-depth=")
+                        && !f.starts_with("error-limit=")
+                        && !f.starts_with("sanitize=")
+                        && RelocModel::from_feature(f).is_none()
+                })
+                .map(String::from)
+                .collect()
+            })
+            .unwrap_or_default(),
+        lsp: matches.subcommand_matches("lsp").is_some(),
+        interpret: args.is_present("interpret"),
+        jit: args.is_present("jit"),
+        in_place: fmt.map(|f| f.is_present("in-place")).unwrap_or_default(),
+        emit: args.value_of("emit").or(fmt.map(|_| "c")).map(|v| match v {
+            "tokens" => Emit::Tokens,
+            "tokens-json" => Emit::TokensJson,
+            "ast-json" => Emit::AstJson,
+            "dot" => Emit::Dot,
+            "callgraph" => Emit::CallGraph,
+            "c" => Emit::C,
+            "symbols" => Emit::Symbols,
+            "symbols-json" => Emit::SymbolsJson,
+            _ => Emit::Ast,
+        }),
         files: args
             .values_of("input")
-            .unwrap()
-            .map(|v| PathBuf::from(v))
-            .collect(),
+            .map(|v| v.map(PathBuf::from).collect())
+            .unwrap_or_default(),
     };
 
-    // validate
-    if let Some(_) = opts.output {
-        if opts.files.len() > 1 && (opts.compile_only || opts.complie_as_only) {
-            panic!("can not specify '-o' with '-S' or '-c' when working with multiple input files");
-        }
+    // validate, only count inputs which produce an output with -S or -c
+    let outputs = opts
+        .files
+        .iter()
+        .map(|f| Input::from_path(f).kind)
+        .filter(|k| match k {
+            InputKind::Source => opts.compile_only || opts.complie_as_only,
+            InputKind::Asm => opts.complie_as_only,
+            InputKind::Object => false,
+        })
+        .count();
+    if opts.run.is_some() && (opts.compile_only || opts.complie_as_only || opts.emit.is_some()) {
+        return Err(DriverError::Usage(
+            "can not use 'run' with '-S', '-c' or '--emit'".into(),
+        ));
+    }
+    if opts.interpret && opts.jit {
+        return Err(DriverError::Usage(
+            "can not use '--interpret' with '--jit'".into(),
+        ));
+    }
+    if (opts.interpret || opts.jit)
+        && (opts.run.is_some() || opts.compile_only || opts.complie_as_only || opts.emit.is_some())
+    {
+        let mode = if opts.jit { "--jit" } else { "--interpret" };
+        return Err(DriverError::Usage(format!(
+            "can not use '{}' with 'run', '-S', '-c' or '--emit'",
+            mode
+        )));
+    }
+    if opts.in_place && opts.output.is_some() {
+        return Err(DriverError::Usage("can not use '-i' with '-o'".into()));
+    }
+    if opts.output.is_some() && opts.output_dir().is_none() && outputs > 1 {
+        return Err(DriverError::Usage(
+            "can not specify '-o' with '-S' or '-c' when working with multiple input files".into(),
+        ));
     }
 
-    opts
+    Ok(opts)
+}
+
+/// errors reported per source without '-ferror-limit=<n>'
+const ERROR_LIMIT: usize = 20;
+
+/// supported '-f<option>' flags
+const KNOWN_FEATURES: &[&str] = &[
+    "time-report",
+    "freestanding",
+    "hosted",
+    "instrument-functions",
+    "common",
+    "no-common",
+    "stack-usage",
+    "function-sections",
+    "data-sections",
+];
+
+/// gcc style long options starting with a single dash, i.e '-Xlinker'
+const SINGLE_DASH_LONG_OPTS: &[&str] = &[
+    "Xlinker",
+    "save-temps",
+    "dumpversion",
+    "dumpmachine",
+    "std",
+    "march",
+    "shared",
+    "nostdlib",
+    "nostartfiles",
+];
+
+/// rewrite single dash long options to the double dash form understood by clap
+fn normalize_args<I: IntoIterator<Item = String>>(args: I) -> Vec<String> {
+    args.into_iter()
+        .map(|a| {
+            let name = a
+                .strip_prefix('-')
+                .filter(|n| !n.starts_with('-'))
+                .and_then(|n| n.split('=').next());
+            match name {
+                Some(n) if SINGLE_DASH_LONG_OPTS.contains(&n) => format!("-{}", a),
+                Some("O") => String::from("-O1"),
+                _ => a,
+            }
+        })
+        .collect()
+}
+
+/// collect options for the linker from '-Wl,<args>' and '-Xlinker <arg>' in command line order
+fn linker_args(args: &ArgMatches) -> Vec<String> {
+    let mut vec: Vec<(usize, String)> = Vec::new();
+    if let (Some(indices), Some(values)) = (args.indices_of("warning"), args.values_of("warning")) {
+        indices
+            .zip(values)
+            .filter_map(|(i, w)| w.strip_prefix("l,").map(|l| (i, l)))
+            .for_each(|(i, l)| l.split(',').for_each(|a| vec.push((i, a.to_string()))));
+    }
+    if let (Some(indices), Some(values)) = (args.indices_of("xlinker"), args.values_of("xlinker")) {
+        indices
+            .zip(values)
+            .for_each(|(i, a)| vec.push((i, a.to_string())));
+    }
+
+    // stable sort keeps the order of comma separated arguments
+    vec.sort_by_key(|(i, _)| *i);
+    vec.into_iter().map(|(_, a)| a).collect()
 }
 
 fn create_arg_app() -> App<'static> {
     App::new("tncc")
         .author("Tri Nguyen")
         .author("Toy C compiler in Rust targets ARM (Linux and macOS)")
-        .arg(
-            Arg::new("arg-S")
-                .short('S')
-                .help("Emit assembly only; do not run assembler or linker"),
-        )
-        .arg(
-            Arg::new("arg-c")
-                .short('c')
-                .help("Emit assembly and run assembler; do not run linker"),
+        .disable_version_flag(true)
+        .subcommand_negates_reqs(true)
+        .args(compile_args())
+        .subcommand(
+            App::new("run")
+                .about("Compile to a temporary executable and run it")
+                .args(compile_args())
+                .arg(
+                    Arg::new("args")
+                        .last(true)
+                        .multiple_values(true)
+                        .help("Arguments passed to the program after '--'"),
+                ),
         )
-        .arg(
-            Arg::new("output")
-                .short('o')
-                .value_name("file")
-                .help("Output path"),
-        )
-        .arg(
-            Arg::new("verbose")
-                .long("verbose")
-                .short('v')
-                .help("print verbose logging"),
-        )
-        .arg(
-            Arg::new("debug")
-                .long("debug")
-                .short('d')
-                .help("print debug logging"),
-        )
-        .arg(
-            Arg::new("input")
-                .required(true)
-                .multiple(true)
-                .help("input C source files"),
+        .subcommand(App::new("lsp").about("Run the language server on stdin and stdout"))
+        .subcommand(
+            App::new("fmt")
+                .about("Format C sources to stdout (or the output file)")
+                .args(compile_args())
+                .arg(
+                    Arg::new("in-place")
+                        .short('i')
+                        .help("Rewrite the input files with the formatted source"),
+                ),
         )
 }
 
+/// options for compiling, shared by the 'run' subcommand
+fn compile_args() -> Vec<Arg<'static>> {
+    vec![
+        Arg::new("arg-S")
+            .short('S')
+            .help("Emit assembly only; do not run assembler or linker"),
+        Arg::new("arg-c")
+            .short('c')
+            .help("Emit assembly and run assembler; do not run linker"),
+        Arg::new("emit")
+            .long("emit")
+            .value_name("phase")
+            .possible_values(["tokens", "tokens-json", "ast", "ast-json", "dot", "callgraph", "c", "symbols", "symbols-json"])
+            .help("Dump tokens (as text or JSON), AST (as text, JSON, Graphviz DOT or formatted C), the call graph (as Graphviz DOT) or symbols per scope to stdout (or the output file) instead of compiling"),
+        Arg::new("emit-map")
+            .long("emit-map")
+            .value_name("file")
+            .help("Write the map of the linked sections and symbols to the file"),
+        Arg::new("coverage")
+            .long("coverage")
+            .help("Count the executed lines, the program writes them to '<source>.cov' at exit"),
+        Arg::new("reproducible")
+            .long("reproducible")
+            .help("Produce the same assembly and objects on every run and host, without the directories of the sources"),
+        Arg::new("strip")
+            .short('s')
+            .help("Strip symbols from the linked executable or library"),
+        Arg::new("interpret")
+            .long("interpret")
+            .help("Evaluate the source and exit with the value returned by main, without the assembler or linker"),
+        Arg::new("jit")
+            .long("jit")
+            .help("Experimental: run main in memory without the assembler or linker, aarch64 hosts only"),
+        Arg::new("watch")
+            .long("watch")
+            .help("Recompile whenever an input file changes"),
+        Arg::new("shared")
+            .long("shared")
+            .help("Link a shared library (.so or .dylib) to load with dlopen, compile with '-fPIC'"),
+        Arg::new("nostdlib")
+            .long("nostdlib")
+            .help("Link without the startup files and the C library, the program defines '_start'"),
+        Arg::new("nostartfiles")
+            .long("nostartfiles")
+            .help("Link without the startup files, the program defines '_start'"),
+        Arg::new("save-temps")
+            .long("save-temps")
+            .help("Keep intermediate asm and object files next to the sources"),
+        Arg::new("emit-compile-commands")
+            .long("emit-compile-commands")
+            .help("Write or update compile_commands.json in the current directory"),
+        Arg::new("output")
+            .short('o')
+            .value_name("file")
+            .help("Output path, or directory ending with '/' for outputs of '-S' and '-c'"),
+        Arg::new("lib-dir")
+            .short('L')
+            .value_name("dir")
+            .multiple_occurrences(true)
+            .number_of_values(1)
+            .help("Add directory to library search path"),
+        Arg::new("lib")
+            .short('l')
+            .value_name("library")
+            .multiple_occurrences(true)
+            .number_of_values(1)
+            .help("Link with library"),
+        Arg::new("warning")
+            .short('W')
+            .value_name("option")
+            .multiple_occurrences(true)
+            .number_of_values(1)
+            .allow_hyphen_values(true)
+            .help("Enable warning, or pass comma separated options to the linker with '-Wl,<args>'"),
+        Arg::new("opt-level")
+            .short('O')
+            .value_name("level")
+            .multiple_occurrences(true)
+            .number_of_values(1)
+            .possible_values(["0", "1", "2", "s"])
+            .help("Optimization level, '-O' is the same as '-O1'"),
+        Arg::new("std")
+            .long("std")
+            .value_name("standard")
+            .possible_values(["c89", "c90", "c99", "c11", "c17", "c18", "knr"])
+            .help("Language standard, 'knr' accepts declarations without a type and old-style definitions with a warning"),
+        Arg::new("march")
+            .long("march")
+            .value_name("arch")
+            .help("Instruction set, i.e 'armv8.1-a' or 'armv8-a+lse' for the atomic instructions instead of loops of exclusive loads and stores"),
+        Arg::new("asm-dialect")
+            .long("asm-dialect")
+            .value_name("dialect")
+            .possible_values(["gnu", "darwin"])
+            .help("Directives for GNU as or the Xcode assembler, defaults to the assembler of the target"),
+        Arg::new("feature")
+            .short('f')
+            .value_name("option")
+            .multiple_occurrences(true)
+            .number_of_values(1)
+            .help("Code generation and driver option, i.e '-ftime-report' to print time spent per phase, '-ftabstop=<n>' for columns of tabs in reported locations, '-fconstexpr-depth=<n>' for nested calls with '--interpret', '-ferror-limit=<n>' to stop after n errors (0 for no limit), '-fPIC' for shared libraries, '-ffreestanding' to define __STDC_HOSTED__ as 0, '-finstrument-functions' to call the profiling hooks, '-fcommon' to merge the tentative definitions of several files, '-fstack-usage' to write the stack usage of the functions to <source>.su, '-ffunction-sections' and '-fdata-sections' to place each function or variable in its own section and remove the unused ones when linking or '-fno-pie' to address extern data directly"),
+        Arg::new("xlinker")
+            .long("Xlinker")
+            .value_name("arg")
+            .multiple_occurrences(true)
+            .number_of_values(1)
+            .allow_hyphen_values(true)
+            .help("Pass argument to the linker"),
+        Arg::new("sysroot").long("sysroot").value_name("dir").help(
+            "Root directory of system libraries (default: 'xcrun --show-sdk-path' on macOS)",
+        ),
+        Arg::new("as-path")
+            .long("as-path")
+            .value_name("path")
+            .help("Assembler to use (default: $TNCC_AS, /usr/bin/as or 'as' on PATH)"),
+        Arg::new("ld-path")
+            .long("ld-path")
+            .value_name("path")
+            .help("Linker to use (default: $TNCC_LD, /usr/bin/ld or 'ld' on PATH)"),
+        Arg::new("verbose")
+            .long("verbose")
+            .short('v')
+            .help("print verbose logging"),
+        Arg::new("debug")
+            .long("debug")
+            .short('d')
+            .help("print debug logging of all phases, same as '--log=all'"),
+        Arg::new("log")
+            .long("log")
+            .value_name("phases")
+            .multiple_occurrences(true)
+            .help("print logging of the phases, i.e '--log=scan,parse=trace', from scan, parse, semantics, codegen, assemble, link or all at debug level unless given"),
+        Arg::new("version")
+            .long("version")
+            .help("Print version, git revision, target and tool paths"),
+        Arg::new("dumpversion")
+            .long("dumpversion")
+            .help("Print the version only"),
+        Arg::new("dumpmachine")
+            .long("dumpmachine")
+            .help("Print the default target triple"),
+        Arg::new("input")
+            .required_unless_present_any(["version", "dumpversion", "dumpmachine"])
+            .multiple(true)
+            .help("input C source files, '-' to read from standard input"),
+    ]
+}
+
 fn init_logger(opts: &Opts) {
-    let level = if opts.debug {
-        "debug"
-    } else if opts.verbose {
+    let level = if opts.verbose {
         "info"
     } else {
         "warn" // default
     };
     let env = Env::default().filter_or("MY_LOG_LEVEL", level);
-    Builder::from_env(env)
-        .format(|buf, record| writeln!(buf, "[{}] {}", record.level(), record.args()))
+    let mut builder = Builder::from_env(env);
+    // records of a phase are targeted at its name
+    for (phase, level) in &opts.log {
+        builder.filter_module(phase.name(), *level);
+    }
+    builder
+        .format(|buf, record| match Phase::from_name(record.target()) {
+            Some(p) => writeln!(buf, "[{} {}] {}", record.level(), p.name(), record.args()),
+            None => writeln!(buf, "[{}] {}", record.level(), record.args()),
+        })
         .init();
 }
+
+/// phases and levels of '--log', 'all' for every phase and debug if the level is not given,
+/// '--debug' logs every phase at debug level before the options
+fn parse_log<'a, I: Iterator<Item = &'a str>>(
+    debug: bool,
+    specs: I,
+) -> Result<Vec<(Phase, LevelFilter)>, DriverError> {
+    let mut log = Vec::new();
+    if debug {
+        log.extend(Phase::ALL.iter().map(|p| (*p, LevelFilter::Debug)));
+    }
+    for spec in specs.flat_map(|s| s.split(',')) {
+        let (name, level) = match spec.split_once('=') {
+            Some((n, l)) => {
+                let level = l.parse().map_err(|_| {
+                    DriverError::Usage(format!("invalid log level '{}' in '--log={}'", l, spec))
+                })?;
+                (n, level)
+            }
+            None => (spec, LevelFilter::Debug),
+        };
+        match (name, Phase::from_name(name)) {
+            ("all", _) => log.extend(Phase::ALL.iter().map(|p| (*p, level))),
+            (_, Some(p)) => log.push((p, level)),
+            (_, None) => {
+                return Err(DriverError::Usage(format!(
+                    "unknown phase '{}' in '--log', expected one of {} or all",
+                    name,
+                    Phase::ALL.map(|p| p.name()).join(", ")
+                )))
+            }
+        }
+    }
+    Ok(log)
+}
+
+#[cfg(test)]
+mod test {
+    use test_case::test_case;
+
+    use std::path::Path;
+
+    use tncc::util::{Input, TargetOs};
+
+    use super::{
+        create_output, final_output, linker_command, normalize_args, parse_opts_from,
+        stack_usage_path,
+    };
+
+    fn args(v: &[&str]) -> Vec<String> {
+        std::iter::once("tncc")
+            .chain(v.iter().copied())
+            .map(String::from)
+            .collect()
+    }
+
+    // single dash long options get a second dash in place, other arguments keep their order
+    #[test_case(&["-o", "a.out", "a.c"] => args(&["-o", "a.out", "a.c"]); "output before input")]
+    #[test_case(&["a.c", "-o", "a.out"] => args(&["a.c", "-o", "a.out"]); "output after input")]
+    #[test_case(&["a.c", "-Wl,--gc-sections", "-o", "a"] => args(&["a.c", "-Wl,--gc-sections", "-o", "a"]); "wl passthrough")]
+    #[test_case(&["-Xlinker", "-z", "a.c", "-save-temps"] => args(&["--Xlinker", "-z", "a.c", "--save-temps"]))]
+    #[test_case(&["-std=c99", "-O", "--verbose", "a.c"] => args(&["--std=c99", "-O1", "--verbose", "a.c"]))]
+    #[test_case(&["-nostdlib", "-nostartfiles", "-shared"] => args(&["--nostdlib", "--nostartfiles", "--shared"]))]
+    fn normalize(v: &[&str]) -> Vec<String> {
+        normalize_args(args(v))
+    }
+
+    // '-Wl,' arguments split at the commas, in command line order with '-Xlinker'
+    #[test_case(&["a.o", "-Wl,-z,now", "-o", "a", "-Xlinker", "--gc-sections"] => vec!["-z", "now", "--gc-sections"])]
+    #[test_case(&["-Xlinker", "-Map=a.map", "a.o", "-Wl,-s"] => vec!["-Map=a.map", "-s"])]
+    #[test_case(&["-Wall", "a.o"] => Vec::<String>::new(); "warnings are not linker arguments")]
+    fn linker_args(v: &[&str]) -> Vec<String> {
+        parse_opts_from(args(v)).unwrap().linker_args
+    }
+
+    #[test_case(&["-o", "out", "a.c", "b.o"])]
+    #[test_case(&["a.c", "b.o", "-o", "out"])]
+    fn output_and_inputs(v: &[&str]) {
+        let opts = parse_opts_from(args(v)).unwrap();
+        assert_eq!(opts.output.as_deref(), Some("out"));
+        assert_eq!(opts.files, ["a.c", "b.o"].map(std::path::PathBuf::from));
+    }
+
+    // '-o -' is the standard output like gcc, the report of the stack usage is named by the source
+    #[test_case(&["-S", "a.c", "-o", "-"] => ("-".to_string(), "a.su".to_string()); "stdout")]
+    #[test_case(&["-S", "a.c", "-o", "out.s"] => ("out.s".to_string(), "out.su".to_string()); "file")]
+    #[test_case(&["-c", "a.c", "-o", "-"] => ("-".to_string(), "a.su".to_string()); "object to stdout")]
+    fn output_paths(v: &[&str]) -> (String, String) {
+        let opts = parse_opts_from(args(v)).unwrap();
+        let out = final_output(&opts, Path::new("a.c"), "s").unwrap();
+        let su = stack_usage_path(&opts, Path::new("a.c")).unwrap();
+        (out.display().to_string(), su.display().to_string())
+    }
+
+    #[test]
+    fn output_to_stdout() {
+        create_output(Path::new("-")).unwrap();
+        assert!(!Path::new("-").exists());
+    }
+
+    // the checked operators do not exist, the checks are refused instead of ignored
+    #[test_case("-fsanitize=shift" => "'-fsanitize=shift' is not supported, tncc does not yet support the checked operators")]
+    #[test_case("-fsanitize=integer-divide-by-zero,shift" => "'-fsanitize=integer-divide-by-zero' is not supported, tncc does not yet support the checked operators")]
+    #[test_case("-fsanitize=address" => "unrecognized argument to -fsanitize= option: 'address'")]
+    fn sanitize(arg: &str) -> String {
+        parse_opts_from(args(&["a.c", arg]))
+            .err()
+            .map(|e| e.to_string())
+            .unwrap_or_default()
+    }
+
+    /// arguments of the linker after the object and the output, the SDK of macOS is '/sdk'
+    fn link_line(target: TargetOs, v: &[&str]) -> Vec<String> {
+        let v: Vec<&str> = std::iter::once("a.o").chain(v.iter().copied()).collect();
+        let mut opts = parse_opts_from(args(&v)).unwrap();
+        opts.target = target;
+        if target == TargetOs::MacOs {
+            opts.sysroot = Some("/sdk".into());
+        }
+        let inputs = [Input::from_path(Path::new("a.o"))];
+        let cmd = linker_command(&opts, &inputs, Path::new("a.out")).unwrap();
+        cmd.get_args()
+            .skip(3)
+            .map(|a| a.to_string_lossy().into_owned())
+            .collect()
+    }
+
+    const CRT1: &str = "/usr/lib/aarch64-linux-gnu/crt1.o";
+    const CRTI: &str = "/usr/lib/aarch64-linux-gnu/crti.o";
+    const CRTN: &str = "/usr/lib/aarch64-linux-gnu/crtn.o";
+    const LIBDIR: &str = "-L/usr/lib/aarch64-linux-gnu";
+
+    // the startup files and the C library are dropped by '-nostdlib', '-nostartfiles' keeps libc
+    #[test_case(TargetOs::Linux, &[] => vec![CRT1, CRTI, CRTN, LIBDIR, "-lc"])]
+    #[test_case(TargetOs::Linux, &["-nostartfiles"] => vec![LIBDIR, "-lc"])]
+    #[test_case(TargetOs::Linux, &["-nostdlib"] => Vec::<String>::new())]
+    #[test_case(TargetOs::Linux, &["-nostdlib", "-nostartfiles"] => Vec::<String>::new(); "both")]
+    #[test_case(TargetOs::Linux, &["-shared"] => vec!["-shared", CRTI, CRTN, LIBDIR, "-lc"])]
+    #[test_case(TargetOs::Linux, &["-shared", "-nostdlib"] => vec!["-shared"])]
+    #[test_case(TargetOs::MacOs, &[] => vec!["-dynamic", "-arch", "arm64", "-syslibroot", "/sdk", "-lSystem"])]
+    #[test_case(TargetOs::MacOs, &["-nostdlib"] => vec!["-static", "-arch", "arm64", "-syslibroot", "/sdk", "-e", "__start"])]
+    #[test_case(TargetOs::MacOs, &["-nostartfiles"] => vec!["-dynamic", "-arch", "arm64", "-syslibroot", "/sdk", "-lSystem"])]
+    #[test_case(TargetOs::MacOs, &["-shared", "-nostdlib"] => vec!["-dylib", "-arch", "arm64", "-syslibroot", "/sdk"])]
+    fn startup_files(target: TargetOs, v: &[&str]) -> Vec<String> {
+        link_line(target, v)
+    }
+
+    // GNU ld writes the map with '-Map' and strips with '-s', Xcode ld with '-map' and '-S -x'
+    #[test_case(TargetOs::Linux, &["--emit-map=out.map"] => vec!["-Map", "out.map"]; "gnu map")]
+    #[test_case(TargetOs::MacOs, &["--emit-map=out.map"] => vec!["-map", "out.map"]; "darwin map")]
+    #[test_case(TargetOs::Linux, &["-s"] => vec!["-s"]; "gnu strip")]
+    #[test_case(TargetOs::MacOs, &["-s"] => vec!["-S", "-x"]; "darwin strip")]
+    #[test_case(TargetOs::Linux, &["-s", "--emit-map", "out.map"] => vec!["-Map", "out.map", "-s"]; "map then strip")]
+    fn map_and_strip(target: TargetOs, v: &[&str]) -> Vec<String> {
+        let default = link_line(target, &[]);
+        link_line(target, v).split_off(default.len())
+    }
+}
@@ -1,354 +1,1429 @@
+use std::{collections::VecDeque, convert::TryFrom};
+
 use crate::{
     ast::*,
-    common::{TokType, Token},
+    common::{CompileError, Diagnostics, FixIt, Span, TokType, Token},
+    consteval::eval_int,
+    intern::Symbol,
+    scan::LexError,
+    typenames::TypeNames,
+    util::Standard,
 };
 
-pub fn parse(tokens: Vec<Token>) -> Ast {
+/// errors are boxed to keep the frames of the recursive descent small
+type ParseResult<T> = Result<T, Box<CompileError>>;
+
+/// maximum nesting of blocks, function calls, parentheses and unary operators, deeper input is
+/// rejected instead of overflowing the stack
+const MAX_DEPTH: usize = 256;
+
+/// number of tokens the parser can peek ahead by default, LL(2)
+const LOOKAHEAD: usize = 2;
+
+/// binary operators with binding power, higher binds tighter, all are left associative
+const BINARY_OPS: &[(TokType, ArithOp, u8)] = &[
+    (TokType::Plus, ArithOp::Add, 10),
+    (TokType::Minus, ArithOp::Sub, 10),
+];
+
+/// prefix operators with binding power, tighter than any binary operator
+const UNARY_OPS: &[(TokType, UnaryOp, u8)] = &[
+    (TokType::Minus, UnaryOp::Neg, 100),
+    (TokType::Plus, UnaryOp::Plus, 100),
+];
+
+/// C keywords scanned as identifiers because the constructs are not implemented yet
+const UNSUPPORTED_KEYWORDS: &[&str] = &[
+    "auto",
+    "break",
+    "case",
+    "const",
+    "continue",
+    "default",
+    "do",
+    "double",
+    "else",
+    "enum",
+    "float",
+    "for",
+    "goto",
+    "if",
+    "inline",
+    "long",
+    "register",
+    "restrict",
+    "short",
+    "signed",
+    "sizeof",
+    "struct",
+    "switch",
+    "typedef",
+    "union",
+    "unsigned",
+    "while",
+    "_Bool",
+    "_Complex",
+    "_Imaginary",
+];
+
+fn binary_op(tok: &TokType) -> Option<(ArithOp, u8)> {
+    BINARY_OPS
+        .iter()
+        .find(|(t, _, _)| t == tok)
+        .map(|(_, op, bp)| (*op, *bp))
+}
+
+fn unary_op(tok: &TokType) -> Option<(UnaryOp, u8)> {
+    UNARY_OPS
+        .iter()
+        .find(|(t, _, _)| t == tok)
+        .map(|(_, op, bp)| (*op, *bp))
+}
+
+/// parse the tokens, the first syntax error is returned
+pub fn parse<I: IntoIterator<Item = Token>>(tokens: I) -> Result<Ast, CompileError> {
+    parse_with(tokens, Standard::default())
+}
+
+/// parse the tokens in the language standard, the first syntax error is returned
+pub fn parse_with<I: IntoIterator<Item = Token>>(
+    tokens: I,
+    std: Standard,
+) -> Result<Ast, CompileError> {
+    let mut parser = Parser::new(tokens.into_iter().map(Ok));
+    parser.std = std;
+    let ast = parser.parse();
+    match parser.errors.into_iter().next() {
+        Some(e) => Err(e),
+        None => Ok(ast),
+    }
+}
+
+/// parse tokens while they are scanned, i.e from `Lexer`, without buffering the whole input
+///
+/// invalid input is parsed as `Unknown` token, all lexical errors are reported together with
+/// the syntax errors which are not caused by them
+pub fn parse_stream<I>(tokens: I) -> Result<Ast, Diagnostics>
+where
+    I: Iterator<Item = Result<Token, LexError>>,
+{
+    parse_stream_with(tokens, Standard::default())
+}
+
+/// parse tokens while they are scanned in the language standard
+pub fn parse_stream_with<I>(tokens: I, std: Standard) -> Result<Ast, Diagnostics>
+where
+    I: Iterator<Item = Result<Token, LexError>>,
+{
     let mut parser = Parser::new(tokens);
-    return parser.parse();
+    parser.std = std;
+    let ast = parser.parse();
+    let mut errors: Vec<CompileError> = parser
+        .lex_errors
+        .into_iter()
+        .map(CompileError::from)
+        .collect();
+    let lex_count = errors.len();
+    for e in parser.errors {
+        if !errors[..lex_count].iter().any(|l| l.span == e.span) {
+            errors.push(e);
+        }
+    }
+    if errors.is_empty() {
+        return Ok(ast);
+    }
+    errors.sort_by_key(|e| e.span.map_or(usize::MAX, |s| s.start));
+    Err(Diagnostics(errors))
+}
+
+/// declared name with the derivations from the name outward, i.e `*a[10]` is array of pointers
+struct Declarator {
+    /// none for abstract declarator
+    name: Option<Symbol>,
+    derived: Vec<Derived>,
+}
+
+enum Derived {
+    /// pointer, volatile qualified if true
+    Pointer(bool),
+    /// optional length
+    Array(Option<u64>),
+    /// none for the unspecified `()`
+    Function(Option<Vec<Param>>),
+    /// parameter names of an old-style definition, i.e `f(a, b)`
+    Identifiers(Vec<Param>),
+}
+
+/// parameter of a function declarator, the name is optional outside of function definitions
+struct Param {
+    id: NodeId,
+    span: Span,
+    loc: (u32, u32),
+    data_type: Type,
+    name: Option<Symbol>,
+    /// name in an identifier list without a declaration
+    implicit_int: bool,
+}
+
+impl Param {
+    fn into_decl(self) -> ParseResult<ParamDecl> {
+        let name = self.name.ok_or_else(|| CompileError {
+            msg: format!("parameter name omitted at {}:{}", self.loc.0, self.loc.1),
+            loc: Some(self.loc),
+            span: Some(self.span),
+            fixits: Vec::new(),
+            notes: Vec::new(),
+        })?;
+        Ok(ParamDecl {
+            id: self.id,
+            span: self.span,
+            data_type: self.data_type,
+            name,
+            implicit_int: self.implicit_int,
+        })
+    }
+}
+
+/// attribute name without the surrounding underscores, `__aligned__` is `aligned`
+fn attr_name(name: Symbol) -> Symbol {
+    match name
+        .as_str()
+        .strip_prefix("__")
+        .and_then(|n| n.strip_suffix("__"))
+    {
+        Some(n) if !n.is_empty() => Symbol::intern(n),
+        _ => name,
+    }
+}
+
+fn qualify(ty: Type, volatile: bool) -> Type {
+    match volatile {
+        true => Type::Volatile(Box::new(ty)),
+        false => ty,
+    }
+}
+
+/// type of the declarator, the outermost derivation is applied last
+fn derive(base: Type, derived: &[Derived]) -> Type {
+    derived.iter().rev().fold(base, |ty, d| match d {
+        Derived::Pointer(volatile) => qualify(Type::Pointer(Box::new(ty)), *volatile),
+        Derived::Array(len) => Type::Array(Box::new(ty), *len),
+        Derived::Function(params) => Type::Function(
+            Box::new(ty),
+            params
+                .as_ref()
+                .map(|params| params.iter().map(|p| p.data_type.clone()).collect()),
+        ),
+        // the names do not declare the parameters outside of the definition
+        Derived::Identifiers(_) => Type::Function(Box::new(ty), None),
+    })
+}
+
+struct Parser<I: Iterator<Item = Result<Token, LexError>>> {
+    tokens: I,
+    /// scanned tokens which are not consumed yet
+    buf: VecDeque<Token>,
+    /// last consumed token
+    prev: Option<Token>,
+    /// errors of the token stream, replaced by `Unknown` tokens
+    lex_errors: Vec<LexError>,
+    /// syntax errors, parsing continues after each of them
+    errors: Vec<CompileError>,
+    depth: usize,
+    next_id: u32,
+    /// number of tokens kept in `buf`
+    k: usize,
+    /// tokens consumed while a tentative parse is active, replayed on backtracking
+    log: Vec<Token>,
+    /// nested tentative parses
+    marks: Vec<Mark>,
+    std: Standard,
+    /// typedef names and the ordinary identifiers hiding them, classifies identifiers
+    names: TypeNames,
 }
 
-struct Parser {
-    tokens: Vec<Token>,
-    index: usize,
+/// parser state at the start of a tentative parse
+struct Mark {
+    /// length of the log
+    log: usize,
+    prev: Option<Token>,
+    depth: usize,
+    next_id: u32,
 }
 
-impl Parser {
-    pub fn new(tokens: Vec<Token>) -> Parser {
-        Parser { tokens, index: 0 }
+impl<I: Iterator<Item = Result<Token, LexError>>> Parser<I> {
+    pub fn new(tokens: I) -> Self {
+        Parser::with_lookahead(tokens, LOOKAHEAD)
     }
 
+    /// parser peeking up to k tokens ahead
+    pub fn with_lookahead(tokens: I, k: usize) -> Self {
+        let mut parser = Parser {
+            tokens,
+            buf: VecDeque::with_capacity(k),
+            prev: None,
+            lex_errors: Vec::new(),
+            errors: Vec::new(),
+            depth: 0,
+            next_id: 0,
+            k: k.max(1),
+            log: Vec::new(),
+            marks: Vec::new(),
+            std: Standard::default(),
+            names: TypeNames::default(),
+        };
+        parser.fill();
+        parser
+    }
+
+    /// parse the whole input, syntax errors are collected and the broken declarations or
+    /// statements are skipped
     pub fn parse(&mut self) -> Ast {
-        let mut ast = Ast { 0: Vec::new() };
+        let mut ast = Ast(Vec::new());
 
         // parse external decl
         loop {
-            let peek = self.peek();
-            match peek {
-                Some(t) if (self.is_data_type(t)) => {
-                    let (return_type, name) = self.parse_declarator();
-                    let ext = match self.peek() {
-                        // parse function
-                        Some(t) if t.tok == TokType::ParentOpen => {
-                            let (params, cmp_stmt) = self.parse_func_params_body();
-                            ExtDecl::Func(FuncDecl {
-                                return_type,
-                                name,
-                                params,
-                                cmp_stmt,
-                            })
-                        }
-                        // parse global function
-                        _ => {
-                            let expr = match self.peek() {
-                                Some(t) if t.tok == TokType::Assign => {
-                                    self.consume_any();
-                                    Some(self.parse_expr())
-                                }
-                                _ => None,
-                            };
-                            self.consume(TokType::Semicolon);
-                            ExtDecl::Global(GlobalVarDecl(return_type, name, expr))
-                        }
-                    };
-                    ast.0.push(ext);
+            match self.parse_ext_decl() {
+                Ok(Some(ext)) => ast.0.push(ext),
+                Ok(None) => break,
+                Err(e) => {
+                    self.depth = 0;
+                    self.report(*e);
+                    self.synchronize(true);
                 }
-                None => break,
-                Some(t) => panic!("unexpected {}", t),
             }
         }
 
         ast
     }
 
-    /// parse function parameters and body (compound statement)
-    fn parse_func_params_body(&mut self) -> (Vec<ParamDecl>, CmpStmt) {
-        // parameters
-        self.consume(TokType::ParentOpen);
-        let params = self.parse_parameters();
-        self.consume(TokType::ParentClose);
+    /// parse function or global variable, none at the end of input
+    fn parse_ext_decl(&mut self) -> ParseResult<Option<ExtDecl>> {
+        self.check_supported()?;
+        match self.peek() {
+            Some(t) if t.tok == TokType::KeywordStaticAssert => {
+                return Ok(Some(ExtDecl::StaticAssert(self.parse_static_assert()?)))
+            }
+            Some(t) if self.is_decl_start(t) || self.is_implicit_int(t) => {}
+            Some(t) if matches!(t.tok, TokType::KeywordExtern | TokType::KeywordStatic) => {}
+            None => return Ok(None),
+            Some(t) => {
+                return Err(Box::new(CompileError::at_token(
+                    format!("unexpected {}", t),
+                    t,
+                )))
+            }
+        }
+        let id = self.new_id();
+        let start = self.start();
+        let mut attrs = self.parse_attributes()?;
+        let (external, internal) = self.parse_storage_class()?;
+        let implicit_int = self
+            .peek()
+            .is_some_and(|t| !self.is_data_type(t) && self.is_implicit_int(t));
+        let base = match implicit_int {
+            true => Type::Base(DataType::Int),
+            false => self.parse_data_type()?,
+        };
+        let decl = self.parse_declarator(true)?;
+        let name = decl.name.expect("declarator with name");
+        self.names.declare_ordinary(name, self.span_from(start))?;
+        attrs.extend(self.parse_attributes()?);
+        let mut derived = decl.derived;
+        let ext = match derived.first() {
+            // parse function, the declarator ends with the parameters
+            Some(Derived::Function(_)) if self.is_peek_tok(TokType::BracketOpen) => {
+                let params = match derived.remove(0) {
+                    Derived::Function(params) => params,
+                    _ => unreachable!(),
+                };
+                let prototype = params.is_some();
+                let params = params
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(Param::into_decl)
+                    .collect::<ParseResult<Vec<ParamDecl>>>()?;
+                let cmp_stmt = self.parse_function_body(&params)?;
+                ExtDecl::Func(FuncDecl {
+                    id,
+                    span: self.span_from(start),
+                    return_type: derive(base, &derived),
+                    name,
+                    params,
+                    prototype,
+                    external,
+                    internal,
+                    implicit_int,
+                    attrs,
+                    cmp_stmt,
+                })
+            }
+            // parse old-style definition, the parameters are declared before the body
+            Some(Derived::Identifiers(_)) if self.is_old_style_body() => {
+                let mut params = match derived.remove(0) {
+                    Derived::Identifiers(params) => params,
+                    _ => unreachable!(),
+                };
+                self.parse_param_declarations(&mut params)?;
+                let params = params
+                    .into_iter()
+                    .map(Param::into_decl)
+                    .collect::<ParseResult<Vec<ParamDecl>>>()?;
+                let cmp_stmt = self.parse_function_body(&params)?;
+                ExtDecl::Func(FuncDecl {
+                    id,
+                    span: self.span_from(start),
+                    return_type: derive(base, &derived),
+                    name,
+                    params,
+                    prototype: false,
+                    external,
+                    internal,
+                    implicit_int,
+                    attrs,
+                    cmp_stmt,
+                })
+            }
+            // parse global variable or function prototype
+            _ => {
+                let init = match self.peek() {
+                    Some(t) if t.tok == TokType::Assign => {
+                        self.consume_any();
+                        Some(self.parse_initializer()?)
+                    }
+                    _ => None,
+                };
+                self.consume(TokType::Semicolon)?;
+                ExtDecl::Global(GlobalVarDecl {
+                    id,
+                    span: self.span_from(start),
+                    data_type: derive(base, &derived),
+                    name,
+                    init,
+                    external,
+                    internal,
+                    implicit_int,
+                    attrs,
+                })
+            }
+        };
+        Ok(Some(ext))
+    }
+
+    /// `extern` or `static` before the type, at most one of them
+    fn parse_storage_class(&mut self) -> ParseResult<(bool, bool)> {
+        let (mut external, mut internal) = (false, false);
+        while let Some(t) = self.peek() {
+            match t.tok {
+                TokType::KeywordExtern | TokType::KeywordStatic if external || internal => {
+                    return Err(Box::new(CompileError::at_token(
+                        format!(
+                            "multiple storage classes in declaration specifiers at {}:{}",
+                            t.loc.0, t.loc.1
+                        ),
+                        t,
+                    )))
+                }
+                TokType::KeywordExtern => external = true,
+                TokType::KeywordStatic => internal = true,
+                _ => break,
+            }
+            self.consume_any();
+        }
+        Ok((external, internal))
+    }
 
-        // compound statement
-        let cmp_stmt = self.parse_compound_stmt();
+    /// keep the syntax error, errors at the end of input after a previous error are only the
+    /// consequence of skipping
+    fn report(&mut self, e: CompileError) {
+        if self.errors.is_empty() || e.span.is_some() {
+            self.errors.push(e);
+        }
+    }
 
-        (params, cmp_stmt)
+    /// skip tokens after a syntax error until the end of the statement
+    ///
+    /// stop after ';' or a skipped block, the '}' closing the enclosing block is only consumed
+    /// at the top level, so is the ';' ending a declaration with a block, i.e `struct s {};`
+    fn synchronize(&mut self, top_level: bool) {
+        let mut depth = 0;
+        while let Some(t) = self.peek_tok() {
+            match t {
+                TokType::Semicolon if depth == 0 => {
+                    self.consume_any();
+                    return;
+                }
+                TokType::BracketOpen => depth += 1,
+                TokType::BracketClose if depth == 0 && !top_level => return,
+                TokType::BracketClose if depth <= 1 => {
+                    self.consume_any();
+                    if top_level && self.is_peek_tok(TokType::Semicolon) {
+                        self.consume_any();
+                    }
+                    return;
+                }
+                TokType::BracketClose => depth -= 1,
+                _ => {}
+            }
+            self.consume_any();
+        }
     }
 
-    /// parse list of parameters
-    fn parse_parameters(&mut self) -> Vec<ParamDecl> {
-        let mut vec: Vec<ParamDecl> = Vec::new();
+    /// parse list of parameters, `(void)` declares none and `()` leaves them unspecified
+    fn parse_parameters(&mut self) -> ParseResult<Option<Vec<Param>>> {
+        if self.lookahead_is(&[TokType::KeywordVoid, TokType::ParentClose]) {
+            self.consume_any();
+            return Ok(Some(Vec::new()));
+        }
+
+        self.check_supported()?;
+        let mut vec: Vec<Param> = Vec::new();
         match self.peek() {
             Some(t) if self.is_data_type(t) => {
-                vec.push(self.parse_parameter());
+                vec.push(self.parse_parameter()?);
 
                 // check if comma
                 loop {
                     match self.peek() {
                         Some(t) if t.tok == TokType::Comma => {
                             self.consume_any();
-                            vec.push(self.parse_parameter());
+                            vec.push(self.parse_parameter()?);
                         }
                         _ => break,
                     }
                 }
             }
-            _ => (),
+            _ => return Ok(None),
         }
 
-        vec
+        Ok(Some(vec))
+    }
+
+    fn parse_parameter(&mut self) -> ParseResult<Param> {
+        let id = self.new_id();
+        let start = self.start();
+        let loc = self.peek().map(|t| t.loc).unwrap_or_default();
+        let base = self.parse_data_type()?;
+        let decl = self.parse_declarator(false)?;
+        Ok(Param {
+            id,
+            span: self.span_from(start),
+            loc,
+            data_type: derive(base, &decl.derived),
+            name: decl.name,
+            implicit_int: false,
+        })
+    }
+
+    /// K&R declarations may omit the type specifier, i.e `main() {}`
+    fn is_implicit_int(&self, tok: &Token) -> bool {
+        self.std == Standard::Knr
+            && match &tok.tok {
+                TokType::ID(s) => !self.names.is_type_name(*s),
+                t => *t == TokType::Star,
+            }
     }
 
-    fn parse_parameter(&mut self) -> ParamDecl {
-        let (dt, id) = self.parse_declarator();
-        ParamDecl {
-            data_type: dt,
-            name: id,
+    /// K&R parameter names instead of declarations, i.e `f(a, b)`
+    fn is_identifier_list(&mut self) -> bool {
+        self.std == Standard::Knr && self.is_ref() && self.check_supported().is_ok()
+    }
+
+    /// comma separated names, each is int until it is declared
+    fn parse_identifier_list(&mut self) -> ParseResult<Vec<Param>> {
+        let mut vec = Vec::new();
+        loop {
+            let id = self.new_id();
+            let start = self.start();
+            let loc = self.peek().map(|t| t.loc).unwrap_or_default();
+            let name = self.parse_id()?;
+            vec.push(Param {
+                id,
+                span: self.span_from(start),
+                loc,
+                data_type: Type::Base(DataType::Int),
+                name: Some(name),
+                implicit_int: true,
+            });
+            if !self.is_peek_tok(TokType::Comma) {
+                return Ok(vec);
+            }
+            self.consume_any();
+        }
+    }
+
+    /// the body or the declarations of the parameters follow the identifier list
+    fn is_old_style_body(&self) -> bool {
+        match self.peek() {
+            Some(t) => t.tok == TokType::BracketOpen || self.is_data_type(t),
+            None => false,
         }
     }
 
-    fn parse_declarator(&mut self) -> (DataType, String) {
-        let dt = self.parse_data_type();
-        (dt, self.parse_id())
+    /// declarations between the identifier list and the body, i.e `int a, *b;`
+    fn parse_param_declarations(&mut self, params: &mut [Param]) -> ParseResult<()> {
+        while self.peek().is_some_and(|t| self.is_data_type(t)) {
+            let base = self.parse_data_type()?;
+            loop {
+                let start = self.start();
+                let loc = self.peek().map(|t| t.loc).unwrap_or_default();
+                let decl = self.parse_declarator(true)?;
+                let name = decl.name.expect("declarator with name");
+                let error = |msg: String| CompileError {
+                    msg: format!("{} at {}:{}", msg, loc.0, loc.1),
+                    loc: Some(loc),
+                    span: Some(self.span_from(start)),
+                    fixits: Vec::new(),
+                    notes: Vec::new(),
+                };
+                let param = match params.iter_mut().find(|p| p.name == Some(name)) {
+                    Some(p) if p.implicit_int => p,
+                    Some(_) => {
+                        return Err(Box::new(error(format!(
+                            "redefinition of parameter '{}'",
+                            name
+                        ))))
+                    }
+                    None => {
+                        return Err(Box::new(error(format!(
+                            "declaration for parameter '{}' but no such parameter",
+                            name
+                        ))))
+                    }
+                };
+                param.data_type = derive(base.clone(), &decl.derived);
+                param.implicit_int = false;
+                if !self.is_peek_tok(TokType::Comma) {
+                    break;
+                }
+                self.consume_any();
+            }
+            self.consume(TokType::Semicolon)?;
+        }
+        Ok(())
     }
 
-    fn parse_compound_stmt(&mut self) -> CmpStmt {
-        self.consume(TokType::BracketOpen);
+    /// pointers, then the name or a parenthesized declarator, then array and function suffixes
+    ///
+    /// the name is optional if not `named`, i.e abstract declarator of a prototype parameter
+    fn parse_declarator(&mut self, named: bool) -> ParseResult<Declarator> {
+        let mut pointers = Vec::new();
+        while self.is_peek_tok(TokType::Star) {
+            self.consume_any();
+            pointers.push(self.parse_qualifiers());
+        }
 
-        let mut stmts: Vec<Stmt> = Vec::new();
+        let mut decl = if let Some(inner) = self.parse_nested_declarator(named)? {
+            inner
+        } else if named || self.is_id() {
+            Declarator {
+                name: Some(self.parse_id()?),
+                derived: Vec::new(),
+            }
+        } else {
+            Declarator {
+                name: None,
+                derived: Vec::new(),
+            }
+        };
 
-        // parse stmts
+        // suffixes bind tighter than the pointers
         loop {
-            if let Some(stmt) = self.parse_stmt() {
-                stmts.push(stmt);
+            if self.is_peek_tok(TokType::SquareOpen) {
+                self.consume_any();
+                let len = match self.is_peek_tok(TokType::SquareClose) {
+                    true => None,
+                    false => Some(self.parse_array_size()?),
+                };
+                self.consume(TokType::SquareClose)?;
+                decl.derived.push(Derived::Array(len));
+            } else if self.is_peek_tok(TokType::ParentOpen) {
+                self.enter()?;
+                self.consume_any();
+                let derived = match self.is_identifier_list() {
+                    true => Derived::Identifiers(self.parse_identifier_list()?),
+                    false => Derived::Function(self.parse_parameters()?),
+                };
+                self.consume(TokType::ParentClose)?;
+                self.depth -= 1;
+                decl.derived.push(derived);
             } else {
                 break;
             }
         }
+        // the last '*' is the closest to the name
+        decl.derived
+            .extend(pointers.into_iter().rev().map(Derived::Pointer));
+        Ok(decl)
+    }
+
+    /// length of array declarator, an integer constant expression
+    fn parse_array_size(&mut self) -> ParseResult<u64> {
+        let expr = self.parse_expr()?;
+        let size = eval_int(&expr)?;
+        u64::try_from(size.value).map_err(|_| {
+            Box::new(CompileError::at_span(
+                "size of array is negative",
+                expr.span,
+            ))
+        })
+    }
+
+    /// parenthesized declarator, none if there is no '('
+    ///
+    /// in an abstract declarator '(' may also start the parameters, i.e `int (*)(int)`, the
+    /// nested declarator is tried first
+    fn parse_nested_declarator(&mut self, named: bool) -> ParseResult<Option<Declarator>> {
+        if !self.is_peek_tok(TokType::ParentOpen) {
+            return Ok(None);
+        }
+        if named {
+            return self.parse_paren_declarator(true).map(Some);
+        }
+        Ok(self.tentative(|p| p.parse_paren_declarator(false)).ok())
+    }
 
-        self.consume(TokType::BracketClose);
+    fn parse_paren_declarator(&mut self, named: bool) -> ParseResult<Declarator> {
+        self.enter()?;
+        self.consume(TokType::ParentOpen)?;
+        let inner = self.parse_declarator(named)?;
+        if inner.name.is_none() && inner.derived.is_empty() {
+            // '()' are the parameters
+            return Err(Box::new(CompileError::new("empty declarator")));
+        }
+        self.consume(TokType::ParentClose)?;
+        self.depth -= 1;
+        Ok(inner)
+    }
 
-        CmpStmt { stmts }
+    /// body of a function definition, the parameters are in scope
+    fn parse_function_body(&mut self, params: &[ParamDecl]) -> ParseResult<CmpStmt> {
+        self.names.push_scope();
+        let res = params
+            .iter()
+            .try_for_each(|p| self.names.declare_ordinary(p.name, p.span))
+            .map_err(Box::new)
+            .and_then(|_| self.parse_compound_stmt());
+        self.names.pop_scope();
+        res
     }
 
-    fn parse_stmt(&mut self) -> Option<Stmt> {
+    fn parse_compound_stmt(&mut self) -> ParseResult<CmpStmt> {
+        self.enter()?;
+        let id = self.new_id();
+        let start = self.start();
+        self.consume(TokType::BracketOpen)?;
+        self.names.push_scope();
+
+        let mut stmts: Vec<Stmt> = Vec::new();
+
+        // parse stmts, skip to the next statement on error
+        loop {
+            let depth = self.depth;
+            match self.parse_stmt() {
+                Ok(Some(stmt)) => stmts.push(stmt),
+                Ok(None) => break,
+                Err(e) => {
+                    self.depth = depth;
+                    self.report(*e);
+                    self.synchronize(false);
+                    if self.peek().is_none() {
+                        break;
+                    }
+                }
+            }
+        }
+
+        self.names.pop_scope();
+        self.consume(TokType::BracketClose)?;
+        self.depth -= 1;
+
+        Ok(CmpStmt {
+            id,
+            span: self.span_from(start),
+            stmts,
+        })
+    }
+
+    fn parse_stmt(&mut self) -> ParseResult<Option<Stmt>> {
+        self.check_supported()?;
         if self.is_expr() {
-            return Some(self.parse_expr_stmt());
+            return Ok(Some(self.parse_expr_stmt()?));
+        }
+
+        if self.is_peek_tok(TokType::BracketClose) {
+            return Ok(None);
         }
 
-        let stmt = match self.peek() {
-            Some(t) if self.is_data_type(t) => self.parse_var_decl_stmt(),
-            Some(t) if t.tok == TokType::KeywordReturn => self.parse_return_stmt(),
-            Some(t) if t.tok == TokType::BracketOpen => Stmt::Compound(self.parse_compound_stmt()),
-            Some(t) if t.tok == TokType::BracketClose => return None,
-            Some(t) => panic!("unexpected {}", t),
-            _ => panic!("unexpected EOF"),
+        let id = self.new_id();
+
+        let start = self.start();
+        let kind = match self.peek() {
+            Some(t) if self.is_decl_start(t) => self.parse_var_decl_stmt()?,
+            Some(t) if t.tok == TokType::KeywordReturn => self.parse_return_stmt()?,
+            Some(t) if t.tok == TokType::KeywordStaticAssert => {
+                StmtKind::StaticAssert(self.parse_static_assert()?)
+            }
+            Some(t) if t.tok == TokType::KeywordAsm => self.parse_asm_stmt()?,
+            // storage classes are only supported at file scope
+            Some(t) if matches!(t.tok, TokType::KeywordExtern | TokType::KeywordStatic) => {
+                return Err(Box::new(CompileError::at_token(
+                    format!(
+                        "tncc does not yet support '{}' at {}:{}",
+                        t.tok, t.loc.0, t.loc.1
+                    ),
+                    t,
+                )))
+            }
+            Some(t) if t.tok == TokType::BracketOpen => {
+                StmtKind::Compound(self.parse_compound_stmt()?)
+            }
+            Some(t) => {
+                return Err(Box::new(CompileError::at_token(
+                    format!("unexpected {}", t),
+                    t,
+                )))
+            }
+            _ => return Err(Box::new(CompileError::new("unexpected EOF"))),
         };
-        Some(stmt)
+        Ok(Some(Stmt {
+            id,
+            span: self.span_from(start),
+            kind,
+        }))
     }
 
-    fn parse_var_decl_stmt(&mut self) -> Stmt {
-        let decl = self.parse_var_decl();
-        self.consume(TokType::Semicolon);
-        Stmt::VarDecl(decl)
+    fn parse_var_decl_stmt(&mut self) -> ParseResult<StmtKind> {
+        let decl = self.parse_var_decl()?;
+        self.consume(TokType::Semicolon)?;
+        Ok(StmtKind::VarDecl(decl))
     }
 
-    fn parse_var_decl(&mut self) -> VarDecl {
-        let data_type = self.parse_data_type();
-        let name: String = self.parse_id();
-        let expr = if self.is_peek_tok(TokType::Assign) {
-            self.consume(TokType::Assign);
-            Some(self.parse_expr())
+    fn parse_var_decl(&mut self) -> ParseResult<VarDecl> {
+        let id = self.new_id();
+        let start = self.start();
+        let mut attrs = self.parse_attributes()?;
+        let base = self.parse_data_type()?;
+        let decl = self.parse_declarator(true)?;
+        let name = decl.name.expect("declarator with name");
+        self.names.declare_ordinary(name, self.span_from(start))?;
+        attrs.extend(self.parse_attributes()?);
+        let init = if self.is_peek_tok(TokType::Assign) {
+            self.consume(TokType::Assign)?;
+            Some(self.parse_initializer()?)
         } else {
             None
         };
-        VarDecl(data_type, name, expr)
+        Ok(VarDecl {
+            id,
+            span: self.span_from(start),
+            data_type: derive(base, &decl.derived),
+            name,
+            init,
+            attrs,
+        })
+    }
+
+    /// expression or brace enclosed list of designated initializers, i.e `{1, [2] = 7}`
+    fn parse_initializer(&mut self) -> ParseResult<Expr> {
+        if !self.is_peek_tok(TokType::BracketOpen) {
+            return self.parse_expr();
+        }
+        self.enter()?;
+        let id = self.new_id();
+        let start = self.start();
+        self.consume(TokType::BracketOpen)?;
+        let mut items = Vec::new();
+        // trailing comma is allowed
+        while !self.is_peek_tok(TokType::BracketClose) {
+            let mut designators = Vec::new();
+            while let Some(d) = self.parse_designator()? {
+                designators.push(d);
+            }
+            if !designators.is_empty() {
+                self.consume(TokType::Assign)?;
+            }
+            let value = self.parse_initializer()?;
+            items.push(InitItem { designators, value });
+            if !self.is_peek_tok(TokType::Comma) {
+                break;
+            }
+            self.consume_any();
+        }
+        self.consume(TokType::BracketClose)?;
+        self.depth -= 1;
+        Ok(Expr {
+            id,
+            span: self.span_from(start),
+            kind: ExprKind::InitList(items),
+        })
+    }
+
+    /// `[constant-expression]` or `.identifier`
+    fn parse_designator(&mut self) -> ParseResult<Option<Designator>> {
+        match self.peek_tok() {
+            Some(TokType::SquareOpen) => {
+                self.consume_any();
+                let index = self.parse_expr()?;
+                self.consume(TokType::SquareClose)?;
+                Ok(Some(Designator::Index(index)))
+            }
+            Some(TokType::Dot) => {
+                self.consume_any();
+                Ok(Some(Designator::Member(self.parse_id()?)))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// `_Static_assert(expr, "message");`, the message is optional since C23
+    fn parse_static_assert(&mut self) -> ParseResult<StaticAssert> {
+        let id = self.new_id();
+        let start = self.start();
+        self.consume(TokType::KeywordStaticAssert)?;
+        self.consume(TokType::ParentOpen)?;
+        let cond = self.parse_expr()?;
+        let message = match self.is_peek_tok(TokType::Comma) {
+            true => {
+                self.consume_any();
+                Some(self.expect_str_lit()?)
+            }
+            false => None,
+        };
+        self.consume(TokType::ParentClose)?;
+        self.consume(TokType::Semicolon)?;
+        Ok(StaticAssert {
+            id,
+            span: self.span_from(start),
+            cond,
+            message,
+        })
+    }
+
+    /// GNU attribute specifiers, i.e `__attribute__((noreturn, aligned(8)))`
+    ///
+    /// the list may be empty and the arguments are kept as expressions, i.e `format(printf, 1, 2)`
+    fn parse_attributes(&mut self) -> ParseResult<Vec<Attribute>> {
+        let mut attrs = Vec::new();
+        while self.is_peek_tok(TokType::KeywordAttribute) {
+            self.consume_any();
+            self.consume(TokType::ParentOpen)?;
+            self.consume(TokType::ParentOpen)?;
+            loop {
+                if self.is_id() {
+                    let start = self.start();
+                    let name = attr_name(self.parse_id()?);
+                    let args = match self.is_peek_tok(TokType::ParentOpen) {
+                        true => {
+                            self.consume_any();
+                            let args = self.parse_arguments()?;
+                            self.consume(TokType::ParentClose)?;
+                            args
+                        }
+                        false => Vec::new(),
+                    };
+                    attrs.push(Attribute {
+                        span: self.span_from(start),
+                        name,
+                        args,
+                    });
+                }
+                if !self.is_peek_tok(TokType::Comma) {
+                    break;
+                }
+                self.consume_any();
+            }
+            self.consume(TokType::ParentClose)?;
+            self.consume(TokType::ParentClose)?;
+        }
+        Ok(attrs)
+    }
+
+    /// `asm("nop")`, basic asm is always volatile so the qualifier is allowed but not needed
+    fn parse_asm_stmt(&mut self) -> ParseResult<StmtKind> {
+        self.consume(TokType::KeywordAsm)?;
+        while self.is_asm_qualifier() {
+            self.consume_any();
+        }
+        self.consume(TokType::ParentOpen)?;
+        let code = self.expect_str_lit()?;
+        if let Some(t) = self.peek().filter(|t| t.tok == TokType::Colon) {
+            return Err(Box::new(CompileError::at_token(
+                format!(
+                    "tncc does not yet support asm operands at {}:{}",
+                    t.loc.0, t.loc.1
+                ),
+                t,
+            )));
+        }
+        self.consume(TokType::ParentClose)?;
+        self.consume(TokType::Semicolon)?;
+        Ok(StmtKind::Asm(code))
     }
 
-    fn parse_return_stmt(&mut self) -> Stmt {
-        self.consume(TokType::KeywordReturn);
+    fn is_asm_qualifier(&self) -> bool {
+        match self.peek().map(|t| &t.tok) {
+            Some(TokType::KeywordVolatile) => true,
+            Some(TokType::ID(s)) => *s == "__volatile__" || *s == "__volatile",
+            _ => false,
+        }
+    }
+
+    fn parse_return_stmt(&mut self) -> ParseResult<StmtKind> {
+        self.consume(TokType::KeywordReturn)?;
         let expr: Option<Expr> = if self.is_expr() {
-            Some(self.parse_expr())
+            Some(self.parse_expr()?)
         } else {
             None
         };
-        self.consume(TokType::Semicolon);
-        Stmt::Return(expr)
+        self.consume(TokType::Semicolon)?;
+        Ok(StmtKind::Return(expr))
     }
 
     /// statement that invoke an expression, i.e function call
-    fn parse_expr_stmt(&mut self) -> Stmt {
-        let e = self.parse_expr();
-        self.consume(TokType::Semicolon);
-        Stmt::Expr(e)
+    fn parse_expr_stmt(&mut self) -> ParseResult<Stmt> {
+        let id = self.new_id();
+        let start = self.start();
+        let e = self.parse_expr()?;
+        self.consume(TokType::Semicolon)?;
+        Ok(Stmt {
+            id,
+            span: self.span_from(start),
+            kind: StmtKind::Expr(e),
+        })
     }
 
     fn is_expr(&mut self) -> bool {
-        self.is_int_const_expr() || self.is_ref()
+        self.is_int_const_expr()
+            || self.is_str_lit_expr()
+            || self.is_ref()
+            || self.is_peek_tok(TokType::ParentOpen)
+            || self.peek_tok().and_then(unary_op).is_some()
+    }
+
+    fn parse_expr(&mut self) -> ParseResult<Expr> {
+        self.parse_expr_bp(0)
+    }
+
+    /// precedence climbing, parse operators binding tighter than the minimum binding power
+    fn parse_expr_bp(&mut self, min_bp: u8) -> ParseResult<Expr> {
+        let start = self.start();
+        let mut left = self.parse_prefix_expr()?;
+        self.check_member_access()?;
+        while let Some((op, bp)) = self.peek_tok().and_then(binary_op) {
+            // left associative, the right operand only takes tighter operators
+            if bp <= min_bp {
+                break;
+            }
+            self.consume_any();
+            let right = self.parse_expr_bp(bp)?;
+            left = Expr {
+                id: self.new_id(),
+                span: self.span_from(start),
+                kind: ExprKind::Arith(Box::new(left), op, Box::new(right)),
+            };
+        }
+        Ok(left)
     }
 
-    fn parse_expr(&mut self) -> Expr {
-        if self.is_int_const_expr() {
-            self.parse_int_const_expr()
+    /// unary operators followed by the operand, or primary expression
+    fn parse_prefix_expr(&mut self) -> ParseResult<Expr> {
+        if self.is_peek_tok(TokType::ParentOpen) {
+            return self.parse_paren_expr();
+        }
+
+        self.check_supported()?;
+        let id = self.new_id();
+        let start = self.start();
+        let kind = if let Some((op, bp)) = self.peek_tok().and_then(unary_op) {
+            self.enter()?;
+            self.consume_any();
+            let operand = self.parse_expr_bp(bp)?;
+            self.depth -= 1;
+            ExprKind::Unary(op, Box::new(operand))
+        } else if self.is_int_const_expr() {
+            self.parse_int_const_expr()?
+        } else if self.is_str_lit_expr() {
+            ExprKind::StrLit(self.parse_str_lit())
         } else if self.is_ref() {
-            self.parse_ref_expr()
+            self.parse_ref_expr()?
         } else {
-            panic!("expected expression but {:?}", self.peek())
-        }
+            return Err(Box::new(match self.peek() {
+                Some(t) => CompileError::at_token(format!("expected expression but {}", t), t),
+                None => CompileError::new("expected expression but EOF"),
+            }));
+        };
+        Ok(Expr {
+            id,
+            span: self.span_from(start),
+            kind,
+        })
+    }
+
+    /// parenthesized expression, the grouping is kept by the shape of the tree only
+    fn parse_paren_expr(&mut self) -> ParseResult<Expr> {
+        self.enter()?;
+        self.consume(TokType::ParentOpen)?;
+        let expr = self.parse_expr()?;
+        self.consume(TokType::ParentClose)?;
+        self.depth -= 1;
+        Ok(expr)
     }
 
     fn is_int_const_expr(&mut self) -> bool {
-        match self.peek() {
+        matches!(
+            self.peek(),
             Some(Token {
                 tok: TokType::NumInt(_),
-                loc: _,
-            }) => true,
-            _ => false,
-        }
+                ..
+            })
+        )
     }
 
-    fn parse_int_const_expr(&mut self) -> Expr {
+    fn parse_int_const_expr(&mut self) -> ParseResult<ExprKind> {
         match self.next() {
             Some(Token {
                 tok: TokType::NumInt(v),
-                loc: _,
-            }) => Expr::IntConst(*v as i64),
-            Some(t) => panic!("expected int constant but {}", t),
-            None => panic!("unexpected EOF"),
+                ..
+            }) => Ok(ExprKind::IntConst(*v as i64)),
+            Some(t) => Err(Box::new(CompileError::at_token(
+                format!("expected int constant but {}", t),
+                t,
+            ))),
+            None => Err(Box::new(CompileError::new("unexpected EOF"))),
         }
     }
 
-    fn is_ref(&mut self) -> bool {
-        match self.peek() {
+    fn is_str_lit_expr(&mut self) -> bool {
+        matches!(
+            self.peek(),
             Some(Token {
-                tok: TokType::ID(_),
-                loc: _,
-            }) => true,
-            _ => false,
+                tok: TokType::Str(_),
+                ..
+            })
+        )
+    }
+
+    /// string literal required by the syntax, i.e the message of `_Static_assert`
+    fn expect_str_lit(&mut self) -> ParseResult<Vec<u8>> {
+        if !self.is_str_lit_expr() {
+            return Err(Box::new(match self.peek() {
+                Some(t) => CompileError::at_token(format!("expected string literal but {}", t), t),
+                None => CompileError::new("expected string literal but EOF"),
+            }));
         }
+        Ok(self.parse_str_lit())
+    }
+
+    /// adjacent string literals are concatenated
+    fn parse_str_lit(&mut self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        while let Some(Token {
+            tok: TokType::Str(s),
+            ..
+        }) = self.peek()
+        {
+            bytes.extend_from_slice(s);
+            self.consume_any();
+        }
+        bytes
+    }
+
+    /// identifier of a variable or function, type names start declarations
+    fn is_ref(&mut self) -> bool {
+        matches!(
+            self.peek(),
+            Some(Token {
+                tok: TokType::ID(s),
+                ..
+            }) if !self.names.is_type_name(*s)
+        )
     }
 
     /// parse function or variable call
     ///
     /// TODO parse array index
-    fn parse_ref_expr(&mut self) -> Expr {
-        let name = self.parse_id();
+    fn parse_ref_expr(&mut self) -> ParseResult<ExprKind> {
+        let name = self.parse_id()?;
         match self.peek() {
             Some(t) if t.tok == TokType::ParentOpen => self.parse_function_call_expr(name),
-            _ => Expr::VarRef(name),
+            _ => Ok(ExprKind::VarRef(name)),
         }
     }
 
-    fn parse_function_call_expr(&mut self, name: String) -> Expr {
-        self.consume(TokType::ParentOpen);
-        let args = self.parse_arguments();
-        self.consume(TokType::ParentClose);
-        Expr::FunctionCall(name, args)
+    fn parse_function_call_expr(&mut self, name: Symbol) -> ParseResult<ExprKind> {
+        self.enter()?;
+        self.consume(TokType::ParentOpen)?;
+        let args = self.parse_arguments()?;
+        self.consume(TokType::ParentClose)?;
+        self.depth -= 1;
+        Ok(ExprKind::FunctionCall(name, args))
     }
 
-    fn parse_arguments(&mut self) -> Vec<Expr> {
+    fn parse_arguments(&mut self) -> ParseResult<Vec<Expr>> {
         if self.is_expr() {
             let mut vec: Vec<Expr> = Vec::new();
-            vec.push(self.parse_expr());
+            vec.push(self.parse_expr()?);
             loop {
                 if self.is_peek_tok(TokType::Comma) {
                     self.consume_any();
-                    vec.push(self.parse_expr());
+                    vec.push(self.parse_expr()?);
                 } else {
                     break;
                 }
             }
-            vec
+            Ok(vec)
         } else {
-            Vec::with_capacity(0)
+            Ok(Vec::with_capacity(0))
+        }
+    }
+
+    /// byte offset where the next node starts
+    fn start(&self) -> usize {
+        self.peek()
+            .map(|t| t.span.start)
+            .unwrap_or_else(|| self.prev_end())
+    }
+
+    /// byte offset after the last consumed token
+    fn prev_end(&self) -> usize {
+        self.prev.as_ref().map(|t| t.span.end).unwrap_or(0)
+    }
+
+    /// span from the start offset to the last consumed token
+    fn span_from(&self, start: usize) -> Span {
+        Span::new(start, self.prev_end())
+    }
+
+    /// pull tokens from the stream until the lookahead is full
+    fn fill(&mut self) {
+        while self.buf.len() < self.k {
+            match self.tokens.next() {
+                Some(Ok(t)) => self.buf.push_back(t),
+                Some(Err(e)) => {
+                    self.buf.push_back(e.to_token());
+                    self.lex_errors.push(e);
+                }
+                None => break,
+            }
+        }
+    }
+
+    /// run the parse function, on error rewind to the tokens and state before it so the caller
+    /// can try another alternative
+    ///
+    /// errors are returned instead of reported, the lexical errors of the scanned tokens are kept
+    fn tentative<T, F>(&mut self, f: F) -> ParseResult<T>
+    where
+        F: FnOnce(&mut Self) -> ParseResult<T>,
+    {
+        self.marks.push(Mark {
+            log: self.log.len(),
+            prev: self.prev.clone(),
+            depth: self.depth,
+            next_id: self.next_id,
+        });
+        let res = f(self);
+        let mark = self.marks.pop().unwrap();
+        if res.is_err() {
+            for t in self.log.drain(mark.log..).rev() {
+                self.buf.push_front(t);
+            }
+            self.prev = mark.prev;
+            self.depth = mark.depth;
+            self.next_id = mark.next_id;
+        }
+        if self.marks.is_empty() {
+            self.log.clear();
+        }
+        res
+    }
+
+    /// allocate id for the next node
+    fn new_id(&mut self) -> NodeId {
+        let id = NodeId(self.next_id);
+        self.next_id += 1;
+        id
+    }
+
+    /// enter nested block or call, leave by decreasing the depth
+    fn enter(&mut self) -> ParseResult<()> {
+        self.depth += 1;
+        if self.depth <= MAX_DEPTH {
+            return Ok(());
+        }
+        Err(Box::new(match self.peek() {
+            Some(t) => {
+                CompileError::at_token(format!("nesting is too deep at {}:{}", t.loc.0, t.loc.1), t)
+            }
+            None => CompileError::new("nesting is too deep"),
+        }))
+    }
+
+    /// report a known C keyword as a limitation of the compiler instead of a syntax error
+    fn check_supported(&self) -> ParseResult<()> {
+        match self.peek() {
+            Some(
+                t @ Token {
+                    tok: TokType::ID(s),
+                    ..
+                },
+            ) if UNSUPPORTED_KEYWORDS.contains(&s.as_str()) => {
+                Err(Box::new(CompileError::at_token(
+                    format!(
+                        "tncc does not yet support '{}' at {}:{}",
+                        s, t.loc.0, t.loc.1
+                    ),
+                    t,
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// member access needs struct types, report it as a limitation instead of a syntax error
+    fn check_member_access(&self) -> ParseResult<()> {
+        match self.peek() {
+            Some(t) if matches!(t.tok, TokType::Arrow | TokType::Dot) => {
+                Err(Box::new(CompileError::at_token(
+                    format!(
+                        "tncc does not yet support '{}' at {}:{}",
+                        t.tok, t.loc.0, t.loc.1
+                    ),
+                    t,
+                )))
+            }
+            _ => Ok(()),
         }
     }
 
+    /// start of a declaration, the type or the attributes before it
+    fn is_decl_start(&self, tok: &Token) -> bool {
+        tok.tok == TokType::KeywordAttribute || self.is_data_type(tok)
+    }
+
     fn is_data_type(&self, tok: &Token) -> bool {
-        self.has_value(Parser::parse_data_type_opt(tok))
+        match &tok.tok {
+            TokType::ID(s) => self.names.is_type_name(*s),
+            t => *t == TokType::KeywordVolatile || self.has_value(Self::parse_data_type_opt(tok)),
+        }
     }
 
     const fn parse_data_type_opt(tok: &Token) -> Option<DataType> {
         match tok.tok {
             TokType::KeywordInt => Some(DataType::Int),
+            TokType::KeywordChar => Some(DataType::Char),
             TokType::KeywordVoid => Some(DataType::Void),
             _ => None,
         }
     }
 
-    fn parse_data_type(&mut self) -> DataType {
-        let t = self.next().expect("unexpected EOF");
-        Parser::parse_data_type_opt(t).expect(&format!("expected data type but {}", t))
+    /// base type with the qualifiers before or after it, i.e `volatile int` or `int volatile`
+    fn parse_data_type(&mut self) -> ParseResult<Type> {
+        let before = self.parse_qualifiers();
+        let t = self
+            .next()
+            .cloned()
+            .ok_or_else(|| CompileError::new("unexpected EOF"))?;
+        let ty = match t.tok {
+            TokType::ID(s) => self.names.type_of(s),
+            _ => Self::parse_data_type_opt(&t).map(Type::Base),
+        }
+        .ok_or_else(|| CompileError::at_token(format!("expected data type but {}", t), &t))?;
+        let after = self.parse_qualifiers();
+        Ok(qualify(ty, before || after))
     }
 
-    fn parse_id(&mut self) -> String {
+    /// true if the type is volatile qualified, a repeated qualifier is the same as one
+    fn parse_qualifiers(&mut self) -> bool {
+        let mut volatile = false;
+        while self.is_peek_tok(TokType::KeywordVolatile) {
+            self.consume_any();
+            volatile = true;
+        }
+        volatile
+    }
+
+    fn parse_id(&mut self) -> ParseResult<Symbol> {
         match self.next() {
             Some(Token {
                 tok: TokType::ID(s),
-                loc: _,
-            }) => s.to_string(),
-            Some(t) => panic!("exepcted ID but {}", t),
-            _ => panic!("unexpected EOF"),
+                ..
+            }) => Ok(*s),
+            Some(t) => Err(Box::new(CompileError::at_token(
+                format!("exepcted ID but {}", t),
+                t,
+            ))),
+            _ => Err(Box::new(CompileError::new("unexpected EOF"))),
         }
     }
 
     fn is_id(&mut self) -> bool {
-        match self.peek() {
+        matches!(
+            self.peek(),
             Some(Token {
                 tok: TokType::ID(_),
-                loc: _,
-            }) => true,
-            _ => false,
-        }
+                ..
+            })
+        )
     }
 
     fn has_value<T>(&self, opt: Option<T>) -> bool {
-        match opt {
-            Some(_) => true,
-            _ => false,
-        }
+        opt.is_some()
     }
 
     fn is_peek_tok(&mut self, tok: TokType) -> bool {
-        match self.peek() {
-            Some(Token { tok: t, loc: _ }) if *t == tok => true,
-            _ => false,
-        }
+        matches!(self.peek(), Some(Token { tok: t, .. }) if *t == tok)
     }
 
     fn consume_any(&mut self) {
         let _ = self.next();
     }
 
-    fn consume(&mut self, tok: TokType) {
-        let item = self
-            .next()
-            .expect(format!("expected {} but EOF", tok).as_str());
-        match item {
-            Token { tok: t, loc: _ } if *t == tok => (),
-            t => panic!("expected {} but {}", tok, t),
+    /// consume the expected token, an unexpected token is left for error recovery
+    fn consume(&mut self, tok: TokType) -> ParseResult<()> {
+        let e = match self.peek() {
+            Some(Token { tok: t, .. }) if *t == tok => {
+                self.consume_any();
+                return Ok(());
+            }
+            Some(t) => CompileError::at_token(format!("expected {} but {}", tok, t), t),
+            None => CompileError::new(format!("expected {} but EOF", tok)),
+        };
+        // a missing ';' is inserted after the previous token
+        match (tok, &self.prev) {
+            (TokType::Semicolon, Some(_)) => {
+                Err(Box::new(e.with_fixit(FixIt::insert(self.prev_end(), ";"))))
+            }
+            _ => Err(Box::new(e)),
         }
     }
 }
 
-enum ExprRefType {
-    FunctionCall,
-    ArrayIndex,
-    VarRef,
-}
-
 trait TokenPeeker {
     fn next(&mut self) -> Option<&Token>;
     fn peek(&self) -> Option<&Token>;
     fn lookahead(&self, i: usize) -> Option<&Token>;
     fn peek_tok(&self) -> Option<&TokType>;
     fn lookahead_tok(&self, i: usize) -> Option<&TokType>;
+
+    /// the next tokens are exactly the given ones, at most k
+    fn lookahead_is(&self, toks: &[TokType]) -> bool {
+        toks.iter()
+            .enumerate()
+            .all(|(i, t)| self.lookahead_tok(i) == Some(t))
+    }
 }
 
-impl TokenPeeker for Parser {
+impl<I: Iterator<Item = Result<Token, LexError>>> TokenPeeker for Parser<I> {
     fn next(&mut self) -> Option<&Token> {
-        let t = self.tokens.get(self.index);
-        self.index = self.index + 1;
-        t
+        let t = self.buf.pop_front()?;
+        if !self.marks.is_empty() {
+            self.log.push(t.clone());
+        }
+        self.prev = Some(t);
+        self.fill();
+        self.prev.as_ref()
     }
 
     fn peek(&self) -> Option<&Token> {
@@ -356,7 +1431,8 @@ impl TokenPeeker for Parser {
     }
 
     fn lookahead(&self, i: usize) -> Option<&Token> {
-        self.tokens.get(self.index + i)
+        debug_assert!(i < self.k);
+        self.buf.get(i)
     }
 
     fn peek_tok(&self) -> Option<&TokType> {
@@ -372,9 +1448,14 @@ impl TokenPeeker for Parser {
 mod test {
     use test_case::test_case;
 
-    use crate::scan;
+    use crate::{
+        ast::printer::print_c,
+        ast::*,
+        common::{FixIt, Span, TokType},
+        scan, Lexer, Standard,
+    };
 
-    use super::parse;
+    use super::{parse, parse_stream, parse_with, Parser, TokenPeeker};
 
     #[test_case("int main() { return 1; }")]
     #[test_case("int main() { }")]
@@ -384,27 +1465,374 @@ mod test {
     #[test_case("void foo(int x, int y) {}")]
     #[test_case("void foo() { int a = undefined(x, 3); }")]
     #[test_case("void foo() { undefined(3); }")]
+    #[test_case("int puts(char *s); int main() { puts(\"hi\"); }")]
+    #[test_case("int a[] = {1, [2] = 3, };")]
+    #[test_case("_Static_assert(1, \"one\"); int main() { _Static_assert(2 - 1); }")]
+    #[test_case("int main() { int m[2][2] = {{1}, [1][1] = 2}; int n = {}; }")]
+    #[test_case(
+        "__attribute__((noreturn)) void f(void); int g __attribute__((aligned(8), used)) = 1;"
+    )]
+    #[test_case("int printf(char *f) __attribute__((format(printf, 1, 2), , __used__));")]
+    #[test_case("int main() { __attribute__((aligned(4))) char c; char d __attribute__(()); }")]
     fn pass_program(src: &str) {
-        parse(scan(src));
+        parse(scan(src).unwrap()).unwrap();
+    }
+
+    #[test_case("main" => "unexpected identifier 'main' at 1:1")]
+    #[test_case("int main" => "expected ; but EOF")]
+    #[test_case("int test {" => "expected ; but { at 1:10")]
+    #[test_case("int test() {" => "unexpected EOF")]
+    #[test_case("int main() { return 1 }" => "expected ; but } at 1:23")]
+    #[test_case("struct s { int a; };" => "tncc does not yet support 'struct' at 1:1")]
+    #[test_case("union { int a; char c; } u;" => "tncc does not yet support 'union' at 1:1")]
+    #[test_case("int main() { struct { union { int a; }; } s; }" => "tncc does not yet support 'struct' at 1:14")]
+    #[test_case("int main() { while (1) {} }" => "tncc does not yet support 'while' at 1:14")]
+    #[test_case("int f(short c) {}" => "tncc does not yet support 'short' at 1:7")]
+    #[test_case("enum color { RED, GREEN }; int main() { return 0; }" => "tncc does not yet support 'enum' at 1:1")]
+    #[test_case("int f(enum color c) { return 0; }" => "tncc does not yet support 'enum' at 1:7")]
+    #[test_case("int main() { switch (1) { case 1: case 1: return 0; } }" => "tncc does not yet support 'switch' at 1:14"; "duplicate cases")]
+    #[test_case("int main() { return 1; case 2: return 0; }" => "tncc does not yet support 'case' at 1:24")]
+    #[test_case("int f(int x) { switch (x) { case 0: return 1; case 1: return 2; case 2: return 3; case 3: return 4; } return 0; }" => "tncc does not yet support 'switch' at 1:16"; "dense cases")]
+    #[test_case("int main() { int y = 2; { default: return 0; } }" => "tncc does not yet support 'default' at 1:27")]
+    #[test_case("int g = sizeof(int);" => "tncc does not yet support 'sizeof' at 1:9")]
+    #[test_case("int f(int *p) { return p->x; }" => "tncc does not yet support '->' at 1:25")]
+    #[test_case("int f(int *p) { return -(p)->x + 1; }" => "tncc does not yet support '->' at 1:28")]
+    #[test_case("int f(int p) { return p.x; }" => "tncc does not yet support '.' at 1:24")]
+    #[test_case("int g = .;" => "expected expression but . at 1:9")]
+    #[test_case("int a[2] = {[0] 1};" => "expected = but number '1' at 1:17")]
+    #[test_case("int a[2] = {.0 = 1};" => "expected expression but number '0' at 1:13")]
+    #[test_case("int a[2] = {1, 2" => "expected } but EOF")]
+    #[test_case("int main() { extern int g; }" => "tncc does not yet support 'extern' at 1:14")]
+    #[test_case("extern;" => "expected data type but ; at 1:7")]
+    #[test_case("static extern int g;" => "multiple storage classes in declaration specifiers at 1:8")]
+    #[test_case("int main() { static int n; }" => "tncc does not yet support 'static' at 1:14")]
+    #[test_case("int main() { asm(\"mov x0, %0\" : : \"r\"(1)); }" => "tncc does not yet support asm operands at 1:31")]
+    #[test_case("int main() { asm(nop); }" => "expected string literal but identifier 'nop' at 1:18")]
+    #[test_case("int g __attribute__((aligned);" => "expected ) but ; at 1:30")]
+    #[test_case("int g __attribute__(used);" => "expected ( but identifier 'used' at 1:21")]
+    #[test_case("__attribute__((used)) g;" => "expected data type but identifier 'g' at 1:23")]
+    #[test_case("volatile x;" => "expected data type but identifier 'x' at 1:10")]
+    #[test_case("_Static_assert(1, 2);" => "expected string literal but number '2' at 1:19")]
+    #[test_case("int main() { _Static_assert(1 \"m\"); }" => "expected ) but string \"m\" at 1:31")]
+    fn failed_program(src: &str) -> String {
+        parse(scan(src).unwrap()).unwrap_err().to_string()
     }
 
-    #[test_case("main" => panics "unexpected identifier 'main' at 1:1")]
-    #[test_case("int main" => panics "expected ; but EOF")]
-    #[test_case("int test {" => panics "expected ; but {")]
-    #[test_case("int test() {" => panics "unexpected EOF")]
-    #[test_case("int main() { return 1 }" => panics "expected ; but }")]
-    fn failed_program(src: &str) {
-        parse(scan(src));
+    #[test_case("main() { return 0; }")]
+    #[test_case("g; *p = 0; f(); int h(a);")]
+    #[test_case("f(a, s) char *s; { return a; }")]
+    #[test_case("int f(a, b, c) int a, *b; char c; {}")]
+    fn pass_knr(src: &str) {
+        parse_with(scan(src).unwrap(), Standard::Knr).unwrap();
+    }
+
+    #[test_case("int f(a) int b; {}" => "declaration for parameter 'b' but no such parameter at 1:14")]
+    #[test_case("int f(a) int a; int a; {}" => "redefinition of parameter 'a' at 1:21")]
+    #[test_case("int f(a) int a {}" => "expected ; but { at 1:16")]
+    #[test_case("struct s { int a; };" => "tncc does not yet support 'struct' at 1:1")]
+    fn failed_knr(src: &str) -> String {
+        parse_with(scan(src).unwrap(), Standard::Knr)
+            .unwrap_err()
+            .to_string()
+    }
+
+    #[test_case("int main() { return 1 }" => vec![FixIt::insert(21, ";")])]
+    #[test_case("int g = 1\nint main" => vec![FixIt::insert(9, ";")]; "after the previous line")]
+    #[test_case("int main" => vec![FixIt::insert(8, ";")]; "at EOF")]
+    #[test_case("int a[2 = 1;" => Vec::<FixIt>::new())]
+    fn missing_semicolon_fixits(src: &str) -> Vec<FixIt> {
+        parse(scan(src).unwrap()).unwrap_err().fixits
+    }
+
+    #[test_case(256, true)]
+    #[test_case(257, false)]
+    #[test_case(100_000, false)]
+    fn nested_blocks(depth: usize, ok: bool) {
+        let src = format!("int main() {}{}", "{".repeat(depth), "}".repeat(depth));
+        assert_eq!(parse(scan(&src).unwrap()).is_ok(), ok);
+    }
+
+    #[test]
+    fn nested_calls() {
+        let src = format!(
+            "int main() {{ return {}1{}; }}",
+            "f(".repeat(1000),
+            ")".repeat(1000)
+        );
+        let err = parse(scan(&src).unwrap()).unwrap_err().to_string();
+        assert!(err.starts_with("nesting is too deep at 1:"), "{}", err);
+    }
+
+    #[test_case("int g = 1 - 2 + 3;", "1 - 2 + 3")]
+    #[test_case("int g = -1 - -f(a+b, -c) + +d;", "-1 - -f(a + b, -c) + +d")]
+    #[test_case("int g = - - -a;", "- - -a")]
+    fn parse_operators(src: &str, expected: &str) {
+        let ast = parse(scan(src).unwrap()).unwrap();
+        let e = match &ast.0[0] {
+            ExtDecl::Global(g) => g.init.as_ref().unwrap(),
+            _ => unreachable!(),
+        };
+        assert_eq!(&src[e.span.start..e.span.end], &src[8..src.len() - 1]);
+        assert_eq!(print_c(&ast), format!("int g = {};\n", expected));
+    }
+
+    #[test_case("int g = (1);", "1")]
+    #[test_case("int g = ((a + b)) - c;", "a + b - c")]
+    #[test_case("int g = a - (b - (c + 1));", "a - (b - (c + 1))")]
+    #[test_case("int g = -(a + f((b), (1)));", "-(a + f(b, 1))")]
+    fn parse_parens(src: &str, expected: &str) {
+        let ast = parse(scan(src).unwrap()).unwrap();
+        assert_eq!(print_c(&ast), format!("int g = {};\n", expected));
+    }
+
+    #[test_case("int g = (1;" => "expected ) but ; at 1:11")]
+    #[test_case("int g = ();" => "expected expression but ) at 1:10")]
+    #[test_case("int main() { (a) = 1; }" => "expected ; but = at 1:18")]
+    fn invalid_parens(src: &str) -> String {
+        parse(scan(src).unwrap()).unwrap_err().to_string()
+    }
+
+    #[test_case("int *a[10];", "int *a[10];\n")]
+    #[test_case("int (*fp)(int);", "int (*fp)(int);\n")]
+    #[test_case("int a[2][3];", "int a[2][3];\n")]
+    #[test_case("int a[(2 + 3) - -1];", "int a[6];\n")]
+    #[test_case("int (*(x))[];", "int (*x)[];\n")]
+    #[test_case(
+        "int f(int *, int (*)(void), int[]);",
+        "int f(int *, int (*)(void), int[]);\n"
+    )]
+    #[test_case("int *f(int a) {}", "int *f(int a) {\n}\n")]
+    #[test_case("int main() { int *p[2]; }", "int main() {\n    int *p[2];\n}\n")]
+    #[test_case(
+        "volatile int v; int volatile volatile w;",
+        "volatile int v;\nvolatile int w;\n"
+    )]
+    #[test_case(
+        "int *volatile p; volatile char *volatile *q;",
+        "int *volatile p;\nvolatile char *volatile *q;\n"
+    )]
+    #[test_case(
+        "int (*volatile fp)(volatile int, int *volatile);",
+        "int (*volatile fp)(volatile int, int *volatile);\n"
+    )]
+    #[test_case(
+        "int main() { volatile int a[2]; }",
+        "int main() {\n    volatile int a[2];\n}\n"
+    )]
+    fn parse_declarators(src: &str, expected: &str) {
+        let ast = parse(scan(src).unwrap()).unwrap();
+        assert_eq!(print_c(&ast), expected);
+    }
+
+    #[test]
+    fn declarator_types() {
+        let ast = parse(scan("int *a[10]; int (*fp)(int);").unwrap()).unwrap();
+        let types: Vec<Type> = ast
+            .0
+            .iter()
+            .map(|ext| match ext {
+                ExtDecl::Global(g) => g.data_type.clone(),
+                _ => unreachable!(),
+            })
+            .collect();
+        let int = || Box::new(Type::Base(DataType::Int));
+        assert_eq!(
+            types,
+            vec![
+                Type::Array(Box::new(Type::Pointer(int())), Some(10)),
+                Type::Pointer(Box::new(Type::Function(
+                    int(),
+                    Some(vec![Type::Base(DataType::Int)])
+                ))),
+            ]
+        );
+    }
+
+    #[test_case("int main(void) {}", true, "int main(void) {\n}\n")]
+    #[test_case("int main() {}", false, "int main() {\n}\n")]
+    #[test_case("int f(void *p) {}", true, "int f(void *p) {\n}\n")]
+    fn void_params(src: &str, prototype: bool, expected: &str) {
+        let ast = parse(scan(src).unwrap()).unwrap();
+        match &ast.0[0] {
+            ExtDecl::Func(f) => assert_eq!(f.prototype, prototype),
+            _ => unreachable!(),
+        }
+        assert_eq!(print_c(&ast), expected);
+    }
+
+    #[test_case("int f();" => Type::Function(Box::new(Type::Base(DataType::Int)), None))]
+    #[test_case("int f(void);" => Type::Function(Box::new(Type::Base(DataType::Int)), Some(vec![])))]
+    fn void_prototype(src: &str) -> Type {
+        match parse(scan(src).unwrap()).unwrap().0.remove(0) {
+            ExtDecl::Global(g) => g.data_type,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn tentative_rewinds() {
+        let mut p = Parser::new(scan("a b c;").unwrap().into_iter().map(Ok));
+        let res = p.tentative(|p| {
+            p.parse_id()?;
+            p.parse_id()?;
+            p.consume(TokType::Semicolon)
+        });
+        assert_eq!(
+            res.unwrap_err().to_string(),
+            "expected ; but identifier 'c' at 1:5"
+        );
+        assert_eq!(p.parse_id().unwrap(), "a");
+
+        let res = p.tentative(|p| p.tentative(|p| p.parse_id()));
+        assert_eq!(res.unwrap(), "b");
+        assert_eq!(p.parse_id().unwrap(), "c");
+        assert!(p.lookahead_is(&[TokType::Semicolon]));
+    }
+
+    #[test]
+    fn configurable_lookahead() {
+        let tokens = scan("int f(void);").unwrap().into_iter().map(Ok);
+        let mut p = Parser::with_lookahead(tokens, 4);
+        p.consume_any();
+        assert!(p.lookahead_is(&[
+            TokType::ID("f".into()),
+            TokType::ParentOpen,
+            TokType::KeywordVoid,
+            TokType::ParentClose,
+        ]));
+        assert!(!p.lookahead_is(&[TokType::ID("f".into()), TokType::ParentClose]));
+    }
+
+    /// parse with `T` declared as a typedef name of `char *`
+    fn parse_typedef_t(src: &str, std: Standard) -> Result<String, String> {
+        let tokens = scan(src).unwrap().into_iter().map(Ok);
+        let mut p = Parser::new(tokens);
+        p.std = std;
+        let char_ptr = Type::Pointer(Box::new(Type::Base(DataType::Char)));
+        p.names
+            .declare_type("T".into(), char_ptr, Span::default())
+            .unwrap();
+        let ast = p.parse();
+        match p.errors.into_iter().next() {
+            Some(e) => Err(e.to_string()),
+            None => Ok(print_c(&ast)),
+        }
+    }
+
+    #[test_case("T g; T f(T a) { T(x); return 0; }" => Ok("char *g;\n\nchar *f(char *a) {\n    char *x;\n    return 0;\n}\n".to_string()); "type name")]
+    #[test_case("int main() { int T; T(x); }" => Ok("int main() {\n    int T;\n    T(x);\n}\n".to_string()); "hidden by a local")]
+    #[test_case("int f(int T) { { T(x); } return 0; }" => Ok("int f(int T) {\n    {\n        T(x);\n    }\n    return 0;\n}\n".to_string()); "hidden by a parameter")]
+    #[test_case("int main() { { int T; } T(x); }" => Ok("int main() {\n    {\n        int T;\n    }\n    char *x;\n}\n".to_string()); "visible after the block")]
+    #[test_case("int T;" => Err("'T' redeclared as different kind of symbol".to_string()))]
+    #[test_case("int g = T;" => Err("expected expression but identifier 'T' at 1:9".to_string()))]
+    fn classify_type_names(src: &str) -> Result<String, String> {
+        parse_typedef_t(src, Standard::default())
+    }
+
+    #[test]
+    fn knr_type_names() {
+        // `T x;` is not an implicit int declaration of `T` and `f(a) T a;` has an identifier list
+        let c = parse_typedef_t("T x; f(a) T a; { return 0; }", Standard::Knr).unwrap();
+        assert_eq!(c, "char *x;\n\nint f(a)\nchar *a;\n{\n    return 0;\n}\n");
+    }
+
+    #[test_case("int f(int) {}" => "parameter name omitted at 1:7")]
+    #[test_case("int a[x];" => "expression is not an integer constant")]
+    #[test_case("int a[1 - 2];" => "size of array is negative")]
+    #[test_case("int a[2;" => "expected ] but ; at 1:8")]
+    #[test_case("int (*)(int);" => "exepcted ID but ) at 1:7")]
+    fn invalid_declarators(src: &str) -> String {
+        parse(scan(src).unwrap()).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn nested_parens() {
+        let src = format!("int g = {}1{};", "(".repeat(1000), ")".repeat(1000));
+        let err = parse(scan(&src).unwrap()).unwrap_err().to_string();
+        assert!(err.starts_with("nesting is too deep at 1:"), "{}", err);
+    }
+
+    #[test]
+    fn left_associative() {
+        let ast = parse(scan("int g = 1 - 2 + 3;").unwrap()).unwrap();
+        let e = match &ast.0[0] {
+            ExtDecl::Global(g) => g.init.as_ref().unwrap(),
+            _ => unreachable!(),
+        };
+        match &e.kind {
+            ExprKind::Arith(left, ArithOp::Add, right) => {
+                assert!(matches!(left.kind, ExprKind::Arith(_, ArithOp::Sub, _)));
+                assert!(matches!(right.kind, ExprKind::IntConst(3)));
+            }
+            _ => panic!("unexpected {:?}", e),
+        }
+    }
+
+    #[test_case("int g = 1 +;" => "expected expression but ; at 1:12")]
+    #[test_case("int g = 1 2;" => "expected ; but number '2' at 1:11")]
+    fn invalid_operators(src: &str) -> String {
+        parse(scan(src).unwrap()).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn nested_unary() {
+        let src = format!("int g = {}1;", "-".repeat(1000));
+        let err = parse(scan(&src).unwrap()).unwrap_err().to_string();
+        assert!(err.starts_with("nesting is too deep at 1:"), "{}", err);
     }
 
     #[test_case("int g = 101; void foo() { int a = g;}")]
     #[test_case("int g = 101; void foo() { int g = 2; { int g = 3; }}")]
     fn parse_global(src: &str) {
-        parse(scan(src));
+        parse(scan(src).unwrap()).unwrap();
     }
 
     // #[test_case("int main() { int a; a = 1; }")]
     // fn parse_stmt(src: &str) {
     //     parse(scan(src));
     // }
+
+    #[test]
+    fn node_spans() {
+        let src = "int g = 1;\nint main() { return f(1, g); }";
+        let ast = parse(scan(src).unwrap()).unwrap();
+        let text = |span: Span| &src[span.start..span.end];
+        let func = match &ast.0[..] {
+            [ExtDecl::Global(g), ExtDecl::Func(f)] => {
+                assert_eq!(text(g.span), "int g = 1;");
+                f
+            }
+            _ => unreachable!(),
+        };
+        assert_eq!(text(func.span), "int main() { return f(1, g); }");
+        assert_eq!(text(func.cmp_stmt.span), "{ return f(1, g); }");
+        let stmt = &func.cmp_stmt.stmts[0];
+        assert_eq!(text(stmt.span), "return f(1, g);");
+        match &stmt.kind {
+            StmtKind::Return(Some(e)) => assert_eq!(text(e.span), "f(1, g)"),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test_case("int g = 1; int main() { return f(g, 2); }")]
+    #[test_case("int main() { int a = 100; { return a; } }")]
+    fn stream_same_as_buffered(src: &str) {
+        let buffered = serde_json::to_string(&parse(scan(src).unwrap()).unwrap()).unwrap();
+        let streamed = serde_json::to_string(&parse_stream(Lexer::new(src)).unwrap()).unwrap();
+        assert_eq!(streamed, buffered);
+    }
+
+    #[test_case("int a = 1 $ 2;" => "unexpected char: $ at 1:11")]
+    #[test_case("int main() { return 1 } $" => "expected ; but } at 1:23\nunexpected char: $ at 1:25"; "lex error after parse error")]
+    #[test_case("int main() { int a = 1 int b = 2; return a }" => "expected ; but int at 1:24\nexpected ; but } at 1:44"; "missing semicolons")]
+    #[test_case("int f(int a int b) { return 1; } int g = ;" => "expected ) but int at 1:13\nexpected expression but ; at 1:42"; "skip broken function")]
+    #[test_case("int main() { x y; { return 1 } return; } main" => "expected ; but identifier 'y' at 1:16\nexpected ; but } at 1:30\nunexpected identifier 'main' at 1:42"; "nested block")]
+    #[test_case("int main() { return (1; }" => "expected ) but ; at 1:23"; "no cascade at block end")]
+    #[test_case("int main() { return 1 " => "expected ; but EOF"; "no cascade at EOF")]
+    #[test_case("int a = 1 # 2; int b = $;" => "unexpected char: # at 1:11\nunexpected char: $ at 1:24"; "multiple lex errors")]
+    #[test_case("int main() { return 1 }" => "expected ; but } at 1:23")]
+    #[test_case("struct r { unsigned flags : 3; }; int g;" => "tncc does not yet support 'struct' at 1:1"; "bit field")]
+    fn stream_errors(src: &str) -> String {
+        parse_stream(Lexer::new(src)).unwrap_err().to_string()
+    }
 }
@@ -0,0 +1,377 @@
+//! Generate random C programs within the supported subset, like a tiny csmith
+//!
+//! Programs are valid C without undefined behavior: every expression has a bound of its absolute
+//! value, so the arithmetic never overflows `int`, variables are initialized before use and calls
+//! only go to functions defined before, so there is no recursion. The same seed gives the same
+//! program
+
+use std::fmt::Write;
+
+/// limits of the generated programs
+#[derive(Debug, Clone, Copy)]
+pub struct GenConfig {
+    /// functions before `main`
+    pub max_funcs: usize,
+    pub max_params: usize,
+    pub max_globals: usize,
+    /// statements of a block
+    pub max_stmts: usize,
+    /// nesting of blocks
+    pub max_block_depth: u32,
+    /// nesting of operators and calls in an expression
+    pub max_expr_depth: u32,
+    /// only constants, calls, blocks and returns, which the code generation supports
+    pub codegen_only: bool,
+}
+
+impl Default for GenConfig {
+    fn default() -> Self {
+        GenConfig {
+            max_funcs: 4,
+            max_params: 3,
+            max_globals: 3,
+            max_stmts: 5,
+            max_block_depth: 2,
+            max_expr_depth: 3,
+            codegen_only: false,
+        }
+    }
+}
+
+/// bound of the arguments of calls
+const ARG_BOUND: i64 = 10_000;
+
+/// bound of the values of variables and returned values
+const VALUE_BOUND: i64 = 1 << 24;
+
+/// generate a program with the default limits
+pub fn generate(seed: u64) -> String {
+    generate_with(seed, GenConfig::default())
+}
+
+pub fn generate_with(seed: u64, config: GenConfig) -> String {
+    let mut gen = ProgramGen {
+        rng: Rng(seed),
+        config,
+        out: String::new(),
+        funcs: Vec::new(),
+        scopes: Vec::new(),
+        names: 0,
+    };
+    gen.program();
+    gen.out
+}
+
+/// splitmix64, small and good enough to pick program shapes
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// value in `0..=max`
+    fn upto(&mut self, max: u64) -> u64 {
+        self.next() % (max + 1)
+    }
+
+    /// true with the probability of one in `n`
+    fn one_in(&mut self, n: u64) -> bool {
+        self.next().is_multiple_of(n)
+    }
+}
+
+/// variable in scope with the bound of its value
+#[derive(Clone)]
+struct Var {
+    name: String,
+    bound: i64,
+}
+
+/// function defined so far
+struct Func {
+    name: String,
+    params: usize,
+    bound: i64,
+}
+
+struct ProgramGen {
+    rng: Rng,
+    config: GenConfig,
+    out: String,
+    funcs: Vec<Func>,
+    /// variables of the open scopes, the first is the translation unit
+    scopes: Vec<Vec<Var>>,
+    /// counter for unique names
+    names: usize,
+}
+
+impl ProgramGen {
+    fn program(&mut self) {
+        self.scopes.push(Vec::new());
+        for _ in 0..self.rng.upto(self.config.max_globals as u64) {
+            let name = self.new_name("g");
+            // initializers are constant expressions
+            let (init, bound) = self.const_expr(self.config.max_expr_depth, 1000);
+            writeln!(self.out, "int {} = {};", name, init).unwrap();
+            self.declare(name, bound);
+        }
+        self.out.push('\n');
+
+        for _ in 0..=self.rng.upto(self.config.max_funcs as u64) {
+            let name = self.new_name("f");
+            let params = self.rng.upto(self.config.max_params as u64) as usize;
+            self.function(&name, params);
+        }
+        self.function("main", 0);
+        self.scopes.pop();
+    }
+
+    fn function(&mut self, name: &str, params: usize) {
+        let params: Vec<Var> = (0..params)
+            .map(|_| Var {
+                name: self.new_name("p"),
+                bound: ARG_BOUND,
+            })
+            .collect();
+        let list = match params.is_empty() {
+            true => String::from("void"),
+            false => params
+                .iter()
+                .map(|p| format!("int {}", p.name))
+                .collect::<Vec<_>>()
+                .join(", "),
+        };
+        writeln!(self.out, "int {}({}) {{", name, list).unwrap();
+        self.scopes.push(params.clone());
+        self.stmts(1, self.config.max_block_depth);
+        let (ret, bound) = self.expr(self.config.max_expr_depth, VALUE_BOUND);
+        writeln!(self.out, "    return {};", ret).unwrap();
+        self.scopes.pop();
+        self.out.push_str("}\n\n");
+
+        self.funcs.push(Func {
+            name: name.to_string(),
+            params: params.len(),
+            bound,
+        });
+    }
+
+    /// statements of a block at the indentation level
+    fn stmts(&mut self, level: usize, depth: u32) {
+        let indent = "    ".repeat(level);
+        for _ in 0..self.rng.upto(self.config.max_stmts as u64) {
+            match self.rng.upto(4) {
+                // nested block, may return early
+                0 if depth > 0 => {
+                    writeln!(self.out, "{}{{", indent).unwrap();
+                    self.scopes.push(Vec::new());
+                    self.stmts(level + 1, depth - 1);
+                    if self.rng.one_in(4) {
+                        let (ret, _) = self.expr(self.config.max_expr_depth, VALUE_BOUND);
+                        writeln!(self.out, "{}    return {};", indent, ret).unwrap();
+                    }
+                    self.scopes.pop();
+                    writeln!(self.out, "{}}}", indent).unwrap();
+                }
+                // call for its side effects only
+                1 if !self.funcs.is_empty() => {
+                    let (call, _) = self.call(self.config.max_expr_depth, VALUE_BOUND, None);
+                    writeln!(self.out, "{}{};", indent, call).unwrap();
+                }
+                // expression statement instead of variables
+                _ if self.config.codegen_only => {
+                    let (e, _) = self.expr(self.config.max_expr_depth, VALUE_BOUND);
+                    writeln!(self.out, "{}{};", indent, e).unwrap();
+                }
+                // local variable, sometimes shadowing an outer one
+                _ => {
+                    let shadowed = self.visible().filter(|_| self.rng.one_in(3));
+                    let name = shadowed.unwrap_or_else(|| self.new_name("v"));
+                    // the variable is in scope of its own initializer, so it is not referred
+                    let (init, bound) = self.expr_without(&name);
+                    writeln!(self.out, "{}int {} = {};", indent, name, init).unwrap();
+                    self.declare(name, bound);
+                }
+            }
+        }
+    }
+
+    /// expression with the absolute value at most `budget`, return the expression and its bound
+    fn expr(&mut self, depth: u32, budget: i64) -> (String, i64) {
+        self.expr_excluding(depth, budget, None)
+    }
+
+    fn expr_without(&mut self, name: &str) -> (String, i64) {
+        self.expr_excluding(self.config.max_expr_depth, VALUE_BOUND, Some(name))
+    }
+
+    fn expr_excluding(&mut self, depth: u32, budget: i64, excluded: Option<&str>) -> (String, i64) {
+        let choice = match depth {
+            0 if self.config.codegen_only => 0,
+            0 => self.rng.upto(1),
+            _ if self.config.codegen_only => self.rng.upto(1) * 5,
+            _ => self.rng.upto(5),
+        };
+        match choice {
+            1 => {
+                let vars: Vec<Var> = self
+                    .in_scope()
+                    .into_iter()
+                    .filter(|v| v.bound <= budget && Some(v.name.as_str()) != excluded)
+                    .collect();
+                if !vars.is_empty() {
+                    let v = &vars[self.rng.upto(vars.len() as u64 - 1) as usize];
+                    return (v.name.clone(), v.bound);
+                }
+            }
+            2 | 3 => {
+                let (l, lb) = self.expr_excluding(depth - 1, budget / 2, excluded);
+                let (r, rb) = self.expr_excluding(depth - 1, budget / 2, excluded);
+                let op = if self.rng.one_in(2) { '+' } else { '-' };
+                return (format!("({} {} {})", l, op, r), lb + rb);
+            }
+            4 => {
+                let (e, b) = self.expr_excluding(depth - 1, budget, excluded);
+                return (format!("-({})", e), b);
+            }
+            5 if self.funcs.iter().any(|f| f.bound <= budget) => {
+                return self.call(depth, budget, excluded)
+            }
+            _ => {}
+        }
+        let c = self.rng.upto(budget.clamp(0, 99) as u64) as i64;
+        (c.to_string(), c)
+    }
+
+    /// constant expression without references or calls
+    fn const_expr(&mut self, depth: u32, budget: i64) -> (String, i64) {
+        match depth > 0 && self.rng.one_in(2) {
+            true => {
+                let (l, lb) = self.const_expr(depth - 1, budget / 2);
+                let (r, rb) = self.const_expr(depth - 1, budget / 2);
+                (format!("({} - {})", l, r), lb + rb)
+            }
+            false => {
+                let c = self.rng.upto(budget.clamp(0, 99) as u64) as i64;
+                (c.to_string(), c)
+            }
+        }
+    }
+
+    /// call of a defined function returning at most `budget`
+    fn call(&mut self, depth: u32, budget: i64, excluded: Option<&str>) -> (String, i64) {
+        let candidates: Vec<usize> = (0..self.funcs.len())
+            .filter(|i| self.funcs[*i].bound <= budget)
+            .collect();
+        let f = candidates[self.rng.upto(candidates.len() as u64 - 1) as usize];
+        let args: Vec<String> = (0..self.funcs[f].params)
+            .map(|_| {
+                self.expr_excluding(depth.saturating_sub(1), ARG_BOUND, excluded)
+                    .0
+            })
+            .collect();
+        let func = &self.funcs[f];
+        (format!("{}({})", func.name, args.join(", ")), func.bound)
+    }
+
+    /// variables visible from the innermost scope, inner ones hide outer ones
+    fn in_scope(&self) -> Vec<Var> {
+        let mut vars: Vec<Var> = Vec::new();
+        for scope in self.scopes.iter().rev() {
+            for v in scope.iter().rev() {
+                if !vars.iter().any(|x| x.name == v.name) {
+                    vars.push(v.clone());
+                }
+            }
+        }
+        vars
+    }
+
+    /// a name of an outer scope which is not declared in the innermost one yet
+    fn visible(&mut self) -> Option<String> {
+        let inner = self.scopes.last()?;
+        let outer: Vec<String> = self
+            .in_scope()
+            .into_iter()
+            .map(|v| v.name)
+            .filter(|n| !inner.iter().any(|v| &v.name == n))
+            .collect();
+        match outer.is_empty() {
+            true => None,
+            false => Some(outer[self.rng.upto(outer.len() as u64 - 1) as usize].clone()),
+        }
+    }
+
+    fn declare(&mut self, name: String, bound: i64) {
+        self.scopes
+            .last_mut()
+            .expect("open scope")
+            .push(Var { name, bound });
+    }
+
+    fn new_name(&mut self, prefix: &str) -> String {
+        self.names += 1;
+        format!("{}{}", prefix, self.names)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{analyse, difftest, gen_asm, parse, scan, util::TargetOs, GenOpts};
+
+    use super::{generate, generate_with, GenConfig};
+
+    /// number of programs, override with `TNCC_PROGEN_CASES`
+    fn cases() -> u64 {
+        std::env::var("TNCC_PROGEN_CASES")
+            .ok()
+            .and_then(|n| n.parse().ok())
+            .unwrap_or(30)
+    }
+
+    #[test]
+    fn same_seed_same_program() {
+        assert_eq!(generate(7), generate(7));
+        assert_ne!(generate(7), generate(8));
+    }
+
+    #[test]
+    fn codegen_generated() {
+        let config = GenConfig {
+            codegen_only: true,
+            ..GenConfig::default()
+        };
+        for seed in 0..cases() {
+            let src = generate_with(seed, config);
+            let ast = parse(scan(&src).unwrap()).unwrap();
+            gen_asm(&ast, &GenOpts::new(TargetOs::Linux))
+                .unwrap_or_else(|e| panic!("seed {}: {}\n{}", seed, e, src));
+        }
+    }
+
+    // generated programs pass the front-end, code generation may not support them yet
+    #[test]
+    fn compile_generated() {
+        for seed in 0..cases() {
+            let src = generate(seed);
+            let ast = parse(scan(&src).unwrap())
+                .unwrap_or_else(|e| panic!("seed {}: {}\n{}", seed, e, src));
+            analyse(&ast).unwrap_or_else(|e| panic!("seed {}: {}\n{}", seed, e, src));
+            let _ = gen_asm(&ast, &GenOpts::new(TargetOs::Linux));
+        }
+    }
+
+    #[test]
+    fn differential_generated() {
+        for seed in 0..cases() {
+            if let Err(e) = difftest::compare(&generate(seed)) {
+                panic!("seed {} differs\n{}", seed, e)
+            }
+        }
+    }
+}
@@ -1,41 +1,256 @@
-use std::str::Chars;
+use std::{
+    convert::TryFrom,
+    fmt::{self, Display},
+    str::Chars,
+};
 
-use crate::common::{TokType, Token};
+use serde::Serialize;
+
+use crate::{
+    atomics::MEMORY_ORDERS,
+    common::{CompileError, Span, TokType, Token, Trivia, TriviaKind},
+    intern::Symbol,
+    timing::Phase,
+};
 
 /// scan the input source code into array of tokens
-pub fn scan(src: &str) -> Vec<Token> {
-    let input = ScanInput::from(src.chars());
-    input
-        .into_iter()
+pub fn scan(src: &str) -> Result<Vec<Token>, CompileError> {
+    Lexer::new(src)
+        .collect::<Result<_, _>>()
+        .map_err(CompileError::from)
+}
+
+/// scan the whole input, invalid input is replaced by `Unknown` tokens and reported as errors
+pub fn scan_recover(src: &str) -> (Vec<Token>, Vec<LexError>) {
+    let mut errors = Vec::new();
+    let tokens = Lexer::new(src)
+        .map(|r| {
+            r.unwrap_or_else(|e| {
+                let t = e.to_token();
+                errors.push(e);
+                t
+            })
+        })
+        .collect();
+    (tokens, errors)
+}
+
+/// token with its source text, i.e for '--emit=tokens-json'
+#[derive(Debug, Serialize)]
+pub struct TokenInfo<'a> {
+    pub kind: &'static str,
+    pub text: &'a str,
+    pub span: Span,
+    pub line: u32,
+    pub col: u32,
+}
+
+/// describe the tokens scanned from the source
+pub fn token_infos<'a>(src: &'a str, tokens: &[Token]) -> Vec<TokenInfo<'a>> {
+    tokens
+        .iter()
+        .map(|t| TokenInfo {
+            kind: t.tok.kind(),
+            text: src.get(t.span.start..t.span.end).unwrap_or_default(),
+            span: t.span,
+            line: t.loc.0,
+            col: t.loc.1,
+        })
         .collect()
 }
 
+/// error of the lexer
+#[derive(Debug, Clone, PartialEq)]
+pub struct LexError {
+    pub kind: LexErrorKind,
+    /// location (line,column) of the error
+    pub loc: (u32, u32),
+    /// byte offsets of the invalid input
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LexErrorKind {
+    /// character which can not start any token
+    UnexpectedChar(char),
+    /// number does not fit in 64 bits
+    NumberTooLarge,
+    /// exponent of floating constant without digits
+    MalformedNumber,
+    /// block comment without '*/'
+    UnterminatedComment,
+    /// string literal without closing '"' on the line
+    UnterminatedString,
+    /// unknown escape sequence or value out of the range of a byte
+    InvalidEscape(char),
+}
+
+impl Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (line, col) = self.loc;
+        match self.kind {
+            LexErrorKind::UnexpectedChar(c) => {
+                write!(f, "unexpected char: {} at {}:{}", c, line, col)
+            }
+            LexErrorKind::NumberTooLarge => write!(f, "number is too large at {}:{}", line, col),
+            LexErrorKind::MalformedNumber => write!(f, "malformed number at {}:{}", line, col),
+            LexErrorKind::UnterminatedComment => {
+                write!(f, "unterminated comment at {}:{}", line, col)
+            }
+            LexErrorKind::UnterminatedString => {
+                write!(f, "missing terminating '\"' at {}:{}", line, col)
+            }
+            LexErrorKind::InvalidEscape(c) => {
+                write!(f, "invalid escape sequence '\\{}' at {}:{}", c, line, col)
+            }
+        }
+    }
+}
+
+impl LexError {
+    /// `Unknown` token in place of the invalid input
+    pub fn to_token(&self) -> Token {
+        Token {
+            tok: TokType::Unknown,
+            loc: self.loc,
+            span: self.span,
+            leading: Vec::new(),
+        }
+    }
+}
+
+impl std::error::Error for LexError {}
+
+impl From<LexError> for CompileError {
+    fn from(e: LexError) -> Self {
+        let mut err = CompileError::at(e.to_string(), e.loc);
+        err.span = Some(e.span);
+        err
+    }
+}
+
+/// default columns between tab stops, same as gcc '-ftabstop'
+pub const DEFAULT_TAB_WIDTH: u32 = 8;
+
+/// streaming lexer, iterate tokens of the source lazily
+///
+/// the invalid character is skipped after an error, so iteration can continue
 #[derive(Debug)]
-struct ScanInput<'a> {
+pub struct Lexer<'a> {
     chars: Chars<'a>,
     lookahead: Option<char>,
+    /// position of the next char
+    cur: Cursor,
+    /// position before the last char, restored by `put_back`
+    last: Cursor,
+    tab_width: u32,
+    keep_trivia: bool,
+    /// value of `__STDC_HOSTED__`, none keeps the name as an identifier
+    hosted: Option<bool>,
+    /// trivia skipped since the last token
+    trivia: Vec<Trivia>,
+}
+
+/// line, column and byte offset in the source
+#[derive(Debug, Clone, Copy)]
+struct Cursor {
     line: u32,
     col: u32,
+    pos: usize,
+    /// last char is '\r', so a following '\n' does not start another line
+    after_cr: bool,
+}
+
+impl Cursor {
+    /// move over the char, '\r\n' is a single line break and tabs advance to the next tab stop
+    fn advance(&mut self, c: char, tab_width: u32) {
+        self.pos += c.len_utf8();
+        match c {
+            '\n' if self.after_cr => {}
+            '\n' | '\r' => {
+                self.line += 1;
+                self.col = 1;
+            }
+            '\t' => self.col = (self.col - 1) / tab_width * tab_width + tab_width + 1,
+            _ => self.col += 1,
+        }
+        self.after_cr = c == '\r';
+    }
+}
+
+/// location (line,column) of the byte offset as reported by the scanner
+pub fn locate(src: &str, offset: usize, tab_width: u32) -> (u32, u32) {
+    let mut cur = Cursor {
+        line: 1,
+        col: 1,
+        pos: 0,
+        after_cr: false,
+    };
+    for c in src.chars() {
+        if cur.pos >= offset {
+            break;
+        }
+        cur.advance(c, tab_width.max(1));
+    }
+    (cur.line, cur.col)
+}
+
+impl<'a> Lexer<'a> {
+    pub fn new(src: &'a str) -> Self {
+        Lexer::from(src.chars())
+    }
+
+    /// columns between tab stops for reported locations
+    pub fn tab_width(mut self, width: u32) -> Self {
+        self.tab_width = width.max(1);
+        self
+    }
+
+    /// attach whitespace and comments to the following token instead of discarding them
+    pub fn keep_trivia(mut self, keep: bool) -> Self {
+        self.keep_trivia = keep;
+        self
+    }
+
+    /// replace `__STDC_HOSTED__` by 1, or 0 for a freestanding environment
+    ///
+    /// there is no preprocessor, the predefined macro is expanded by the scanner
+    pub fn hosted(mut self, hosted: bool) -> Self {
+        self.hosted = Some(hosted);
+        self
+    }
+
+    /// trivia after the last token, available once the iteration is done
+    pub fn trailing_trivia(&self) -> &[Trivia] {
+        &self.trivia
+    }
 }
 
 /// token iterator for input
-impl<'a> Iterator for ScanInput<'a> {
-    type Item = Token;
+impl<'a> Iterator for Lexer<'a> {
+    type Item = Result<Token, LexError>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.scan_token()
+        let tok = self.scan_token();
+        if let Some(Ok(t)) = &tok {
+            debug!(target: Phase::Scan.name(), "{}:{}\t{}", t.loc.0, t.loc.1, t.tok);
+        }
+        tok
     }
 }
 
-/// next and peek operator with single char lookahead
-impl<'a> ScanInput<'a> {
+/// next and peek operator with char lookahead
+impl<'a> Lexer<'a> {
     /// scan next token
-    fn scan_token(&mut self) -> Option<Token> {
-        // skip whitespace
-        self.skip_whitespace();
+    fn scan_token(&mut self) -> Option<Result<Token, LexError>> {
+        // skip whitespace and comments
+        if let Err(e) = self.skip_trivia() {
+            return Some(Err(e));
+        }
 
-        // cache column
-        let col = self.col;
+        // cache column and offset
+        let loc = (self.cur.line, self.cur.col);
+        let start = self.cur.pos;
 
         // start with letter -> ID or keyword
         // underscore is allowed
@@ -48,29 +263,93 @@ impl<'a> ScanInput<'a> {
                     '{' => TokType::BracketOpen,
                     '}' => TokType::BracketClose,
                     ';' => TokType::Semicolon,
+                    ':' => TokType::Colon,
+                    '-' if self.peek(0) == Some('>') => {
+                        self.next();
+                        TokType::Arrow
+                    }
                     '-' => TokType::Minus,
                     '+' => TokType::Plus,
                     '=' => TokType::Assign,
                     ',' => TokType::Comma,
-                    t if t.is_ascii_alphabetic() => self.scan_keyword_or_id(t),
-                    t if t.is_ascii_digit() => self.scan_num(t),
-                    t => panic!("unexpected char: {}", t),
+                    '*' => TokType::Star,
+                    '[' => TokType::SquareOpen,
+                    ']' => TokType::SquareClose,
+                    '"' => match self.scan_str() {
+                        Ok(typ) => typ,
+                        Err(kind) => return Some(Err(self.error(kind, loc, start))),
+                    },
+                    t if t.is_ascii_alphabetic() || t == '_' => self.scan_keyword_or_id(t),
+                    // number may start with the decimal point, i.e .5
+                    '.' => match self.next() {
+                        Some(d) if d.is_ascii_digit() => {
+                            self.put_back(d);
+                            match self.scan_num('.') {
+                                Ok(typ) => typ,
+                                Err(kind) => return Some(Err(self.error(kind, loc, start))),
+                            }
+                        }
+                        Some(c) => {
+                            self.put_back(c);
+                            TokType::Dot
+                        }
+                        None => TokType::Dot,
+                    },
+                    t if t.is_ascii_digit() => match self.scan_num(t) {
+                        Ok(typ) => typ,
+                        Err(kind) => return Some(Err(self.error(kind, loc, start))),
+                    },
+                    t => return Some(Err(self.error(LexErrorKind::UnexpectedChar(t), loc, start))),
                 };
-                Some(self.new_token(typ, col))
+                Some(Ok(self.new_token(typ, loc, start)))
             }
         }
     }
 
-    /// skip whitespace, tabs and new line
-    fn skip_whitespace(&mut self) {
+    /// skip whitespace, tabs, new line and comments
+    fn skip_trivia(&mut self) -> Result<(), LexError> {
         loop {
-            match self.next() {
-                Some(c) if c.is_ascii_whitespace() => (),
-                Some(c) => {
-                    self.put_back(c);
-                    break;
+            let loc = (self.cur.line, self.cur.col);
+            let start = self.cur.pos;
+            let kind = match (self.peek(0), self.peek(1)) {
+                (Some(c), _) if c.is_ascii_whitespace() => {
+                    while matches!(self.peek(0), Some(c) if c.is_ascii_whitespace()) {
+                        self.next();
+                    }
+                    TriviaKind::Whitespace
+                }
+                (Some('/'), Some('/')) => {
+                    while !matches!(self.peek(0), None | Some('\n') | Some('\r')) {
+                        self.next();
+                    }
+                    TriviaKind::LineComment
+                }
+                (Some('/'), Some('*')) => {
+                    self.next();
+                    self.next();
+                    loop {
+                        match self.next() {
+                            Some('*') if self.peek(0) == Some('/') => {
+                                self.next();
+                                break;
+                            }
+                            Some(_) => {}
+                            None => {
+                                return Err(self.error(
+                                    LexErrorKind::UnterminatedComment,
+                                    loc,
+                                    start,
+                                ))
+                            }
+                        }
+                    }
+                    TriviaKind::BlockComment
                 }
-                _ => break,
+                _ => return Ok(()),
+            };
+            if self.keep_trivia {
+                let span = Span::new(start, self.cur.pos);
+                self.trivia.push(Trivia { kind, span });
             }
         }
     }
@@ -91,42 +370,170 @@ impl<'a> ScanInput<'a> {
             }
         }
 
+        // predefined macro
+        if let (Some(hosted), "__STDC_HOSTED__") = (self.hosted, str.as_str()) {
+            return TokType::NumInt(hosted as u64);
+        }
+        if let Some((_, order)) = MEMORY_ORDERS.iter().find(|(name, _)| *name == str) {
+            return TokType::NumInt(*order);
+        }
+
         // keywords have higher priority
         match str.as_str() {
             "int" => TokType::KeywordInt,
+            "char" => TokType::KeywordChar,
             "void" => TokType::KeywordVoid,
             "return" => TokType::KeywordReturn,
-            _ => TokType::ID(str),
+            "_Static_assert" => TokType::KeywordStaticAssert,
+            "volatile" => TokType::KeywordVolatile,
+            "__attribute__" | "__attribute" => TokType::KeywordAttribute,
+            "asm" | "__asm__" | "__asm" => TokType::KeywordAsm,
+            "extern" => TokType::KeywordExtern,
+            "static" => TokType::KeywordStatic,
+            _ => TokType::ID(Symbol::intern(&str)),
         }
     }
 
-    /// scan positive number: int or double
-    fn scan_num(&mut self, c: char) -> TokType {
-        let (num1, _) = self.scan_pos_num(self.char_to_u64(c));
-        match self.next() {
-            Some('.') => {
-                let (num2, ct) = self.scan_pos_num(0);
-                let real: f64 = num1 as f64 + (num2 as f64).powi(-(ct as i32));
-                TokType::NumReal(real)
+    /// scan decimal integer or floating constant with optional fraction, exponent and `f` suffix
+    ///
+    /// digits are accumulated and converted at once, so the value is correctly rounded
+    fn scan_num(&mut self, c: char) -> Result<TokType, LexErrorKind> {
+        let mut text = String::new();
+        text.push(c);
+        self.scan_digits(&mut text);
+        let mut real = c == '.';
+        let mut next = self.next();
+
+        // fraction
+        if !real && next == Some('.') {
+            real = true;
+            text.push('.');
+            self.scan_digits(&mut text);
+            next = self.next();
+        }
+
+        // exponent with optional sign
+        if let Some(e @ 'e') | Some(e @ 'E') = next {
+            real = true;
+            text.push(e);
+            next = self.next();
+            if let Some(sign @ '+') | Some(sign @ '-') = next {
+                text.push(sign);
+                next = self.next();
             }
-            Some(c) => {
-                self.put_back(c);
-                TokType::NumInt(num1)
+            match next {
+                Some(d) if d.is_ascii_digit() => {
+                    text.push(d);
+                    self.scan_digits(&mut text);
+                    next = self.next();
+                }
+                _ => {
+                    if let Some(c) = next {
+                        self.put_back(c);
+                    }
+                    return Err(LexErrorKind::MalformedNumber);
+                }
             }
-            _ => TokType::NumInt(num1),
+        }
+
+        // float suffix
+        let single = real && matches!(next, Some('f') | Some('F'));
+        if single {
+            next = self.next();
+        }
+        if let Some(c) = next {
+            self.put_back(c);
+        }
+
+        if !real {
+            return text
+                .parse()
+                .map(TokType::NumInt)
+                .map_err(|_| LexErrorKind::NumberTooLarge);
+        }
+        let v: f64 = text.parse().map_err(|_| LexErrorKind::MalformedNumber)?;
+        let v = if single { v as f32 as f64 } else { v };
+        match v.is_finite() {
+            true => Ok(TokType::NumReal(v)),
+            false => Err(LexErrorKind::NumberTooLarge),
         }
     }
 
-    /// scan positive natural number
-    fn scan_pos_num(&mut self, prefix: u64) -> (u64, u32) {
-        let mut num = prefix;
-        let mut count = 0;
+    /// scan string literal after the opening quote
+    ///
+    /// an invalid escape is reported after the closing quote, so scanning resumes after the
+    /// literal
+    fn scan_str(&mut self) -> Result<TokType, LexErrorKind> {
+        let mut bytes = Vec::new();
+        let mut error = None;
         loop {
             match self.next() {
-                Some(c) if c.is_ascii_digit() => {
-                    num = num * 10 + self.char_to_u64(c);
-                    count = count + 1;
+                Some('"') => break,
+                None | Some('\n') | Some('\r') => return Err(LexErrorKind::UnterminatedString),
+                Some('\\') => match self.scan_escape() {
+                    Ok(b) => bytes.push(b),
+                    Err(kind) => {
+                        error.get_or_insert(kind);
+                    }
+                },
+                Some(c) => bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes()),
+            }
+        }
+        match error {
+            Some(kind) => Err(kind),
+            None => Ok(TokType::Str(bytes)),
+        }
+    }
+
+    /// byte of the escape sequence after the backslash
+    fn scan_escape(&mut self) -> Result<u8, LexErrorKind> {
+        let c = match self.next() {
+            Some(c) => c,
+            None => return Err(LexErrorKind::UnterminatedString),
+        };
+        let b = match c {
+            'n' => b'\n',
+            't' => b'\t',
+            'r' => b'\r',
+            'a' => 0x07,
+            'b' => 0x08,
+            'f' => 0x0c,
+            'v' => 0x0b,
+            '\\' | '\'' | '"' | '?' => c as u8,
+            // up to three octal digits
+            '0'..='7' => {
+                let mut v = c.to_digit(8).unwrap();
+                for _ in 0..2 {
+                    match self.peek(0).and_then(|d| d.to_digit(8)) {
+                        Some(d) => {
+                            self.next();
+                            v = v * 8 + d;
+                        }
+                        None => break,
+                    }
                 }
+                u8::try_from(v).map_err(|_| LexErrorKind::InvalidEscape(c))?
+            }
+            // any number of hex digits
+            'x' => {
+                let mut v: Option<u32> = None;
+                while let Some(d) = self.peek(0).and_then(|d| d.to_digit(16)) {
+                    self.next();
+                    v = Some(v.unwrap_or(0).saturating_mul(16).saturating_add(d));
+                }
+                v.and_then(|v| u8::try_from(v).ok())
+                    .ok_or(LexErrorKind::InvalidEscape(c))?
+            }
+            c => return Err(LexErrorKind::InvalidEscape(c)),
+        };
+        Ok(b)
+    }
+
+    /// append the following digits to the text
+    fn scan_digits(&mut self, text: &mut String) {
+        loop {
+            match self.next() {
+                Some(c) if c.is_ascii_digit() => text.push(c),
                 Some(c) => {
                     self.put_back(c);
                     break;
@@ -134,55 +541,62 @@ impl<'a> ScanInput<'a> {
                 None => break,
             }
         }
-        (num, count)
-    }
-
-    fn char_to_u64(&self, ch: char) -> u64 {
-        ch.to_digit(10).unwrap() as u64
     }
 
-    fn new_token(&mut self, tok_type: TokType, col: u32) -> Token {
+    fn new_token(&mut self, tok_type: TokType, loc: (u32, u32), start: usize) -> Token {
         Token {
             tok: tok_type,
-            loc: (self.line, col),
+            loc,
+            span: Span::new(start, self.cur.pos),
+            leading: std::mem::take(&mut self.trivia),
+        }
+    }
+
+    fn error(&self, kind: LexErrorKind, loc: (u32, u32), start: usize) -> LexError {
+        LexError {
+            kind,
+            loc,
+            span: Span::new(start, self.cur.pos),
         }
     }
 
     /// next character
     fn next(&mut self) -> Option<char> {
-        let opt = match self.lookahead {
-            Some(c) => {
-                self.lookahead = None;
-                Some(c)
-            }
-            _ => self.chars.next(),
-        };
+        let c = self.lookahead.take().or_else(|| self.chars.next())?;
+        self.last = self.cur;
+        self.cur.advance(c, self.tab_width);
+        Some(c)
+    }
 
-        // advance column and line
-        if let Some(c) = opt {
-            if c == '\n' || c == '\r' {
-                self.line = self.line + 1;
-                self.col = 1;
-            } else {
-                self.col = self.col + 1;
-            }
-        };
-        opt
+    /// n-th char ahead without consuming it, 0 is the next char
+    fn peek(&self, n: usize) -> Option<char> {
+        self.lookahead.into_iter().chain(self.chars.clone()).nth(n)
     }
 
+    /// undo the last `next`
     fn put_back(&mut self, ch: char) {
         self.lookahead = Some(ch);
-        self.col = self.col - 1;
+        self.cur = self.last;
     }
 }
 
-impl<'a> From<Chars<'a>> for ScanInput<'a> {
+impl<'a> From<Chars<'a>> for Lexer<'a> {
     fn from(chs: Chars<'a>) -> Self {
-        ScanInput {
-            chars: chs,
-            lookahead: None,
+        let start = Cursor {
             line: 1,
             col: 1,
+            pos: 0,
+            after_cr: false,
+        };
+        Lexer {
+            chars: chs,
+            lookahead: None,
+            cur: start,
+            last: start,
+            tab_width: DEFAULT_TAB_WIDTH,
+            keep_trivia: false,
+            hosted: None,
+            trivia: Vec::new(),
         }
     }
 }
@@ -191,28 +605,276 @@ impl<'a> From<Chars<'a>> for ScanInput<'a> {
 mod test {
     use test_case::test_case;
 
-    use crate::common::TokType;
+    use crate::common::{Span, TokType, TriviaKind};
 
-    use super::scan;
+    use super::{
+        locate, scan, scan_recover, token_infos, LexErrorKind, Lexer, Symbol, DEFAULT_TAB_WIDTH,
+    };
 
     #[test_case("int return void main")]
     #[test_case("1 1.1 0 0.2")]
     #[test_case("a var1")]
     #[test_case("int () ( ) {} { } ; =")]
+    #[test_case("int *a[10]")]
     fn valid_tokens(src: &str) {
-        assert_eq!(!scan(src).is_empty(), true);
+        assert!(!scan(src).unwrap().is_empty());
     }
 
     #[test_case("int main() { return 1; }")]
     #[test_case("int main() { int a = 100; return 1; }")]
     fn valid_program(src: &str) {
-        assert_eq!(!scan(src).is_empty(), true);
+        assert!(!scan(src).unwrap().is_empty());
     }
 
     #[test_case("void", TokType::KeywordVoid)]
-    #[test_case("voida", TokType::ID(String::from("voida")))]
+    #[test_case("char", TokType::KeywordChar)]
+    #[test_case("->", TokType::Arrow)]
+    #[test_case("-x", TokType::Minus)]
+    #[test_case(".x", TokType::Dot)]
+    #[test_case(": 3", TokType::Colon)]
+    #[test_case("_Static_assert", TokType::KeywordStaticAssert)]
+    #[test_case("volatile", TokType::KeywordVolatile)]
+    #[test_case("__attribute__", TokType::KeywordAttribute)]
+    #[test_case("__asm__", TokType::KeywordAsm)]
+    #[test_case("extern", TokType::KeywordExtern)]
+    #[test_case("static", TokType::KeywordStatic)]
+    #[test_case("_x1", TokType::ID(Symbol::intern("_x1")))]
+    #[test_case(".", TokType::Dot; "dot at the end")]
+    #[test_case("voida", TokType::ID(Symbol::intern("voida")))]
     fn single_token(src: &str, tok: TokType) {
-        let toks = scan(src);
+        let toks = scan(src).unwrap();
         assert_eq!(toks.first().unwrap().tok, tok);
     }
+
+    #[test_case("int a = 1 $ 2;" => "unexpected char: $ at 1:11")]
+    #[test_case("int main() {\n  #\n}" => "unexpected char: # at 2:3")]
+    fn invalid_char(src: &str) -> String {
+        scan(src).unwrap_err().to_string()
+    }
+
+    #[test_case(r#""hello""# => b"hello".to_vec())]
+    #[test_case(r#""""# => Vec::<u8>::new(); "empty")]
+    #[test_case(r#""a\n\t\\\"\'\?""# => b"a\n\t\\\"'?".to_vec(); "simple escapes")]
+    #[test_case(r#""\0\101\1234\x41\x7e""# => b"\0AS4A~".to_vec(); "numeric escapes")]
+    #[test_case(r#""é""# => "é".as_bytes().to_vec(); "utf8")]
+    fn string_literal(src: &str) -> Vec<u8> {
+        match scan(src).unwrap().remove(0).tok {
+            TokType::Str(s) => s,
+            t => panic!("unexpected {:?}", t),
+        }
+    }
+
+    #[test_case("f(\"abc);" => "missing terminating '\"' at 1:3")]
+    #[test_case("f(\"abc\n\");" => "missing terminating '\"' at 1:3")]
+    #[test_case(r#"f("\q");"# => r"invalid escape sequence '\q' at 1:3")]
+    #[test_case(r#"f("\x");"# => r"invalid escape sequence '\x' at 1:3")]
+    #[test_case(r#"f("\x100");"# => r"invalid escape sequence '\x' at 1:3")]
+    #[test_case(r#"f("\400");"# => r"invalid escape sequence '\4' at 1:3")]
+    fn invalid_string(src: &str) -> String {
+        scan(src).unwrap_err().to_string()
+    }
+
+    #[test_case("18446744073709551615", TokType::NumInt(u64::MAX))]
+    fn max_number(src: &str, tok: TokType) {
+        assert_eq!(scan(src).unwrap()[0].tok, tok);
+    }
+
+    #[test_case("int a = 18446744073709551616;" => "number is too large at 1:9")]
+    #[test_case("int a = 1e999;" => "number is too large at 1:9")]
+    #[test_case("int a = 1e+;" => "malformed number at 1:9")]
+    #[test_case("int a = 2.5ex;" => "malformed number at 1:9")]
+    fn invalid_number(src: &str) -> String {
+        scan(src).unwrap_err().to_string()
+    }
+
+    #[test_case("1.25", 1.25)]
+    #[test_case("0.1", 0.1)]
+    #[test_case("1.99999999999999999999", 2.0)]
+    #[test_case("1e9", 1e9)]
+    #[test_case("2.5e-3", 2.5e-3)]
+    #[test_case("1E+2", 100.0)]
+    #[test_case(".5", 0.5)]
+    #[test_case("3.", 3.0)]
+    #[test_case("0.1f", 0.1f32 as f64)]
+    #[test_case("1e3F", 1000.0)]
+    fn real_number(src: &str, v: f64) {
+        let toks = scan(src).unwrap();
+        assert_eq!(toks.len(), 1);
+        assert_eq!(toks[0].tok, TokType::NumReal(v));
+    }
+
+    #[test_case("1f", vec!["number '1'", "identifier 'f'"])]
+    #[test_case("12.5e3x", vec!["number '12500'", "identifier 'x'"])]
+    fn number_then_id(src: &str, toks: Vec<&str>) {
+        let v: Vec<_> = scan(src)
+            .unwrap()
+            .iter()
+            .map(|t| t.tok.to_string())
+            .collect();
+        assert_eq!(v, toks);
+    }
+
+    #[test]
+    fn lexer_continues_after_error() {
+        let items: Vec<_> = Lexer::new("a $ b").collect();
+        assert_eq!(items.len(), 3);
+        let err = items[1].as_ref().unwrap_err();
+        assert_eq!(err.kind, LexErrorKind::UnexpectedChar('$'));
+        assert_eq!(err.loc, (1, 3));
+        assert_eq!(err.span, Span::new(2, 3));
+        assert_eq!(
+            items[2].as_ref().unwrap().tok,
+            TokType::ID(Symbol::intern("b"))
+        );
+    }
+
+    #[test_case("int main", vec![(0, 3), (4, 8)])]
+    #[test_case("  é = 12.5;", vec![(5, 6), (7, 11), (11, 12)]; "multi-byte char")]
+    fn token_span(src: &str, spans: Vec<(usize, usize)>) {
+        let toks: Vec<_> = Lexer::new(src).filter_map(|t| t.ok()).collect();
+        let v: Vec<_> = toks.iter().map(|t| (t.span.start, t.span.end)).collect();
+        assert_eq!(v, spans);
+    }
+
+    #[test]
+    fn recover_unknown_tokens() {
+        let (toks, errors) = scan_recover("int a = 1 $ 2 # 99999999999999999999;");
+        let types: Vec<_> = toks.iter().map(|t| t.tok.to_string()).collect();
+        assert_eq!(
+            types,
+            vec![
+                "int",
+                "identifier 'a'",
+                "=",
+                "number '1'",
+                "invalid token",
+                "number '2'",
+                "invalid token",
+                "invalid token",
+                ";"
+            ]
+        );
+        let errors: Vec<_> = errors.iter().map(|e| e.to_string()).collect();
+        assert_eq!(
+            errors,
+            vec![
+                "unexpected char: $ at 1:11",
+                "unexpected char: # at 1:15",
+                "number is too large at 1:17"
+            ]
+        );
+    }
+
+    #[test_case(None => TokType::ID(Symbol::intern("__STDC_HOSTED__")))]
+    #[test_case(Some(true) => TokType::NumInt(1))]
+    #[test_case(Some(false) => TokType::NumInt(0))]
+    fn predefined_hosted(hosted: Option<bool>) -> TokType {
+        let lexer = Lexer::new("__STDC_HOSTED__");
+        let toks: Vec<_> = match hosted {
+            Some(h) => lexer.hosted(h).collect(),
+            None => lexer.collect(),
+        };
+        toks[0].as_ref().unwrap().tok.clone()
+    }
+
+    #[test_case("__ATOMIC_RELAXED" => TokType::NumInt(0))]
+    #[test_case("__ATOMIC_SEQ_CST" => TokType::NumInt(5))]
+    #[test_case("__ATOMIC_SEQ" => TokType::ID(Symbol::intern("__ATOMIC_SEQ")))]
+    fn predefined_memory_order(src: &str) -> TokType {
+        let toks: Vec<_> = Lexer::new(src).collect();
+        toks[0].as_ref().unwrap().tok.clone()
+    }
+
+    #[test_case("int\n1\nx", DEFAULT_TAB_WIDTH, vec![(1, 1), (2, 1), (3, 1)]; "put back newline")]
+    #[test_case("a\r\nb\rc\n\nd", DEFAULT_TAB_WIDTH, vec![(1, 1), (2, 1), (3, 1), (5, 1)]; "crlf")]
+    #[test_case("\ta\tbb\tc", DEFAULT_TAB_WIDTH, vec![(1, 9), (1, 17), (1, 25)]; "tabs")]
+    #[test_case("  \ta \tb", 4, vec![(1, 5), (1, 9)]; "tab width")]
+    #[test_case("é\tx", 4, vec![(1, 1), (1, 5)]; "multi-byte char")]
+    fn token_loc(src: &str, tab_width: u32, locs: Vec<(u32, u32)>) {
+        let toks: Vec<_> = Lexer::new(src).tab_width(tab_width).collect();
+        let v: Vec<_> = toks
+            .iter()
+            .map(|t| match t {
+                Ok(t) => t.loc,
+                Err(e) => e.loc,
+            })
+            .collect();
+        assert_eq!(v, locs);
+    }
+
+    #[test_case("a\r\n\tb", 4, 4 => (2, 5))]
+    #[test_case("é\tx", 3, 4 => (1, 5))]
+    #[test_case("ab", 10, 8 => (1, 3); "end of input")]
+    fn locate_offset(src: &str, offset: usize, tab_width: u32) -> (u32, u32) {
+        locate(src, offset, tab_width)
+    }
+
+    #[test_case("int /* a */ main // b\n()" => vec!["int", "identifier 'main'", "(", ")"])]
+    #[test_case("a/**/b/* * / **/c" => vec!["identifier 'a'", "identifier 'b'", "identifier 'c'"])]
+    #[test_case("a // b" => vec!["identifier 'a'"])]
+    fn skip_comments(src: &str) -> Vec<String> {
+        scan(src)
+            .unwrap()
+            .iter()
+            .map(|t| t.tok.to_string())
+            .collect()
+    }
+
+    #[test_case("int a; /* b" => "unterminated comment at 1:8")]
+    #[test_case("a / b" => "unexpected char: / at 1:3")]
+    fn invalid_comment(src: &str) -> String {
+        scan(src).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn keep_trivia() {
+        let src = "int /* a */ main\n// b\n() ";
+        let mut lexer = Lexer::new(src).keep_trivia(true);
+        let toks: Vec<_> = lexer.by_ref().map(|t| t.unwrap()).collect();
+        let trivia = |i: usize| -> Vec<(TriviaKind, &str)> {
+            toks[i]
+                .leading
+                .iter()
+                .map(|t| (t.kind, &src[t.span.start..t.span.end]))
+                .collect()
+        };
+        assert!(trivia(0).is_empty());
+        assert_eq!(
+            trivia(1),
+            vec![
+                (TriviaKind::Whitespace, " "),
+                (TriviaKind::BlockComment, "/* a */"),
+                (TriviaKind::Whitespace, " ")
+            ]
+        );
+        assert_eq!(
+            trivia(2),
+            vec![
+                (TriviaKind::Whitespace, "\n"),
+                (TriviaKind::LineComment, "// b"),
+                (TriviaKind::Whitespace, "\n")
+            ]
+        );
+        assert_eq!(lexer.trailing_trivia().len(), 1);
+        assert!(Lexer::new(src).all(|t| t.unwrap().leading.is_empty()));
+    }
+
+    #[test]
+    fn tokens_json() {
+        let src = "int x = 1.5e2;";
+        let json = serde_json::to_value(token_infos(src, &scan(src).unwrap())).unwrap();
+        assert_eq!(json.as_array().unwrap().len(), 5);
+        assert_eq!(
+            json[1],
+            serde_json::json!({
+                "kind": "identifier",
+                "text": "x",
+                "span": { "start": 4, "end": 5 },
+                "line": 1,
+                "col": 5,
+            })
+        );
+        assert_eq!(json[3]["kind"], "num_real");
+        assert_eq!(json[3]["text"], "1.5e2");
+    }
 }
@@ -3,49 +3,1072 @@
 //! Decorate abstract syntax tree with type information
 
 use crate::{
-    ast::{Ast, CmpStmt, ExtDecl, Stmt},
-    symtable::SymTable,
+    ast::{
+        walk_cmp_stmt, walk_expr, walk_static_assert, walk_stmt, ArithOp, Ast, Attribute, CmpStmt,
+        DataType, Expr, ExprKind, ExtDecl, FuncDecl, GlobalVarDecl, NodeId, StaticAssert, Stmt,
+        StmtKind, Type, UnaryOp, VarDecl, Visitor,
+    },
+    atomics::{Atomic, MemoryOrder},
+    attrs::{alias_target, check_attrs, has_attr, Subject},
+    builtins::Builtin,
+    callgraph::CallGraph,
+    common::{escape, CompileError, FixIt, Span},
+    consteval::{eval_int, is_constant},
+    init::flatten,
+    intern::Symbol,
+    sidetable::SideTable,
+    symtable::{Decl, DeclCreation, DeclKind, SymTable},
+    util::{TargetOs, Warning},
 };
 
-pub fn analyse(ast: &mut Ast) {
-    // create symbol table
-    let mut table = SymTable::new();
+/// results of semantics analysis
+#[derive(Debug, Default)]
+pub struct Decorations {
+    /// type of expressions, arrays and functions decayed to pointers
+    pub types: SideTable<Type>,
+    /// declaration referred by variable references, function calls and assignments
+    pub symbols: SideTable<NodeId>,
+    /// declarations by node id
+    pub table: SymTable,
+    /// warnings in source order, reported by the driver if enabled
+    pub warnings: Vec<(Warning, CompileError)>,
+    /// plain `char` of the analysed target is signed
+    pub char_signed: bool,
+}
+
+/// analyse for the host like the driver without '--target'
+pub fn analyse(ast: &Ast) -> Result<Decorations, CompileError> {
+    analyse_with(ast, TargetOs::current())
+}
+
+/// analyse for the target, the range of plain `char` depends on it
+pub fn analyse_with(ast: &Ast, target: TargetOs) -> Result<Decorations, CompileError> {
+    let mut analyser = Analyser {
+        decor: Decorations {
+            char_signed: target.char_signed(),
+            ..Decorations::default()
+        },
+        error: None,
+        ret: None,
+    };
+
+    // travel through the ast in the global scope
+    analyser.decor.table.push_scope(None);
+    analyser.visit_ast(ast);
+    analyser.decor.table.pop_scope();
+    analyser.check_linkage_attrs(ast);
+    analyser.check_unused_functions(ast);
+
+    match analyser.error {
+        Some(e) => Err(e),
+        None => Ok(analyser.decor),
+    }
+}
+
+/// name, attributes, location and internal linkage of a file scope declaration
+fn ext_attrs(ext: &ExtDecl) -> Option<(Symbol, &[Attribute], Span, bool)> {
+    match ext {
+        ExtDecl::Func(f) => Some((f.name, &f.attrs, f.span, f.internal)),
+        ExtDecl::Global(g) => Some((g.name, &g.attrs, g.span, g.internal)),
+        ExtDecl::StaticAssert(_) => None,
+    }
+}
+
+/// build the symbol table while visiting the AST, keep the first error
+struct Analyser {
+    decor: Decorations,
+    error: Option<CompileError>,
+    /// return type of the function being analysed
+    ret: Option<Type>,
+}
+
+impl Analyser {
+    fn insert_decl<T: DeclCreation>(&mut self, decl: &T) {
+        if self.error.is_none() {
+            self.error = self.decor.table.insert_decl(decl).err();
+        }
+    }
+
+    /// resolve the name to its declaration
+    fn resolve(&mut self, id: NodeId, name: Symbol) -> Option<&Decl> {
+        let decl = self.decor.table.lookup(name)?;
+        self.decor.symbols.insert(id, decl.id);
+        Some(decl)
+    }
+
+    /// `main` returns int and takes no parameters or `(int argc, char **argv)`
+    fn check_main(&mut self, func: &FuncDecl) {
+        if func.name != "main" || self.error.is_some() {
+            return;
+        }
+        let int = Type::Base(DataType::Int);
+        let argv = Type::Pointer(Box::new(Type::Pointer(Box::new(Type::Base(
+            DataType::Char,
+        )))));
+        let params: Vec<Type> = func.params.iter().map(|p| p.data_type.decay()).collect();
+        let msg = if func.return_type != int {
+            "return type of 'main' is not 'int'"
+        } else if !(params.is_empty() || params == [int, argv]) {
+            "parameters of 'main' are not (void) or (int, char **)"
+        } else {
+            return;
+        };
+        self.error = Some(CompileError::at_span(msg, func.span));
+    }
+
+    /// the initializer fits the declared type, globals are initialized by constant expressions
+    /// folded into the data section
+    fn check_init(
+        &mut self,
+        name: Symbol,
+        ty: &Type,
+        init: &Option<Expr>,
+        span: Span,
+        global: bool,
+    ) {
+        let init = match init {
+            Some(e) if self.error.is_none() => e,
+            _ => return,
+        };
+        let res = match ty {
+            Type::Function(_, _) => Err(CompileError::at_span(
+                format!("function '{}' is initialized like a variable", name),
+                span,
+            )),
+            _ => flatten(ty, init).and_then(|flat| {
+                flat.values.values().for_each(|v| self.check_void(v));
+                if let Some(e) = self.error.take() {
+                    return Err(e);
+                }
+                if global {
+                    flat.values.values().try_for_each(|v| check_constant(v))?;
+                }
+                flat.values
+                    .values()
+                    .for_each(|v| self.check_conversion(v, &flat.scalar));
+                Ok(())
+            }),
+        };
+        self.error = res.err();
+    }
+
+    /// the value converted implicitly to a narrower integer type keeps its value, the range of
+    /// the value is inferred from the constants and the types of the operands
+    fn check_conversion(&mut self, expr: &Expr, to: &Type) {
+        let to = match to.unqualified().as_base() {
+            Some(dt) => dt,
+            None => return,
+        };
+        let signed = self.decor.char_signed;
+        let (to_min, to_max) = match int_range(to, signed) {
+            Some(r) => r,
+            None => return,
+        };
+        let msg = match (is_constant(expr), self.value_range(expr)) {
+            (true, Some((v, _, from))) if v < to_min || v > to_max => format!(
+                "conversion from '{}' to '{}' changes value from '{}' to '{}'",
+                Type::Base(from),
+                Type::Base(to),
+                v,
+                wrap_int(v, to, signed)
+            ),
+            (false, Some((lo, hi, from)))
+                if rank(from) > rank(to) && (lo < to_min || hi > to_max) =>
+            {
+                let msg = format!(
+                    "conversion from '{}' to '{}' may change value",
+                    Type::Base(from),
+                    Type::Base(to)
+                );
+                match int_range(from, signed) == Some((lo, hi)) {
+                    true => msg,
+                    false => format!("{}, the value is in [{}, {}]", msg, lo, hi),
+                }
+            }
+            _ => return,
+        };
+        let e = CompileError::at_span(msg, expr.span);
+        self.decor.warnings.push((Warning::Conversion, e));
+    }
+
+    /// smallest and largest value of the integer expression and its type after the promotions,
+    /// none for pointers or unknown types
+    fn value_range(&self, expr: &Expr) -> Option<(i64, i64, DataType)> {
+        if is_constant(expr) {
+            let c = eval_int(expr).ok()?;
+            return Some((c.value, c.value, c.ty));
+        }
+        let promote = |dt: DataType| match rank(dt) < rank(DataType::Int) {
+            true => DataType::Int,
+            false => dt,
+        };
+        let (lo, hi, ty) = match &expr.kind {
+            ExprKind::Arith(l, op, r) => {
+                let (l, r) = (self.value_range(l)?, self.value_range(r)?);
+                let ty = promote(match rank(l.2) < rank(r.2) {
+                    true => r.2,
+                    false => l.2,
+                });
+                let (lo, hi) = match op {
+                    ArithOp::Add => (l.0.checked_add(r.0)?, l.1.checked_add(r.1)?),
+                    ArithOp::Sub => (l.0.checked_sub(r.1)?, l.1.checked_sub(r.0)?),
+                };
+                (lo, hi, ty)
+            }
+            // the value of the first argument converted to long
+            ExprKind::FunctionCall(name, args)
+                if Builtin::of(name.as_str()) == Some(Builtin::Expect) =>
+            {
+                let (lo, hi, _) = self.value_range(args.first()?)?;
+                (lo, hi, DataType::Long)
+            }
+            ExprKind::Unary(op, e) => {
+                let (lo, hi, ty) = self.value_range(e)?;
+                match op {
+                    UnaryOp::Neg => (hi.checked_neg()?, lo.checked_neg()?, promote(ty)),
+                    UnaryOp::Plus => (lo, hi, promote(ty)),
+                }
+            }
+            _ => {
+                let ty = self.unqualified_type(expr)?.as_base()?;
+                let (lo, hi) = int_range(ty, self.decor.char_signed)?;
+                (lo, hi, ty)
+            }
+        };
+        // the result wraps if it does not fit the type
+        let (min, max) = int_range(ty, self.decor.char_signed)?;
+        match lo < min || hi > max {
+            true => Some((min, max, ty)),
+            false => Some((lo, hi, ty)),
+        }
+    }
+
+    /// the condition is an integer constant expression and not zero
+    fn check_static_assert(&mut self, sa: &StaticAssert) {
+        if self.error.is_some() {
+            return;
+        }
+        let res = match is_constant(&sa.cond) {
+            true => eval_int(&sa.cond),
+            false => Err(CompileError::at_span(
+                "expression in static assertion is not constant",
+                sa.cond.span,
+            )),
+        };
+        self.error = match res {
+            Ok(c) if c.value != 0 => None,
+            Ok(_) => {
+                let msg = match &sa.message {
+                    Some(m) => format!("static assertion failed: \"{}\"", escape(m)),
+                    None => String::from("static assertion failed"),
+                };
+                Some(CompileError::at_span(msg, sa.span))
+            }
+            Err(e) => Some(e),
+        };
+    }
+
+    /// the value of an expression statement is discarded, only calls and reads of volatile
+    /// objects have side effects
+    fn check_unused_value(&mut self, expr: &Expr) {
+        let msg = match &expr.kind {
+            ExprKind::FunctionCall(_, _) => return,
+            ExprKind::VarRef(name) if self.is_volatile(*name) => return,
+            _ if self.has_side_effect(expr) => "value computed is not used",
+            _ => "statement with no effect",
+        };
+        self.decor
+            .warnings
+            .push((Warning::UnusedValue, CompileError::at_span(msg, expr.span)));
+    }
+
+    /// the value of a call to a void function is only discarded, the declaration of the
+    /// function is noted
+    fn check_void(&mut self, expr: &Expr) {
+        if self.error.is_some() || self.unqualified_type(expr) != Some(Type::Base(DataType::Void)) {
+            return;
+        }
+        let mut e = CompileError::at_span("void value not ignored as it ought to be", expr.span);
+        if let ExprKind::FunctionCall(name, _) = &expr.kind {
+            let decl = self.decor.symbols.get(expr.id);
+            if let Some(d) = decl.and_then(|d| self.decor.table.decl(*d)) {
+                e = e.with_note(format!("'{}' declared here", name), d.span);
+            }
+        }
+        self.error = Some(e);
+    }
 
-    // enter new scope
-    table.push_scope();
+    /// objects with storage have a complete type, void is only the type of function results
+    fn check_void_object(&mut self, name: Symbol, ty: &Type, span: Span) {
+        if self.error.is_none() && *ty.unqualified() == Type::Base(DataType::Void) {
+            let msg = format!("variable '{}' declared void", name);
+            self.error = Some(CompileError::at_span(msg, span));
+        }
+    }
+
+    /// the local variable or parameter hides a variable of an enclosing scope, functions are
+    /// not reported
+    fn check_shadow(&mut self, name: Symbol, span: Span) {
+        let outer = match self.decor.table.lookup_outer(name).map(|d| &d.kind) {
+            Some(DeclKind::Var(_)) => "a previous local",
+            Some(DeclKind::Param(_)) => "a parameter",
+            Some(DeclKind::GlobalVar(_) | DeclKind::Tentative(_) | DeclKind::Extern(_)) => {
+                "a global declaration"
+            }
+            Some(DeclKind::Func(_)) | None => return,
+        };
+        let msg = format!("declaration of '{}' shadows {}", name, outer);
+        let e = CompileError::at_span(msg, span);
+        self.decor.warnings.push((Warning::Shadow, e));
+    }
+
+    /// `static` functions only called by themselves, the warnings are kept in source order
+    fn check_unused_functions(&mut self, ast: &Ast) {
+        let calls = CallGraph::build(ast);
+        let aliased: Vec<Symbol> = ast
+            .0
+            .iter()
+            .filter_map(ext_attrs)
+            .filter_map(|(_, attrs, _, _)| alias_target(attrs))
+            .collect();
+        for f in calls.unused_static().filter(|f| !aliased.contains(&f.name)) {
+            let msg = format!("'{}' defined but not used", f.name);
+            let e = CompileError::at_span(msg, f.span);
+            self.decor.warnings.push((Warning::UnusedFunction, e));
+        }
+        self.decor
+            .warnings
+            .sort_by_key(|(_, e)| e.span.map(|s| s.start));
+    }
 
-    // travel through the ast
-    for ext_decl in &ast.0 {
-        match ext_decl {
-            ExtDecl::Func(decl) => {
-                table.cur_scope().insert_decl(&decl.name, decl);
-                analyse_cmp_stmt(&mut table, &decl.cmp_stmt);
+    /// weak symbols are external, aliases are not defined themselves and name a symbol defined
+    /// in the translation unit
+    fn check_linkage_attrs(&mut self, ast: &Ast) {
+        for (name, attrs, span, internal) in ast.0.iter().filter_map(ext_attrs) {
+            if self.error.is_some() {
+                return;
+            }
+            if internal && has_attr(attrs, "weak") {
+                let msg = format!("weak declaration of '{}' must be public", name);
+                self.error = Some(CompileError::at_span(msg, span));
+                continue;
             }
-            ExtDecl::Global(decl) => table.cur_scope().insert_decl(&(decl.1), decl),
+            let target = match alias_target(attrs) {
+                Some(t) => t,
+                None => continue,
+            };
+            let defines = |name: Symbol| {
+                ast.0.iter().any(|ext| match ext {
+                    ExtDecl::Func(f) => f.name == name,
+                    ExtDecl::Global(g) => {
+                        g.name == name && g.has_storage() && alias_target(&g.attrs).is_none()
+                    }
+                    ExtDecl::StaticAssert(_) => false,
+                })
+            };
+            let defined = ast.0.iter().any(|ext| match ext {
+                ExtDecl::Func(f) => f.name == name,
+                ExtDecl::Global(g) => g.name == name && g.init.is_some(),
+                ExtDecl::StaticAssert(_) => false,
+            });
+            let msg = if defined {
+                format!("'{}' defined both normally and as 'alias' attribute", name)
+            } else if !defines(target) {
+                format!("'{}' aliased to undefined symbol '{}'", name, target)
+            } else {
+                continue;
+            };
+            self.error = Some(CompileError::at_span(msg, span));
         }
     }
 
-    // pop scope
-    table.pop_scope();
+    /// old-style definition and the declarations defaulting to int, accepted in K&R mode
+    fn check_knr(&mut self, func: &FuncDecl) {
+        if func.implicit_int {
+            let bare = !func.external && !func.internal && func.attrs.is_empty();
+            self.warn_implicit_int(func.name, func.span, bare);
+        }
+        if !func.prototype && !func.params.is_empty() {
+            let e = CompileError::at_span("old-style function definition", func.span);
+            self.decor.warnings.push((Warning::OldStyleDefinition, e));
+        }
+        for p in func.params.iter().filter(|p| p.implicit_int) {
+            let msg = format!("type of '{}' defaults to 'int'", p.name);
+            let e = CompileError::at_span(msg, p.span);
+            self.decor.warnings.push((Warning::ImplicitInt, e));
+        }
+    }
+
+    /// arguments of the known attributes, the other attributes are ignored with a warning
+    fn check_attrs(&mut self, attrs: &[Attribute], subject: Subject) {
+        match check_attrs(attrs, subject) {
+            Ok(ignored) => ignored
+                .into_iter()
+                .for_each(|e| self.decor.warnings.push((Warning::Attributes, e))),
+            Err(e) if self.error.is_none() => self.error = Some(e),
+            Err(_) => {}
+        }
+    }
+
+    /// `int` is inserted before a declaration starting with the declarator
+    fn warn_implicit_int(&mut self, name: Symbol, span: Span, bare: bool) {
+        let msg = format!("type defaults to 'int' in declaration of '{}'", name);
+        let e = CompileError::at_span(msg, span);
+        let e = match bare {
+            true => e.with_fixit(FixIt::insert(span.start, "int ")),
+            false => e,
+        };
+        self.decor.warnings.push((Warning::ImplicitInt, e));
+    }
+
+    fn is_volatile(&self, name: Symbol) -> bool {
+        self.decor
+            .table
+            .lookup(name)
+            .is_some_and(|d| d.ty().is_volatile())
+    }
+
+    /// the expression calls a function or reads a volatile object
+    fn has_side_effect(&self, expr: &Expr) -> bool {
+        match &expr.kind {
+            ExprKind::IntConst(_) | ExprKind::StrLit(_) => false,
+            ExprKind::VarRef(name) => self.is_volatile(*name),
+            ExprKind::InitList(items) => items.iter().any(|i| self.has_side_effect(&i.value)),
+            ExprKind::FunctionCall(_, _) => true,
+            ExprKind::Arith(l, _, r) => self.has_side_effect(l) || self.has_side_effect(r),
+            ExprKind::Unary(_, e) => self.has_side_effect(e),
+        }
+    }
+
+    /// type of the value of the checked operand
+    fn unqualified_type(&self, expr: &Expr) -> Option<Type> {
+        self.decor
+            .types
+            .get(expr.id)
+            .map(|t| t.unqualified().clone())
+    }
+
+    /// the arguments match the parameters of the function signature
+    fn check_call(&mut self, expr: &Expr, name: Symbol, args: &[Expr]) {
+        let sig = match self.decor.table.lookup(name).map(|d| &d.kind) {
+            Some(DeclKind::Func(f)) => f.sig.clone(),
+            _ => return,
+        };
+        args.iter().for_each(|a| self.check_void(a));
+        if let Some(params) = &sig.param_types {
+            args.iter()
+                .zip(params)
+                .for_each(|(a, p)| self.check_conversion(a, p));
+        }
+        let expected = sig.param_types.as_ref().map_or(0, |p| p.len());
+        if !sig.accepts(args.len()) {
+            self.check_arg_count(expr, name, args.len(), expected);
+        }
+    }
+
+    fn check_arg_count(&mut self, expr: &Expr, name: Symbol, args: usize, expected: usize) {
+        if self.error.is_none() && args != expected {
+            let amount = match args < expected {
+                true => "few",
+                false => "many",
+            };
+            self.error = Some(CompileError::at_span(
+                format!(
+                    "too {} arguments to function {}, expected {} but {}",
+                    amount, name, expected, args
+                ),
+                expr.span,
+            ));
+        }
+    }
+
+    /// arguments of a builtin, the type of the call is given by the builtin instead of a
+    /// declaration
+    fn check_builtin(
+        &mut self,
+        expr: &Expr,
+        name: Symbol,
+        builtin: Builtin,
+        args: &[Expr],
+    ) -> Option<Type> {
+        if let Builtin::Atomic(atomic) = builtin {
+            return self.check_atomic(expr, name, atomic, args);
+        }
+        args.iter().for_each(|a| self.check_void(a));
+        self.check_arg_count(expr, name, args.len(), builtin.arity());
+        match builtin {
+            // the operands are converted to long
+            Builtin::Expect => Some(Type::Base(DataType::Long)),
+            _ => Some(Type::Base(DataType::Void)),
+        }
+    }
+
+    /// the object of an atomic builtin is an integer or a pointer and the memory order is a
+    /// constant valid for the operation, the call has the type of the object
+    fn check_atomic(
+        &mut self,
+        expr: &Expr,
+        name: Symbol,
+        atomic: Atomic,
+        args: &[Expr],
+    ) -> Option<Type> {
+        args.iter().for_each(|a| self.check_void(a));
+        self.check_arg_count(expr, name, args.len(), atomic.arity());
+        if self.error.is_some() {
+            return None;
+        }
+        let pointer = args.first().filter(|_| atomic.operands() > 0);
+        let object = match pointer.map(|p| (p, self.unqualified_type(p))) {
+            Some((_, Some(Type::Pointer(t)))) if is_atomic_object(t.unqualified()) => {
+                Some(t.unqualified().clone())
+            }
+            Some((p, _)) => {
+                let msg = format!("operand of '{}' is not a pointer to an integer", name);
+                self.error = Some(CompileError::at_span(msg, p.span));
+                return None;
+            }
+            None => None,
+        };
+        if let Some(arg) = args.last().filter(|_| atomic.order.is_none()) {
+            let order = match is_constant(arg) {
+                true => eval_int(arg)
+                    .ok()
+                    .and_then(|v| MemoryOrder::from_value(v.value)),
+                false => None,
+            };
+            let msg = match order {
+                Some(o) if atomic.valid_order(o) => None,
+                Some(_) => Some(format!("invalid memory model for '{}'", name)),
+                None => Some(format!(
+                    "invalid memory model argument {} of '{}'",
+                    args.len(),
+                    name
+                )),
+            };
+            if let Some(msg) = msg {
+                self.error = Some(CompileError::at_span(msg, arg.span));
+                return None;
+            }
+        }
+        match atomic.has_value() {
+            true => object,
+            false => Some(Type::Base(DataType::Void)),
+        }
+    }
 }
 
-pub fn analyse_cmp_stmt<'a>(table: &mut SymTable<'a>, cmp_stmt: &'a CmpStmt) {
-    // enter new scope
-    table.push_scope();
+impl<'a> Visitor<'a> for Analyser {
+    fn visit_func_decl(&mut self, func: &'a FuncDecl) {
+        self.insert_decl(func);
+        self.check_main(func);
+        self.check_knr(func);
+        self.check_attrs(&func.attrs, Subject::Function);
 
-    for stmt in &cmp_stmt.stmts {
-        match stmt {
-            Stmt::Compound(st) => analyse_cmp_stmt(table, st),
-            Stmt::VarDecl(decl) => table.cur_scope().insert_decl(&decl.1, decl),
-            Stmt::Assignment(_, _) => {}
-            Stmt::Return(_) => {}
-            Stmt::Expr(_) => {}
+        // parameters share the scope with the outermost block of the body
+        self.decor.table.push_scope(Some(func.id));
+        for p in &func.params {
+            self.check_shadow(p.name, p.span);
+            self.insert_decl(p);
         }
+        self.ret = Some(func.return_type.clone());
+        walk_cmp_stmt(self, &func.cmp_stmt);
+        self.ret = None;
+        self.decor.table.pop_scope();
     }
 
-    // pop scope
-    table.pop_scope();
+    fn visit_global_var_decl(&mut self, decl: &'a GlobalVarDecl) {
+        self.insert_decl(decl);
+        let (name, span) = (decl.name, decl.span);
+        if decl.implicit_int {
+            let bare = !decl.external && !decl.internal && decl.attrs.is_empty();
+            self.warn_implicit_int(name, span, bare);
+        }
+        let subject = match decl.data_type {
+            Type::Function(_, _) => Subject::Function,
+            _ => Subject::Global,
+        };
+        self.check_attrs(&decl.attrs, subject);
+        if !decl.external {
+            self.check_void_object(name, &decl.data_type, span);
+        }
+        self.check_init(name, &decl.data_type, &decl.init, span, true);
+    }
+
+    fn visit_cmp_stmt(&mut self, cmp_stmt: &'a CmpStmt) {
+        // enter new scope
+        self.decor.table.push_scope(Some(cmp_stmt.id));
+        walk_cmp_stmt(self, cmp_stmt);
+        self.decor.table.pop_scope();
+    }
+
+    fn visit_stmt(&mut self, stmt: &'a Stmt) {
+        match &stmt.kind {
+            StmtKind::Expr(e) => self.check_unused_value(e),
+            // the target is resolved by the statement id
+            StmtKind::Assignment(name, _) => {
+                self.resolve(stmt.id, *name);
+            }
+            _ => {}
+        }
+        walk_stmt(self, stmt);
+        match &stmt.kind {
+            // a void function may return the value of a void call
+            StmtKind::Return(Some(e)) => {
+                if let Some(ret) = self.ret.clone() {
+                    if *ret.unqualified() != Type::Base(DataType::Void) {
+                        self.check_void(e);
+                    }
+                    self.check_conversion(e, &ret);
+                }
+            }
+            StmtKind::Assignment(name, e) => {
+                self.check_void(e);
+                if let Some(ty) = self.decor.table.lookup(*name).map(|d| d.ty()) {
+                    self.check_conversion(e, &ty);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn visit_var_decl(&mut self, decl: &'a VarDecl) {
+        // the variable is in scope of its own initializer
+        self.check_shadow(decl.name, decl.span);
+        self.insert_decl(decl);
+        self.check_attrs(&decl.attrs, Subject::Local);
+        self.check_void_object(decl.name, &decl.data_type, decl.span);
+        if let Some(e) = &decl.init {
+            self.visit_expr(e);
+        }
+        self.check_init(decl.name, &decl.data_type, &decl.init, decl.span, false);
+    }
+
+    fn visit_static_assert(&mut self, sa: &'a StaticAssert) {
+        walk_static_assert(self, sa);
+        self.check_static_assert(sa);
+    }
+
+    fn visit_expr(&mut self, expr: &'a Expr) {
+        walk_expr(self, expr);
+        let typ = match &expr.kind {
+            ExprKind::IntConst(_) => Some(Type::Base(DataType::Int)),
+            // array of char including the null, decayed to `char *`
+            ExprKind::StrLit(s) => {
+                let len = s.len() as u64 + 1;
+                Some(Type::Array(Box::new(Type::Base(DataType::Char)), Some(len)).decay())
+            }
+            ExprKind::VarRef(name) => self.resolve(expr.id, *name).map(|d| d.ty().decay()),
+            ExprKind::FunctionCall(name, args) if Builtin::of(name.as_str()).is_some() => {
+                let builtin = Builtin::of(name.as_str()).expect("builtin");
+                self.check_builtin(expr, *name, builtin, args)
+            }
+            // implicit declaration returns int
+            ExprKind::FunctionCall(name, args) => {
+                self.check_call(expr, *name, args);
+                let ret = self.resolve(expr.id, *name).map(|d| d.data_type());
+                Some(ret.unwrap_or(Type::Base(DataType::Int)))
+            }
+            // the value of an operand is not qualified
+            ExprKind::Arith(left, _, right) => {
+                self.check_void(left);
+                self.check_void(right);
+                self.unqualified_type(left)
+            }
+            ExprKind::Unary(_, e) => {
+                self.check_void(e);
+                self.unqualified_type(e)
+            }
+            ExprKind::InitList(_) => None,
+        };
+        if let Some(t) = typ {
+            self.decor.types.insert(expr.id, t);
+        }
+    }
+}
+
+/// integers and pointers are accessed atomically
+fn is_atomic_object(ty: &Type) -> bool {
+    matches!(
+        ty,
+        Type::Base(DataType::Char | DataType::Short | DataType::Int | DataType::Long)
+            | Type::Pointer(_)
+    )
+}
+
+/// range of the integer type, plain `char` is unsigned unless `char_signed`, none for the other
+/// types
+fn int_range(dt: DataType, char_signed: bool) -> Option<(i64, i64)> {
+    match dt {
+        DataType::Char if !char_signed => Some((u8::MIN.into(), u8::MAX.into())),
+        DataType::Char => Some((i8::MIN.into(), i8::MAX.into())),
+        DataType::Short => Some((i16::MIN.into(), i16::MAX.into())),
+        DataType::Int => Some((i32::MIN.into(), i32::MAX.into())),
+        DataType::Long => Some((i64::MIN, i64::MAX)),
+        _ => None,
+    }
+}
+
+/// conversion rank of the integer types
+fn rank(dt: DataType) -> u8 {
+    match dt {
+        DataType::Char => 1,
+        DataType::Short => 2,
+        DataType::Int => 3,
+        DataType::Long => 4,
+        _ => 0,
+    }
+}
+
+/// value converted to the narrower integer type
+fn wrap_int(value: i64, dt: DataType, char_signed: bool) -> i64 {
+    match dt {
+        DataType::Char if !char_signed => value as u8 as i64,
+        DataType::Char => value as i8 as i64,
+        DataType::Short => value as i16 as i64,
+        DataType::Int => value as i32 as i64,
+        _ => value,
+    }
+}
+
+/// value of a static initializer
+fn check_constant(expr: &Expr) -> Result<(), CompileError> {
+    match is_constant(expr) {
+        true => eval_int(expr).map(|_| ()),
+        false => Err(CompileError::at_span(
+            "initializer element is not constant",
+            expr.span,
+        )),
+    }
+}
+
+/// the translation unit defines `main`
+pub fn defines_main(ast: &Ast) -> bool {
+    ast.0
+        .iter()
+        .any(|ext| matches!(ext, ExtDecl::Func(f) if f.name == "main"))
 }
 
 #[cfg(test)]
-mod test {}
+mod test {
+    use test_case::test_case;
+
+    use crate::{
+        ast::{DataType, ExtDecl, NodeId, StmtKind, Type},
+        common::{FixIt, Span},
+        parse, parse_with, scan,
+        symtable::DeclKind,
+        util::TargetOs,
+        Standard, Warning,
+    };
+
+    use super::{analyse, analyse_with};
+
+    #[test_case("int g = 1; int main() { int g = 2; { int g = 3; } return g; }")]
+    #[test_case("int f(int); int f(int a) {} int f(int); int main() { return f(1); }")]
+    #[test_case("int f(); int main() { return f(1, 2); }")]
+    #[test_case("int main() { int f(int); return f(1); }")]
+    #[test_case("int main(void) { return 0; }")]
+    #[test_case("void bar(void) {} void f(void) { bar(); return bar(); }"; "discarded void values")]
+    #[test_case("extern void g; void *p;"; "void declarations")]
+    #[test_case("void f() {} int g; int *p;")]
+    #[test_case("int g = -(1 + 2); int *p = 0;")]
+    #[test_case(
+        "int a[] = {1, [3] = 2}; int main() { int b[2][2] = {1, [1] = {2}}; int c = {3}; }"
+    )]
+    #[test_case("int f(); int main() { int a[2] = {f(), [1] = 2}; }")]
+    #[test_case("_Static_assert(1 + 1, \"ok\"); int main() { _Static_assert(-1); }")]
+    #[test_case("int f(volatile int a); int f(int a) { return 0; }"; "qualified parameter")]
+    #[test_case("extern int g; int main() { return g; } int g = 1; extern int g;")]
+    #[test_case("extern int a[]; extern int a[2]; int a[2];"; "completed array")]
+    fn pass_program(src: &str) {
+        analyse(&parse(scan(src).unwrap()).unwrap()).unwrap();
+    }
+
+    #[test_case("int main() { int a = 1; int a = 2; }" => "a is already define as local variable")]
+    #[test_case("int g = 1; int g = 2;" => "g is already define as global variable")]
+    #[test_case("int g; char g;" => "conflicting types for g"; "tentative definitions")]
+    #[test_case("int f(int x) { int x; }" => "x is already define as function parameter")]
+    #[test_case("int f() {} int f() {}" => "redefinition of 'f'")]
+    #[test_case("int f(void) { return 0; } int f(void); int f(void) { return 1; }" => "redefinition of 'f'"; "prototype between")]
+    #[test_case("int f; int f() {}" => "f is already define as global variable"; "variable then function")]
+    #[test_case("int f() {} int f;" => "f is already define as function")]
+    #[test_case("int f(int); int f(void) {}" => "conflicting types for f")]
+    #[test_case("int f(int); void f();" => "conflicting types for f")]
+    #[test_case("int f; int f();" => "f is already define as global variable")]
+    #[test_case("extern int g; extern char g;" => "conflicting types for g")]
+    #[test_case("int g[2]; extern int g[3];" => "conflicting types for g")]
+    #[test_case("extern int g; int g = 1; int g; int g = 2;" => "g is already define as global variable")]
+    #[test_case("void main() {}" => "return type of 'main' is not 'int'")]
+    #[test_case("int main(int argc) {}" => "parameters of 'main' are not (void) or (int, char **)")]
+    #[test_case("int main(int a, int *b[]) {}" => "parameters of 'main' are not (void) or (int, char **)")]
+    #[test_case("int f(int a, int b); int main() { f(1); }" => "too few arguments to function f, expected 2 but 1")]
+    #[test_case("int f(void) {} int main() { f(1); }" => "too many arguments to function f, expected 0 but 1")]
+    #[test_case("void bar(void); int main() { int a = bar(); }" => "void value not ignored as it ought to be")]
+    #[test_case("void g;" => "variable 'g' declared void")]
+    #[test_case("int main() { volatile void v; }" => "variable 'v' declared void")]
+    #[test_case("void bar(void); int main() { int a[2] = {1, bar()}; }" => "void value not ignored as it ought to be"; "init list")]
+    #[test_case("void bar(void); int main() { return bar(); }" => "void value not ignored as it ought to be"; "returned")]
+    #[test_case("void bar(void); int main() { return 1 + bar(); }" => "void value not ignored as it ought to be"; "operand")]
+    #[test_case("void bar(void); int main() { return -bar(); }" => "void value not ignored as it ought to be"; "unary operand")]
+    #[test_case("void bar(void); int f(int); int main() { return f(bar()); }" => "void value not ignored as it ought to be"; "argument")]
+    #[test_case("int g; int h = g;" => "initializer element is not constant")]
+    #[test_case("int f(); int g = f() + 1;" => "initializer element is not constant")]
+    #[test_case("int g = 2147483647 + 1;" => "integer overflow in constant expression")]
+    #[test_case("int a[2] = 1;" => "invalid initializer")]
+    #[test_case("int a[2] = {1, 2, 3};" => "excess elements in array initializer")]
+    #[test_case("int main() { int a[2] = {[-1] = 0}; }" => "array index in initializer exceeds array bounds")]
+    #[test_case("int main() { int n; int a[2] = {[n] = 1}; }" => "nonconstant array index in initializer")]
+    #[test_case("int a[2] = {.x = 1};" => "field name not in record or union initializer")]
+    #[test_case("int g; int a[2] = {1, g};" => "initializer element is not constant")]
+    #[test_case("int n = {1, 2};" => "excess elements in scalar initializer")]
+    #[test_case("_Static_assert(1 - 1, \"zero\");" => "static assertion failed: \"zero\"")]
+    #[test_case("int main() { _Static_assert(0); }" => "static assertion failed")]
+    #[test_case("int g; _Static_assert(g, \"g\");" => "expression in static assertion is not constant")]
+    #[test_case("_Static_assert(2147483647 + 1, \"\");" => "integer overflow in constant expression")]
+    fn failed_program(src: &str) -> String {
+        analyse(&parse(scan(src).unwrap()).unwrap())
+            .unwrap_err()
+            .to_string()
+    }
+
+    #[test_case("int g; char g;" => vec![("previous declaration of 'g' is here".to_string(), Span::new(0, 6))])]
+    #[test_case("int f(int); void f();" => vec![("previous declaration of 'f' is here".to_string(), Span::new(0, 11))])]
+    #[test_case("int f(void) {}\nint f(void) {}" => vec![("previous definition of 'f' is here".to_string(), Span::new(0, 14))])]
+    #[test_case("int f(void) {} int f(int);" => vec![("previous definition of 'f' is here".to_string(), Span::new(0, 14))]; "conflicting prototype")]
+    #[test_case("int f(int); int f(void) {}" => vec![("previous declaration of 'f' is here".to_string(), Span::new(0, 11))]; "conflicting definition")]
+    #[test_case("int g = 1; int g = 2;" => vec![("previous definition of 'g' is here".to_string(), Span::new(0, 10))])]
+    #[test_case("void bar(void);\nint main() { int a = bar(); }" => vec![("'bar' declared here".to_string(), Span::new(0, 15))])]
+    #[test_case("int main() { int a;\n int a; }" => vec![("previous declaration of 'a' is here".to_string(), Span::new(13, 18))])]
+    fn error_notes(src: &str) -> Vec<(String, Span)> {
+        let e = analyse(&parse(scan(src).unwrap()).unwrap()).unwrap_err();
+        e.notes
+            .into_iter()
+            .map(|n| (n.msg, n.span.unwrap()))
+            .collect()
+    }
+
+    #[test_case("int main() { int a = 1; int a = 2; }" => "int a = 2")]
+    #[test_case("int f(int x, int x) {}" => "int x")]
+    #[test_case("int f() {} int f() {}" => "int f() {}")]
+    #[test_case("int main() { _Static_assert(0, \"m\"); }" => "_Static_assert(0, \"m\");")]
+    fn error_span(src: &str) -> &str {
+        let e = analyse(&parse(scan(src).unwrap()).unwrap()).unwrap_err();
+        let span = e.span.unwrap();
+        &src[span.start..span.end]
+    }
+
+    // ids in parsing order: g 0, 1 1, main 2, { 3, stmt 4, g 5, 2 6, stmt 7, { 8, stmt 9, g 10,
+    // 3 11, return 12, g 13, stmt 14, foo() 15
+    #[test_case(13 => Some(5))]
+    #[test_case(15 => None)]
+    fn resolve_symbol(id: u32) -> Option<u32> {
+        let src = "int g = 1; int main() { int g = 2; { int g = 3; } return g; foo(); }";
+        let decor = analyse(&parse(scan(src).unwrap()).unwrap()).unwrap();
+        decor.symbols.get(NodeId(id)).map(|d| d.0)
+    }
+
+    // the call resolves to the definition, which keeps the parameters of the prototype
+    #[test]
+    fn table_after_analysis() {
+        let src = "int f(int); int f(int a) {} int main() { return f(1); }";
+        let decor = analyse(&parse(scan(src).unwrap()).unwrap()).unwrap();
+        let call = decor.symbols.iter().map(|(_, d)| *d).next().unwrap();
+        let decl = decor.table.decl(call).unwrap();
+        assert_eq!(&src[decl.span.start..decl.span.end], "int f(int a) {}");
+        match &decl.kind {
+            DeclKind::Func(f) => {
+                assert!(f.defined);
+                assert_eq!(f.sig.param_types, Some(vec![Type::Base(DataType::Int)]));
+            }
+            k => panic!("unexpected {:?}", k),
+        }
+        // the prototype stays queryable by its own id
+        assert_eq!(decor.table.decls().count(), 4);
+    }
+
+    #[test]
+    fn scope_tree() {
+        let src = "int g; int f(int a) { int b; { int c; } }";
+        let ast = parse(scan(src).unwrap()).unwrap();
+        let table = analyse(&ast).unwrap().table;
+        let (func, block) = match &ast.0[1] {
+            ExtDecl::Func(f) => match &f.cmp_stmt.stmts[1].kind {
+                StmtKind::Compound(c) => (f.id, c.id),
+                _ => unreachable!(),
+            },
+            _ => unreachable!(),
+        };
+
+        let inner = table.scope_of(block).unwrap();
+        let outer = table.scope(inner).parent.unwrap();
+        assert_eq!(table.scope_of(func), Some(outer));
+        assert_eq!(table.scope(outer).parent, table.global_scope());
+
+        // names resolve from any scope after the analysis
+        let name = |s, n: &str| table.resolve(s, n.into()).map(|d| d.name.as_str());
+        assert_eq!(name(inner, "a"), Some("a"));
+        assert_eq!(name(inner, "g"), Some("g"));
+        assert_eq!(name(outer, "c"), None);
+        let mut names: Vec<_> = table.scope_decls(outer).map(|d| d.name.as_str()).collect();
+        names.sort_unstable();
+        assert_eq!(names, vec!["a", "b"]);
+    }
+
+    #[test_case("int main() { f(); 1; }" => vec!["statement with no effect"])]
+    #[test_case("int g; int main() { { g + 1; } -g; }" => vec!["statement with no effect", "statement with no effect"])]
+    #[test_case("int main() { f() + 1; }" => vec!["value computed is not used"])]
+    #[test_case("volatile int v; int main() { v; -v; int w; w; }" => vec!["value computed is not used", "statement with no effect"]; "volatile reads")]
+    fn unused_value(src: &str) -> Vec<String> {
+        let decor = analyse(&parse(scan(src).unwrap()).unwrap()).unwrap();
+        decor.warnings.into_iter().map(|(_, e)| e.msg).collect()
+    }
+
+    #[test_case("void f() __attribute__((noreturn, hot)); int main() { int a __attribute__((used)); }" => vec!["'hot' attribute directive ignored", "'used' attribute ignored"])]
+    #[test_case("int g __attribute__((aligned(3)));" => panics "requested alignment is not a positive power of 2")]
+    fn attribute_warnings(src: &str) -> Vec<String> {
+        let decor = analyse(&parse(scan(src).unwrap()).unwrap()).unwrap();
+        decor.warnings.into_iter().map(|(_, e)| e.msg).collect()
+    }
+
+    #[test_case("static int f(void) { return 0; } int g(void) __attribute__((alias(\"f\"))); int v; extern int w __attribute__((weak, alias(\"v\")));" => None; "aliases")]
+    #[test_case("int f(void) __attribute__((weak)); int main() { return f(); }" => None; "weak reference")]
+    #[test_case("static int v __attribute__((weak));" => Some("weak declaration of 'v' must be public".to_string()))]
+    #[test_case("int g(void) __attribute__((alias(\"f\")));" => Some("'g' aliased to undefined symbol 'f'".to_string()))]
+    #[test_case("int f(void); int g(void) __attribute__((alias(\"f\")));" => Some("'g' aliased to undefined symbol 'f'".to_string()); "prototype")]
+    #[test_case("int v; extern int w __attribute__((alias(\"v\"))); int x __attribute__((alias(\"w\")));" => Some("'x' aliased to undefined symbol 'w'".to_string()); "alias of alias")]
+    #[test_case("int f(void) { return 0; } int g(void) __attribute__((alias(\"f\"))); int g(void) { return 1; }" => Some("'g' defined both normally and as 'alias' attribute".to_string()))]
+    fn linkage_attrs(src: &str) -> Option<String> {
+        analyse(&parse(scan(src).unwrap()).unwrap())
+            .err()
+            .map(|e| e.msg)
+    }
+
+    #[test_case("int main() { int c[1]; __sync_synchronize(); return __atomic_fetch_add(c, 1, __ATOMIC_SEQ_CST); }" => None; "valid")]
+    #[test_case("int main() { char f[1]; __atomic_store_n(f, 1, __ATOMIC_RELEASE); __atomic_thread_fence(__ATOMIC_ACQUIRE); }" => None; "store and fence")]
+    #[test_case("int main() { int c[1]; __sync_fetch_and_add(c); }" => Some("too few arguments to function __sync_fetch_and_add, expected 2 but 1".to_string()))]
+    #[test_case("int main() { __sync_fetch_and_add(1, 1); }" => Some("operand of '__sync_fetch_and_add' is not a pointer to an integer".to_string()))]
+    #[test_case("int main() { char *p[1]; __atomic_load_n(p, __ATOMIC_RELAXED); }" => None; "pointer object")]
+    #[test_case("int main() { int c[1]; __atomic_store_n(c, 1, __ATOMIC_ACQUIRE); }" => Some("invalid memory model for '__atomic_store_n'".to_string()))]
+    #[test_case("int main() { int c[1]; int o; __atomic_load_n(c, o); }" => Some("invalid memory model argument 2 of '__atomic_load_n'".to_string()))]
+    #[test_case("int main() { int c[1]; __atomic_load_n(c, 7); }" => Some("invalid memory model argument 2 of '__atomic_load_n'".to_string()); "out of range")]
+    #[test_case("int main() { int c[1]; return __atomic_store_n(c, 1, __ATOMIC_RELAXED); }" => Some("void value not ignored as it ought to be".to_string()))]
+    fn atomic_builtins(src: &str) -> Option<String> {
+        analyse(&parse(scan(src).unwrap()).unwrap())
+            .err()
+            .map(|e| e.msg)
+    }
+
+    #[test_case("int main() { int r = __builtin_expect(1 + 1, 1); __builtin_trap(); }" => None; "valid")]
+    #[test_case("void f(void) { __builtin_unreachable(); }" => None; "unreachable")]
+    #[test_case("int main() { __builtin_expect(1); }" => Some("too few arguments to function __builtin_expect, expected 2 but 1".to_string()))]
+    #[test_case("int main() { __builtin_trap(1); }" => Some("too many arguments to function __builtin_trap, expected 0 but 1".to_string()))]
+    #[test_case("void f(void); int main() { return __builtin_expect(f(), 0); }" => Some("void value not ignored as it ought to be".to_string()))]
+    #[test_case("int main() { return __builtin_unreachable(); }" => Some("void value not ignored as it ought to be".to_string()); "void result")]
+    fn builtins(src: &str) -> Option<String> {
+        analyse(&parse(scan(src).unwrap()).unwrap())
+            .err()
+            .map(|e| e.msg)
+    }
+
+    // the value of the hint is the range of its argument, not of long
+    #[test_case("int main() { char c = __builtin_expect(1 + 2, 1); }" => Vec::<String>::new())]
+    #[test_case("int main() { char c = __builtin_expect(300, 1); }" => vec!["conversion from 'long' to 'char' may change value, the value is in [300, 300]"])]
+    fn expect_conversion(src: &str) -> Vec<String> {
+        let decor = analyse(&parse(scan(src).unwrap()).unwrap()).unwrap();
+        decor.warnings.into_iter().map(|(_, e)| e.msg).collect()
+    }
+
+    #[test_case("main() { return 0; }" => vec!["type defaults to 'int' in declaration of 'main'"])]
+    #[test_case("g = 1; *p;" => vec!["type defaults to 'int' in declaration of 'g'", "type defaults to 'int' in declaration of 'p'"])]
+    #[test_case("int f(a, b) char *b; { return a; }" => vec!["old-style function definition", "type of 'a' defaults to 'int'"])]
+    #[test_case("int f() { return 0; }" => Vec::<String>::new(); "unspecified parameters")]
+    fn knr_warnings(src: &str) -> Vec<String> {
+        let ast = parse_with(scan(src).unwrap(), Standard::Knr).unwrap();
+        let decor = analyse(&ast).unwrap();
+        decor.warnings.into_iter().map(|(_, e)| e.msg).collect()
+    }
+
+    #[test_case("main() { return 0; }" => vec![FixIt::insert(0, "int ")])]
+    #[test_case("static f() {} extern g; __attribute__((used)) h; k;" => vec![FixIt::insert(49, "int ")]; "declarator first only")]
+    fn knr_fixits(src: &str) -> Vec<FixIt> {
+        let ast = parse_with(scan(src).unwrap(), Standard::Knr).unwrap();
+        let decor = analyse(&ast).unwrap();
+        decor
+            .warnings
+            .into_iter()
+            .flat_map(|(_, e)| e.fixits)
+            .collect()
+    }
+
+    #[test_case("static int f(void) { return f(); } static int g(void); int g(void) { return 0; } static int h(void) { return g(); } int main() { return 0; }" => vec!["'f' defined but not used", "'h' defined but not used"])]
+    #[test_case("static int f(void); int main() { return f(); } static int f(void) { return 1; }" => Vec::<String>::new(); "called before the definition")]
+    fn unused_functions(src: &str) -> Vec<String> {
+        let decor = analyse(&parse(scan(src).unwrap()).unwrap()).unwrap();
+        decor.warnings.into_iter().map(|(_, e)| e.msg).collect()
+    }
+
+    // plain char is signed on macOS, unsigned on Linux
+    #[test_case(TargetOs::MacOs, "int main() { char c = 300; return c; }" => vec!["conversion from 'int' to 'char' changes value from '300' to '44'"])]
+    #[test_case(TargetOs::MacOs, "int i = 5000000000;" => vec!["conversion from 'long' to 'int' changes value from '5000000000' to '705032704'"])]
+    #[test_case(TargetOs::MacOs, "char a[3] = {1, -128, 256};" => vec!["conversion from 'int' to 'char' changes value from '256' to '0'"])]
+    #[test_case(TargetOs::MacOs, "int f(int i) { char c = i; return c; }" => vec!["conversion from 'int' to 'char' may change value"])]
+    #[test_case(TargetOs::MacOs, "char f(char c) { return c + 1; }" => vec!["conversion from 'int' to 'char' may change value, the value is in [-127, 128]"])]
+    #[test_case(TargetOs::MacOs, "char f(char c) { return -c; }" => vec!["conversion from 'int' to 'char' may change value, the value is in [-127, 128]"]; "negated")]
+    #[test_case(TargetOs::MacOs, "char f(char c) { return c; } int g(char c) { return c + 1; }" => Vec::<String>::new(); "wider or same")]
+    #[test_case(TargetOs::MacOs, "void h(char); int main() { h(127); h(128); }" => vec!["conversion from 'int' to 'char' changes value from '128' to '-128'"])]
+    #[test_case(TargetOs::Linux, "int main() { char d = 200; return d; }" => Vec::<String>::new(); "unsigned char fits")]
+    #[test_case(TargetOs::Linux, "char a[3] = {1, -128, 256};" => vec!["conversion from 'int' to 'char' changes value from '-128' to '128'", "conversion from 'int' to 'char' changes value from '256' to '0'"]; "unsigned array")]
+    #[test_case(TargetOs::Linux, "char f(char c) { return c + 1; }" => vec!["conversion from 'int' to 'char' may change value, the value is in [1, 256]"]; "unsigned sum")]
+    #[test_case(TargetOs::Linux, "char f(char c) { return -c; }" => vec!["conversion from 'int' to 'char' may change value, the value is in [-255, 0]"]; "unsigned negated")]
+    #[test_case(TargetOs::Linux, "void h(char); int main() { h(255); h(-1); }" => vec!["conversion from 'int' to 'char' changes value from '-1' to '255'"]; "unsigned argument")]
+    fn conversion_warnings(target: TargetOs, src: &str) -> Vec<String> {
+        warnings_of(src, Warning::Conversion, target)
+    }
+
+    #[test_case("int g = 5; int main() { int g = 2; { int g = 3; } return g; }" => vec!["declaration of 'g' shadows a global declaration", "declaration of 'g' shadows a previous local"])]
+    #[test_case("extern int e; int f(int e) { { int e = 1; } return e; }" => vec!["declaration of 'e' shadows a global declaration", "declaration of 'e' shadows a parameter"])]
+    #[test_case("int puts(); int main() { int puts = 1; { int a; } int a; return a; }" => Vec::<String>::new(); "functions and siblings")]
+    fn shadow_warnings(src: &str) -> Vec<String> {
+        warnings_of(src, Warning::Shadow, TargetOs::Linux)
+    }
+
+    fn warnings_of(src: &str, warning: Warning, target: TargetOs) -> Vec<String> {
+        let decor = analyse_with(&parse(scan(src).unwrap()).unwrap(), target).unwrap();
+        decor
+            .warnings
+            .into_iter()
+            .filter(|(w, _)| *w == warning)
+            .map(|(_, e)| e.msg)
+            .collect()
+    }
+
+    #[test_case("int main() { return 1; }", 3 => Some("int".to_string()))]
+    #[test_case("void f() {} int main() { f(); }", 5 => Some("void".to_string()))]
+    #[test_case("int main() { g(); }", 3 => Some("int".to_string()))]
+    #[test_case("int main() { return a; }", 3 => None)]
+    #[test_case("int a[3]; int main() { return a; }", 5 => Some("int *".to_string()); "array decays")]
+    #[test_case("int f(int a[]) { return a; }", 4 => Some("int *".to_string()); "array parameter")]
+    #[test_case("int main() { return \"ab\"; }", 3 => Some("char *".to_string()); "string literal")]
+    #[test_case("int f(int); int main() { return f; }", 5 => Some("int (*)(int)".to_string()); "function decays")]
+    #[test_case("volatile int v; int main() { return v; }", 4 => Some("volatile int".to_string()); "volatile object")]
+    #[test_case("volatile int v; int main() { return v + 1; }", 6 => Some("int".to_string()); "volatile value")]
+    fn expr_type(src: &str, id: u32) -> Option<String> {
+        let decor = analyse(&parse(scan(src).unwrap()).unwrap()).unwrap();
+        decor.types.get(NodeId(id)).map(|t| t.to_string())
+    }
+
+    // the adjusted parameter types are compatible with pointers
+    #[test_case("int f(int a[]); int f(int *a) { return 0; }")]
+    #[test_case("int f(int a[4]); int f(int a[]) { return 0; }")]
+    #[test_case("int f(int g(int)); int f(int (*g)(int)) { return 0; }")]
+    fn adjusted_params(src: &str) {
+        let decor = analyse(&parse(scan(src).unwrap()).unwrap()).unwrap();
+        let params: Vec<_> = decor
+            .table
+            .decls()
+            .filter(|d| matches!(d.kind, DeclKind::Param(_)))
+            .map(|d| d.data_type())
+            .collect();
+        assert!(params.iter().all(|t| matches!(t, Type::Pointer(_))));
+    }
+}
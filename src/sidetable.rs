@@ -0,0 +1,68 @@
+//! Side tables to decorate AST nodes with information computed by a pass
+
+use std::collections::HashMap;
+
+use crate::ast::NodeId;
+
+/// map from node id to the data of type `T`
+#[derive(Debug, Clone)]
+pub struct SideTable<T> {
+    map: HashMap<NodeId, T>,
+}
+
+impl<T> SideTable<T> {
+    pub fn new() -> Self {
+        SideTable {
+            map: HashMap::new(),
+        }
+    }
+
+    /// set data of the node, return the previous data if any
+    pub fn insert(&mut self, id: NodeId, value: T) -> Option<T> {
+        self.map.insert(id, value)
+    }
+
+    pub fn get(&self, id: NodeId) -> Option<&T> {
+        self.map.get(&id)
+    }
+
+    pub fn contains(&self, id: NodeId) -> bool {
+        self.map.contains_key(&id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (NodeId, &T)> {
+        self.map.iter().map(|(k, v)| (*k, v))
+    }
+}
+
+impl<T> Default for SideTable<T> {
+    fn default() -> Self {
+        SideTable::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::ast::NodeId;
+
+    use super::SideTable;
+
+    #[test]
+    fn test_side_table() {
+        let mut t = SideTable::new();
+        assert!(t.is_empty());
+        assert_eq!(t.insert(NodeId(1), "a"), None);
+        assert_eq!(t.insert(NodeId(1), "b"), Some("a"));
+        assert_eq!(t.get(NodeId(1)), Some(&"b"));
+        assert!(!t.contains(NodeId(2)));
+        assert_eq!(t.len(), 1);
+    }
+}
@@ -0,0 +1,228 @@
+//! List declared symbols per scope with their storage, for --emit=symbols
+
+use serde::Serialize;
+
+use crate::{
+    ast::{walk_cmp_stmt, Ast, CmpStmt, FuncDecl, GlobalVarDecl, Type, VarDecl, Visitor},
+    codegen::{layout_frame, Frame},
+};
+
+/// symbols declared in a scope
+#[derive(Debug, Serialize)]
+pub struct ScopeInfo {
+    /// 'global', function name or 'block'
+    pub name: String,
+    /// nesting level, 0 is the global scope
+    pub depth: usize,
+    pub symbols: Vec<SymbolInfo>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SymbolInfo {
+    pub name: String,
+    pub kind: SymbolKind,
+    pub data_type: Type,
+    pub storage: Storage,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum SymbolKind {
+    Function,
+    GlobalVar,
+    Param,
+    LocalVar,
+}
+
+/// where the symbol lives at runtime
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum Storage {
+    /// code in the text section
+    Text,
+    /// global data
+    Data,
+    /// data defined in another object
+    External,
+    /// offset from sp in the frame, none if the frame can not be laid out yet
+    Frame(Option<u32>),
+}
+
+/// collect scopes in the order of appearance
+pub fn list_symbols(ast: &Ast) -> Vec<ScopeInfo> {
+    let mut l = Lister {
+        scopes: Vec::new(),
+        stack: Vec::new(),
+        frame: None,
+    };
+    l.push_scope("global");
+    l.visit_ast(ast);
+    l.scopes
+}
+
+/// render scopes as indented table
+pub fn format_symbols(scopes: &[ScopeInfo]) -> String {
+    let mut str = String::new();
+    for scope in scopes {
+        let indent = "  ".repeat(scope.depth);
+        str.push_str(&format!("{}scope {}\n", indent, scope.name));
+        for s in &scope.symbols {
+            let storage = match s.storage {
+                Storage::Text => String::from("text"),
+                Storage::Data => String::from("data"),
+                Storage::External => String::from("extern"),
+                Storage::Frame(Some(offset)) => format!("[sp, #{}]", offset),
+                Storage::Frame(None) => String::from("stack"),
+            };
+            str.push_str(&format!(
+                "{}  {:<12} {:<10} {:<8} {}\n",
+                indent,
+                s.name,
+                kind_name(s.kind),
+                s.data_type.to_string(),
+                storage
+            ));
+        }
+    }
+    str
+}
+
+fn kind_name(kind: SymbolKind) -> &'static str {
+    match kind {
+        SymbolKind::Function => "function",
+        SymbolKind::GlobalVar => "global",
+        SymbolKind::Param => "param",
+        SymbolKind::LocalVar => "local",
+    }
+}
+
+struct Lister {
+    scopes: Vec<ScopeInfo>,
+    /// indices of the open scopes
+    stack: Vec<usize>,
+    /// frame of the current function
+    frame: Option<Frame>,
+}
+
+impl Lister {
+    fn push_scope(&mut self, name: &str) {
+        self.stack.push(self.scopes.len());
+        self.scopes.push(ScopeInfo {
+            name: name.to_string(),
+            depth: self.stack.len() - 1,
+            symbols: Vec::new(),
+        });
+    }
+
+    fn pop_scope(&mut self) {
+        self.stack.pop();
+    }
+
+    fn add(&mut self, name: &str, kind: SymbolKind, data_type: Type, storage: Storage) {
+        let i = *self.stack.last().unwrap();
+        self.scopes[i].symbols.push(SymbolInfo {
+            name: name.to_string(),
+            kind,
+            data_type,
+            storage,
+        });
+    }
+
+    fn frame_offset(&self, id: crate::ast::NodeId) -> Storage {
+        Storage::Frame(self.frame.as_ref().and_then(|f| f.offsets.get(id).copied()))
+    }
+}
+
+impl<'a> Visitor<'a> for Lister {
+    fn visit_func_decl(&mut self, func: &'a FuncDecl) {
+        self.add(
+            func.name.as_str(),
+            SymbolKind::Function,
+            func.return_type.clone(),
+            Storage::Text,
+        );
+
+        // parameters share the scope with the outermost block of the body
+        self.frame = layout_frame(func).ok();
+        self.push_scope(func.name.as_str());
+        for p in &func.params {
+            let storage = self.frame_offset(p.id);
+            self.add(
+                p.name.as_str(),
+                SymbolKind::Param,
+                p.data_type.clone(),
+                storage,
+            );
+        }
+        walk_cmp_stmt(self, &func.cmp_stmt);
+        self.pop_scope();
+        self.frame = None;
+    }
+
+    fn visit_global_var_decl(&mut self, decl: &'a GlobalVarDecl) {
+        let storage = match decl.external && decl.init.is_none() {
+            true => Storage::External,
+            false => Storage::Data,
+        };
+        self.add(
+            decl.name.as_str(),
+            SymbolKind::GlobalVar,
+            decl.data_type.clone(),
+            storage,
+        );
+    }
+
+    fn visit_cmp_stmt(&mut self, cmp_stmt: &'a CmpStmt) {
+        self.push_scope("block");
+        walk_cmp_stmt(self, cmp_stmt);
+        self.pop_scope();
+    }
+
+    fn visit_var_decl(&mut self, decl: &'a VarDecl) {
+        let storage = self.frame_offset(decl.id);
+        self.add(
+            decl.name.as_str(),
+            SymbolKind::LocalVar,
+            decl.data_type.clone(),
+            storage,
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{parse, scan};
+
+    use super::{format_symbols, list_symbols, Storage, SymbolKind};
+
+    #[test]
+    fn test_list_symbols() {
+        let src = "int g; int main(int x) { int a = 1; { int b; } }";
+        let scopes = list_symbols(&parse(scan(src).unwrap()).unwrap());
+        let names: Vec<_> = scopes.iter().map(|s| (s.name.as_str(), s.depth)).collect();
+        assert_eq!(names, vec![("global", 0), ("main", 1), ("block", 2)]);
+        assert_eq!(scopes[0].symbols[1].kind, SymbolKind::Function);
+        let main: Vec<_> = scopes[1]
+            .symbols
+            .iter()
+            .map(|s| (s.name.as_str(), s.storage))
+            .collect();
+        assert_eq!(
+            main,
+            vec![
+                ("x", Storage::Frame(Some(12))),
+                ("a", Storage::Frame(Some(8)))
+            ]
+        );
+        assert_eq!(scopes[2].symbols[0].storage, Storage::Frame(Some(4)));
+    }
+
+    #[test]
+    fn test_format_symbols() {
+        let src = "int g; void f(void v) { }";
+        let v = format_symbols(&list_symbols(&parse(scan(src).unwrap()).unwrap()));
+        assert_eq!(
+            v,
+            "scope global\n  g            global     int      data\n  f            function   void     text\n\
+             \x20 scope f\n    v            param      void     stack\n"
+        );
+    }
+}
@@ -1,151 +1,452 @@
 //! Symbol table for the AST to support decorating the AST
+//!
+//! declarations are copied out of the AST and kept by node id, so the table outlives the
+//! analysis and can be queried by later phases
 
 use std::{
+    cmp::Ordering,
     collections::{hash_map::Entry, HashMap},
     fmt::Display,
 };
 
-use crate::ast::{FuncDecl, GlobalVarDecl, ParamDecl, VarDecl};
+use crate::{
+    ast::{FuncDecl, GlobalVarDecl, NodeId, ParamDecl, Type, VarDecl},
+    common::{CompileError, Span},
+    intern::Symbol,
+    sidetable::SideTable,
+    timing::Phase,
+};
+
+#[derive(Debug, Default)]
+pub struct SymTable {
+    /// tree of scopes in the order of opening, the first is the translation unit
+    scopes: Vec<Scope>,
+    /// innermost open scope during the analysis
+    cur: Option<ScopeId>,
+    /// scope opened by a function or block node
+    by_node: SideTable<ScopeId>,
+    /// every declaration by its node id
+    decls: SideTable<Decl>,
+}
 
+/// index of a scope in the tree
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScopeId(pub usize);
+
+/// names declared in a scope, mapped to the id of the declaration
 #[derive(Debug)]
-pub struct SymTable<'a> {
-    stack: Vec<SymScope<'a>>,
+pub struct Scope {
+    pub parent: Option<ScopeId>,
+    /// function or block opening the scope, none for the translation unit
+    pub node: Option<NodeId>,
+    names: HashMap<Symbol, NodeId>,
 }
 
-impl<'a> SymTable<'a> {
-    pub const fn new() -> Self {
-        SymTable { stack: Vec::new() }
+impl SymTable {
+    pub fn new() -> Self {
+        SymTable::default()
     }
 
-    pub fn push_scope(&mut self) {
-        let scope = SymScope::new();
-        debug!("push \n{}", self);
-        self.stack.push(scope);
+    /// open a nested scope of the node, the scope is kept after it is closed
+    pub fn push_scope(&mut self, node: Option<NodeId>) {
+        let id = ScopeId(self.scopes.len());
+        self.scopes.push(Scope {
+            parent: self.cur,
+            node,
+            names: HashMap::new(),
+        });
+        if let Some(n) = node {
+            self.by_node.insert(n, id);
+        }
+        self.cur = Some(id);
+        trace!(target: Phase::Semantics.name(), "push \n{}", self);
     }
 
     pub fn pop_scope(&mut self) {
-        debug!("pop \n{}", self);
-        let _ = self.stack.pop();
+        trace!(target: Phase::Semantics.name(), "pop \n{}", self);
+        self.cur = self.cur.and_then(|s| self.scopes[s.0].parent);
+    }
+
+    /// declare the name in the innermost scope
+    pub fn insert_decl<T: DeclCreation>(&mut self, decl: &T) -> Result<(), CompileError> {
+        let mut decl = decl.to_decl();
+        let cur = self.cur.expect("open scope");
+        let mut entry = match self.scopes[cur.0].names.entry(decl.name) {
+            Entry::Vacant(e) => {
+                e.insert(decl.id);
+                self.decls.insert(decl.id, decl);
+                return Ok(());
+            }
+            Entry::Occupied(e) => e,
+        };
+
+        let old = self.decls.get(*entry.get()).expect("declaration of scope");
+        let ranks = (old.kind.linkage_rank(), decl.kind.linkage_rank());
+        match (&old.kind, &mut decl.kind) {
+            // prototypes may be repeated before and after the definition
+            (DeclKind::Func(f), DeclKind::Func(new)) if !(f.defined && new.defined) => {
+                let name = decl.name;
+                new.redeclare(f, name, decl.span)
+                    .map_err(|e| e.with_note(previous(old), old.span))?;
+                if !new.defined {
+                    // keep the definition or the first prototype
+                    decl.id = old.id;
+                    decl.span = old.span;
+                }
+                entry.insert(decl.id);
+                self.decls.insert(decl.id, decl);
+                Ok(())
+            }
+            // extern declarations and tentative definitions may be repeated before and after
+            // the definition
+            (
+                DeclKind::Extern(a) | DeclKind::Tentative(a) | DeclKind::GlobalVar(a),
+                DeclKind::Extern(b) | DeclKind::Tentative(b) | DeclKind::GlobalVar(b),
+            ) if ranks != (2, 2) => {
+                if !is_compatible(a, b) {
+                    return Err(CompileError::at_span(
+                        format!("conflicting types for {}", decl.name),
+                        decl.span,
+                    )
+                    .with_note(previous(old), old.span));
+                }
+                // keep the definition or the first declaration completing the type
+                let replace = match ranks.0.cmp(&ranks.1) {
+                    Ordering::Less => true,
+                    Ordering::Equal => matches!(a, Type::Array(_, None)) && a != b,
+                    Ordering::Greater => false,
+                };
+                if replace {
+                    entry.insert(decl.id);
+                    self.decls.insert(decl.id, decl);
+                }
+                Ok(())
+            }
+            // the body of a function is given once in the translation unit
+            (DeclKind::Func(_), DeclKind::Func(_)) => Err(CompileError::at_span(
+                format!("redefinition of '{}'", decl.name),
+                decl.span,
+            )
+            .with_note(previous(old), old.span)),
+            _ => Err(CompileError::at_span(
+                format!("{} is already define as {}", decl.name, old.format_type()),
+                decl.span,
+            )
+            .with_note(previous(old), old.span)),
+        }
+    }
+
+    /// find declaration from the innermost open scope
+    pub fn lookup(&self, name: Symbol) -> Option<&Decl> {
+        self.resolve(self.cur?, name)
+    }
+
+    /// find declaration from the scope enclosing the innermost open scope, the declaration a
+    /// new one in the innermost scope hides
+    pub fn lookup_outer(&self, name: Symbol) -> Option<&Decl> {
+        self.resolve(self.scopes[self.cur?.0].parent?, name)
+    }
+
+    /// find declaration from the scope outward, also after the analysis
+    ///
+    /// every declaration of the scope is visible, including the ones after a reference
+    pub fn resolve(&self, scope: ScopeId, name: Symbol) -> Option<&Decl> {
+        let mut cur = Some(scope);
+        while let Some(s) = cur {
+            let scope = &self.scopes[s.0];
+            if let Some(id) = scope.names.get(&name) {
+                return self.decls.get(*id);
+            }
+            cur = scope.parent;
+        }
+        None
+    }
+
+    /// scope of the translation unit, none before the analysis
+    pub fn global_scope(&self) -> Option<ScopeId> {
+        self.scopes.first().map(|_| ScopeId(0))
     }
 
-    pub fn cur_scope(&mut self) -> &mut SymScope<'a> {
-        let len = self.stack.len();
-        self.stack.get_mut(len - 1).unwrap()
+    pub fn scope(&self, id: ScopeId) -> &Scope {
+        &self.scopes[id.0]
+    }
+
+    /// scope opened by the function or block
+    pub fn scope_of(&self, node: NodeId) -> Option<ScopeId> {
+        self.by_node.get(node).copied()
+    }
+
+    /// declarations of the scope without the outer scopes
+    pub fn scope_decls(&self, id: ScopeId) -> impl Iterator<Item = &Decl> {
+        self.scopes[id.0]
+            .names
+            .values()
+            .filter_map(move |d| self.decls.get(*d))
+    }
+
+    /// declaration with the node id, also after the analysis
+    pub fn decl(&self, id: NodeId) -> Option<&Decl> {
+        self.decls.get(id)
+    }
+
+    pub fn decls(&self) -> impl Iterator<Item = &Decl> {
+        self.decls.iter().map(|(_, d)| d)
     }
 }
 
-#[derive(Debug)]
-pub struct SymScope<'a> {
-    map: HashMap<String, DeclRef<'a>>,
+/// declaration copied from the AST
+#[derive(Debug, Clone, PartialEq)]
+pub struct Decl {
+    /// the definition of a function, the first prototype until it is defined
+    pub id: NodeId,
+    pub span: Span,
+    pub name: Symbol,
+    pub kind: DeclKind,
 }
 
-impl<'a> SymScope<'a> {
-    pub fn new() -> Self {
-        SymScope {
-            map: HashMap::new(),
+#[derive(Debug, Clone, PartialEq)]
+pub enum DeclKind {
+    /// global variable with an initializer
+    GlobalVar(Type),
+    /// global variable without an initializer, zero unless another declaration initializes it
+    Tentative(Type),
+    /// global variable declared `extern` without an initializer, defined elsewhere
+    Extern(Type),
+    Var(Type),
+    /// adjusted type, array and function parameters are pointers
+    Param(Type),
+    Func(FuncEntry),
+}
+
+impl DeclKind {
+    /// the definition of a global replaces the tentative definitions, which replace the extern
+    /// declarations
+    fn linkage_rank(&self) -> u8 {
+        match self {
+            DeclKind::Extern(_) => 0,
+            DeclKind::Tentative(_) => 1,
+            _ => 2,
+        }
+    }
+}
+
+/// signature of a function, shared by its prototypes, definition and calls
+#[derive(Debug, Clone, PartialEq)]
+pub struct FuncSig {
+    pub return_type: Type,
+    /// none if the parameters are not specified by `()`
+    pub param_types: Option<Vec<Type>>,
+    /// more arguments may follow the parameters
+    pub variadic: bool,
+}
+
+impl FuncSig {
+    /// signature of a function type, none for other types
+    pub fn from_type(ty: &Type) -> Option<FuncSig> {
+        match ty {
+            Type::Function(ret, params) => Some(FuncSig {
+                return_type: ret.as_ref().clone(),
+                // qualifiers of parameters do not change the function type
+                param_types: params
+                    .as_ref()
+                    .map(|p| p.iter().map(|t| t.unqualified().decay()).collect()),
+                variadic: false,
+            }),
+            _ => None,
         }
     }
 
-    pub fn insert_decl<T>(&mut self, name: &str, decl: &'a T)
-    where
-        T: DeclRefCreation<'a>,
-    {
-        match self.map.entry(name.to_string()) {
-            Entry::Occupied(v) => {
-                panic!("{} is already define as {}", name, v.get().format_type());
+    /// same return type and parameters, unspecified parameters are compatible with any
+    pub fn is_compatible(&self, other: &FuncSig) -> bool {
+        self.return_type == other.return_type
+            && self.variadic == other.variadic
+            && match (&self.param_types, &other.param_types) {
+                (Some(a), Some(b)) => a == b,
+                _ => true,
             }
-            Entry::Vacant(_) => {
-                let v: DeclRef<'a> = decl.to_decl_ref();
-                self.map.insert(name.to_string(), v);
-            }
-        };
     }
 
-    pub fn lookup_decl<T>(&self, name: &str) -> Option<&DeclRef> {
-        self.map.get(name)
+    /// the number of arguments matches the parameters
+    pub fn accepts(&self, args: usize) -> bool {
+        match &self.param_types {
+            Some(params) if self.variadic => args >= params.len(),
+            Some(params) => args == params.len(),
+            None => true,
+        }
     }
 }
 
-#[derive(Debug)]
-pub enum DeclRef<'a> {
-    GlobalVar(&'a GlobalVarDecl),
-    Var(&'a VarDecl),
-    Param(&'a ParamDecl),
-    Func(&'a FuncDecl),
+/// function declared by prototypes or defined
+#[derive(Debug, Clone, PartialEq)]
+pub struct FuncEntry {
+    pub sig: FuncSig,
+    pub defined: bool,
 }
 
-impl<'a> DeclRef<'a> {
+impl FuncEntry {
+    /// check the declaration against the previous one, the parameters are taken from the first
+    /// declaration specifying them
+    fn redeclare(&mut self, old: &FuncEntry, name: Symbol, span: Span) -> Result<(), CompileError> {
+        if !old.sig.is_compatible(&self.sig) {
+            return Err(CompileError::at_span(
+                format!("conflicting types for {}", name),
+                span,
+            ));
+        }
+        if self.sig.param_types.is_none() {
+            self.sig.param_types = old.sig.param_types.clone();
+        }
+        self.defined |= old.defined;
+        Ok(())
+    }
+}
+
+impl Decl {
+    /// declared type, the function type of a function
+    pub fn ty(&self) -> Type {
+        match &self.kind {
+            DeclKind::Func(f) => Type::Function(
+                Box::new(f.sig.return_type.clone()),
+                f.sig.param_types.clone(),
+            ),
+            _ => self.data_type(),
+        }
+    }
+
+    /// type of variable or return type of function
+    pub fn data_type(&self) -> Type {
+        match &self.kind {
+            DeclKind::GlobalVar(t)
+            | DeclKind::Tentative(t)
+            | DeclKind::Extern(t)
+            | DeclKind::Var(t)
+            | DeclKind::Param(t) => t.clone(),
+            DeclKind::Func(f) => f.sig.return_type.clone(),
+        }
+    }
+
     fn format_type(&self) -> &str {
-        match self {
-            DeclRef::GlobalVar(_) => "global variable",
-            DeclRef::Var(_) => "local variable",
-            DeclRef::Param(_) => "function parameter",
-            DeclRef::Func(_) => "funcation",
+        match self.kind {
+            DeclKind::GlobalVar(_) | DeclKind::Tentative(_) | DeclKind::Extern(_) => {
+                "global variable"
+            }
+            DeclKind::Var(_) => "local variable",
+            DeclKind::Param(_) => "function parameter",
+            DeclKind::Func(_) => "function",
         }
     }
 }
 
-impl<'a> Display for SymTable<'a> {
+/// open scopes from the innermost
+impl Display for SymTable {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let v = self
-            .stack
-            .iter()
-            .rev()
-            .map(|s| format!("{}", s))
-            .fold(String::from("----------------------"), |acc, v| {
-                acc + v.as_str() + "\n----------------------"
-            });
+        let mut v = String::from("----------------------");
+        let mut cur = self.cur;
+        while let Some(s) = cur {
+            for d in self.scope_decls(s) {
+                v = v + "\n" + format!("{} -> {}", d.name, d).as_str();
+            }
+            v += "\n----------------------";
+            cur = self.scopes[s.0].parent;
+        }
         write!(f, "{}", v)
     }
 }
 
-impl<'a> Display for SymScope<'a> {
+impl Display for Decl {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let v = self
-            .map
-            .iter()
-            .map(|(k, v)| format!("{} -> {}", k, v))
-            .fold(String::new(), |acc, v| acc + "\n" + v.as_str());
-        write!(f, "{}", v)
+        write!(f, "{} '{}'", self.format_type(), self.name)
     }
 }
 
-impl<'a> Display for DeclRef<'a> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let name: &str = match self {
-            DeclRef::GlobalVar(d) => &d.1,
-            DeclRef::Var(d) => &d.1,
-            DeclRef::Param(d) => &d.name,
-            DeclRef::Func(d) => &d.name,
+/// copy the declaration out of the AST node
+pub trait DeclCreation {
+    fn to_decl(&self) -> Decl;
+}
+
+impl DeclCreation for FuncDecl {
+    fn to_decl(&self) -> Decl {
+        let param_types = match self.prototype {
+            true => Some(self.params.iter().map(|p| p.data_type.decay()).collect()),
+            false => None,
+        };
+        let sig = FuncSig {
+            return_type: self.return_type.clone(),
+            param_types,
+            variadic: false,
         };
-        write!(f, "{} '{}'", self.format_type(), name)
+        Decl {
+            id: self.id,
+            span: self.span,
+            name: self.name,
+            kind: DeclKind::Func(FuncEntry { sig, defined: true }),
+        }
     }
 }
 
-pub trait DeclRefCreation<'a> {
-    fn to_decl_ref(&'a self) -> DeclRef<'a>;
+impl DeclCreation for VarDecl {
+    fn to_decl(&self) -> Decl {
+        Decl {
+            id: self.id,
+            span: self.span,
+            name: self.name,
+            kind: prototype(&self.data_type)
+                .unwrap_or_else(|| DeclKind::Var(self.data_type.clone())),
+        }
+    }
 }
 
-impl<'a> DeclRefCreation<'a> for FuncDecl {
-    fn to_decl_ref(&'a self) -> DeclRef<'a> {
-        DeclRef::Func(self)
+impl DeclCreation for GlobalVarDecl {
+    fn to_decl(&self) -> Decl {
+        let ty = self.data_type.clone();
+        let kind = match (self.external, &self.init) {
+            (_, Some(_)) => DeclKind::GlobalVar(ty),
+            (true, None) => DeclKind::Extern(ty),
+            (false, None) => DeclKind::Tentative(ty),
+        };
+        Decl {
+            id: self.id,
+            span: self.span,
+            name: self.name,
+            kind: prototype(&self.data_type).unwrap_or(kind),
+        }
     }
 }
 
-impl<'a> DeclRefCreation<'a> for VarDecl {
-    fn to_decl_ref(&'a self) -> DeclRef<'a> {
-        DeclRef::Var(self)
-    }
+/// note at the declaration conflicting with a later one
+fn previous(old: &Decl) -> String {
+    let what = match old.kind {
+        DeclKind::Func(FuncEntry { defined: true, .. }) | DeclKind::GlobalVar(_) => "definition",
+        _ => "declaration",
+    };
+    format!("previous {} of '{}' is here", what, old.name)
 }
 
-impl<'a> DeclRefCreation<'a> for GlobalVarDecl {
-    fn to_decl_ref(&'a self) -> DeclRef<'a> {
-        DeclRef::GlobalVar(self)
+/// same type, an array of unknown length is compatible with any length
+fn is_compatible(a: &Type, b: &Type) -> bool {
+    match (a, b) {
+        (Type::Array(a, n), Type::Array(b, m)) => a == b && (n.is_none() || m.is_none() || n == m),
+        _ => a == b,
     }
 }
 
-impl<'a> DeclRefCreation<'a> for ParamDecl {
-    fn to_decl_ref(&'a self) -> DeclRef<'a> {
-        DeclRef::Param(self)
+impl DeclCreation for ParamDecl {
+    fn to_decl(&self) -> Decl {
+        Decl {
+            id: self.id,
+            span: self.span,
+            name: self.name,
+            kind: DeclKind::Param(self.data_type.decay()),
+        }
     }
 }
+
+/// declaration of function type, at the top level or in a block
+fn prototype(ty: &Type) -> Option<DeclKind> {
+    FuncSig::from_type(ty).map(|sig| {
+        DeclKind::Func(FuncEntry {
+            sig,
+            defined: false,
+        })
+    })
+}
@@ -0,0 +1,344 @@
+//! Description of the targets for the code generators
+//!
+//! A backend generates the assembly of an architecture. The differences between the object
+//! formats and the assemblers are data of `TargetDesc` instead of matches on the target in the
+//! generator, the driver only asks `backend` for the generator of the options
+
+use std::{fmt, io};
+
+use crate::{
+    ast::Ast,
+    codegen::{Arm64Backend, GenOpts, StackUsage},
+    common::CompileError,
+    coverage::Coverage,
+    util::{AsmDialect, TargetOs},
+};
+
+/// code generator of an architecture
+pub trait TargetBackend {
+    /// registers, calling convention and assembler syntax of the target
+    fn desc(&self) -> &TargetDesc;
+
+    /// generate the assembly of the AST to the writer, counting the executed lines with
+    /// `--coverage`, return the stack usage of the functions
+    fn write_asm(
+        &self,
+        ast: &Ast,
+        coverage: Option<&Coverage>,
+        out: &mut dyn io::Write,
+    ) -> Result<Vec<StackUsage>, CompileError>;
+}
+
+/// generator for the options, aarch64 is the only architecture
+pub fn backend(opts: &GenOpts) -> Box<dyn TargetBackend> {
+    Box::new(Arm64Backend::new(opts.clone()))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TargetDesc {
+    pub arch: &'static str,
+    pub os: TargetOs,
+    pub reg_classes: Vec<RegClass>,
+    pub call_conv: CallConv,
+    /// plain `char` is signed, see `TargetOs::char_signed`
+    pub char_signed: bool,
+    pub object: ObjectFormat,
+    pub syntax: AsmSyntax,
+}
+
+impl TargetDesc {
+    /// registers of the class, empty if the target has no such class
+    pub fn regs(&self, class: &str) -> &[Reg] {
+        self.reg_classes
+            .iter()
+            .find(|c| c.name == class)
+            .map_or(&[], |c| &c.regs)
+    }
+}
+
+/// registers for one purpose, i.e "argument" or "temporary"
+#[derive(Debug, Clone, PartialEq)]
+pub struct RegClass {
+    pub name: &'static str,
+    pub regs: Vec<Reg>,
+}
+
+/// how functions are called
+#[derive(Debug, Clone, PartialEq)]
+pub struct CallConv {
+    /// registers of the first arguments in order, the others are not supported yet
+    pub arg_regs: Vec<Reg>,
+    /// register of the return value
+    pub ret_reg: Reg,
+    pub frame_pointer: Reg,
+    /// register of the return address
+    pub link_reg: Reg,
+    /// alignment of sp in bytes
+    pub stack_align: u32,
+}
+
+/// general purpose register of aarch64, the only architecture
+#[derive(Debug, PartialEq, PartialOrd, Clone, Copy)]
+pub enum Reg {
+    X0,
+    X1,
+    X2,
+    X3,
+    X4,
+    X5,
+    X6,
+    X7,
+    X9,
+    X10,
+    X11,
+    X12,
+    X13,
+    X14,
+    X15,
+    X29,
+    X30,
+    /// zero register
+    Xzr,
+}
+
+impl Reg {
+    /// name of the 32 bit view `wN` for values up to 4 bytes
+    pub fn sized(self, size: u32) -> String {
+        let name = self.to_string();
+        match size {
+            0..=4 => name.replacen('x', "w", 1),
+            _ => name,
+        }
+    }
+}
+
+impl fmt::Display for Reg {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = format!("{:?}", self);
+        write!(f, "{}", name.to_lowercase())
+    }
+}
+
+/// symbol names and relocations of the object file format
+#[derive(Debug, Clone, PartialEq)]
+pub struct ObjectFormat {
+    /// prefix of the symbols of C names, `_` on Mach-O
+    pub symbol_prefix: &'static str,
+    /// prefix of the labels the assembler does not put in the object
+    pub local_prefix: &'static str,
+    /// label of the n-th string literal without n
+    pub string_prefix: &'static str,
+    /// page of a symbol and the offset in the page
+    pub page: (Reloc, Reloc),
+    /// page of the GOT entry of a symbol and the offset in the page
+    pub got_page: (Reloc, Reloc),
+    /// the static model copies external data into the executable
+    pub copy_relocations: bool,
+    /// globals of a shared library may be interposed by other images
+    pub interposition: bool,
+    /// directive keeping a symbol from dead stripping by the linker, none if the linker
+    /// keeps unreferenced symbols
+    pub no_dead_strip: Option<&'static str>,
+    /// directive making a defined symbol weak, another definition of the name is chosen by the
+    /// linker
+    pub weak_definition: &'static str,
+    /// the weak definition is exported by the directive of the global symbols too
+    pub weak_exported: bool,
+    /// directive of a weak undefined symbol, its address is 0 if no object defines it
+    pub weak_reference: &'static str,
+    /// functions and variables may be placed in sections of their own, '-ffunction-sections'
+    pub section_per_symbol: bool,
+    /// the linker splits the sections at the symbols, Mach-O has no sections per symbol
+    pub subsections_via_symbols: bool,
+    /// option of the linker removing the sections not referenced
+    pub gc_sections: &'static str,
+}
+
+impl ObjectFormat {
+    /// Mach-O on macOS, ELF otherwise
+    pub fn of(os: TargetOs) -> Self {
+        match os {
+            TargetOs::MacOs => ObjectFormat {
+                symbol_prefix: "_",
+                local_prefix: "L",
+                string_prefix: "l_.str.",
+                page: (Reloc::new("", "@PAGE"), Reloc::new("", "@PAGEOFF")),
+                got_page: (Reloc::new("", "@GOTPAGE"), Reloc::new("", "@GOTPAGEOFF")),
+                copy_relocations: false,
+                interposition: false,
+                no_dead_strip: Some(".no_dead_strip"),
+                weak_definition: ".weak_definition",
+                weak_exported: true,
+                weak_reference: ".weak_reference",
+                section_per_symbol: false,
+                subsections_via_symbols: true,
+                gc_sections: "-dead_strip",
+            },
+            _ => ObjectFormat {
+                symbol_prefix: "",
+                local_prefix: ".L",
+                string_prefix: ".LC",
+                page: (Reloc::new("", ""), Reloc::new(":lo12:", "")),
+                got_page: (Reloc::new(":got:", ""), Reloc::new(":got_lo12:", "")),
+                copy_relocations: true,
+                interposition: true,
+                no_dead_strip: None,
+                weak_definition: ".weak",
+                weak_exported: false,
+                weak_reference: ".weak",
+                section_per_symbol: true,
+                subsections_via_symbols: false,
+                gc_sections: "--gc-sections",
+            },
+        }
+    }
+
+    /// directive of the section of its own for the symbol, none if the object format shares
+    /// the section
+    pub fn symbol_section(&self, kind: SectionKind, symbol: &str) -> Option<String> {
+        let (flags, ty) = match kind {
+            SectionKind::Text => ("ax", "@progbits"),
+            SectionKind::Data => ("aw", "@progbits"),
+            SectionKind::Bss => ("aw", "@nobits"),
+        };
+        self.section_per_symbol
+            .then(|| format!(".section {}.{},\"{}\",{}", kind.shared(), symbol, flags, ty))
+    }
+}
+
+/// content of a section
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SectionKind {
+    Text,
+    /// initialized variables
+    Data,
+    /// variables initialized to zero
+    Bss,
+}
+
+impl SectionKind {
+    /// directive of the section shared by the symbols
+    pub fn shared(&self) -> &'static str {
+        match self {
+            SectionKind::Text => ".text",
+            SectionKind::Data => ".data",
+            SectionKind::Bss => ".bss",
+        }
+    }
+}
+
+/// relocation operator spelled around the symbol
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reloc {
+    pub prefix: &'static str,
+    pub suffix: &'static str,
+}
+
+impl Reloc {
+    pub const fn new(prefix: &'static str, suffix: &'static str) -> Self {
+        Reloc { prefix, suffix }
+    }
+
+    pub fn apply(&self, symbol: &str) -> String {
+        format!("{}{}{}", self.prefix, symbol, self.suffix)
+    }
+}
+
+/// spelling of the assembler directives
+#[derive(Debug, Clone, PartialEq)]
+pub struct AsmSyntax {
+    /// export a symbol
+    pub global: &'static str,
+    /// section of the string literals
+    pub cstring_section: &'static str,
+    /// section of the pointers to the constructors
+    pub init_array_section: &'static str,
+    /// the alignment of `.comm` is a power of 2 instead of bytes
+    pub comm_align_log2: bool,
+}
+
+impl AsmSyntax {
+    pub fn of(dialect: AsmDialect) -> Self {
+        match dialect {
+            AsmDialect::Darwin => AsmSyntax {
+                global: ".globl",
+                cstring_section: ".section __TEXT,__cstring,cstring_literals",
+                init_array_section: ".section __DATA,__mod_init_func,mod_init_funcs",
+                comm_align_log2: true,
+            },
+            AsmDialect::Gnu => AsmSyntax {
+                global: ".global",
+                cstring_section: ".section .rodata",
+                init_array_section: ".section .init_array,\"aw\"",
+                comm_align_log2: false,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use test_case::test_case;
+
+    use crate::{
+        parse, scan,
+        util::{AsmDialect, TargetOs},
+        GenOpts,
+    };
+
+    use super::{backend, ObjectFormat, Reg, SectionKind};
+
+    #[test]
+    fn arm64_desc() {
+        let b = backend(&GenOpts::new(TargetOs::Linux));
+        let desc = b.desc();
+        assert_eq!(desc.arch, "aarch64");
+        assert_eq!(desc.call_conv.arg_regs.len(), 8);
+        assert_eq!(desc.call_conv.ret_reg, Reg::X0);
+        assert_eq!(desc.regs("argument"), desc.call_conv.arg_regs.as_slice());
+        assert_eq!(desc.regs("temporary").first(), Some(&Reg::X9));
+        assert!(desc.regs("vector").is_empty());
+    }
+
+    // the dialect may differ from the assembler of the target
+    #[test_case(TargetOs::MacOs, None => (".globl", "_main".to_string()))]
+    #[test_case(TargetOs::Linux, None => (".global", "main".to_string()))]
+    #[test_case(TargetOs::Linux, Some(AsmDialect::Darwin) => (".globl", "main".to_string()))]
+    fn syntax(target: TargetOs, dialect: Option<AsmDialect>) -> (&'static str, String) {
+        let opts = GenOpts {
+            dialect,
+            ..GenOpts::new(target)
+        };
+        let b = backend(&opts);
+        let desc = b.desc();
+        let symbol = format!("{}main", desc.object.symbol_prefix);
+        (desc.syntax.global, symbol)
+    }
+
+    #[test_case(TargetOs::MacOs => ("g@PAGE".to_string(), "g@GOTPAGEOFF".to_string()))]
+    #[test_case(TargetOs::Linux => ("g".to_string(), ":got_lo12:g".to_string()))]
+    fn relocs(target: TargetOs) -> (String, String) {
+        let f = ObjectFormat::of(target);
+        (f.page.0.apply("g"), f.got_page.1.apply("g"))
+    }
+
+    #[test_case(TargetOs::Linux, SectionKind::Text => Some(".section .text.f,\"ax\",@progbits".to_string()))]
+    #[test_case(TargetOs::Linux, SectionKind::Bss => Some(".section .bss.f,\"aw\",@nobits".to_string()))]
+    #[test_case(TargetOs::MacOs, SectionKind::Data => None)]
+    fn symbol_section(target: TargetOs, kind: SectionKind) -> Option<String> {
+        ObjectFormat::of(target).symbol_section(kind, "f")
+    }
+
+    #[test]
+    fn write_asm() {
+        let ast = parse(scan("int main() { return 1; }").unwrap()).unwrap();
+        let mut out = Vec::new();
+        let usage = backend(&GenOpts::new(TargetOs::Linux))
+            .write_asm(&ast, None, &mut out)
+            .unwrap();
+        assert_eq!(usage.len(), 1);
+        assert!(String::from_utf8(out)
+            .unwrap()
+            .contains("main:\n\tmov x0, #1\n"));
+    }
+}
@@ -0,0 +1,137 @@
+//! Measure duration of compiler phases for -ftime-report
+//!
+//! the phases also scope the logging of '--log=<phase>,...', their names are the log targets
+
+use std::{
+    cell::Cell,
+    fmt::{self, Display},
+    time::{Duration, Instant},
+};
+
+/// phases of the pipeline, in the order of execution
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Phase {
+    Scan,
+    Parse,
+    Semantics,
+    Codegen,
+    Assemble,
+    Link,
+}
+
+impl Phase {
+    pub const ALL: [Phase; 6] = [
+        Phase::Scan,
+        Phase::Parse,
+        Phase::Semantics,
+        Phase::Codegen,
+        Phase::Assemble,
+        Phase::Link,
+    ];
+
+    /// name in the time report and target of the log records
+    pub fn name(&self) -> &'static str {
+        match self {
+            Phase::Scan => "scan",
+            Phase::Parse => "parse",
+            Phase::Semantics => "semantics",
+            Phase::Codegen => "codegen",
+            Phase::Assemble => "assemble",
+            Phase::Link => "link",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Phase::ALL.iter().copied().find(|p| p.name() == name)
+    }
+}
+
+/// accumulated duration per phase over all input files
+#[derive(Debug, Default)]
+pub struct Timings {
+    durations: [Cell<Duration>; 6],
+}
+
+impl Timings {
+    pub fn new() -> Self {
+        Timings::default()
+    }
+
+    /// run the closure and add its duration to the phase
+    pub fn time<T, F: FnOnce() -> T>(&self, phase: Phase, f: F) -> T {
+        let start = Instant::now();
+        let v = f();
+        let d = &self.durations[phase as usize];
+        d.set(d.get() + start.elapsed());
+        v
+    }
+
+    pub fn get(&self, phase: Phase) -> Duration {
+        self.durations[phase as usize].get()
+    }
+
+    pub fn total(&self) -> Duration {
+        self.durations.iter().map(|d| d.get()).sum()
+    }
+}
+
+impl Display for Timings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let total = self.total().as_secs_f64();
+        writeln!(f, "===== tncc time report =====")?;
+        writeln!(f, "{:<12}{:>12}{:>8}", "phase", "time (ms)", "%")?;
+        for phase in Phase::ALL.iter() {
+            let secs = self.get(*phase).as_secs_f64();
+            let percent = if total > 0.0 {
+                secs / total * 100.0
+            } else {
+                0.0
+            };
+            writeln!(
+                f,
+                "{:<12}{:>12.3}{:>8.1}",
+                phase.name(),
+                secs * 1000.0,
+                percent
+            )?;
+        }
+        writeln!(f, "{:<12}{:>12.3}{:>8.1}", "total", total * 1000.0, 100.0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::{Phase, Timings};
+
+    #[test]
+    fn test_accumulate() {
+        let t = Timings::new();
+        let v = t.time(Phase::Parse, || {
+            std::thread::sleep(Duration::from_millis(2));
+            1
+        });
+        t.time(Phase::Parse, || ());
+        assert_eq!(v, 1);
+        assert!(t.get(Phase::Parse) >= Duration::from_millis(2));
+        assert_eq!(t.get(Phase::Scan), Duration::ZERO);
+        assert_eq!(t.total(), t.get(Phase::Parse));
+    }
+
+    #[test]
+    fn test_names() {
+        for p in Phase::ALL {
+            assert_eq!(Phase::from_name(p.name()), Some(p));
+        }
+        assert_eq!(Phase::from_name("all"), None);
+    }
+
+    #[test]
+    fn test_report() {
+        let s = Timings::new().to_string();
+        ["phase", "scan", "semantics", "link", "total"]
+            .iter()
+            .for_each(|i| assert!(s.contains(i), "'{}' is not reported", i));
+    }
+}
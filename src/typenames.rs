@@ -0,0 +1,157 @@
+//! Classification of identifiers as type names or ordinary identifiers
+//!
+//! `T(x);` declares `x` if `T` names a type and calls `T` otherwise, so the parser asks the scopes
+//! of the declarations parsed so far before choosing the production (the "lexer hack"). Type
+//! names and ordinary identifiers share a name space, an inner declaration of either hides the
+//! outer one
+
+use std::collections::HashMap;
+
+use crate::{
+    ast::Type,
+    common::{CompileError, Span},
+    intern::Symbol,
+};
+
+/// kind of an identifier in the innermost scope declaring it
+#[derive(Debug, Clone, PartialEq)]
+pub enum IdentClass {
+    /// typedef name standing for the type
+    TypeName(Type),
+    /// variable, function or parameter, also undeclared names
+    Ordinary,
+}
+
+/// names declared in the open scopes, the file scope is the first
+#[derive(Debug, Clone)]
+pub struct TypeNames {
+    scopes: Vec<HashMap<Symbol, IdentClass>>,
+}
+
+impl Default for TypeNames {
+    fn default() -> Self {
+        TypeNames {
+            scopes: vec![HashMap::new()],
+        }
+    }
+}
+
+impl TypeNames {
+    pub fn push_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    /// close the innermost scope, the file scope is never closed
+    pub fn pop_scope(&mut self) {
+        if self.scopes.len() > 1 {
+            self.scopes.pop();
+        }
+    }
+
+    /// declare a variable, function or parameter in the innermost scope
+    pub fn declare_ordinary(&mut self, name: Symbol, span: Span) -> Result<(), CompileError> {
+        self.declare(name, IdentClass::Ordinary, span)
+    }
+
+    /// declare a typedef name in the innermost scope, repeating it with the same type is allowed
+    pub fn declare_type(&mut self, name: Symbol, ty: Type, span: Span) -> Result<(), CompileError> {
+        self.declare(name, IdentClass::TypeName(ty), span)
+    }
+
+    fn declare(&mut self, name: Symbol, class: IdentClass, span: Span) -> Result<(), CompileError> {
+        let scope = self.scopes.last_mut().expect("file scope");
+        match (scope.get(&name), &class) {
+            (Some(IdentClass::TypeName(a)), IdentClass::TypeName(b)) if a != b => Err(
+                CompileError::at_span(format!("conflicting types for '{}'", name), span),
+            ),
+            (Some(IdentClass::TypeName(_)), IdentClass::Ordinary)
+            | (Some(IdentClass::Ordinary), IdentClass::TypeName(_)) => Err(CompileError::at_span(
+                format!("'{}' redeclared as different kind of symbol", name),
+                span,
+            )),
+            _ => {
+                scope.insert(name, class);
+                Ok(())
+            }
+        }
+    }
+
+    /// class of the name from the innermost scope declaring it
+    pub fn classify(&self, name: Symbol) -> IdentClass {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|s| s.get(&name))
+            .cloned()
+            .unwrap_or(IdentClass::Ordinary)
+    }
+
+    /// type named by the identifier, none for an ordinary identifier
+    pub fn type_of(&self, name: Symbol) -> Option<Type> {
+        match self.classify(name) {
+            IdentClass::TypeName(ty) => Some(ty),
+            IdentClass::Ordinary => None,
+        }
+    }
+
+    pub fn is_type_name(&self, name: Symbol) -> bool {
+        self.type_of(name).is_some()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use test_case::test_case;
+
+    use crate::{
+        ast::{DataType, Type},
+        common::Span,
+        intern::Symbol,
+    };
+
+    use super::{IdentClass, TypeNames};
+
+    fn int() -> Type {
+        Type::Base(DataType::Int)
+    }
+
+    #[test]
+    fn classify() {
+        let t = Symbol::from("T");
+        let mut names = TypeNames::default();
+        assert_eq!(names.classify(t), IdentClass::Ordinary);
+        names.declare_type(t, int(), Span::default()).unwrap();
+        assert_eq!(names.classify(t), IdentClass::TypeName(int()));
+
+        // an inner variable hides the type until the end of its scope
+        names.push_scope();
+        names.declare_ordinary(t, Span::default()).unwrap();
+        assert!(!names.is_type_name(t));
+        names.push_scope();
+        assert!(!names.is_type_name(t));
+        names.pop_scope();
+        names.pop_scope();
+        assert_eq!(names.type_of(t), Some(int()));
+
+        names.pop_scope();
+        assert!(names.is_type_name(t), "file scope is kept");
+    }
+
+    #[test_case(true, Some(Type::Base(DataType::Int)) => None; "same type")]
+    #[test_case(true, Some(Type::Base(DataType::Char)) => Some("conflicting types for 'T'".to_string()))]
+    #[test_case(true, None => Some("'T' redeclared as different kind of symbol".to_string()); "variable after type")]
+    #[test_case(false, Some(Type::Base(DataType::Int)) => Some("'T' redeclared as different kind of symbol".to_string()); "type after variable")]
+    fn redeclare(first_type: bool, second: Option<Type>) -> Option<String> {
+        let t = Symbol::from("T");
+        let mut names = TypeNames::default();
+        match first_type {
+            true => names.declare_type(t, int(), Span::default()).unwrap(),
+            false => names.declare_ordinary(t, Span::default()).unwrap(),
+        }
+        let res = match second {
+            Some(ty) => names.declare_type(t, ty, Span::default()),
+            None => names.declare_ordinary(t, Span::default()),
+        };
+        res.err().map(|e| e.msg)
+    }
+}
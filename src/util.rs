@@ -1,41 +1,231 @@
 use std::{
-    ffi::OsStr,
+    cell::RefCell,
+    collections::HashSet,
+    ffi::{OsStr, OsString},
+    fs, io,
     path::{Path, PathBuf},
+    process::Command,
+    time::{SystemTime, UNIX_EPOCH},
 };
 
-/// path to new asm file
-pub fn new_output_asm(path: &PathBuf, is_temp: bool) -> PathBuf {
-    new_output(path, "s", is_temp)
+/// input path to read C source from standard input
+pub const STDIN_PATH: &str = "-";
+
+/// default name of outputs for source read from standard input
+const STDIN_NAME: &str = "stdin";
+
+/// check if input is the standard input
+pub fn is_stdin(path: &Path) -> bool {
+    path == Path::new(STDIN_PATH)
+}
+
+/// check if output is the standard output, '-o -' like gcc
+pub fn is_stdout(path: &Path) -> bool {
+    path == Path::new(STDIN_PATH)
+}
+
+/// path to new asm file, inside the temp dir if given otherwise next to the source
+pub fn new_output_asm(path: &Path, temps: Option<&TempDir>) -> PathBuf {
+    new_output(path, "s", temps)
 }
 
-/// path to new obj file
-pub fn new_output_obj(path: &Path, is_temp: bool) -> PathBuf {
-    new_output(path, "o", is_temp)
+/// path to new obj file, inside the temp dir if given otherwise next to the source
+pub fn new_output_obj(path: &Path, temps: Option<&TempDir>) -> PathBuf {
+    new_output(path, "o", temps)
 }
 
-/// output executable file with using the basename only
+/// output executable file with using the basename only, 'a.out' for standard input
 pub fn new_output_executable(path: &Path) -> PathBuf {
+    if is_stdin(path) {
+        return PathBuf::from("a.out");
+    }
     get_basename(path)
 }
 
-fn new_output(path: &Path, ext: &str, is_temp: bool) -> PathBuf {
-    if is_temp {
-        new_temp_file(path.file_name().unwrap(), ext)
+/// path to the shared library of '-shared' named after the first input
+pub fn new_output_shared(path: &Path, target: TargetOs) -> PathBuf {
+    let ext = match target {
+        TargetOs::MacOs => "dylib",
+        _ => "so",
+    };
+    match is_stdin(path) {
+        true => PathBuf::from(with_ext(OsStr::new("a"), ext)),
+        false => path.with_file_name(with_ext(path.file_stem().unwrap(), ext)),
+    }
+}
+
+fn new_output(path: &Path, ext: &str, temps: Option<&TempDir>) -> PathBuf {
+    let path = if is_stdin(path) {
+        Path::new(STDIN_NAME)
     } else {
-        get_basename(path).with_extension(ext)
+        path
+    };
+    match temps {
+        Some(t) => t.new_file(path, ext),
+        None => path.with_file_name(with_ext(path.file_stem().unwrap(), ext)),
     }
 }
 
+/// path to output file of '-S' or '-c' named after the input, inside the directory
+///
+/// use empty directory for the current directory like gcc
+pub fn new_output_in_dir(dir: &Path, path: &Path, ext: &str) -> PathBuf {
+    let path = if is_stdin(path) {
+        Path::new(STDIN_NAME)
+    } else {
+        path
+    };
+    dir.join(with_ext(path.file_stem().unwrap(), ext))
+}
+
 /// get basename with full parent path
 fn get_basename(path: &Path) -> PathBuf {
     path.with_file_name(path.file_stem().unwrap())
 }
 
-/// create tmp file
-fn new_temp_file(name: &OsStr, ext: &str) -> PathBuf {
-    let mut p = std::env::temp_dir();
-    p.push(name);
-    p.with_extension(ext)
+/// append extension to the file stem, the stem may contain dots
+fn with_ext(stem: &OsStr, ext: &str) -> OsString {
+    let mut name = stem.to_os_string();
+    if !ext.is_empty() {
+        name.push(".");
+        name.push(ext);
+    }
+    name
+}
+
+/// unique directory per invocation for intermediate files, removed on drop
+#[derive(Debug)]
+pub struct TempDir {
+    path: PathBuf,
+    names: RefCell<HashSet<OsString>>,
+}
+
+impl TempDir {
+    /// create new directory `tncc-<pid>-<n>` in the system temp dir
+    pub fn new() -> io::Result<Self> {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.subsec_nanos())
+            .unwrap_or_default();
+        let mut attempt: u32 = 0;
+        loop {
+            let mut path = std::env::temp_dir();
+            path.push(format!(
+                "tncc-{}-{}",
+                std::process::id(),
+                nanos.wrapping_add(attempt)
+            ));
+            match fs::create_dir(&path) {
+                Ok(_) => {
+                    return Ok(TempDir {
+                        path,
+                        names: RefCell::new(HashSet::new()),
+                    })
+                }
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists && attempt < 100 => attempt += 1,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// new file named after the input, sources with the same name get a numbered suffix
+    pub fn new_file(&self, path: &Path, ext: &str) -> PathBuf {
+        let stem = path.file_stem().unwrap_or_else(|| OsStr::new(STDIN_NAME));
+        let mut names = self.names.borrow_mut();
+        let mut name = stem.to_os_string();
+        let mut n = 0;
+        while names.contains(&name) {
+            n += 1;
+            name = stem.to_os_string();
+            name.push(format!("-{}", n));
+        }
+        let path = self.path.join(with_ext(&name, ext));
+        names.insert(name);
+        path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_dir_all(&self.path) {
+            warn!("failed to remove temp dir {:?}: {}", self.path, e);
+        }
+    }
+}
+
+/// kind of input file, classified by extension
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InputKind {
+    /// C source code (.c)
+    Source,
+    /// ARM assembly (.s)
+    Asm,
+    /// object file (.o)
+    Object,
+}
+
+/// input file for a stage of the pipeline
+#[derive(Debug)]
+pub struct Input {
+    pub path: PathBuf,
+    pub kind: InputKind,
+}
+
+impl Input {
+    pub fn new(path: PathBuf, kind: InputKind) -> Self {
+        Input { path, kind }
+    }
+
+    /// classify by extension, unknown extensions are treated as C source
+    pub fn from_path(path: &Path) -> Self {
+        let kind = match path.extension().and_then(|e| e.to_str()) {
+            _ if is_stdin(path) => InputKind::Source,
+            Some("s") => InputKind::Asm,
+            Some("o") => InputKind::Object,
+            _ => InputKind::Source,
+        };
+        Input::new(path.to_path_buf(), kind)
+    }
+}
+
+/// locate the active macOS SDK using `xcrun --show-sdk-path`
+///
+/// works with both Xcode and Command Line Tools installs
+pub fn find_macos_sdk() -> Option<PathBuf> {
+    let output = Command::new("xcrun").arg("--show-sdk-path").output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let path = String::from_utf8(output.stdout).ok()?;
+    match path.trim() {
+        "" => None,
+        p => Some(PathBuf::from(p)),
+    }
+}
+
+/// search executable in the directories of PATH
+pub fn find_in_path(name: &str) -> Option<PathBuf> {
+    let paths = std::env::var_os("PATH")?;
+    std::env::split_paths(&paths)
+        .map(|dir| dir.join(name))
+        .find(|p| p.is_file())
+}
+
+/// resolve path of a system tool: option, environment variable, default path or search on PATH
+pub fn resolve_tool(opt: Option<&str>, env: &str, default: &str) -> PathBuf {
+    let name = Path::new(default)
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or(default);
+    opt.map(PathBuf::from)
+        .or_else(|| std::env::var_os(env).map(PathBuf::from))
+        .or_else(|| Some(PathBuf::from(default)).filter(|p| p.is_file()))
+        .or_else(|| find_in_path(name))
+        .unwrap_or_else(|| PathBuf::from(default))
 }
 
 /// check if current os is aarch64
@@ -51,12 +241,338 @@ pub enum TargetOs {
 }
 
 impl TargetOs {
+    /// target triple, i.e for '-dumpmachine'
+    pub fn triple(&self) -> String {
+        match self {
+            TargetOs::MacOs => format!("{}-apple-darwin", TARGET_ARCH),
+            TargetOs::Linux => format!("{}-linux-gnu", TARGET_ARCH),
+            TargetOs::Other => format!("{}-unknown-none", TARGET_ARCH),
+        }
+    }
+
+    /// plain `char` is signed, unsigned in the AAPCS64 of Linux and signed on Apple platforms
+    pub fn char_signed(&self) -> bool {
+        *self == TargetOs::MacOs
+    }
+
     pub fn current() -> Self {
         match std::env::consts::OS {
             "macos" => TargetOs::MacOs,
             "linux" => TargetOs::Linux,
-            t => TargetOs::Other,
+            _ => TargetOs::Other,
+        }
+    }
+}
+
+/// architecture of all targets
+pub const TARGET_ARCH: &str = "aarch64";
+
+/// optimization level from '-O<level>'
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Default)]
+pub enum OptLevel {
+    #[default]
+    O0,
+    O1,
+    O2,
+    /// optimize for size
+    Os,
+}
+
+impl OptLevel {
+    /// parse the level after '-O', empty is the same as '-O1'
+    pub fn parse(level: &str) -> Option<Self> {
+        match level {
+            "0" => Some(OptLevel::O0),
+            "" | "1" => Some(OptLevel::O1),
+            "2" => Some(OptLevel::O2),
+            "s" => Some(OptLevel::Os),
+            _ => None,
+        }
+    }
+
+    /// command line flag of the level
+    pub fn flag(&self) -> &'static str {
+        match self {
+            OptLevel::O0 => "-O0",
+            OptLevel::O1 => "-O1",
+            OptLevel::O2 => "-O2",
+            OptLevel::Os => "-Os",
+        }
+    }
+}
+
+/// instruction set from '-march=<arch>', only the extensions changing the generated code are
+/// distinguished
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Default)]
+pub enum Arch {
+    #[default]
+    Armv8,
+    /// ARMv8.1 and later, or ARMv8 with the atomic instructions (LSE)
+    Armv8Lse,
+}
+
+impl Arch {
+    pub fn parse(name: &str) -> Option<Self> {
+        let (base, exts) = name.split_once('+').unwrap_or((name, ""));
+        let mut arch = match base {
+            "armv8-a" => Arch::Armv8,
+            "armv8.1-a" | "armv8.2-a" | "armv8.3-a" | "armv8.4-a" | "armv8.5-a" | "armv9-a" => {
+                Arch::Armv8Lse
+            }
+            _ => return None,
+        };
+        for ext in exts.split('+').filter(|e| !e.is_empty()) {
+            arch = match ext {
+                "lse" => Arch::Armv8Lse,
+                "nolse" => Arch::Armv8,
+                _ => arch,
+            };
+        }
+        Some(arch)
+    }
+
+    /// command line flag of the architecture
+    pub fn flag(&self) -> &'static str {
+        match self {
+            Arch::Armv8 => "-march=armv8-a",
+            Arch::Armv8Lse => "-march=armv8.1-a",
+        }
+    }
+
+    /// atomic read-modify-write instructions instead of loops of exclusive loads and stores
+    pub fn lse(&self) -> bool {
+        *self == Arch::Armv8Lse
+    }
+}
+
+/// relocation model from '-fpie', '-fpic' and '-fno-pie', how the addresses of globals are
+/// materialized
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum RelocModel {
+    /// addresses are fixed at link time, external data is copied into the executable
+    Static,
+    /// position independent executable, external data is addressed through the GOT
+    #[default]
+    Pie,
+    /// position independent code of a shared library, every global may be interposed and is
+    /// addressed through the GOT
+    Pic,
+}
+
+impl RelocModel {
+    /// parse the feature after '-f'
+    pub fn from_feature(feature: &str) -> Option<Self> {
+        match feature {
+            "pie" | "PIE" => Some(RelocModel::Pie),
+            "pic" | "PIC" => Some(RelocModel::Pic),
+            "no-pie" | "no-PIE" | "no-pic" | "no-PIC" => Some(RelocModel::Static),
+            _ => None,
+        }
+    }
+
+    /// command line flag of the model
+    pub fn flag(&self) -> &'static str {
+        match self {
+            RelocModel::Static => "-fno-pie",
+            RelocModel::Pie => "-fpie",
+            RelocModel::Pic => "-fpic",
+        }
+    }
+}
+
+/// spelling of the assembler directives from '--asm-dialect', independent of the target
+///
+/// the relocation operators and the names of symbols and labels follow the object format of
+/// the target
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AsmDialect {
+    /// GNU as, `.global`, ELF sections and the alignment of `.comm` in bytes
+    Gnu,
+    /// integrated assembler of Xcode clang, `.globl`, Mach-O sections and the alignment of
+    /// `.comm` as power of 2
+    Darwin,
+}
+
+impl AsmDialect {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "gnu" => Some(AsmDialect::Gnu),
+            "darwin" => Some(AsmDialect::Darwin),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            AsmDialect::Gnu => "gnu",
+            AsmDialect::Darwin => "darwin",
+        }
+    }
+
+    /// assembler of the target
+    pub fn of(target: TargetOs) -> Self {
+        match target {
+            TargetOs::MacOs => AsmDialect::Darwin,
+            _ => AsmDialect::Gnu,
+        }
+    }
+}
+
+/// runtime check from '-fsanitize=<name>,...'
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Sanitizer {
+    /// the divisor of `/` and `%` is zero
+    IntegerDivideByZero,
+    /// the shift amount is negative or not less than the width of the type
+    Shift,
+}
+
+impl Sanitizer {
+    /// parse the comma separated names, the unknown name is the error
+    pub fn parse_list(names: &str) -> Result<Vec<Self>, String> {
+        names
+            .split(',')
+            .map(|n| match n {
+                "integer-divide-by-zero" => Ok(Sanitizer::IntegerDivideByZero),
+                "shift" => Ok(Sanitizer::Shift),
+                _ => Err(n.to_string()),
+            })
+            .collect()
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Sanitizer::IntegerDivideByZero => "integer-divide-by-zero",
+            Sanitizer::Shift => "shift",
+        }
+    }
+}
+
+/// language standard from '-std=<name>', only K&R changes what is accepted
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Standard {
+    C89,
+    C99,
+    C11,
+    #[default]
+    C17,
+    /// pre-ANSI C, declarations may omit the type and functions may be defined old-style
+    Knr,
+}
+
+impl Standard {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "c89" | "c90" => Some(Standard::C89),
+            "c99" => Some(Standard::C99),
+            "c11" => Some(Standard::C11),
+            "c17" | "c18" => Some(Standard::C17),
+            "knr" => Some(Standard::Knr),
+            _ => None,
+        }
+    }
+
+    /// command line flag of the standard
+    pub fn flag(&self) -> &'static str {
+        match self {
+            Standard::C89 => "-std=c89",
+            Standard::C99 => "-std=c99",
+            Standard::C11 => "-std=c11",
+            Standard::C17 => "-std=c17",
+            Standard::Knr => "-std=knr",
+        }
+    }
+}
+
+/// warning reported without stopping the compilation, enabled by '-W<name>'
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Warning {
+    /// expression statement computing a value nobody uses
+    UnusedValue,
+    /// declaration without a type specifier, K&R only
+    ImplicitInt,
+    /// function defined with a list of parameter names, K&R only
+    OldStyleDefinition,
+    /// unknown attribute or attribute not applying to the declaration
+    Attributes,
+    /// `static` function no other function calls
+    UnusedFunction,
+    /// implicit conversion to a narrower integer type changing the value
+    Conversion,
+    /// local variable or parameter hiding a declaration of an enclosing scope
+    Shadow,
+}
+
+impl Warning {
+    /// every warning by name
+    pub const KNOWN: &'static [Warning] = &[
+        Warning::UnusedValue,
+        Warning::ImplicitInt,
+        Warning::OldStyleDefinition,
+        Warning::Attributes,
+        Warning::UnusedFunction,
+        Warning::Conversion,
+        Warning::Shadow,
+    ];
+
+    /// warnings enabled by '-Wall'
+    pub const ALL: &'static [Warning] = &[
+        Warning::UnusedValue,
+        Warning::ImplicitInt,
+        Warning::OldStyleDefinition,
+        Warning::Attributes,
+        Warning::UnusedFunction,
+    ];
+
+    /// warnings enabled without any '-W' option
+    pub const DEFAULT: &'static [Warning] = &[
+        Warning::ImplicitInt,
+        Warning::OldStyleDefinition,
+        Warning::Attributes,
+    ];
+
+    /// name after '-W'
+    pub fn name(&self) -> &'static str {
+        match self {
+            Warning::UnusedValue => "unused-value",
+            Warning::ImplicitInt => "implicit-int",
+            Warning::OldStyleDefinition => "old-style-definition",
+            Warning::Attributes => "attributes",
+            Warning::UnusedFunction => "unused-function",
+            Warning::Conversion => "conversion",
+            Warning::Shadow => "shadow",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Warning::KNOWN.iter().copied().find(|w| w.name() == name)
+    }
+
+    /// warnings enabled by the '-W' options in order, '-Wno-<name>' disables the warning
+    ///
+    /// return the enabled warnings and the unknown options
+    pub fn enabled(flags: &[String]) -> (Vec<Warning>, Vec<&str>) {
+        let mut enabled = Warning::DEFAULT.to_vec();
+        let mut unknown = Vec::new();
+        for flag in flags {
+            let (name, on) = match flag.strip_prefix("no-") {
+                Some(n) => (n, false),
+                None => (flag.as_str(), true),
+            };
+            let warnings = match (name, Warning::from_name(name)) {
+                ("all", _) => Warning::ALL.to_vec(),
+                (_, Some(w)) => vec![w],
+                (_, None) => {
+                    unknown.push(flag.as_str());
+                    continue;
+                }
+            };
+            enabled.retain(|w| !warnings.contains(w));
+            if on {
+                enabled.extend(warnings);
+            }
         }
+        (enabled, unknown)
     }
 }
 
@@ -67,7 +583,11 @@ mod test {
 
     use test_case::test_case;
 
-    use super::{get_basename, new_output_asm, new_output_obj};
+    use super::{
+        find_in_path, get_basename, new_output_asm, new_output_in_dir, new_output_obj,
+        new_output_shared, resolve_tool, Arch, Input, InputKind, OptLevel, RelocModel, Sanitizer,
+        Standard, TargetOs, TempDir, Warning,
+    };
 
     #[test_case("main.c", "main")]
     #[test_case("../parent_main.c", "../parent_main")]
@@ -82,10 +602,12 @@ mod test {
     }
 
     #[test_case("main.c", "main.s")]
+    #[test_case("-", "stdin.s")]
+    #[test_case("tmp/test.long.c", "tmp/test.long.s")]
     #[test_case("/Users/tmp/test_long.c", "/Users/tmp/test_long.s")]
     fn test_asm_ouput(src: &str, expected: &str) {
         assert_eq!(
-            new_output_asm(&PathBuf::from(src), false).to_str().unwrap(),
+            new_output_asm(&PathBuf::from(src), None).to_str().unwrap(),
             expected
         )
     }
@@ -94,8 +616,134 @@ mod test {
     #[test_case("/Users/tmp/test_long.c", "/Users/tmp/test_long.o")]
     fn test_obj_output(src: &str, expected: &str) {
         assert_eq!(
-            new_output_obj(&PathBuf::from(src), false).to_str().unwrap(),
+            new_output_obj(&PathBuf::from(src), None).to_str().unwrap(),
             expected
         )
     }
+
+    #[test_case("src/lib.c", TargetOs::Linux => "src/lib.so")]
+    #[test_case("lib.c", TargetOs::MacOs => "lib.dylib")]
+    #[test_case("-", TargetOs::Linux => "a.so")]
+    fn test_shared_output(src: &str, target: TargetOs) -> String {
+        new_output_shared(&PathBuf::from(src), target)
+            .display()
+            .to_string()
+    }
+
+    #[test_case("main.c", InputKind::Source)]
+    #[test_case("tmp/no_ext", InputKind::Source)]
+    #[test_case("start.s", InputKind::Asm)]
+    #[test_case("-", InputKind::Source)]
+    #[test_case("/Users/tmp/lib.o", InputKind::Object)]
+    fn test_input_kind(src: &str, expected: InputKind) {
+        assert_eq!(Input::from_path(&PathBuf::from(src)).kind, expected)
+    }
+
+    #[test]
+    fn test_temp_dir_unique_names() {
+        let temps = TempDir::new().unwrap();
+        let a = new_output_asm(&PathBuf::from("a/main.c"), Some(&temps));
+        let b = new_output_asm(&PathBuf::from("b/main.c"), Some(&temps));
+        assert_eq!(a, temps.path().join("main.s"));
+        assert_eq!(b, temps.path().join("main-1.s"));
+
+        // removed on drop
+        let dir = temps.path().to_path_buf();
+        assert!(dir.is_dir());
+        drop(temps);
+        assert!(!dir.exists());
+    }
+
+    #[test_case("", "/Users/tmp/main.c", "o" => "main.o")]
+    #[test_case("build", "src/main.c", "o" => "build/main.o")]
+    #[test_case("build/", "-", "s" => "build/stdin.s")]
+    fn test_output_in_dir(dir: &str, src: &str, ext: &str) -> String {
+        new_output_in_dir(&PathBuf::from(dir), &PathBuf::from(src), ext)
+            .to_str()
+            .unwrap()
+            .to_string()
+    }
+
+    #[test_case("0" => Some(OptLevel::O0))]
+    #[test_case("" => Some(OptLevel::O1))]
+    #[test_case("2" => Some(OptLevel::O2))]
+    #[test_case("s" => Some(OptLevel::Os))]
+    #[test_case("fast" => None)]
+    fn test_opt_level(level: &str) -> Option<OptLevel> {
+        OptLevel::parse(level)
+    }
+
+    #[test_case("armv8-a" => Some(Arch::Armv8))]
+    #[test_case("armv8.2-a" => Some(Arch::Armv8Lse))]
+    #[test_case("armv8-a+crc+lse" => Some(Arch::Armv8Lse))]
+    #[test_case("armv8.1-a+nolse" => Some(Arch::Armv8))]
+    #[test_case("armv8-a+sve" => Some(Arch::Armv8); "other extension")]
+    #[test_case("x86-64" => None)]
+    fn test_arch(name: &str) -> Option<Arch> {
+        Arch::parse(name)
+    }
+
+    #[test_case("knr" => Some(Standard::Knr))]
+    #[test_case("c90" => Some(Standard::C89))]
+    #[test_case("c18" => Some(Standard::C17))]
+    #[test_case("gnu99" => None)]
+    fn test_standard(name: &str) -> Option<Standard> {
+        Standard::parse(name)
+    }
+
+    #[test_case("PIE" => Some(RelocModel::Pie))]
+    #[test_case("no-pie" => Some(RelocModel::Static))]
+    #[test_case("PIC" => Some(RelocModel::Pic))]
+    #[test_case("no-pic" => Some(RelocModel::Static))]
+    #[test_case("plt" => None)]
+    fn test_reloc_model(feature: &str) -> Option<RelocModel> {
+        RelocModel::from_feature(feature)
+    }
+
+    #[test_case("shift" => Ok(vec![Sanitizer::Shift]))]
+    #[test_case("integer-divide-by-zero,shift" => Ok(vec![Sanitizer::IntegerDivideByZero, Sanitizer::Shift]))]
+    #[test_case("shift,address" => Err("address".to_string()))]
+    fn test_sanitizers(names: &str) -> Result<Vec<Sanitizer>, String> {
+        Sanitizer::parse_list(names)
+    }
+
+    #[test_case(&["unused-value"] => (vec![Warning::ImplicitInt, Warning::OldStyleDefinition, Warning::Attributes, Warning::UnusedValue], vec![]))]
+    #[test_case(&["all", "no-unused-value"] => (vec![Warning::ImplicitInt, Warning::OldStyleDefinition, Warning::Attributes, Warning::UnusedFunction], vec![]))]
+    #[test_case(&["all", "conversion"] => (vec![Warning::UnusedValue, Warning::ImplicitInt, Warning::OldStyleDefinition, Warning::Attributes, Warning::UnusedFunction, Warning::Conversion], vec![]); "not in all")]
+    #[test_case(&["unused-function"] => (vec![Warning::ImplicitInt, Warning::OldStyleDefinition, Warning::Attributes, Warning::UnusedFunction], vec![]))]
+    #[test_case(&["no-all", "unused-value"] => (vec![Warning::UnusedValue], vec![]))]
+    #[test_case(&["no-implicit-int", "no-attributes"] => (vec![Warning::OldStyleDefinition], vec![]))]
+    #[test_case(&["extra", "no-bogus"] => (Warning::DEFAULT.to_vec(), vec!["extra", "no-bogus"]))]
+    fn test_enabled_warnings(flags: &[&'static str]) -> (Vec<Warning>, Vec<&'static str>) {
+        let owned: Vec<String> = flags.iter().map(|f| f.to_string()).collect();
+        let (enabled, unknown) = Warning::enabled(&owned);
+        let unknown = flags
+            .iter()
+            .copied()
+            .filter(|f| unknown.contains(f))
+            .collect();
+        (enabled, unknown)
+    }
+
+    #[test]
+    fn test_find_in_path() {
+        assert!(find_in_path("sh").is_some());
+        assert!(find_in_path("tncc-no-such-tool").is_none());
+    }
+
+    #[test]
+    fn test_resolve_tool() {
+        assert_eq!(
+            resolve_tool(Some("/opt/as"), "TNCC_TEST_NO_ENV", "/usr/bin/as"),
+            PathBuf::from("/opt/as")
+        );
+        assert_eq!(
+            resolve_tool(None, "TNCC_TEST_NO_ENV", "/no/such/dir/tncc-no-such-tool"),
+            PathBuf::from("/no/such/dir/tncc-no-such-tool")
+        );
+        assert_eq!(
+            resolve_tool(None, "TNCC_TEST_NO_ENV", "/no/such/dir/sh"),
+            find_in_path("sh").unwrap()
+        );
+    }
 }
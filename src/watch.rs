@@ -0,0 +1,68 @@
+//! Watch input files and recompile on change
+
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    sync::mpsc::{channel, RecvTimeoutError},
+    time::Duration,
+};
+
+use notify::{EventKind, RecursiveMode, Watcher};
+
+/// wait time to coalesce the burst of events from a single save
+const DEBOUNCE: Duration = Duration::from_millis(100);
+
+/// call `on_change` once at start and after every change of the files, never returns on success
+///
+/// parent directories are watched since editors often save by replacing the file
+pub fn watch_files<F: FnMut()>(files: &[PathBuf], mut on_change: F) -> notify::Result<()> {
+    let (tx, rx) = channel();
+    let mut watcher = notify::recommended_watcher(tx)?;
+
+    let files: HashSet<PathBuf> = files.iter().map(|f| absolute(f)).collect();
+    let dirs: HashSet<PathBuf> = files
+        .iter()
+        .filter_map(|f| f.parent().map(Path::to_path_buf))
+        .collect();
+    for dir in &dirs {
+        watcher.watch(dir, RecursiveMode::NonRecursive)?;
+    }
+
+    on_change();
+    loop {
+        // block until an input file is modified
+        let event = rx
+            .recv()
+            .map_err(|e| notify::Error::generic(&e.to_string()))??;
+        if !is_relevant(&event, &files) {
+            continue;
+        }
+
+        // drop the remaining events of the same save
+        loop {
+            match rx.recv_timeout(DEBOUNCE) {
+                Ok(_) => continue,
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err(notify::Error::generic("watcher disconnected"))
+                }
+            }
+        }
+        on_change();
+    }
+}
+
+fn is_relevant(event: &notify::Event, files: &HashSet<PathBuf>) -> bool {
+    matches!(
+        event.kind,
+        EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_)
+    ) && event.paths.iter().any(|p| files.contains(&absolute(p)))
+}
+
+fn absolute(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| {
+        std::env::current_dir()
+            .map(|d| d.join(path))
+            .unwrap_or_else(|_| path.to_path_buf())
+    })
+}
@@ -0,0 +1,13 @@
+int add(int a, int b) {
+    return a + b;
+}
+
+int twice(int x);
+
+int main() {
+    return add(twice(10), -add(1, 2)) - 1;
+}
+
+int twice(int x) {
+    return x + x;
+}
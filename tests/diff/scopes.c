@@ -0,0 +1,16 @@
+int g = 7;
+
+int shadow(int g) {
+    {
+        int g = 3;
+    }
+    return g;
+}
+
+int main() {
+    int a = g + 1;
+    {
+        int a = 100;
+        return shadow(a) - g + 1;
+    }
+}
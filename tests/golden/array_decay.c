@@ -0,0 +1,15 @@
+int table[4];
+
+int sum(int a[], int n) {
+    return 0;
+}
+
+int main() {
+    int local[3];
+    sum(local, 3);
+    {
+        int table[2];
+        sum(table, 2);
+    }
+    return sum(table, 4);
+}
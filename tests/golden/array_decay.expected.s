@@ -0,0 +1,42 @@
+	.text
+	.global sum
+	.p2align 2
+sum:
+	sub sp, sp, #16
+	str x0, [sp, #8]
+	str w1, [sp, #4]
+	mov x0, #0
+	add sp, sp, #16
+	ret
+
+	.global main
+	.p2align 2
+main:
+	sub sp, sp, #32
+	stp x29, x30, [sp, #-16]!
+	mov x29, sp
+	add x0, sp, #36
+	mov x1, #3
+	bl sum
+	ldp x29, x30, [sp], #16
+	stp x29, x30, [sp, #-16]!
+	mov x29, sp
+	add x0, sp, #28
+	mov x1, #2
+	bl sum
+	ldp x29, x30, [sp], #16
+	stp x29, x30, [sp, #-16]!
+	mov x29, sp
+	adrp x0, table
+	add x0, x0, :lo12:table
+	mov x1, #4
+	bl sum
+	ldp x29, x30, [sp], #16
+	add sp, sp, #32
+	ret
+
+	.bss
+	.global table
+	.p2align 2
+table:
+	.zero 16
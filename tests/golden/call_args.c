@@ -0,0 +1,7 @@
+void foo(int a, int b, int c) {
+}
+
+int main() {
+    foo(1, 2, 3);
+    return foo(4, 5, 6);
+}
@@ -0,0 +1,29 @@
+	.text
+	.global foo
+	.p2align 2
+foo:
+	sub sp, sp, #16
+	str w0, [sp, #12]
+	str w1, [sp, #8]
+	str w2, [sp, #4]
+	add sp, sp, #16
+	ret
+
+	.global main
+	.p2align 2
+main:
+	stp x29, x30, [sp, #-16]!
+	mov x29, sp
+	mov x0, #1
+	mov x1, #2
+	mov x2, #3
+	bl foo
+	ldp x29, x30, [sp], #16
+	stp x29, x30, [sp, #-16]!
+	mov x29, sp
+	mov x0, #4
+	mov x1, #5
+	mov x2, #6
+	bl foo
+	ldp x29, x30, [sp], #16
+	ret
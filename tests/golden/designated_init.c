@@ -0,0 +1,15 @@
+int primes[] = {2, 3, [4] = 7, 11};
+char grid[2][3] = {5, [1] = {1, [2] = 9}};
+int flat[2][2] = {1, 2, 3};
+int zero[4] = {0};
+
+int use(int *p);
+int show(char *s);
+
+int main() {
+    int a[5] = {[3] = 4, 1, [0] = -1};
+    char s[] = {1, 2, 300};
+    use(a);
+    show(s);
+    return use(primes);
+}
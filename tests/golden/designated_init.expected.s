@@ -0,0 +1,68 @@
+	.text
+	.global main
+	.p2align 2
+main:
+	sub sp, sp, #32
+	mov x9, #-1
+	str w9, [sp, #12]
+	str wzr, [sp, #16]
+	str wzr, [sp, #20]
+	mov x9, #4
+	str w9, [sp, #24]
+	mov x9, #1
+	str w9, [sp, #28]
+	mov x9, #1
+	strb w9, [sp, #9]
+	mov x9, #2
+	strb w9, [sp, #10]
+	mov x9, #44
+	strb w9, [sp, #11]
+	stp x29, x30, [sp, #-16]!
+	mov x29, sp
+	add x0, sp, #28
+	bl use
+	ldp x29, x30, [sp], #16
+	stp x29, x30, [sp, #-16]!
+	mov x29, sp
+	add x0, sp, #25
+	bl show
+	ldp x29, x30, [sp], #16
+	stp x29, x30, [sp, #-16]!
+	mov x29, sp
+	adrp x0, primes
+	add x0, x0, :lo12:primes
+	bl use
+	ldp x29, x30, [sp], #16
+	add sp, sp, #32
+	ret
+
+	.data
+	.global primes
+	.p2align 2
+primes:
+	.word 2
+	.word 3
+	.zero 8
+	.word 7
+	.word 11
+	.global grid
+	.p2align 0
+grid:
+	.byte 5
+	.zero 2
+	.byte 1
+	.zero 1
+	.byte 9
+	.global flat
+	.zero 2
+	.p2align 2
+flat:
+	.word 1
+	.word 2
+	.word 3
+	.zero 4
+	.global zero
+	.p2align 2
+zero:
+	.word 0
+	.zero 12
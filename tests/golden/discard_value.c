@@ -0,0 +1,9 @@
+int foo(int x) {
+    return 5;
+}
+
+int main() {
+    1;
+    -foo(2) + 3;
+    return 0;
+}
@@ -0,0 +1,20 @@
+	.text
+	.global foo
+	.p2align 2
+foo:
+	sub sp, sp, #16
+	str w0, [sp, #12]
+	mov x0, #5
+	add sp, sp, #16
+	ret
+
+	.global main
+	.p2align 2
+main:
+	stp x29, x30, [sp, #-16]!
+	mov x29, sp
+	mov x0, #2
+	bl foo
+	ldp x29, x30, [sp], #16
+	mov x0, #0
+	ret
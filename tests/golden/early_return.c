@@ -0,0 +1,13 @@
+int foo() {
+    {
+        return 1;
+    }
+    return 2;
+}
+
+int main() {
+    {
+        foo();
+        return foo();
+    }
+}
@@ -0,0 +1,22 @@
+	.text
+	.globl _foo
+	.p2align 2
+_foo:
+	mov x0, #1
+	b Lfoo_epilogue
+	mov x0, #2
+Lfoo_epilogue:
+	ret
+
+	.globl _main
+	.p2align 2
+_main:
+	stp x29, x30, [sp, #-16]!
+	mov x29, sp
+	bl _foo
+	ldp x29, x30, [sp], #16
+	stp x29, x30, [sp, #-16]!
+	mov x29, sp
+	bl _foo
+	ldp x29, x30, [sp], #16
+	ret
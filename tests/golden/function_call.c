@@ -0,0 +1,7 @@
+int foo(int x, int y) {
+    return 101;
+}
+
+int main() {
+    return foo(3, 4);
+}
\ No newline at end of file
@@ -0,0 +1,21 @@
+	.text
+	.globl _foo
+	.p2align 2
+_foo:
+	sub sp, sp, #16
+	str w0, [sp, #12]
+	str w1, [sp, #8]
+	mov x0, #101
+	add sp, sp, #16
+	ret
+
+	.globl _main
+	.p2align 2
+_main:
+	stp x29, x30, [sp, #-16]!
+	mov x29, sp
+	mov x0, #3
+	mov x1, #4
+	bl _foo
+	ldp x29, x30, [sp], #16
+	ret
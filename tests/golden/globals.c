@@ -0,0 +1,8 @@
+int zero;
+int answer = 40 + 2;
+int f(int);
+int neg = -(2147483647 - 1);
+
+int main() {
+    return 0;
+}
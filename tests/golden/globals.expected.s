@@ -0,0 +1,21 @@
+	.text
+	.globl _main
+	.p2align 2
+_main:
+	mov x0, #0
+	ret
+
+	.data
+	.globl _answer
+	.p2align 2
+_answer:
+	.word 42
+	.globl _neg
+	.p2align 2
+_neg:
+	.word -2147483646
+	.bss
+	.globl _zero
+	.p2align 2
+_zero:
+	.zero 4
@@ -0,0 +1,8 @@
+int puts(char *s);
+int strlen(char *s);
+
+int main() {
+    puts("hello, " "world");
+    puts("tab\tquote\" backslash\\");
+    return strlen("hello");
+}
@@ -0,0 +1,31 @@
+	.text
+	.global main
+	.p2align 2
+main:
+	stp x29, x30, [sp, #-16]!
+	mov x29, sp
+	adrp x0, .LC0
+	add x0, x0, :lo12:.LC0
+	bl puts
+	ldp x29, x30, [sp], #16
+	stp x29, x30, [sp, #-16]!
+	mov x29, sp
+	adrp x0, .LC1
+	add x0, x0, :lo12:.LC1
+	bl puts
+	ldp x29, x30, [sp], #16
+	stp x29, x30, [sp, #-16]!
+	mov x29, sp
+	adrp x0, .LC2
+	add x0, x0, :lo12:.LC2
+	bl strlen
+	ldp x29, x30, [sp], #16
+	ret
+
+	.section .rodata
+.LC0:
+	.asciz "hello, world"
+.LC1:
+	.asciz "tab\tquote\" backslash\\"
+.LC2:
+	.asciz "hello"
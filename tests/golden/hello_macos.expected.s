@@ -0,0 +1,31 @@
+	.text
+	.globl _main
+	.p2align 2
+_main:
+	stp x29, x30, [sp, #-16]!
+	mov x29, sp
+	adrp x0, l_.str.0@PAGE
+	add x0, x0, l_.str.0@PAGEOFF
+	bl _puts
+	ldp x29, x30, [sp], #16
+	stp x29, x30, [sp, #-16]!
+	mov x29, sp
+	adrp x0, l_.str.1@PAGE
+	add x0, x0, l_.str.1@PAGEOFF
+	bl _puts
+	ldp x29, x30, [sp], #16
+	stp x29, x30, [sp, #-16]!
+	mov x29, sp
+	adrp x0, l_.str.2@PAGE
+	add x0, x0, l_.str.2@PAGEOFF
+	bl _strlen
+	ldp x29, x30, [sp], #16
+	ret
+
+	.section __TEXT,__cstring,cstring_literals
+l_.str.0:
+	.asciz "hello, world"
+l_.str.1:
+	.asciz "tab\tquote\" backslash\\"
+l_.str.2:
+	.asciz "hello"
@@ -0,0 +1,6 @@
+	.text
+	.global main
+	.p2align 2
+main:
+	mov x0, #0
+	ret
@@ -0,0 +1,7 @@
+int foo() {
+    return 1;
+}
+
+int main(void) {
+    foo();
+}
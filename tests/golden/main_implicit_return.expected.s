@@ -0,0 +1,16 @@
+	.text
+	.global foo
+	.p2align 2
+foo:
+	mov x0, #1
+	ret
+
+	.global main
+	.p2align 2
+main:
+	stp x29, x30, [sp, #-16]!
+	mov x29, sp
+	bl foo
+	ldp x29, x30, [sp], #16
+	mov x0, #0
+	ret
@@ -0,0 +1,12 @@
+char flag = 1;
+int count = 2;
+char name[3] = {97, 98};
+int after_name = 3;
+char *ptr;
+char last;
+int total;
+static char *table[2];
+
+int main() {
+    return 0;
+}
@@ -0,0 +1,45 @@
+	.text
+	.global main
+	.p2align 2
+main:
+	mov x0, #0
+	ret
+
+	.data
+	.global flag
+	.p2align 0
+flag:
+	.byte 1
+	.global count
+	.zero 3
+	.p2align 2
+count:
+	.word 2
+	.global name
+	.p2align 0
+name:
+	.byte 97
+	.byte 98
+	.zero 1
+	.global after_name
+	.zero 1
+	.p2align 2
+after_name:
+	.word 3
+	.bss
+	.global ptr
+	.p2align 3
+ptr:
+	.zero 8
+	.global last
+	.p2align 0
+last:
+	.zero 1
+	.global total
+	.zero 3
+	.p2align 2
+total:
+	.zero 4
+	.p2align 3
+table:
+	.zero 16